@@ -13,6 +13,10 @@ extern uint32_t _core2_start;
 #define CORE2_CONTROLLER_START_ADDRESS *((volatile uint32_t *) 0xF0040000)
 #define CORE2_CONTROLLER_RUN *((volatile uint32_t *) 0xF0040004)
 #define CORE2_CONTROLLER_STATUS *((volatile uint32_t *) 0xF0040008)
+#define HART_CONTROLLER_HART_COUNT *((volatile uint32_t *) 0xF004000C)
+#define HART_CONTROLLER_START_ADDRESS(hart) *((volatile uint32_t *) (0xF0040100 + (hart) * 0x10))
+#define HART_CONTROLLER_RUN(hart) *((volatile uint32_t *) (0xF0040104 + (hart) * 0x10))
+#define HART_CONTROLLER_STATUS(hart) *((volatile uint32_t *) (0xF0040108 + (hart) * 0x10))
 
 #ifndef RVFM_CUSTOM_CORE2_START
 void start_core2() {
@@ -30,6 +34,19 @@ static inline bool core2_started() {
 	return CORE2_CONTROLLER_STATUS != 0;
 }
 
+static inline uint32_t hart_count() {
+	return HART_CONTROLLER_HART_COUNT;
+}
+
+static inline void start_hart(uint32_t hart, uint32_t start_address) {
+	HART_CONTROLLER_START_ADDRESS(hart) = start_address;
+	HART_CONTROLLER_RUN(hart) = 1;
+}
+
+static inline bool hart_started(uint32_t hart) {
+	return HART_CONTROLLER_STATUS(hart) != 0;
+}
+
 C_END
 
 #endif
@@ -0,0 +1,48 @@
+#ifndef RVFM_PERF_COUNTER_H
+#define RVFM_PERF_COUNTER_H
+
+#include "common.h"
+
+C_START
+
+#define PERF_EVENT_NONE 0
+#define PERF_EVENT_LOAD 1
+#define PERF_EVENT_STORE 2
+#define PERF_EVENT_BRANCH_TAKEN 3
+#define PERF_EVENT_TRAP 4
+#define PERF_EVENT_PERIPHERAL_ACCESS 5
+#define PERF_EVENT_SC_FAIL 6
+#define PERF_EVENT_PAGE_LOCK_CONTENTION 7
+
+#define PERF_COUNTER_FIRST 3
+#define PERF_COUNTER_LAST 31
+
+// counter must be a constant from PERF_COUNTER_FIRST to PERF_COUNTER_LAST
+
+#define perf_counter_select(counter, event) \
+	__asm__ volatile("csrw %0, %1" :: "i"(0x320 + (counter)), "r"((uint32_t)(event)))
+
+#define perf_counter_clear(counter) \
+	__asm__ volatile("csrw %0, zero\n csrw %1, zero" :: "i"(0xB00 + (counter)), "i"(0xB80 + (counter)))
+
+#define perf_counter_read(counter) ({ \
+	uint32_t perf_high, perf_low, perf_high_check; \
+	do { \
+		__asm__ volatile("csrr %0, %1" : "=r"(perf_high) : "i"(0xB80 + (counter))); \
+		__asm__ volatile("csrr %0, %1" : "=r"(perf_low) : "i"(0xB00 + (counter))); \
+		__asm__ volatile("csrr %0, %1" : "=r"(perf_high_check) : "i"(0xB80 + (counter))); \
+	} while (perf_high != perf_high_check); \
+	((uint64_t) perf_high << 32) | perf_low; \
+})
+
+static inline void perf_counters_inhibit(uint32_t counter_bits) {
+	__asm__ volatile("csrs 0x320, %0" :: "r"(counter_bits));
+}
+
+static inline void perf_counters_resume(uint32_t counter_bits) {
+	__asm__ volatile("csrc 0x320, %0" :: "r"(counter_bits));
+}
+
+C_END
+
+#endif
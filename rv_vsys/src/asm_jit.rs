@@ -1,8 +1,14 @@
-use crate::{BranchFunct3, LoadFunct3, Op, OpFunct3Funct7, OpImmFunct3, StoreFunct3, SystemFunct3};
+use std::collections::HashMap;
+use crate::{AtomicFunct7, AtomicSizeFunct3, BranchFunct3, FCvtType, FMvXWClassFunct3, FpCmpFunct3, FpFormatFunct2, FpFormatFunct3, FpFunct7, FpMinMaxFunct3, FpRm, FpSignFunct3, LoadFunct3, Op, OpFunct3Funct7, OpImmFunct3, OpImmShiftFunct, StoreFunct3, SystemFunct3, SystemIntFunct7};
 pub use crate::mem::{MemIO, MemWriteResult, MTimer};
 use num_traits::ToPrimitive;
 
+const REG_RA: u32 = 1;
+const REG_SP: u32 = 2;
+const REG_T1: u32 = 6;
+
 #[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug)]
 pub enum OpcodeType {
 	RV32I_R,
 	RV32I_I,
@@ -10,8 +16,13 @@ pub enum OpcodeType {
 	RV32I_B,
 	RV32I_U,
 	RV32I_J,
+	RV32F_R4,
+	RV32C,
+	RV32C_CB,
+	RV32C_CJ,
 }
 
+#[derive(Clone, Copy, Debug)]
 pub struct AsmResult {
 	pub instruction_address: u32,
 	pub next_instruction_address: u32,
@@ -116,11 +127,55 @@ impl AsmResult {
 			funct7: None,
 		}
 	}
+	
+	// rs3 of the fused multiply-add ops is kept in funct7 along with the format
+	pub fn new_r4_type(op: Op, rd: u32, funct3: u32, rs1: u32, rs2: u32, funct7: u32, address: u32) -> Self {
+		AsmResult {
+			instruction_address: address,
+			next_instruction_address: address + 4,
+			opcode_type: OpcodeType::RV32F_R4,
+			immediate: None,
+			op,
+			funct3: Some(funct3),
+			rs1: Some(rs1),
+			rs2: Some(rs2),
+			rd: Some(rd),
+			funct7: Some(funct7),
+		}
+	}
+	
+	// op is that of the 32 bit equivalent, and funct3 the compressed encoding's
+	pub fn new_c_type(op: Op, opcode_type: OpcodeType, funct3: u32, rs1: Option<u32>, imm: Option<i32>, address: u32) -> Self {
+		AsmResult {
+			instruction_address: address,
+			next_instruction_address: address + 2,
+			opcode_type,
+			immediate: imm,
+			op,
+			funct3: Some(funct3),
+			rs1,
+			rs2: None,
+			rd: None,
+			funct7: None,
+		}
+	}
+}
+
+// a reference to a label which wasn't bound yet when the instruction was emitted
+#[derive(Clone, Copy)]
+enum AsmFixup {
+	Offset(AsmResult),
+	// auipc and the instruction which adds the low 12 bits
+	PcRelative(AsmResult, AsmResult),
+	// a data word holding the label's address
+	Address(u32),
 }
 
 pub struct AsmJit {
 	start_address: u32,
-	bytes: Vec<u8>
+	bytes: Vec<u8>,
+	labels: HashMap<String, u32>,
+	fixups: Vec<(String, AsmFixup)>,
 }
 
 const fn bitfield(x: u32, count: u32, src_bit: u32, dst_bit: u32) -> u32 {
@@ -130,11 +185,150 @@ const fn bitfield(x: u32, count: u32, src_bit: u32, dst_bit: u32) -> u32 {
 	z << dst_bit
 }
 
+// rd', rs1', rs2' - the 3 bit register fields of the compressed formats address x8 - x15
+const fn creg(reg: u32) -> u32 {
+	reg.wrapping_sub(8) & 0b111
+}
+
+const fn cb_branch(funct3: u32, rs1: u32, imm: i32) -> u32 {
+	let imm = imm as u32;
+	0b01 |
+	bitfield(imm, 1, 5, 2) |
+	bitfield(imm, 2, 1, 3) |
+	bitfield(imm, 2, 6, 5) |
+	bitfield(creg(rs1), 3, 0, 7) |
+	bitfield(imm, 2, 3, 10) |
+	bitfield(imm, 1, 8, 12) |
+	bitfield(funct3, 3, 0, 13)
+}
+
+const fn cj_jump(funct3: u32, imm: i32) -> u32 {
+	let imm = imm as u32;
+	0b01 |
+	bitfield(imm, 1, 5, 2) |
+	bitfield(imm, 3, 1, 3) |
+	bitfield(imm, 1, 7, 6) |
+	bitfield(imm, 1, 6, 7) |
+	bitfield(imm, 1, 10, 8) |
+	bitfield(imm, 2, 8, 9) |
+	bitfield(imm, 1, 4, 11) |
+	bitfield(imm, 1, 11, 12) |
+	bitfield(funct3, 3, 0, 13)
+}
+
+// upper and lower parts of a pc relative or absolute value, for auipc or lui and an addi
+const fn split_immediate(value: i32) -> (i32, i32) {
+	let upper = value.wrapping_add(0x800) >> 12;
+	(upper, value.wrapping_sub(upper << 12))
+}
+
 impl AsmJit {
 	pub fn new(start_address: u32) -> Self {
 		AsmJit {
 			start_address: start_address,
-			bytes: Vec::new()
+			bytes: Vec::new(),
+			labels: HashMap::new(),
+			fixups: Vec::new(),
+		}
+	}
+	
+	pub fn current_address(&self) -> u32 {
+		self.start_address + self.bytes.len() as u32
+	}
+	
+	pub fn bytes(&self) -> &[u8] {
+		&self.bytes
+	}
+	
+	// raw data placed between the code, e.g. constants and jump tables
+	pub fn data_u8(&mut self, value: u8) {
+		self.bytes.push(value);
+	}
+	
+	pub fn data_u16(&mut self, value: u16) {
+		self.push_u16(value as u32);
+	}
+	
+	pub fn data_u32(&mut self, value: u32) {
+		self.push_u32(value);
+	}
+	
+	pub fn data_bytes(&mut self, data: &[u8]) {
+		self.bytes.extend_from_slice(data);
+	}
+	
+	pub fn data_label(&mut self, name: &str) {
+		let address = self.current_address();
+		self.push_u32(0);
+		self.refer(name, AsmFixup::Address(address));
+	}
+	
+	// pads with nops up to a power of two alignment, using zero bytes and c.nop to reach a word boundary first
+	pub fn align(&mut self, alignment: u32) {
+		if ! alignment.is_power_of_two() {
+			panic!("alignment {} is not a power of two", alignment);
+		}
+		while self.current_address() & (alignment - 1) != 0 {
+			let address = self.current_address();
+			if (address & 0b1) != 0 {
+				self.data_u8(0);
+			} else if (address & 0b10) != 0 {
+				self.c_nop();
+			} else {
+				self.addi(0, 0, 0);
+			}
+		}
+	}
+	
+	// binds name to the current address, and patches any earlier references to it
+	pub fn label(&mut self, name: &str) {
+		if self.labels.contains_key(name) {
+			panic!("label {} bound twice", name);
+		}
+		let address = self.current_address();
+		self.labels.insert(name.to_string(), address);
+		let fixups: Vec<(String, AsmFixup)> = self.fixups.drain(..).collect();
+		for (label, fixup) in fixups {
+			if label == name {
+				self.apply_fixup(&fixup, address);
+			} else {
+				self.fixups.push((label, fixup));
+			}
+		}
+	}
+	
+	pub fn label_address(&self, name: &str) -> Option<u32> {
+		self.labels.get(name).cloned()
+	}
+	
+	// labels which are referenced but not bound, the code isn't complete until this is empty
+	pub fn unresolved_labels(&self) -> Vec<String> {
+		let mut names: Vec<String> = self.fixups.iter().map(|(name, _)| name.clone()).collect();
+		names.sort();
+		names.dedup();
+		names
+	}
+	
+	fn refer(&mut self, name: &str, fixup: AsmFixup) {
+		match self.label_address(name) {
+			Some(address) => self.apply_fixup(&fixup, address),
+			None => self.fixups.push((name.to_string(), fixup))
+		}
+	}
+	
+	fn apply_fixup(&mut self, fixup: &AsmFixup, target: u32) {
+		match fixup {
+			AsmFixup::Offset(asm_result) => {
+				self.rewrite_immediate(asm_result, target.wrapping_sub(asm_result.instruction_address) as i32);
+			},
+			AsmFixup::PcRelative(upper_result, lower_result) => {
+				let (upper, lower) = split_immediate(target.wrapping_sub(upper_result.instruction_address) as i32);
+				self.rewrite_immediate(upper_result, upper);
+				self.rewrite_immediate(lower_result, lower);
+			},
+			AsmFixup::Address(address) => {
+				self.write_u32(target, *address);
+			}
 		}
 	}
 
@@ -237,15 +431,67 @@ impl AsmJit {
 	}
 	
 	pub fn slli(&mut self, rd: u32, rs: u32, shift: u32) -> AsmResult {
-		self.i_type(Op::OpImm, rd, OpImmFunct3::SllI.to_u32().unwrap(), rs, (shift & 0x1F) as i32)
+		self.shift_imm(OpImmShiftFunct::SllI, rd, rs, shift)
 	}
 	
 	pub fn srli(&mut self, rd: u32, rs: u32, shift: u32) -> AsmResult {
-		self.i_type(Op::OpImm, rd, OpImmFunct3::SrxI.to_u32().unwrap(), rs, (shift & 0x1F) as i32)
+		self.shift_imm(OpImmShiftFunct::SrlI, rd, rs, shift)
 	}
 	
 	pub fn srai(&mut self, rd: u32, rs: u32, shift: u32) -> AsmResult {
-		self.i_type(Op::OpImm, rd, OpImmFunct3::SrxI.to_u32().unwrap(), rs, ((shift & 0x1F) | 0x4000_0000) as i32)
+		self.shift_imm(OpImmShiftFunct::SraI, rd, rs, shift)
+	}
+	
+	pub fn rori(&mut self, rd: u32, rs: u32, shift: u32) -> AsmResult {
+		self.shift_imm(OpImmShiftFunct::RorI, rd, rs, shift)
+	}
+	
+	pub fn bseti(&mut self, rd: u32, rs: u32, bit: u32) -> AsmResult {
+		self.shift_imm(OpImmShiftFunct::BSetI, rd, rs, bit)
+	}
+	
+	pub fn bclri(&mut self, rd: u32, rs: u32, bit: u32) -> AsmResult {
+		self.shift_imm(OpImmShiftFunct::BClrI, rd, rs, bit)
+	}
+	
+	pub fn binvi(&mut self, rd: u32, rs: u32, bit: u32) -> AsmResult {
+		self.shift_imm(OpImmShiftFunct::BInvI, rd, rs, bit)
+	}
+	
+	pub fn bexti(&mut self, rd: u32, rs: u32, bit: u32) -> AsmResult {
+		self.shift_imm(OpImmShiftFunct::BExtI, rd, rs, bit)
+	}
+	
+	pub fn clz(&mut self, rd: u32, rs: u32) -> AsmResult {
+		self.shift_imm(OpImmShiftFunct::Clz, rd, rs, 0)
+	}
+	
+	pub fn ctz(&mut self, rd: u32, rs: u32) -> AsmResult {
+		self.shift_imm(OpImmShiftFunct::Ctz, rd, rs, 0)
+	}
+	
+	pub fn cpop(&mut self, rd: u32, rs: u32) -> AsmResult {
+		self.shift_imm(OpImmShiftFunct::CPop, rd, rs, 0)
+	}
+	
+	pub fn sext_b(&mut self, rd: u32, rs: u32) -> AsmResult {
+		self.shift_imm(OpImmShiftFunct::SextB, rd, rs, 0)
+	}
+	
+	pub fn sext_h(&mut self, rd: u32, rs: u32) -> AsmResult {
+		self.shift_imm(OpImmShiftFunct::SextH, rd, rs, 0)
+	}
+	
+	pub fn rev8(&mut self, rd: u32, rs: u32) -> AsmResult {
+		self.shift_imm(OpImmShiftFunct::Rev8, rd, rs, 0)
+	}
+	
+	pub fn orc_b(&mut self, rd: u32, rs: u32) -> AsmResult {
+		self.shift_imm(OpImmShiftFunct::OrcB, rd, rs, 0)
+	}
+	
+	pub fn zext_h(&mut self, rd: u32, rs: u32) -> AsmResult {
+		self.r_type(Op::Op, rd, OpFunct3Funct7::ZextH.imm3(), rs, 0, OpFunct3Funct7::ZextH.imm7())
 	}
 	
 	pub fn sb(&mut self, rs: u32, rbase: u32, offset: i32) -> AsmResult {
@@ -320,176 +566,1166 @@ impl AsmJit {
 		self.r_type(Op::Op, rd, OpFunct3Funct7::MulHSU.imm3(), rs1, rs2, OpFunct3Funct7::MulHSU.imm7())
 	}
 	
-	pub fn csrrw(&mut self, rd: u32, csr: u32, rs1: u32) -> AsmResult {
-		self.i_type(Op::System, rd, SystemFunct3::CsrRW.to_raw(), rs1, csr as i32)
+	pub fn sh1add(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.r_type(Op::Op, rd, OpFunct3Funct7::Sh1Add.imm3(), rs1, rs2, OpFunct3Funct7::Sh1Add.imm7())
 	}
 	
-	pub fn rewrite_immediate(&mut self, asm_result: &AsmResult, immediate: i32) {
-		match asm_result.opcode_type {
-			OpcodeType::RV32I_I => {
-				self.i_type_at(asm_result.op, asm_result.rd.unwrap(), asm_result.funct3.unwrap(), asm_result.rs1.unwrap(), immediate, asm_result.instruction_address);
-			}
-			OpcodeType::RV32I_S => {
-				self.s_type_at(asm_result.op, asm_result.funct3.unwrap(), asm_result.rs1.unwrap(), asm_result.rs2.unwrap(), immediate, asm_result.instruction_address);
-			}
-			OpcodeType::RV32I_B => {
-				self.b_type_at(asm_result.op, immediate, asm_result.funct3.unwrap(), asm_result.rs1.unwrap(), asm_result.rs2.unwrap(), asm_result.instruction_address);
-			}
-			OpcodeType::RV32I_U => {
-				self.u_type_at(asm_result.op, asm_result.rd.unwrap(), immediate, asm_result.instruction_address);
-			},
-			OpcodeType::RV32I_J => {
-				self.j_type_at(asm_result.op, asm_result.rd.unwrap(), immediate, asm_result.instruction_address);
-			},
-			_ => {
-			}
-		}
+	pub fn sh2add(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.r_type(Op::Op, rd, OpFunct3Funct7::Sh2Add.imm3(), rs1, rs2, OpFunct3Funct7::Sh2Add.imm7())
 	}
-
-	fn r_type(&mut self, op: Op, rd: u32, funct3: u32, rs1: u32, rs2: u32, funct7: u32) -> AsmResult {
-		let opcode_value = op.to_raw() | 
-			bitfield(rd, 5, 0, 7) | 
-			bitfield(funct3, 3, 0, 12) | 
-			bitfield(rs1, 5, 0, 15) | 
-			bitfield(rs2, 5, 0, 20) |
-			bitfield(funct7, 7, 0, 25);
-		let address = self.bytes.len() as u32 + self.start_address;
-		self.push_u32(opcode_value);
-		AsmResult::new_r_type(op, rd, funct3, rs1, rs2, funct7, address)
+	
+	pub fn sh3add(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.r_type(Op::Op, rd, OpFunct3Funct7::Sh3Add.imm3(), rs1, rs2, OpFunct3Funct7::Sh3Add.imm7())
 	}
 	
-	#[allow(unused)]
-	fn r_type_at(&mut self, op: Op, rd: u32, funct3: u32, rs1: u32, rs2: u32, funct7: u32, address: u32) -> AsmResult {
-		let opcode_value = op.to_raw() | 
-			bitfield(rd, 5, 0, 7) | 
-			bitfield(funct3, 3, 0, 12) | 
-			bitfield(rs1, 5, 0, 15) | 
-			bitfield(rs2, 5, 0, 20) |
-			bitfield(funct7, 7, 0, 25);
-		self.write_u32(opcode_value, address);
-		AsmResult::new_r_type(op, rd, funct3, rs1, rs2, funct7, address)
+	pub fn andn(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.r_type(Op::Op, rd, OpFunct3Funct7::AndN.imm3(), rs1, rs2, OpFunct3Funct7::AndN.imm7())
 	}
-
-	fn i_type(&mut self, op: Op, rd: u32, funct3: u32, rs1: u32, imm: i32) -> AsmResult {
-		let opcode_value = op.to_raw() | 
-			bitfield(rd, 5, 0, 7) | 
-			bitfield(funct3, 3, 0, 12) | 
-			bitfield(rs1, 5, 0, 15) | 
-			bitfield(imm as u32, 12, 0, 20);
-		let address = self.bytes.len() as u32 + self.start_address;
-		self.push_u32(opcode_value);
-		AsmResult::new_i_type(op, rd, funct3, rs1, imm, address)
+	
+	pub fn orn(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.r_type(Op::Op, rd, OpFunct3Funct7::OrN.imm3(), rs1, rs2, OpFunct3Funct7::OrN.imm7())
 	}
 	
-	fn i_type_at(&mut self, op: Op, rd: u32, funct3: u32, rs1: u32, imm: i32, address: u32) -> AsmResult {
-		let opcode_value = op.to_raw() | 
-			bitfield(rd, 5, 0, 7) | 
-			bitfield(funct3, 3, 0, 12) | 
-			bitfield(rs1, 5, 0, 15) | 
-			bitfield(imm as u32, 12, 0, 20);
-		self.write_u32(opcode_value, address);
-		AsmResult::new_i_type(op, rd, funct3, rs1, imm, address)
+	pub fn xnor(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.r_type(Op::Op, rd, OpFunct3Funct7::XNor.imm3(), rs1, rs2, OpFunct3Funct7::XNor.imm7())
 	}
-
-	fn s_type(&mut self, op: Op, funct3: u32, rs1: u32, rs2: u32, imm: i32) -> AsmResult {
-		let opcode_value = op.to_raw() | 
-			bitfield(imm as u32, 5, 0, 7) | 
-			bitfield(funct3, 3, 0, 12) | 
-			bitfield(rs1, 5, 0, 15) | 
-			bitfield(rs2, 5, 0, 20) |
-			bitfield(imm as u32, 7, 5, 25);
-		let address = self.bytes.len() as u32 + self.start_address;
-		self.push_u32(opcode_value);
-		AsmResult::new_s_type(op, funct3, rs1, rs2, imm, address)
+	
+	pub fn min(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.r_type(Op::Op, rd, OpFunct3Funct7::Min.imm3(), rs1, rs2, OpFunct3Funct7::Min.imm7())
 	}
 	
-	fn s_type_at(&mut self, op: Op, funct3: u32, rs1: u32, rs2: u32, imm: i32, address: u32) -> AsmResult {
-		let opcode_value = op.to_raw() | 
-			bitfield(imm as u32, 5, 0, 7) | 
-			bitfield(funct3, 3, 0, 12) | 
-			bitfield(rs1, 5, 0, 15) | 
-			bitfield(rs2, 5, 0, 20) |
-			bitfield(imm as u32, 7, 5, 25);
-		self.write_u32(opcode_value, address);
-		AsmResult::new_s_type(op, funct3, rs1, rs2, imm, address)
+	pub fn minu(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.r_type(Op::Op, rd, OpFunct3Funct7::MinU.imm3(), rs1, rs2, OpFunct3Funct7::MinU.imm7())
 	}
 	
-	fn b_type(&mut self, op: Op, imm: i32, funct3: u32, rs1: u32, rs2: u32) -> AsmResult {
-		let opcode_value = op.to_raw() | 
-			bitfield(imm as u32, 1, 11, 7) |
-			bitfield(imm as u32, 4, 1, 8) |
-			bitfield(funct3, 3, 0, 12) | 
-			bitfield(rs1, 5, 0, 15) | 
-			bitfield(rs2, 5, 0, 20) |
-			bitfield(imm as u32, 6, 5, 25) |
-			bitfield(imm as u32, 1, 12, 31);
-		let address = self.bytes.len() as u32 + self.start_address;
-		self.push_u32(opcode_value);
-		AsmResult::new_b_type(op, funct3, rs1, rs2, imm, address)
+	pub fn max(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.r_type(Op::Op, rd, OpFunct3Funct7::Max.imm3(), rs1, rs2, OpFunct3Funct7::Max.imm7())
 	}
 	
-	fn b_type_at(&mut self, op: Op, imm: i32, funct3: u32, rs1: u32, rs2: u32, address: u32) -> AsmResult {
-		let opcode_value = op.to_raw() | 
-			bitfield(imm as u32, 1, 11, 7) |
-			bitfield(imm as u32, 4, 1, 8) |
-			bitfield(funct3, 3, 0, 12) | 
-			bitfield(rs1, 5, 0, 15) | 
-			bitfield(rs2, 5, 0, 20) |
-			bitfield(imm as u32, 6, 5, 25) |
-			bitfield(imm as u32, 1, 12, 31);
-		self.write_u32(opcode_value, address);
-		AsmResult::new_b_type(op, funct3, rs1, rs2, imm, address)
+	pub fn maxu(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.r_type(Op::Op, rd, OpFunct3Funct7::MaxU.imm3(), rs1, rs2, OpFunct3Funct7::MaxU.imm7())
 	}
-
-	fn u_type(&mut self, op: Op, rd: u32, imm: i32) -> AsmResult {
-		let opcode_value = op.to_raw() | 
-			bitfield(rd, 5, 0, 7) | 
-			bitfield(imm as u32, 20, 0, 12);
-		let address = self.bytes.len() as u32 + self.start_address;
-		self.push_u32(opcode_value);
-		AsmResult::new_u_type(op, rd, imm, address)
+	
+	pub fn rol(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.r_type(Op::Op, rd, OpFunct3Funct7::Rol.imm3(), rs1, rs2, OpFunct3Funct7::Rol.imm7())
 	}
 	
-	fn u_type_at(&mut self, op: Op, rd: u32, imm: i32, address: u32) -> AsmResult {
-		let opcode_value = op.to_raw() | 
-			bitfield(rd, 5, 0, 7) | 
-			bitfield(imm as u32, 20, 0, 12);
-		self.write_u32(opcode_value, address);
-		AsmResult::new_u_type(op, rd, imm, address)
+	pub fn ror(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.r_type(Op::Op, rd, OpFunct3Funct7::Ror.imm3(), rs1, rs2, OpFunct3Funct7::Ror.imm7())
 	}
-
-	fn j_type(&mut self, op: Op, rd: u32, imm: i32) -> AsmResult {
-		let opcode_value = op.to_raw() | 
-			bitfield(rd, 5, 0, 7) | 
-			bitfield(imm as u32, 10, 1, 21) |
-			bitfield(imm as u32, 1, 11, 20) |
-			bitfield(imm as u32, 8, 12, 12) |
-			bitfield(imm as u32, 1, 20, 31);
-		let address = self.bytes.len() as u32 + self.start_address;
-		self.push_u32(opcode_value);
-		AsmResult::new_j_type(op, rd, imm, address)
+	
+	pub fn bset(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.r_type(Op::Op, rd, OpFunct3Funct7::BSet.imm3(), rs1, rs2, OpFunct3Funct7::BSet.imm7())
 	}
 	
-	fn j_type_at(&mut self, op: Op, rd: u32, imm: i32, address: u32) -> AsmResult {
-		let opcode_value = op.to_raw() | 
-			bitfield(rd, 5, 0, 7) | 
-			bitfield(imm as u32, 10, 1, 21) |
-			bitfield(imm as u32, 1, 11, 20) |
-			bitfield(imm as u32, 8, 12, 12) |
-			bitfield(imm as u32, 1, 20, 31);
-		self.write_u32(opcode_value, address);
-		AsmResult::new_j_type(op, rd, imm, address)
+	pub fn bclr(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.r_type(Op::Op, rd, OpFunct3Funct7::BClr.imm3(), rs1, rs2, OpFunct3Funct7::BClr.imm7())
 	}
 	
-	fn write_u32(&mut self, value: u32, address: u32) {
-		if (address + 4) > (self.bytes.len() as u32 + self.start_address) || address < self.start_address {
-			panic!("attempt to write instruction outside of jit bounds");
-		}
-		let offset = (address - self.start_address) as usize;
-		self.bytes[offset + 0] = (value >> 0) as u8;
-		self.bytes[offset + 1] = (value >> 8) as u8;
-		self.bytes[offset + 2] = (value >> 16) as u8;
-		self.bytes[offset + 3] = (value >> 24) as u8;
+	pub fn binv(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.r_type(Op::Op, rd, OpFunct3Funct7::BInv.imm3(), rs1, rs2, OpFunct3Funct7::BInv.imm7())
+	}
+	
+	pub fn bext(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.r_type(Op::Op, rd, OpFunct3Funct7::BExt.imm3(), rs1, rs2, OpFunct3Funct7::BExt.imm7())
+	}
+	
+	pub fn div(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.r_type(Op::Op, rd, OpFunct3Funct7::Div.imm3(), rs1, rs2, OpFunct3Funct7::Div.imm7())
+	}
+	
+	pub fn divu(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.r_type(Op::Op, rd, OpFunct3Funct7::DivU.imm3(), rs1, rs2, OpFunct3Funct7::DivU.imm7())
+	}
+	
+	pub fn rem(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.r_type(Op::Op, rd, OpFunct3Funct7::Rem.imm3(), rs1, rs2, OpFunct3Funct7::Rem.imm7())
+	}
+	
+	pub fn remu(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.r_type(Op::Op, rd, OpFunct3Funct7::RemU.imm3(), rs1, rs2, OpFunct3Funct7::RemU.imm7())
+	}
+	
+	// the atomics take their operands in assembly order, e.g. amoadd.w rd, rs2, (rs1), and the _aqrl
+	// forms set the acquire and release ordering bits
+	pub fn lr_w(&mut self, rd: u32, rs1: u32) -> AsmResult {
+		self.lr_w_aqrl(rd, rs1, false, false)
+	}
+	
+	pub fn lr_w_aqrl(&mut self, rd: u32, rs1: u32, aq: bool, rl: bool) -> AsmResult {
+		self.atomic(AtomicFunct7::LoadReserve, rd, 0, rs1, aq, rl)
+	}
+	
+	pub fn sc_w(&mut self, rd: u32, rs2: u32, rs1: u32) -> AsmResult {
+		self.sc_w_aqrl(rd, rs2, rs1, false, false)
+	}
+	
+	pub fn sc_w_aqrl(&mut self, rd: u32, rs2: u32, rs1: u32, aq: bool, rl: bool) -> AsmResult {
+		self.atomic(AtomicFunct7::StoreConditional, rd, rs2, rs1, aq, rl)
+	}
+	
+	pub fn amoswap_w(&mut self, rd: u32, rs2: u32, rs1: u32) -> AsmResult {
+		self.amoswap_w_aqrl(rd, rs2, rs1, false, false)
+	}
+	
+	pub fn amoswap_w_aqrl(&mut self, rd: u32, rs2: u32, rs1: u32, aq: bool, rl: bool) -> AsmResult {
+		self.atomic(AtomicFunct7::Swap, rd, rs2, rs1, aq, rl)
+	}
+	
+	pub fn amoadd_w(&mut self, rd: u32, rs2: u32, rs1: u32) -> AsmResult {
+		self.amoadd_w_aqrl(rd, rs2, rs1, false, false)
+	}
+	
+	pub fn amoadd_w_aqrl(&mut self, rd: u32, rs2: u32, rs1: u32, aq: bool, rl: bool) -> AsmResult {
+		self.atomic(AtomicFunct7::Add, rd, rs2, rs1, aq, rl)
+	}
+	
+	pub fn amoxor_w(&mut self, rd: u32, rs2: u32, rs1: u32) -> AsmResult {
+		self.amoxor_w_aqrl(rd, rs2, rs1, false, false)
+	}
+	
+	pub fn amoxor_w_aqrl(&mut self, rd: u32, rs2: u32, rs1: u32, aq: bool, rl: bool) -> AsmResult {
+		self.atomic(AtomicFunct7::Xor, rd, rs2, rs1, aq, rl)
+	}
+	
+	pub fn amoand_w(&mut self, rd: u32, rs2: u32, rs1: u32) -> AsmResult {
+		self.amoand_w_aqrl(rd, rs2, rs1, false, false)
+	}
+	
+	pub fn amoand_w_aqrl(&mut self, rd: u32, rs2: u32, rs1: u32, aq: bool, rl: bool) -> AsmResult {
+		self.atomic(AtomicFunct7::And, rd, rs2, rs1, aq, rl)
+	}
+	
+	pub fn amoor_w(&mut self, rd: u32, rs2: u32, rs1: u32) -> AsmResult {
+		self.amoor_w_aqrl(rd, rs2, rs1, false, false)
+	}
+	
+	pub fn amoor_w_aqrl(&mut self, rd: u32, rs2: u32, rs1: u32, aq: bool, rl: bool) -> AsmResult {
+		self.atomic(AtomicFunct7::Or, rd, rs2, rs1, aq, rl)
+	}
+	
+	pub fn amomin_w(&mut self, rd: u32, rs2: u32, rs1: u32) -> AsmResult {
+		self.amomin_w_aqrl(rd, rs2, rs1, false, false)
+	}
+	
+	pub fn amomin_w_aqrl(&mut self, rd: u32, rs2: u32, rs1: u32, aq: bool, rl: bool) -> AsmResult {
+		self.atomic(AtomicFunct7::Min, rd, rs2, rs1, aq, rl)
+	}
+	
+	pub fn amomax_w(&mut self, rd: u32, rs2: u32, rs1: u32) -> AsmResult {
+		self.amomax_w_aqrl(rd, rs2, rs1, false, false)
+	}
+	
+	pub fn amomax_w_aqrl(&mut self, rd: u32, rs2: u32, rs1: u32, aq: bool, rl: bool) -> AsmResult {
+		self.atomic(AtomicFunct7::Max, rd, rs2, rs1, aq, rl)
+	}
+	
+	pub fn amominu_w(&mut self, rd: u32, rs2: u32, rs1: u32) -> AsmResult {
+		self.amominu_w_aqrl(rd, rs2, rs1, false, false)
+	}
+	
+	pub fn amominu_w_aqrl(&mut self, rd: u32, rs2: u32, rs1: u32, aq: bool, rl: bool) -> AsmResult {
+		self.atomic(AtomicFunct7::MinU, rd, rs2, rs1, aq, rl)
+	}
+	
+	pub fn amomaxu_w(&mut self, rd: u32, rs2: u32, rs1: u32) -> AsmResult {
+		self.amomaxu_w_aqrl(rd, rs2, rs1, false, false)
+	}
+	
+	pub fn amomaxu_w_aqrl(&mut self, rd: u32, rs2: u32, rs1: u32, aq: bool, rl: bool) -> AsmResult {
+		self.atomic(AtomicFunct7::MaxU, rd, rs2, rs1, aq, rl)
+	}
+	
+	pub fn flw(&mut self, rd: u32, rbase: u32, offset: i32) -> AsmResult {
+		self.i_type(Op::LoadFp, rd, FpFormatFunct3::Width32.to_raw(), rbase, offset)
+	}
+	
+	pub fn fsw(&mut self, rs: u32, rbase: u32, offset: i32) -> AsmResult {
+		self.s_type(Op::StoreFp, FpFormatFunct3::Width32.to_raw(), rbase, rs, offset)
+	}
+	
+	pub fn fld(&mut self, rd: u32, rbase: u32, offset: i32) -> AsmResult {
+		self.i_type(Op::LoadFp, rd, FpFormatFunct3::Width64.to_raw(), rbase, offset)
+	}
+	
+	pub fn fsd(&mut self, rs: u32, rbase: u32, offset: i32) -> AsmResult {
+		self.s_type(Op::StoreFp, FpFormatFunct3::Width64.to_raw(), rbase, rs, offset)
+	}
+	
+	// the _rm forms of the ops with a rounding mode take a static one instead of the dynamic frm
+	pub fn fmadd_s(&mut self, rd: u32, rs1: u32, rs2: u32, rs3: u32) -> AsmResult {
+		self.fmadd_s_rm(rd, rs1, rs2, rs3, FpRm::Dynamic)
+	}
+	
+	pub fn fmadd_s_rm(&mut self, rd: u32, rs1: u32, rs2: u32, rs3: u32, rm: FpRm) -> AsmResult {
+		self.r4_type(Op::MAdd, FpFormatFunct2::Single, rd, [rs1, rs2, rs3], rm)
+	}
+	
+	pub fn fmsub_s(&mut self, rd: u32, rs1: u32, rs2: u32, rs3: u32) -> AsmResult {
+		self.fmsub_s_rm(rd, rs1, rs2, rs3, FpRm::Dynamic)
+	}
+	
+	pub fn fmsub_s_rm(&mut self, rd: u32, rs1: u32, rs2: u32, rs3: u32, rm: FpRm) -> AsmResult {
+		self.r4_type(Op::MSub, FpFormatFunct2::Single, rd, [rs1, rs2, rs3], rm)
+	}
+	
+	pub fn fnmsub_s(&mut self, rd: u32, rs1: u32, rs2: u32, rs3: u32) -> AsmResult {
+		self.fnmsub_s_rm(rd, rs1, rs2, rs3, FpRm::Dynamic)
+	}
+	
+	pub fn fnmsub_s_rm(&mut self, rd: u32, rs1: u32, rs2: u32, rs3: u32, rm: FpRm) -> AsmResult {
+		self.r4_type(Op::NMSub, FpFormatFunct2::Single, rd, [rs1, rs2, rs3], rm)
+	}
+	
+	pub fn fnmadd_s(&mut self, rd: u32, rs1: u32, rs2: u32, rs3: u32) -> AsmResult {
+		self.fnmadd_s_rm(rd, rs1, rs2, rs3, FpRm::Dynamic)
+	}
+	
+	pub fn fnmadd_s_rm(&mut self, rd: u32, rs1: u32, rs2: u32, rs3: u32, rm: FpRm) -> AsmResult {
+		self.r4_type(Op::NMAdd, FpFormatFunct2::Single, rd, [rs1, rs2, rs3], rm)
+	}
+	
+	pub fn fadd_s(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.fadd_s_rm(rd, rs1, rs2, FpRm::Dynamic)
+	}
+	
+	pub fn fadd_s_rm(&mut self, rd: u32, rs1: u32, rs2: u32, rm: FpRm) -> AsmResult {
+		self.fp_op(FpFunct7::Add_S, rd, rs1, rs2, rm.to_raw())
+	}
+	
+	pub fn fsub_s(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.fsub_s_rm(rd, rs1, rs2, FpRm::Dynamic)
+	}
+	
+	pub fn fsub_s_rm(&mut self, rd: u32, rs1: u32, rs2: u32, rm: FpRm) -> AsmResult {
+		self.fp_op(FpFunct7::Sub_S, rd, rs1, rs2, rm.to_raw())
+	}
+	
+	pub fn fmul_s(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.fmul_s_rm(rd, rs1, rs2, FpRm::Dynamic)
+	}
+	
+	pub fn fmul_s_rm(&mut self, rd: u32, rs1: u32, rs2: u32, rm: FpRm) -> AsmResult {
+		self.fp_op(FpFunct7::Mul_S, rd, rs1, rs2, rm.to_raw())
+	}
+	
+	pub fn fdiv_s(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.fdiv_s_rm(rd, rs1, rs2, FpRm::Dynamic)
+	}
+	
+	pub fn fdiv_s_rm(&mut self, rd: u32, rs1: u32, rs2: u32, rm: FpRm) -> AsmResult {
+		self.fp_op(FpFunct7::Div_S, rd, rs1, rs2, rm.to_raw())
+	}
+	
+	pub fn fsqrt_s(&mut self, rd: u32, rs1: u32) -> AsmResult {
+		self.fsqrt_s_rm(rd, rs1, FpRm::Dynamic)
+	}
+	
+	pub fn fsqrt_s_rm(&mut self, rd: u32, rs1: u32, rm: FpRm) -> AsmResult {
+		self.fp_op(FpFunct7::Sqrt_S, rd, rs1, 0, rm.to_raw())
+	}
+	
+	pub fn fsgnj_s(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.fp_op(FpFunct7::Sign_S, rd, rs1, rs2, FpSignFunct3::SignFromRs2.to_raw())
+	}
+	
+	pub fn fsgnjn_s(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.fp_op(FpFunct7::Sign_S, rd, rs1, rs2, FpSignFunct3::SignFromNotRs2.to_raw())
+	}
+	
+	pub fn fsgnjx_s(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.fp_op(FpFunct7::Sign_S, rd, rs1, rs2, FpSignFunct3::SignFromRs1XorRs2.to_raw())
+	}
+	
+	pub fn fmin_s(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.fp_op(FpFunct7::MinMax_S, rd, rs1, rs2, FpMinMaxFunct3::Min.to_raw())
+	}
+	
+	pub fn fmax_s(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.fp_op(FpFunct7::MinMax_S, rd, rs1, rs2, FpMinMaxFunct3::Max.to_raw())
+	}
+	
+	pub fn fcvt_w_s(&mut self, rd: u32, rs1: u32) -> AsmResult {
+		self.fcvt_w_s_rm(rd, rs1, FpRm::Dynamic)
+	}
+	
+	pub fn fcvt_w_s_rm(&mut self, rd: u32, rs1: u32, rm: FpRm) -> AsmResult {
+		self.fp_op(FpFunct7::CvtW_S, rd, rs1, FCvtType::Signed.to_raw(), rm.to_raw())
+	}
+	
+	pub fn fcvt_wu_s(&mut self, rd: u32, rs1: u32) -> AsmResult {
+		self.fcvt_wu_s_rm(rd, rs1, FpRm::Dynamic)
+	}
+	
+	pub fn fcvt_wu_s_rm(&mut self, rd: u32, rs1: u32, rm: FpRm) -> AsmResult {
+		self.fp_op(FpFunct7::CvtW_S, rd, rs1, FCvtType::Unsigned.to_raw(), rm.to_raw())
+	}
+	
+	pub fn fmv_x_w(&mut self, rd: u32, rs1: u32) -> AsmResult {
+		self.fp_op(FpFunct7::MvXWClass_S, rd, rs1, 0, FMvXWClassFunct3::MvXW.to_raw())
+	}
+	
+	pub fn fclass_s(&mut self, rd: u32, rs1: u32) -> AsmResult {
+		self.fp_op(FpFunct7::MvXWClass_S, rd, rs1, 0, FMvXWClassFunct3::Class.to_raw())
+	}
+	
+	pub fn feq_s(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.fp_op(FpFunct7::Cmp_S, rd, rs1, rs2, FpCmpFunct3::Eq.to_raw())
+	}
+	
+	pub fn flt_s(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.fp_op(FpFunct7::Cmp_S, rd, rs1, rs2, FpCmpFunct3::Lt.to_raw())
+	}
+	
+	pub fn fle_s(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.fp_op(FpFunct7::Cmp_S, rd, rs1, rs2, FpCmpFunct3::LEq.to_raw())
+	}
+	
+	pub fn fcvt_s_w(&mut self, rd: u32, rs1: u32) -> AsmResult {
+		self.fcvt_s_w_rm(rd, rs1, FpRm::Dynamic)
+	}
+	
+	pub fn fcvt_s_w_rm(&mut self, rd: u32, rs1: u32, rm: FpRm) -> AsmResult {
+		self.fp_op(FpFunct7::CvtS_W, rd, rs1, FCvtType::Signed.to_raw(), rm.to_raw())
+	}
+	
+	pub fn fcvt_s_wu(&mut self, rd: u32, rs1: u32) -> AsmResult {
+		self.fcvt_s_wu_rm(rd, rs1, FpRm::Dynamic)
+	}
+	
+	pub fn fcvt_s_wu_rm(&mut self, rd: u32, rs1: u32, rm: FpRm) -> AsmResult {
+		self.fp_op(FpFunct7::CvtS_W, rd, rs1, FCvtType::Unsigned.to_raw(), rm.to_raw())
+	}
+	
+	pub fn fmv_w_x(&mut self, rd: u32, rs1: u32) -> AsmResult {
+		self.fp_op(FpFunct7::MvWX_S, rd, rs1, 0, 0)
+	}
+	
+	pub fn fmadd_d(&mut self, rd: u32, rs1: u32, rs2: u32, rs3: u32) -> AsmResult {
+		self.fmadd_d_rm(rd, rs1, rs2, rs3, FpRm::Dynamic)
+	}
+	
+	pub fn fmadd_d_rm(&mut self, rd: u32, rs1: u32, rs2: u32, rs3: u32, rm: FpRm) -> AsmResult {
+		self.r4_type(Op::MAdd, FpFormatFunct2::Double, rd, [rs1, rs2, rs3], rm)
+	}
+	
+	pub fn fmsub_d(&mut self, rd: u32, rs1: u32, rs2: u32, rs3: u32) -> AsmResult {
+		self.fmsub_d_rm(rd, rs1, rs2, rs3, FpRm::Dynamic)
+	}
+	
+	pub fn fmsub_d_rm(&mut self, rd: u32, rs1: u32, rs2: u32, rs3: u32, rm: FpRm) -> AsmResult {
+		self.r4_type(Op::MSub, FpFormatFunct2::Double, rd, [rs1, rs2, rs3], rm)
+	}
+	
+	pub fn fnmsub_d(&mut self, rd: u32, rs1: u32, rs2: u32, rs3: u32) -> AsmResult {
+		self.fnmsub_d_rm(rd, rs1, rs2, rs3, FpRm::Dynamic)
+	}
+	
+	pub fn fnmsub_d_rm(&mut self, rd: u32, rs1: u32, rs2: u32, rs3: u32, rm: FpRm) -> AsmResult {
+		self.r4_type(Op::NMSub, FpFormatFunct2::Double, rd, [rs1, rs2, rs3], rm)
+	}
+	
+	pub fn fnmadd_d(&mut self, rd: u32, rs1: u32, rs2: u32, rs3: u32) -> AsmResult {
+		self.fnmadd_d_rm(rd, rs1, rs2, rs3, FpRm::Dynamic)
+	}
+	
+	pub fn fnmadd_d_rm(&mut self, rd: u32, rs1: u32, rs2: u32, rs3: u32, rm: FpRm) -> AsmResult {
+		self.r4_type(Op::NMAdd, FpFormatFunct2::Double, rd, [rs1, rs2, rs3], rm)
+	}
+	
+	pub fn fadd_d(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.fadd_d_rm(rd, rs1, rs2, FpRm::Dynamic)
+	}
+	
+	pub fn fadd_d_rm(&mut self, rd: u32, rs1: u32, rs2: u32, rm: FpRm) -> AsmResult {
+		self.fp_op(FpFunct7::Add_D, rd, rs1, rs2, rm.to_raw())
+	}
+	
+	pub fn fsub_d(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.fsub_d_rm(rd, rs1, rs2, FpRm::Dynamic)
+	}
+	
+	pub fn fsub_d_rm(&mut self, rd: u32, rs1: u32, rs2: u32, rm: FpRm) -> AsmResult {
+		self.fp_op(FpFunct7::Sub_D, rd, rs1, rs2, rm.to_raw())
+	}
+	
+	pub fn fmul_d(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.fmul_d_rm(rd, rs1, rs2, FpRm::Dynamic)
+	}
+	
+	pub fn fmul_d_rm(&mut self, rd: u32, rs1: u32, rs2: u32, rm: FpRm) -> AsmResult {
+		self.fp_op(FpFunct7::Mul_D, rd, rs1, rs2, rm.to_raw())
+	}
+	
+	pub fn fdiv_d(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.fdiv_d_rm(rd, rs1, rs2, FpRm::Dynamic)
+	}
+	
+	pub fn fdiv_d_rm(&mut self, rd: u32, rs1: u32, rs2: u32, rm: FpRm) -> AsmResult {
+		self.fp_op(FpFunct7::Div_D, rd, rs1, rs2, rm.to_raw())
+	}
+	
+	pub fn fsqrt_d(&mut self, rd: u32, rs1: u32) -> AsmResult {
+		self.fsqrt_d_rm(rd, rs1, FpRm::Dynamic)
+	}
+	
+	pub fn fsqrt_d_rm(&mut self, rd: u32, rs1: u32, rm: FpRm) -> AsmResult {
+		self.fp_op(FpFunct7::Sqrt_D, rd, rs1, 0, rm.to_raw())
+	}
+	
+	pub fn fsgnj_d(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.fp_op(FpFunct7::Sign_D, rd, rs1, rs2, FpSignFunct3::SignFromRs2.to_raw())
+	}
+	
+	pub fn fsgnjn_d(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.fp_op(FpFunct7::Sign_D, rd, rs1, rs2, FpSignFunct3::SignFromNotRs2.to_raw())
+	}
+	
+	pub fn fsgnjx_d(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.fp_op(FpFunct7::Sign_D, rd, rs1, rs2, FpSignFunct3::SignFromRs1XorRs2.to_raw())
+	}
+	
+	pub fn fmin_d(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.fp_op(FpFunct7::MinMax_D, rd, rs1, rs2, FpMinMaxFunct3::Min.to_raw())
+	}
+	
+	pub fn fmax_d(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.fp_op(FpFunct7::MinMax_D, rd, rs1, rs2, FpMinMaxFunct3::Max.to_raw())
+	}
+	
+	pub fn fcvt_s_d(&mut self, rd: u32, rs1: u32) -> AsmResult {
+		self.fcvt_s_d_rm(rd, rs1, FpRm::Dynamic)
+	}
+	
+	pub fn fcvt_s_d_rm(&mut self, rd: u32, rs1: u32, rm: FpRm) -> AsmResult {
+		self.fp_op(FpFunct7::CvtS_D, rd, rs1, 1, rm.to_raw())
+	}
+	
+	// exact conversions, like fcvt_d_w and fcvt_d_wu, use the rounding mode assemblers emit rather than dyn
+	pub fn fcvt_d_s(&mut self, rd: u32, rs1: u32) -> AsmResult {
+		self.fcvt_d_s_rm(rd, rs1, FpRm::ToNearestTieEven)
+	}
+	
+	pub fn fcvt_d_s_rm(&mut self, rd: u32, rs1: u32, rm: FpRm) -> AsmResult {
+		self.fp_op(FpFunct7::CvtD_S, rd, rs1, 0, rm.to_raw())
+	}
+	
+	pub fn fcvt_w_d(&mut self, rd: u32, rs1: u32) -> AsmResult {
+		self.fcvt_w_d_rm(rd, rs1, FpRm::Dynamic)
+	}
+	
+	pub fn fcvt_w_d_rm(&mut self, rd: u32, rs1: u32, rm: FpRm) -> AsmResult {
+		self.fp_op(FpFunct7::CvtW_D, rd, rs1, FCvtType::Signed.to_raw(), rm.to_raw())
+	}
+	
+	pub fn fcvt_wu_d(&mut self, rd: u32, rs1: u32) -> AsmResult {
+		self.fcvt_wu_d_rm(rd, rs1, FpRm::Dynamic)
+	}
+	
+	pub fn fcvt_wu_d_rm(&mut self, rd: u32, rs1: u32, rm: FpRm) -> AsmResult {
+		self.fp_op(FpFunct7::CvtW_D, rd, rs1, FCvtType::Unsigned.to_raw(), rm.to_raw())
+	}
+	
+	pub fn fclass_d(&mut self, rd: u32, rs1: u32) -> AsmResult {
+		self.fp_op(FpFunct7::Class_D, rd, rs1, 0, FMvXWClassFunct3::Class.to_raw())
+	}
+	
+	pub fn feq_d(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.fp_op(FpFunct7::Cmp_D, rd, rs1, rs2, FpCmpFunct3::Eq.to_raw())
+	}
+	
+	pub fn flt_d(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.fp_op(FpFunct7::Cmp_D, rd, rs1, rs2, FpCmpFunct3::Lt.to_raw())
+	}
+	
+	pub fn fle_d(&mut self, rd: u32, rs1: u32, rs2: u32) -> AsmResult {
+		self.fp_op(FpFunct7::Cmp_D, rd, rs1, rs2, FpCmpFunct3::LEq.to_raw())
+	}
+	
+	pub fn fcvt_d_w(&mut self, rd: u32, rs1: u32) -> AsmResult {
+		self.fcvt_d_w_rm(rd, rs1, FpRm::ToNearestTieEven)
+	}
+	
+	pub fn fcvt_d_w_rm(&mut self, rd: u32, rs1: u32, rm: FpRm) -> AsmResult {
+		self.fp_op(FpFunct7::CvtD_W, rd, rs1, FCvtType::Signed.to_raw(), rm.to_raw())
+	}
+	
+	pub fn fcvt_d_wu(&mut self, rd: u32, rs1: u32) -> AsmResult {
+		self.fcvt_d_wu_rm(rd, rs1, FpRm::ToNearestTieEven)
+	}
+	
+	pub fn fcvt_d_wu_rm(&mut self, rd: u32, rs1: u32, rm: FpRm) -> AsmResult {
+		self.fp_op(FpFunct7::CvtD_W, rd, rs1, FCvtType::Unsigned.to_raw(), rm.to_raw())
+	}
+	
+	pub fn fence(&mut self, predecessor: u32, successor: u32) -> AsmResult {
+		self.i_type(Op::Fence, 0, 0, 0, (bitfield(predecessor, 4, 0, 4) | bitfield(successor, 4, 0, 0)) as i32)
+	}
+	
+	pub fn fence_i(&mut self) -> AsmResult {
+		self.i_type(Op::Fence, 0, 0b001, 0, 0)
+	}
+	
+	pub fn ecall(&mut self) -> AsmResult {
+		self.i_type(Op::System, 0, SystemFunct3::Int.to_raw(), 0, 0x000)
+	}
+	
+	pub fn ebreak(&mut self) -> AsmResult {
+		self.i_type(Op::System, 0, SystemFunct3::Int.to_raw(), 0, 0x001)
+	}
+	
+	pub fn mret(&mut self) -> AsmResult {
+		self.i_type(Op::System, 0, SystemFunct3::Int.to_raw(), 0, 0x302)
+	}
+	
+	pub fn sret(&mut self) -> AsmResult {
+		self.i_type(Op::System, 0, SystemFunct3::Int.to_raw(), 0, 0x102)
+	}
+	
+	pub fn sfence_vma(&mut self, rs1: u32, rs2: u32) -> AsmResult {
+		self.r_type(Op::System, 0, SystemFunct3::Int.to_raw(), rs1, rs2, SystemIntFunct7::SFenceVma.to_raw())
+	}
+	
+	pub fn wfi(&mut self) -> AsmResult {
+		self.i_type(Op::System, 0, SystemFunct3::Int.to_raw(), 0, 0x105)
+	}
+	
+	pub fn csrrw(&mut self, rd: u32, csr: u32, rs1: u32) -> AsmResult {
+		self.i_type(Op::System, rd, SystemFunct3::CsrRW.to_raw(), rs1, csr as i32)
+	}
+	
+	pub fn csrrs(&mut self, rd: u32, csr: u32, rs1: u32) -> AsmResult {
+		self.i_type(Op::System, rd, SystemFunct3::CsrRS.to_raw(), rs1, csr as i32)
+	}
+	
+	pub fn csrrc(&mut self, rd: u32, csr: u32, rs1: u32) -> AsmResult {
+		self.i_type(Op::System, rd, SystemFunct3::CsrRC.to_raw(), rs1, csr as i32)
+	}
+	
+	// the immediate forms carry a 5 bit zero extended value in the rs1 field
+	pub fn csrrwi(&mut self, rd: u32, csr: u32, immediate: u32) -> AsmResult {
+		self.i_type(Op::System, rd, SystemFunct3::CsrRWI.to_raw(), immediate, csr as i32)
+	}
+	
+	pub fn csrrsi(&mut self, rd: u32, csr: u32, immediate: u32) -> AsmResult {
+		self.i_type(Op::System, rd, SystemFunct3::CsrRSI.to_raw(), immediate, csr as i32)
+	}
+	
+	pub fn csrrci(&mut self, rd: u32, csr: u32, immediate: u32) -> AsmResult {
+		self.i_type(Op::System, rd, SystemFunct3::CsrRCI.to_raw(), immediate, csr as i32)
+	}
+	
+	pub fn csrr(&mut self, rd: u32, csr: u32) -> AsmResult {
+		self.csrrs(rd, csr, 0)
+	}
+	
+	pub fn csrw(&mut self, csr: u32, rs1: u32) -> AsmResult {
+		self.csrrw(0, csr, rs1)
+	}
+	
+	pub fn csrs(&mut self, csr: u32, rs1: u32) -> AsmResult {
+		self.csrrs(0, csr, rs1)
+	}
+	
+	pub fn csrc(&mut self, csr: u32, rs1: u32) -> AsmResult {
+		self.csrrc(0, csr, rs1)
+	}
+	
+	pub fn mv(&mut self, rd: u32, rs: u32) -> AsmResult {
+		self.addi(rd, rs, 0)
+	}
+	
+	pub fn not(&mut self, rd: u32, rs: u32) -> AsmResult {
+		self.xori(rd, rs, -1)
+	}
+	
+	pub fn neg(&mut self, rd: u32, rs: u32) -> AsmResult {
+		self.sub(rd, 0, rs)
+	}
+	
+	pub fn j(&mut self, offset: i32) -> AsmResult {
+		self.jal(0, offset)
+	}
+	
+	pub fn jr(&mut self, rs: u32) -> AsmResult {
+		self.jalr(0, rs, 0)
+	}
+	
+	pub fn ret(&mut self) -> AsmResult {
+		self.jalr(0, REG_RA, 0)
+	}
+	
+	// the multiple instruction pseudo ops return the last instruction they emit
+	pub fn li(&mut self, rd: u32, immediate: i32) -> AsmResult {
+		let (upper, lower) = split_immediate(immediate);
+		if upper == 0 {
+			return self.addi(rd, 0, lower);
+		}
+		let lui_result = self.lui(rd, upper);
+		if lower == 0 {
+			lui_result
+		} else {
+			self.addi(rd, rd, lower)
+		}
+	}
+	
+	pub fn la(&mut self, rd: u32, label: &str) -> AsmResult {
+		let upper_result = self.auipc(rd, 0);
+		let lower_result = self.addi(rd, rd, 0);
+		self.refer(label, AsmFixup::PcRelative(upper_result, lower_result));
+		lower_result
+	}
+	
+	pub fn call(&mut self, label: &str) -> AsmResult {
+		let upper_result = self.auipc(REG_RA, 0);
+		let lower_result = self.jalr(REG_RA, REG_RA, 0);
+		self.refer(label, AsmFixup::PcRelative(upper_result, lower_result));
+		lower_result
+	}
+	
+	pub fn tail(&mut self, label: &str) -> AsmResult {
+		let upper_result = self.auipc(REG_T1, 0);
+		let lower_result = self.jalr(0, REG_T1, 0);
+		self.refer(label, AsmFixup::PcRelative(upper_result, lower_result));
+		lower_result
+	}
+	
+	pub fn jal_to(&mut self, rd: u32, label: &str) -> AsmResult {
+		let asm_result = self.jal(rd, 0);
+		self.refer(label, AsmFixup::Offset(asm_result));
+		asm_result
+	}
+	
+	pub fn j_to(&mut self, label: &str) -> AsmResult {
+		self.jal_to(0, label)
+	}
+	
+	pub fn beq_to(&mut self, rs1: u32, rs2: u32, label: &str) -> AsmResult {
+		let asm_result = self.beq(rs1, rs2, 0);
+		self.refer(label, AsmFixup::Offset(asm_result));
+		asm_result
+	}
+	
+	pub fn bne_to(&mut self, rs1: u32, rs2: u32, label: &str) -> AsmResult {
+		let asm_result = self.bne(rs1, rs2, 0);
+		self.refer(label, AsmFixup::Offset(asm_result));
+		asm_result
+	}
+	
+	pub fn blt_to(&mut self, rs1: u32, rs2: u32, label: &str) -> AsmResult {
+		let asm_result = self.blt(rs1, rs2, 0);
+		self.refer(label, AsmFixup::Offset(asm_result));
+		asm_result
+	}
+	
+	pub fn bge_to(&mut self, rs1: u32, rs2: u32, label: &str) -> AsmResult {
+		let asm_result = self.bge(rs1, rs2, 0);
+		self.refer(label, AsmFixup::Offset(asm_result));
+		asm_result
+	}
+	
+	pub fn bltu_to(&mut self, rs1: u32, rs2: u32, label: &str) -> AsmResult {
+		let asm_result = self.bltu(rs1, rs2, 0);
+		self.refer(label, AsmFixup::Offset(asm_result));
+		asm_result
+	}
+	
+	pub fn bgeu_to(&mut self, rs1: u32, rs2: u32, label: &str) -> AsmResult {
+		let asm_result = self.bgeu(rs1, rs2, 0);
+		self.refer(label, AsmFixup::Offset(asm_result));
+		asm_result
+	}
+	
+	// RV32C. Registers written rd', rs1' and rs2' in the spec must be x8 - x15.
+	pub fn c_addi4spn(&mut self, rd: u32, immediate: u32) -> AsmResult {
+		self.c_type(Op::OpImm,
+			bitfield(creg(rd), 3, 0, 2) |
+			bitfield(immediate, 1, 3, 5) |
+			bitfield(immediate, 1, 2, 6) |
+			bitfield(immediate, 4, 6, 7) |
+			bitfield(immediate, 2, 4, 11) |
+			bitfield(0b000, 3, 0, 13), 0b000)
+	}
+	
+	pub fn c_fld(&mut self, rd: u32, rbase: u32, offset: u32) -> AsmResult {
+		self.cl_double(Op::LoadFp, 0b001, rd, rbase, offset)
+	}
+	
+	pub fn c_lw(&mut self, rd: u32, rbase: u32, offset: u32) -> AsmResult {
+		self.cl_word(Op::Load, 0b010, rd, rbase, offset)
+	}
+	
+	pub fn c_flw(&mut self, rd: u32, rbase: u32, offset: u32) -> AsmResult {
+		self.cl_word(Op::LoadFp, 0b011, rd, rbase, offset)
+	}
+	
+	pub fn c_fsd(&mut self, rs: u32, rbase: u32, offset: u32) -> AsmResult {
+		self.cl_double(Op::StoreFp, 0b101, rs, rbase, offset)
+	}
+	
+	pub fn c_sw(&mut self, rs: u32, rbase: u32, offset: u32) -> AsmResult {
+		self.cl_word(Op::Store, 0b110, rs, rbase, offset)
+	}
+	
+	pub fn c_fsw(&mut self, rs: u32, rbase: u32, offset: u32) -> AsmResult {
+		self.cl_word(Op::StoreFp, 0b111, rs, rbase, offset)
+	}
+	
+	pub fn c_nop(&mut self) -> AsmResult {
+		self.c_addi(0, 0)
+	}
+	
+	pub fn c_addi(&mut self, rd: u32, immediate: i32) -> AsmResult {
+		self.ci_type(Op::OpImm, 0b01, 0b000, rd, immediate as u32)
+	}
+	
+	pub fn c_jal(&mut self, offset: i32) -> AsmResult {
+		self.cj_type(0b001, offset)
+	}
+	
+	pub fn c_li(&mut self, rd: u32, immediate: i32) -> AsmResult {
+		self.ci_type(Op::OpImm, 0b01, 0b010, rd, immediate as u32)
+	}
+	
+	pub fn c_addi16sp(&mut self, immediate: i32) -> AsmResult {
+		let immediate = immediate as u32;
+		self.c_type(Op::OpImm, 0b01 |
+			bitfield(immediate, 1, 5, 2) |
+			bitfield(immediate, 2, 7, 3) |
+			bitfield(immediate, 1, 6, 5) |
+			bitfield(immediate, 1, 4, 6) |
+			bitfield(REG_SP, 5, 0, 7) |
+			bitfield(immediate, 1, 9, 12) |
+			bitfield(0b011, 3, 0, 13), 0b011)
+	}
+	
+	// immediate is the upper 20 bit value, as for lui
+	pub fn c_lui(&mut self, rd: u32, immediate: i32) -> AsmResult {
+		self.ci_type(Op::Lui, 0b01, 0b011, rd, immediate as u32)
+	}
+	
+	pub fn c_srli(&mut self, rd: u32, shift: u32) -> AsmResult {
+		self.cb_alu(Op::OpImm, 0b00, rd, shift)
+	}
+	
+	pub fn c_srai(&mut self, rd: u32, shift: u32) -> AsmResult {
+		self.cb_alu(Op::OpImm, 0b01, rd, shift)
+	}
+	
+	pub fn c_andi(&mut self, rd: u32, immediate: i32) -> AsmResult {
+		self.cb_alu(Op::OpImm, 0b10, rd, immediate as u32)
+	}
+	
+	pub fn c_sub(&mut self, rd: u32, rs2: u32) -> AsmResult {
+		self.ca_type(0b00, rd, rs2)
+	}
+	
+	pub fn c_xor(&mut self, rd: u32, rs2: u32) -> AsmResult {
+		self.ca_type(0b01, rd, rs2)
+	}
+	
+	pub fn c_or(&mut self, rd: u32, rs2: u32) -> AsmResult {
+		self.ca_type(0b10, rd, rs2)
+	}
+	
+	pub fn c_and(&mut self, rd: u32, rs2: u32) -> AsmResult {
+		self.ca_type(0b11, rd, rs2)
+	}
+	
+	pub fn c_j(&mut self, offset: i32) -> AsmResult {
+		self.cj_type(0b101, offset)
+	}
+	
+	pub fn c_beqz(&mut self, rs1: u32, offset: i32) -> AsmResult {
+		self.cb_type(0b110, rs1, offset)
+	}
+	
+	pub fn c_bnez(&mut self, rs1: u32, offset: i32) -> AsmResult {
+		self.cb_type(0b111, rs1, offset)
+	}
+	
+	pub fn c_slli(&mut self, rd: u32, shift: u32) -> AsmResult {
+		self.ci_type(Op::OpImm, 0b10, 0b000, rd, shift)
+	}
+	
+	pub fn c_fldsp(&mut self, rd: u32, offset: u32) -> AsmResult {
+		self.c_type(Op::LoadFp, 0b10 |
+			bitfield(offset, 3, 6, 2) |
+			bitfield(offset, 2, 3, 5) |
+			bitfield(rd, 5, 0, 7) |
+			bitfield(offset, 1, 5, 12) |
+			bitfield(0b001, 3, 0, 13), 0b001)
+	}
+	
+	pub fn c_lwsp(&mut self, rd: u32, offset: u32) -> AsmResult {
+		self.ci_word_sp(Op::Load, 0b010, rd, offset)
+	}
+	
+	pub fn c_flwsp(&mut self, rd: u32, offset: u32) -> AsmResult {
+		self.ci_word_sp(Op::LoadFp, 0b011, rd, offset)
+	}
+	
+	pub fn c_jr(&mut self, rs1: u32) -> AsmResult {
+		self.cr_type(Op::Jalr, 0b1000, rs1, 0)
+	}
+	
+	pub fn c_mv(&mut self, rd: u32, rs2: u32) -> AsmResult {
+		self.cr_type(Op::Op, 0b1000, rd, rs2)
+	}
+	
+	pub fn c_ebreak(&mut self) -> AsmResult {
+		self.cr_type(Op::System, 0b1001, 0, 0)
+	}
+	
+	pub fn c_jalr(&mut self, rs1: u32) -> AsmResult {
+		self.cr_type(Op::Jalr, 0b1001, rs1, 0)
+	}
+	
+	pub fn c_add(&mut self, rd: u32, rs2: u32) -> AsmResult {
+		self.cr_type(Op::Op, 0b1001, rd, rs2)
+	}
+	
+	pub fn c_fsdsp(&mut self, rs2: u32, offset: u32) -> AsmResult {
+		self.c_type(Op::StoreFp, 0b10 |
+			bitfield(rs2, 5, 0, 2) |
+			bitfield(offset, 3, 6, 7) |
+			bitfield(offset, 3, 3, 10) |
+			bitfield(0b101, 3, 0, 13), 0b101)
+	}
+	
+	pub fn c_swsp(&mut self, rs2: u32, offset: u32) -> AsmResult {
+		self.css_word_sp(Op::Store, 0b110, rs2, offset)
+	}
+	
+	pub fn c_fswsp(&mut self, rs2: u32, offset: u32) -> AsmResult {
+		self.css_word_sp(Op::StoreFp, 0b111, rs2, offset)
+	}
+	
+	pub fn c_jal_to(&mut self, label: &str) -> AsmResult {
+		let asm_result = self.c_jal(0);
+		self.refer(label, AsmFixup::Offset(asm_result));
+		asm_result
+	}
+	
+	pub fn c_j_to(&mut self, label: &str) -> AsmResult {
+		let asm_result = self.c_j(0);
+		self.refer(label, AsmFixup::Offset(asm_result));
+		asm_result
+	}
+	
+	pub fn c_beqz_to(&mut self, rs1: u32, label: &str) -> AsmResult {
+		let asm_result = self.c_beqz(rs1, 0);
+		self.refer(label, AsmFixup::Offset(asm_result));
+		asm_result
+	}
+	
+	pub fn c_bnez_to(&mut self, rs1: u32, label: &str) -> AsmResult {
+		let asm_result = self.c_bnez(rs1, 0);
+		self.refer(label, AsmFixup::Offset(asm_result));
+		asm_result
+	}
+	
+	pub fn rewrite_immediate(&mut self, asm_result: &AsmResult, immediate: i32) {
+		match asm_result.opcode_type {
+			OpcodeType::RV32I_I => {
+				self.i_type_at(asm_result.op, asm_result.rd.unwrap(), asm_result.funct3.unwrap(), asm_result.rs1.unwrap(), immediate, asm_result.instruction_address);
+			}
+			OpcodeType::RV32I_S => {
+				self.s_type_at(asm_result.op, asm_result.funct3.unwrap(), asm_result.rs1.unwrap(), asm_result.rs2.unwrap(), immediate, asm_result.instruction_address);
+			}
+			OpcodeType::RV32I_B => {
+				self.b_type_at(asm_result.op, immediate, asm_result.funct3.unwrap(), asm_result.rs1.unwrap(), asm_result.rs2.unwrap(), asm_result.instruction_address);
+			}
+			OpcodeType::RV32I_U => {
+				self.u_type_at(asm_result.op, asm_result.rd.unwrap(), immediate, asm_result.instruction_address);
+			},
+			OpcodeType::RV32I_J => {
+				self.j_type_at(asm_result.op, asm_result.rd.unwrap(), immediate, asm_result.instruction_address);
+			},
+			OpcodeType::RV32C_CB => {
+				self.write_u16(cb_branch(asm_result.funct3.unwrap(), asm_result.rs1.unwrap(), immediate), asm_result.instruction_address);
+			},
+			OpcodeType::RV32C_CJ => {
+				self.write_u16(cj_jump(asm_result.funct3.unwrap(), immediate), asm_result.instruction_address);
+			},
+			_ => {
+			}
+		}
+	}
+
+	fn r_type(&mut self, op: Op, rd: u32, funct3: u32, rs1: u32, rs2: u32, funct7: u32) -> AsmResult {
+		let opcode_value = op.to_raw() | 
+			bitfield(rd, 5, 0, 7) | 
+			bitfield(funct3, 3, 0, 12) | 
+			bitfield(rs1, 5, 0, 15) | 
+			bitfield(rs2, 5, 0, 20) |
+			bitfield(funct7, 7, 0, 25);
+		let address = self.bytes.len() as u32 + self.start_address;
+		self.push_u32(opcode_value);
+		AsmResult::new_r_type(op, rd, funct3, rs1, rs2, funct7, address)
+	}
+	
+	#[allow(unused)]
+	fn r_type_at(&mut self, op: Op, rd: u32, funct3: u32, rs1: u32, rs2: u32, funct7: u32, address: u32) -> AsmResult {
+		let opcode_value = op.to_raw() | 
+			bitfield(rd, 5, 0, 7) | 
+			bitfield(funct3, 3, 0, 12) | 
+			bitfield(rs1, 5, 0, 15) | 
+			bitfield(rs2, 5, 0, 20) |
+			bitfield(funct7, 7, 0, 25);
+		self.write_u32(opcode_value, address);
+		AsmResult::new_r_type(op, rd, funct3, rs1, rs2, funct7, address)
+	}
+
+	fn r4_type(&mut self, op: Op, format: FpFormatFunct2, rd: u32, sources: [u32; 3], rm: FpRm) -> AsmResult {
+		let [rs1, rs2, rs3] = sources;
+		let funct3 = rm.to_raw();
+		let funct7 = bitfield(format.to_raw(), 2, 0, 0) | bitfield(rs3, 5, 0, 2);
+		let opcode_value = op.to_raw() | 
+			bitfield(rd, 5, 0, 7) | 
+			bitfield(funct3, 3, 0, 12) | 
+			bitfield(rs1, 5, 0, 15) | 
+			bitfield(rs2, 5, 0, 20) |
+			bitfield(funct7, 7, 0, 25);
+		let address = self.bytes.len() as u32 + self.start_address;
+		self.push_u32(opcode_value);
+		AsmResult::new_r4_type(op, rd, funct3, rs1, rs2, funct7, address)
+	}
+	
+	fn fp_op(&mut self, funct7: FpFunct7, rd: u32, rs1: u32, rs2: u32, funct3: u32) -> AsmResult {
+		self.r_type(Op::OpFp, rd, funct3, rs1, rs2, funct7.to_raw())
+	}
+	
+	// aq and rl are the low two bits of funct7
+	fn atomic(&mut self, funct: AtomicFunct7, rd: u32, rs2: u32, rs1: u32, aq: bool, rl: bool) -> AsmResult {
+		let ordering = if aq { 0b10 } else { 0 } | if rl { 0b01 } else { 0 };
+		self.r_type(Op::Atomic, rd, AtomicSizeFunct3::Word.to_raw(), rs1, rs2, funct.to_raw() << 2 | ordering)
+	}
+	
+	fn c_type(&mut self, op: Op, opcode_value: u32, funct3: u32) -> AsmResult {
+		let address = self.bytes.len() as u32 + self.start_address;
+		self.push_u16(opcode_value);
+		AsmResult::new_c_type(op, OpcodeType::RV32C, funct3, None, None, address)
+	}
+	
+	fn ci_type(&mut self, op: Op, quadrant: u32, funct3: u32, rd: u32, imm: u32) -> AsmResult {
+		self.c_type(op, quadrant |
+			bitfield(imm, 5, 0, 2) |
+			bitfield(rd, 5, 0, 7) |
+			bitfield(imm, 1, 5, 12) |
+			bitfield(funct3, 3, 0, 13), funct3)
+	}
+	
+	fn ci_word_sp(&mut self, op: Op, funct3: u32, rd: u32, offset: u32) -> AsmResult {
+		self.c_type(op, 0b10 |
+			bitfield(offset, 2, 6, 2) |
+			bitfield(offset, 3, 2, 4) |
+			bitfield(rd, 5, 0, 7) |
+			bitfield(offset, 1, 5, 12) |
+			bitfield(funct3, 3, 0, 13), funct3)
+	}
+	
+	fn css_word_sp(&mut self, op: Op, funct3: u32, rs2: u32, offset: u32) -> AsmResult {
+		self.c_type(op, 0b10 |
+			bitfield(rs2, 5, 0, 2) |
+			bitfield(offset, 2, 6, 7) |
+			bitfield(offset, 4, 2, 9) |
+			bitfield(funct3, 3, 0, 13), funct3)
+	}
+	
+	fn cl_word(&mut self, op: Op, funct3: u32, rd: u32, rbase: u32, offset: u32) -> AsmResult {
+		self.c_type(op,
+			bitfield(creg(rd), 3, 0, 2) |
+			bitfield(offset, 1, 6, 5) |
+			bitfield(offset, 1, 2, 6) |
+			bitfield(creg(rbase), 3, 0, 7) |
+			bitfield(offset, 3, 3, 10) |
+			bitfield(funct3, 3, 0, 13), funct3)
+	}
+	
+	fn cl_double(&mut self, op: Op, funct3: u32, rd: u32, rbase: u32, offset: u32) -> AsmResult {
+		self.c_type(op,
+			bitfield(creg(rd), 3, 0, 2) |
+			bitfield(offset, 2, 6, 5) |
+			bitfield(creg(rbase), 3, 0, 7) |
+			bitfield(offset, 3, 3, 10) |
+			bitfield(funct3, 3, 0, 13), funct3)
+	}
+	
+	// c.srli, c.srai and c.andi
+	fn cb_alu(&mut self, op: Op, funct2: u32, rd: u32, imm: u32) -> AsmResult {
+		self.c_type(op, 0b01 |
+			bitfield(imm, 5, 0, 2) |
+			bitfield(creg(rd), 3, 0, 7) |
+			bitfield(funct2, 2, 0, 10) |
+			bitfield(imm, 1, 5, 12) |
+			bitfield(0b100, 3, 0, 13), 0b100)
+	}
+	
+	fn ca_type(&mut self, funct2: u32, rd: u32, rs2: u32) -> AsmResult {
+		self.c_type(Op::Op, 0b01 |
+			bitfield(creg(rs2), 3, 0, 2) |
+			bitfield(funct2, 2, 0, 5) |
+			bitfield(creg(rd), 3, 0, 7) |
+			bitfield(0b100011, 6, 0, 10), 0b100)
+	}
+	
+	fn cr_type(&mut self, op: Op, funct4: u32, rd: u32, rs2: u32) -> AsmResult {
+		self.c_type(op, 0b10 |
+			bitfield(rs2, 5, 0, 2) |
+			bitfield(rd, 5, 0, 7) |
+			bitfield(funct4, 4, 0, 12), funct4 >> 1)
+	}
+	
+	fn cb_type(&mut self, funct3: u32, rs1: u32, imm: i32) -> AsmResult {
+		let address = self.bytes.len() as u32 + self.start_address;
+		self.push_u16(cb_branch(funct3, rs1, imm));
+		AsmResult::new_c_type(Op::Branch, OpcodeType::RV32C_CB, funct3, Some(rs1), Some(imm), address)
+	}
+	
+	fn cj_type(&mut self, funct3: u32, imm: i32) -> AsmResult {
+		let address = self.bytes.len() as u32 + self.start_address;
+		self.push_u16(cj_jump(funct3, imm));
+		AsmResult::new_c_type(Op::Jal, OpcodeType::RV32C_CJ, funct3, None, Some(imm), address)
+	}
+	
+	// the unary ops carry their selector in the shift amount field, so shift is 0 for them
+	fn shift_imm(&mut self, funct: OpImmShiftFunct, rd: u32, rs: u32, shift: u32) -> AsmResult {
+		self.i_type(Op::OpImm, rd, funct.imm3(), rs, (funct.imm12() | (shift & 0x1F)) as i32)
+	}
+
+	fn i_type(&mut self, op: Op, rd: u32, funct3: u32, rs1: u32, imm: i32) -> AsmResult {
+		let opcode_value = op.to_raw() | 
+			bitfield(rd, 5, 0, 7) | 
+			bitfield(funct3, 3, 0, 12) | 
+			bitfield(rs1, 5, 0, 15) | 
+			bitfield(imm as u32, 12, 0, 20);
+		let address = self.bytes.len() as u32 + self.start_address;
+		self.push_u32(opcode_value);
+		AsmResult::new_i_type(op, rd, funct3, rs1, imm, address)
+	}
+	
+	fn i_type_at(&mut self, op: Op, rd: u32, funct3: u32, rs1: u32, imm: i32, address: u32) -> AsmResult {
+		let opcode_value = op.to_raw() | 
+			bitfield(rd, 5, 0, 7) | 
+			bitfield(funct3, 3, 0, 12) | 
+			bitfield(rs1, 5, 0, 15) | 
+			bitfield(imm as u32, 12, 0, 20);
+		self.write_u32(opcode_value, address);
+		AsmResult::new_i_type(op, rd, funct3, rs1, imm, address)
+	}
+
+	fn s_type(&mut self, op: Op, funct3: u32, rs1: u32, rs2: u32, imm: i32) -> AsmResult {
+		let opcode_value = op.to_raw() | 
+			bitfield(imm as u32, 5, 0, 7) | 
+			bitfield(funct3, 3, 0, 12) | 
+			bitfield(rs1, 5, 0, 15) | 
+			bitfield(rs2, 5, 0, 20) |
+			bitfield(imm as u32, 7, 5, 25);
+		let address = self.bytes.len() as u32 + self.start_address;
+		self.push_u32(opcode_value);
+		AsmResult::new_s_type(op, funct3, rs1, rs2, imm, address)
+	}
+	
+	fn s_type_at(&mut self, op: Op, funct3: u32, rs1: u32, rs2: u32, imm: i32, address: u32) -> AsmResult {
+		let opcode_value = op.to_raw() | 
+			bitfield(imm as u32, 5, 0, 7) | 
+			bitfield(funct3, 3, 0, 12) | 
+			bitfield(rs1, 5, 0, 15) | 
+			bitfield(rs2, 5, 0, 20) |
+			bitfield(imm as u32, 7, 5, 25);
+		self.write_u32(opcode_value, address);
+		AsmResult::new_s_type(op, funct3, rs1, rs2, imm, address)
+	}
+	
+	fn b_type(&mut self, op: Op, imm: i32, funct3: u32, rs1: u32, rs2: u32) -> AsmResult {
+		let opcode_value = op.to_raw() | 
+			bitfield(imm as u32, 1, 11, 7) |
+			bitfield(imm as u32, 4, 1, 8) |
+			bitfield(funct3, 3, 0, 12) | 
+			bitfield(rs1, 5, 0, 15) | 
+			bitfield(rs2, 5, 0, 20) |
+			bitfield(imm as u32, 6, 5, 25) |
+			bitfield(imm as u32, 1, 12, 31);
+		let address = self.bytes.len() as u32 + self.start_address;
+		self.push_u32(opcode_value);
+		AsmResult::new_b_type(op, funct3, rs1, rs2, imm, address)
+	}
+	
+	fn b_type_at(&mut self, op: Op, imm: i32, funct3: u32, rs1: u32, rs2: u32, address: u32) -> AsmResult {
+		let opcode_value = op.to_raw() | 
+			bitfield(imm as u32, 1, 11, 7) |
+			bitfield(imm as u32, 4, 1, 8) |
+			bitfield(funct3, 3, 0, 12) | 
+			bitfield(rs1, 5, 0, 15) | 
+			bitfield(rs2, 5, 0, 20) |
+			bitfield(imm as u32, 6, 5, 25) |
+			bitfield(imm as u32, 1, 12, 31);
+		self.write_u32(opcode_value, address);
+		AsmResult::new_b_type(op, funct3, rs1, rs2, imm, address)
+	}
+
+	fn u_type(&mut self, op: Op, rd: u32, imm: i32) -> AsmResult {
+		let opcode_value = op.to_raw() | 
+			bitfield(rd, 5, 0, 7) | 
+			bitfield(imm as u32, 20, 0, 12);
+		let address = self.bytes.len() as u32 + self.start_address;
+		self.push_u32(opcode_value);
+		AsmResult::new_u_type(op, rd, imm, address)
+	}
+	
+	fn u_type_at(&mut self, op: Op, rd: u32, imm: i32, address: u32) -> AsmResult {
+		let opcode_value = op.to_raw() | 
+			bitfield(rd, 5, 0, 7) | 
+			bitfield(imm as u32, 20, 0, 12);
+		self.write_u32(opcode_value, address);
+		AsmResult::new_u_type(op, rd, imm, address)
+	}
+
+	fn j_type(&mut self, op: Op, rd: u32, imm: i32) -> AsmResult {
+		let opcode_value = op.to_raw() | 
+			bitfield(rd, 5, 0, 7) | 
+			bitfield(imm as u32, 10, 1, 21) |
+			bitfield(imm as u32, 1, 11, 20) |
+			bitfield(imm as u32, 8, 12, 12) |
+			bitfield(imm as u32, 1, 20, 31);
+		let address = self.bytes.len() as u32 + self.start_address;
+		self.push_u32(opcode_value);
+		AsmResult::new_j_type(op, rd, imm, address)
+	}
+	
+	fn j_type_at(&mut self, op: Op, rd: u32, imm: i32, address: u32) -> AsmResult {
+		let opcode_value = op.to_raw() | 
+			bitfield(rd, 5, 0, 7) | 
+			bitfield(imm as u32, 10, 1, 21) |
+			bitfield(imm as u32, 1, 11, 20) |
+			bitfield(imm as u32, 8, 12, 12) |
+			bitfield(imm as u32, 1, 20, 31);
+		self.write_u32(opcode_value, address);
+		AsmResult::new_j_type(op, rd, imm, address)
+	}
+	
+	fn write_u32(&mut self, value: u32, address: u32) {
+		if (address + 4) > (self.bytes.len() as u32 + self.start_address) || address < self.start_address {
+			panic!("attempt to write instruction outside of jit bounds");
+		}
+		let offset = (address - self.start_address) as usize;
+		self.bytes[offset + 0] = (value >> 0) as u8;
+		self.bytes[offset + 1] = (value >> 8) as u8;
+		self.bytes[offset + 2] = (value >> 16) as u8;
+		self.bytes[offset + 3] = (value >> 24) as u8;
+	}
+	
+	fn write_u16(&mut self, value: u32, address: u32) {
+		if (address + 2) > (self.bytes.len() as u32 + self.start_address) || address < self.start_address {
+			panic!("attempt to write instruction outside of jit bounds");
+		}
+		let offset = (address - self.start_address) as usize;
+		self.bytes[offset .. offset + 2].copy_from_slice(&(value as u16).to_le_bytes());
+	}
+	
+	fn push_u16(&mut self, value: u32) {
+		self.bytes.extend_from_slice(&(value as u16).to_le_bytes());
 	}
 	
 	fn push_u32(&mut self, value: u32) {
@@ -0,0 +1,1340 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::cpu::REG_NAMES;
+use crate::disasm::{csr_name, FP_REG_NAMES};
+use crate::{AsmJit, AsmResult, FpRm};
+
+// GNU style assembly text to machine code through AsmJit, for writing test programs without a cross
+// toolchain and for patching code from the debugger. Statements end at a newline or ';', comments start
+// with '#', and labels are an identifier or a number followed by ':'. Branch and jump targets are labels,
+// numeric labels referenced as 1f / 1b, or absolute addresses as the disassembler prints them.
+// Nothing is compressed implicitly, the c. forms have to be written out.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AsmError {
+	pub line: usize,
+	pub message: String,
+}
+
+impl fmt::Display for AsmError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "line {}: {}", self.line, self.message)
+	}
+}
+
+type RegOp = fn(&mut AsmJit, u32, u32) -> AsmResult;
+type RegRegOp = fn(&mut AsmJit, u32, u32, u32) -> AsmResult;
+type RegImmOp = fn(&mut AsmJit, u32, u32, i32) -> AsmResult;
+type RegRmOp = fn(&mut AsmJit, u32, u32, FpRm) -> AsmResult;
+type RegRegRmOp = fn(&mut AsmJit, u32, u32, u32, FpRm) -> AsmResult;
+type FusedOp = fn(&mut AsmJit, u32, u32, u32, u32, FpRm) -> AsmResult;
+type AtomicOp = fn(&mut AsmJit, u32, u32, u32, bool, bool) -> AsmResult;
+type BranchOp = fn(&mut AsmJit, u32, u32, i32) -> AsmResult;
+type BranchToOp = fn(&mut AsmJit, u32, u32, &str) -> AsmResult;
+
+// operand kinds of the register only instructions, in operand order
+#[derive(Clone, Copy, PartialEq)]
+enum Reg {
+	X,
+	F,
+}
+
+enum Target {
+	Label(String),
+	Offset(i32),
+}
+
+struct LabelReference {
+	line: usize,
+	name: String,
+	address: u32,
+	// width of the signed offset field the label has to reach
+	bits: u32,
+}
+
+struct Assembler<'a> {
+	jit: &'a mut AsmJit,
+	line: usize,
+	constants: HashMap<String, i64>,
+	references: Vec<LabelReference>,
+	// definitions so far of each numeric label
+	numeric_labels: HashMap<u32, u32>,
+}
+
+pub fn assemble(source: &str, address: u32) -> Result<Vec<u8>, AsmError> {
+	let mut jit = AsmJit::new(address);
+	assemble_into(&mut jit, source)?;
+	Ok(jit.bytes().to_vec())
+}
+
+// appends to code already in jit, labels it has bound can be referenced from the source
+pub fn assemble_into(jit: &mut AsmJit, source: &str) -> Result<(), AsmError> {
+	let mut assembler = Assembler {
+		jit,
+		line: 0,
+		constants: HashMap::new(),
+		references: Vec::new(),
+		numeric_labels: HashMap::new(),
+	};
+	for (index, line) in source.lines().enumerate() {
+		assembler.line = index + 1;
+		for statement in split_statements(line) {
+			if let Err(message) = assembler.statement(statement) {
+				return Err(AsmError {
+					line: assembler.line,
+					message,
+				});
+			}
+		}
+	}
+	assembler.finish()
+}
+
+fn split_statements(line: &str) -> Vec<&str> {
+	let mut statements = Vec::new();
+	let mut start = 0;
+	let mut quote = None;
+	let mut escaped = false;
+	for (index, c) in line.char_indices() {
+		match quote {
+			Some(_) if escaped => escaped = false,
+			Some(_) if c == '\\' => escaped = true,
+			Some(q) if c == q => quote = None,
+			Some(_) => {},
+			None if c == '"' || c == '\'' => quote = Some(c),
+			None if c == ';' => {
+				statements.push(&line[start .. index]);
+				start = index + 1;
+			},
+			None if c == '#' => {
+				statements.push(&line[start .. index]);
+				return statements;
+			},
+			None => {}
+		}
+	}
+	statements.push(&line[start ..]);
+	statements
+}
+
+// splits on commas outside of quotes
+fn split_operands(text: &str) -> Vec<&str> {
+	let text = text.trim();
+	if text.is_empty() {
+		return Vec::new();
+	}
+	let mut operands = Vec::new();
+	let mut start = 0;
+	let mut quote = None;
+	let mut escaped = false;
+	for (index, c) in text.char_indices() {
+		match quote {
+			Some(_) if escaped => escaped = false,
+			Some(_) if c == '\\' => escaped = true,
+			Some(q) if c == q => quote = None,
+			Some(_) => {},
+			None if c == '"' || c == '\'' => quote = Some(c),
+			None if c == ',' => {
+				operands.push(text[start .. index].trim());
+				start = index + 1;
+			},
+			None => {}
+		}
+	}
+	operands.push(text[start ..].trim());
+	operands
+}
+
+fn is_identifier(text: &str) -> bool {
+	let mut chars = text.chars();
+	match chars.next() {
+		Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$'),
+		_ => false
+	}
+}
+
+fn numeric_label(text: &str) -> Option<u32> {
+	if ! text.is_empty() && text.chars().all(|c| c.is_ascii_digit()) {
+		text.parse().ok()
+	} else {
+		None
+	}
+}
+
+fn expect_operands(operands: &[&str], count: usize) -> Result<(), String> {
+	if operands.len() != count {
+		Err(format!("expected {} operands, found {}", count, operands.len()))
+	} else {
+		Ok(())
+	}
+}
+
+// an optional rounding mode after the register operands, or the default for the instruction
+fn rounding_mode<'b>(operands: &'b [&'b str], count: usize, default: FpRm) -> Result<(&'b [&'b str], FpRm), String> {
+	if operands.len() != count + 1 {
+		return Ok((operands, default));
+	}
+	let rm = match operands[count] {
+		"rne" => FpRm::ToNearestTieEven,
+		"rtz" => FpRm::ToZero,
+		"rdn" => FpRm::Down,
+		"rup" => FpRm::Up,
+		"rmm" => FpRm::ToNearestTieMaxMagnitude,
+		"dyn" => FpRm::Dynamic,
+		_ => return Err(format!("{} is not a rounding mode", operands[count]))
+	};
+	Ok((&operands[.. count], rm))
+}
+
+// the .aq, .rl and .aqrl ordering suffixes of the atomics
+fn atomic_ordering(mnemonic: &str) -> (&str, bool, bool) {
+	for &(suffix, aq, rl) in &[(".aqrl", true, true), (".aq", true, false), (".rl", false, true)] {
+		if let Some(base) = mnemonic.strip_suffix(suffix) {
+			return (base, aq, rl);
+		}
+	}
+	(mnemonic, false, false)
+}
+
+// fcsr, frm or fflags, for the float csr pseudo-instructions
+fn fp_csr(mnemonic: &str) -> u32 {
+	if mnemonic.contains("csr") {
+		0x003
+	} else if mnemonic.contains("rm") {
+		0x002
+	} else {
+		0x001
+	}
+}
+
+// x0 - x31 or f0 - f31
+fn numbered_reg(text: &str, prefix: char) -> Option<u32> {
+	let number = text.strip_prefix(prefix)?.parse::<u32>().ok()?;
+	if number < 32 && text[1 ..] == number.to_string() {
+		Some(number)
+	} else {
+		None
+	}
+}
+
+fn gpr(text: &str) -> Result<u32, String> {
+	if text == "fp" {
+		return Ok(8);
+	}
+	if let Some(reg) = numbered_reg(text, 'x') {
+		return Ok(reg);
+	}
+	match REG_NAMES.iter().position(|name| *name == text) {
+		Some(reg) => Ok(reg as u32),
+		None => Err(format!("{} is not an integer register", text))
+	}
+}
+
+fn fpr(text: &str) -> Result<u32, String> {
+	if let Some(reg) = numbered_reg(text, 'f') {
+		return Ok(reg);
+	}
+	match FP_REG_NAMES.iter().position(|name| *name == text) {
+		Some(reg) => Ok(reg as u32),
+		None => Err(format!("{} is not a floating point register", text))
+	}
+}
+
+fn reg(kind: Reg, text: &str) -> Result<u32, String> {
+	match kind {
+		Reg::X => gpr(text),
+		Reg::F => fpr(text),
+	}
+}
+
+// the 3 bit register fields of the compressed formats only reach x8 - x15
+fn compressed_reg(kind: Reg, text: &str) -> Result<u32, String> {
+	let reg = reg(kind, text)?;
+	if ! (8 ..= 15).contains(&reg) {
+		return Err(format!("{} is not one of the registers 8 - 15 usable here", text));
+	}
+	Ok(reg)
+}
+
+fn fence_set(text: &str) -> Result<u32, String> {
+	let mut set = 0;
+	for c in text.chars() {
+		set |= match c {
+			'i' => 0b1000,
+			'o' => 0b0100,
+			'r' => 0b0010,
+			'w' => 0b0001,
+			_ => return Err(format!("{} is not a fence set", text))
+		};
+	}
+	Ok(set)
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+	let (digits, radix) = if let Some(digits) = text.strip_prefix("0x").or(text.strip_prefix("0X")) {
+		(digits, 16)
+	} else if let Some(digits) = text.strip_prefix("0b").or(text.strip_prefix("0B")) {
+		(digits, 2)
+	} else {
+		(text, 10)
+	};
+	if digits.is_empty() || ! digits.chars().all(|c| c.is_digit(radix) || c == '_') {
+		return None;
+	}
+	i64::from_str_radix(digits.replace('_', "").as_str(), radix).ok()
+}
+
+fn parse_string(text: &str) -> Result<Vec<u8>, String> {
+	let inner = match text.strip_prefix('"').and_then(|text| text.strip_suffix('"')) {
+		Some(inner) if text.len() >= 2 => inner,
+		_ => return Err(format!("{} is not a string", text))
+	};
+	let mut data = Vec::new();
+	let mut chars = inner.chars();
+	while let Some(c) = chars.next() {
+		let c = if c == '\\' {
+			match chars.next() {
+				Some('n') => '\n',
+				Some('t') => '\t',
+				Some('r') => '\r',
+				Some('0') => '\0',
+				Some('\\') => '\\',
+				Some('"') => '"',
+				Some('\'') => '\'',
+				_ => return Err(format!("unknown escape in {}", text))
+			}
+		} else {
+			c
+		};
+		let mut buffer = [0; 4];
+		data.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+	}
+	Ok(data)
+}
+
+fn check_range(value: i64, min: i64, max: i64) -> Result<i64, String> {
+	if value < min || value > max {
+		Err(format!("{} is out of range {} to {}", value, min, max))
+	} else {
+		Ok(value)
+	}
+}
+
+// a multiple of scale from 0 up to below limit
+fn check_scaled(value: i64, scale: i64, limit: i64) -> Result<u32, String> {
+	if value % scale != 0 {
+		return Err(format!("{} is not a multiple of {}", value, scale));
+	}
+	Ok(check_range(value, 0, limit - scale)? as u32)
+}
+
+// offsets of 32 bits (la, call and data) reach everywhere and can point at data of any alignment
+fn check_offset(offset: i64, bits: u32) -> Result<i32, String> {
+	if bits >= 32 {
+		return Ok(offset as i32);
+	}
+	let reach = 1i64 << (bits - 1);
+	if offset & 1 != 0 {
+		return Err(format!("target offset {} is odd", offset));
+	}
+	if offset < -reach || offset >= reach {
+		return Err(format!("target offset {} is out of range {} to {}", offset, -reach, reach - 2));
+	}
+	Ok(offset as i32)
+}
+
+fn reg_reg_op(mnemonic: &str) -> Option<(RegRegOp, [Reg; 3])> {
+	let op: RegRegOp = match mnemonic {
+		"add" => AsmJit::add,
+		"sub" => AsmJit::sub,
+		"sll" => AsmJit::sll,
+		"slt" => AsmJit::slt,
+		"sltu" => AsmJit::sltu,
+		"xor" => AsmJit::xor,
+		"srl" => AsmJit::srl,
+		"sra" => AsmJit::sra,
+		"or" => AsmJit::or,
+		"and" => AsmJit::and,
+		"mul" => AsmJit::mul,
+		"mulh" => AsmJit::mulh,
+		"mulhu" => AsmJit::mulhu,
+		"mulhsu" => AsmJit::mulhsu,
+		"div" => AsmJit::div,
+		"divu" => AsmJit::divu,
+		"rem" => AsmJit::rem,
+		"remu" => AsmJit::remu,
+		"sh1add" => AsmJit::sh1add,
+		"sh2add" => AsmJit::sh2add,
+		"sh3add" => AsmJit::sh3add,
+		"andn" => AsmJit::andn,
+		"orn" => AsmJit::orn,
+		"xnor" => AsmJit::xnor,
+		"min" => AsmJit::min,
+		"minu" => AsmJit::minu,
+		"max" => AsmJit::max,
+		"maxu" => AsmJit::maxu,
+		"rol" => AsmJit::rol,
+		"ror" => AsmJit::ror,
+		"bset" => AsmJit::bset,
+		"bclr" => AsmJit::bclr,
+		"binv" => AsmJit::binv,
+		"bext" => AsmJit::bext,
+		_ => return fp_reg_reg_op(mnemonic)
+	};
+	Some((op, [Reg::X, Reg::X, Reg::X]))
+}
+
+fn fp_reg_reg_op(mnemonic: &str) -> Option<(RegRegOp, [Reg; 3])> {
+	let op: RegRegOp = match mnemonic {
+		"fsgnj.s" => AsmJit::fsgnj_s,
+		"fsgnjn.s" => AsmJit::fsgnjn_s,
+		"fsgnjx.s" => AsmJit::fsgnjx_s,
+		"fmin.s" => AsmJit::fmin_s,
+		"fmax.s" => AsmJit::fmax_s,
+		"fsgnj.d" => AsmJit::fsgnj_d,
+		"fsgnjn.d" => AsmJit::fsgnjn_d,
+		"fsgnjx.d" => AsmJit::fsgnjx_d,
+		"fmin.d" => AsmJit::fmin_d,
+		"fmax.d" => AsmJit::fmax_d,
+		_ => {
+			let op: RegRegOp = match mnemonic {
+				"feq.s" => AsmJit::feq_s,
+				"flt.s" => AsmJit::flt_s,
+				"fle.s" => AsmJit::fle_s,
+				"feq.d" => AsmJit::feq_d,
+				"flt.d" => AsmJit::flt_d,
+				"fle.d" => AsmJit::fle_d,
+				_ => return None
+			};
+			return Some((op, [Reg::X, Reg::F, Reg::F]));
+		}
+	};
+	Some((op, [Reg::F, Reg::F, Reg::F]))
+}
+
+fn reg_op(mnemonic: &str) -> Option<(RegOp, [Reg; 2])> {
+	let (op, kinds): (RegOp, [Reg; 2]) = match mnemonic {
+		"mv" => (AsmJit::mv, [Reg::X, Reg::X]),
+		"not" => (AsmJit::not, [Reg::X, Reg::X]),
+		"neg" => (AsmJit::neg, [Reg::X, Reg::X]),
+		"seqz" => (|jit, rd, rs| jit.sltiu(rd, rs, 1), [Reg::X, Reg::X]),
+		"snez" => (|jit, rd, rs| jit.sltu(rd, 0, rs), [Reg::X, Reg::X]),
+		"sltz" => (|jit, rd, rs| jit.slt(rd, rs, 0), [Reg::X, Reg::X]),
+		"sgtz" => (|jit, rd, rs| jit.slt(rd, 0, rs), [Reg::X, Reg::X]),
+		"clz" => (AsmJit::clz, [Reg::X, Reg::X]),
+		"ctz" => (AsmJit::ctz, [Reg::X, Reg::X]),
+		"cpop" => (AsmJit::cpop, [Reg::X, Reg::X]),
+		"sext.b" => (AsmJit::sext_b, [Reg::X, Reg::X]),
+		"sext.h" => (AsmJit::sext_h, [Reg::X, Reg::X]),
+		"zext.h" => (AsmJit::zext_h, [Reg::X, Reg::X]),
+		"rev8" => (AsmJit::rev8, [Reg::X, Reg::X]),
+		"orc.b" => (AsmJit::orc_b, [Reg::X, Reg::X]),
+		"fmv.s" => (|jit, rd, rs| jit.fsgnj_s(rd, rs, rs), [Reg::F, Reg::F]),
+		"fneg.s" => (|jit, rd, rs| jit.fsgnjn_s(rd, rs, rs), [Reg::F, Reg::F]),
+		"fabs.s" => (|jit, rd, rs| jit.fsgnjx_s(rd, rs, rs), [Reg::F, Reg::F]),
+		"fmv.d" => (|jit, rd, rs| jit.fsgnj_d(rd, rs, rs), [Reg::F, Reg::F]),
+		"fneg.d" => (|jit, rd, rs| jit.fsgnjn_d(rd, rs, rs), [Reg::F, Reg::F]),
+		"fabs.d" => (|jit, rd, rs| jit.fsgnjx_d(rd, rs, rs), [Reg::F, Reg::F]),
+		"fmv.x.w" | "fmv.x.s" => (AsmJit::fmv_x_w, [Reg::X, Reg::F]),
+		"fclass.s" => (AsmJit::fclass_s, [Reg::X, Reg::F]),
+		"fclass.d" => (AsmJit::fclass_d, [Reg::X, Reg::F]),
+		"fmv.w.x" | "fmv.s.x" => (AsmJit::fmv_w_x, [Reg::F, Reg::X]),
+		_ => return None
+	};
+	Some((op, kinds))
+}
+
+// the float arithmetic which takes a rounding mode
+fn fp_rounding_reg_reg_op(mnemonic: &str) -> Option<RegRegRmOp> {
+	let op: RegRegRmOp = match mnemonic {
+		"fadd.s" => AsmJit::fadd_s_rm,
+		"fsub.s" => AsmJit::fsub_s_rm,
+		"fmul.s" => AsmJit::fmul_s_rm,
+		"fdiv.s" => AsmJit::fdiv_s_rm,
+		"fadd.d" => AsmJit::fadd_d_rm,
+		"fsub.d" => AsmJit::fsub_d_rm,
+		"fmul.d" => AsmJit::fmul_d_rm,
+		"fdiv.d" => AsmJit::fdiv_d_rm,
+		_ => return None
+	};
+	Some(op)
+}
+
+// square roots and conversions, with the rounding mode used when none is written. the exact
+// conversions default to rne like the GNU assembler
+fn fp_rounding_op(mnemonic: &str) -> Option<(RegRmOp, [Reg; 2], FpRm)> {
+	let (op, kinds, rm): (RegRmOp, [Reg; 2], FpRm) = match mnemonic {
+		"fsqrt.s" => (AsmJit::fsqrt_s_rm, [Reg::F, Reg::F], FpRm::Dynamic),
+		"fsqrt.d" => (AsmJit::fsqrt_d_rm, [Reg::F, Reg::F], FpRm::Dynamic),
+		"fcvt.s.d" => (AsmJit::fcvt_s_d_rm, [Reg::F, Reg::F], FpRm::Dynamic),
+		"fcvt.d.s" => (AsmJit::fcvt_d_s_rm, [Reg::F, Reg::F], FpRm::ToNearestTieEven),
+		"fcvt.w.s" => (AsmJit::fcvt_w_s_rm, [Reg::X, Reg::F], FpRm::Dynamic),
+		"fcvt.wu.s" => (AsmJit::fcvt_wu_s_rm, [Reg::X, Reg::F], FpRm::Dynamic),
+		"fcvt.w.d" => (AsmJit::fcvt_w_d_rm, [Reg::X, Reg::F], FpRm::Dynamic),
+		"fcvt.wu.d" => (AsmJit::fcvt_wu_d_rm, [Reg::X, Reg::F], FpRm::Dynamic),
+		"fcvt.s.w" => (AsmJit::fcvt_s_w_rm, [Reg::F, Reg::X], FpRm::Dynamic),
+		"fcvt.s.wu" => (AsmJit::fcvt_s_wu_rm, [Reg::F, Reg::X], FpRm::Dynamic),
+		"fcvt.d.w" => (AsmJit::fcvt_d_w_rm, [Reg::F, Reg::X], FpRm::ToNearestTieEven),
+		"fcvt.d.wu" => (AsmJit::fcvt_d_wu_rm, [Reg::F, Reg::X], FpRm::ToNearestTieEven),
+		_ => return None
+	};
+	Some((op, kinds, rm))
+}
+
+fn fused_op(mnemonic: &str) -> Option<FusedOp> {
+	let op: FusedOp = match mnemonic {
+		"fmadd.s" => AsmJit::fmadd_s_rm,
+		"fmsub.s" => AsmJit::fmsub_s_rm,
+		"fnmsub.s" => AsmJit::fnmsub_s_rm,
+		"fnmadd.s" => AsmJit::fnmadd_s_rm,
+		"fmadd.d" => AsmJit::fmadd_d_rm,
+		"fmsub.d" => AsmJit::fmsub_d_rm,
+		"fnmsub.d" => AsmJit::fnmsub_d_rm,
+		"fnmadd.d" => AsmJit::fnmadd_d_rm,
+		_ => return None
+	};
+	Some(op)
+}
+
+fn imm_op(mnemonic: &str) -> Option<RegImmOp> {
+	let op: RegImmOp = match mnemonic {
+		"addi" => AsmJit::addi,
+		"slti" => AsmJit::slti,
+		"sltiu" => AsmJit::sltiu,
+		"xori" => AsmJit::xori,
+		"ori" => AsmJit::ori,
+		"andi" => AsmJit::andi,
+		_ => return None
+	};
+	Some(op)
+}
+
+fn shift_op(mnemonic: &str) -> Option<RegRegOp> {
+	let op: RegRegOp = match mnemonic {
+		"slli" => AsmJit::slli,
+		"srli" => AsmJit::srli,
+		"srai" => AsmJit::srai,
+		"rori" => AsmJit::rori,
+		"bseti" => AsmJit::bseti,
+		"bclri" => AsmJit::bclri,
+		"binvi" => AsmJit::binvi,
+		"bexti" => AsmJit::bexti,
+		_ => return None
+	};
+	Some(op)
+}
+
+// loads and stores, with the kind of the data register
+fn memory_op(mnemonic: &str) -> Option<(RegImmOp, Reg)> {
+	let (op, kind): (RegImmOp, Reg) = match mnemonic {
+		"lb" => (AsmJit::lb, Reg::X),
+		"lh" => (AsmJit::lh, Reg::X),
+		"lw" => (AsmJit::lw, Reg::X),
+		"lbu" => (AsmJit::lbu, Reg::X),
+		"lhu" => (AsmJit::lhu, Reg::X),
+		"sb" => (AsmJit::sb, Reg::X),
+		"sh" => (AsmJit::sh, Reg::X),
+		"sw" => (AsmJit::sw, Reg::X),
+		"flw" => (AsmJit::flw, Reg::F),
+		"fld" => (AsmJit::fld, Reg::F),
+		"fsw" => (AsmJit::fsw, Reg::F),
+		"fsd" => (AsmJit::fsd, Reg::F),
+		_ => return None
+	};
+	Some((op, kind))
+}
+
+// atomics take rd, rs2, (rs1)
+fn atomic_op(mnemonic: &str) -> Option<AtomicOp> {
+	let op: AtomicOp = match mnemonic {
+		"sc.w" => AsmJit::sc_w_aqrl,
+		"amoswap.w" => AsmJit::amoswap_w_aqrl,
+		"amoadd.w" => AsmJit::amoadd_w_aqrl,
+		"amoxor.w" => AsmJit::amoxor_w_aqrl,
+		"amoand.w" => AsmJit::amoand_w_aqrl,
+		"amoor.w" => AsmJit::amoor_w_aqrl,
+		"amomin.w" => AsmJit::amomin_w_aqrl,
+		"amomax.w" => AsmJit::amomax_w_aqrl,
+		"amominu.w" => AsmJit::amominu_w_aqrl,
+		"amomaxu.w" => AsmJit::amomaxu_w_aqrl,
+		_ => return None
+	};
+	Some(op)
+}
+
+// the branch, whether its operands are swapped, and whether it compares against zero
+fn branch_op(mnemonic: &str) -> Option<(BranchOp, BranchToOp, bool, bool)> {
+	let (op, op_to, swap, zero): (BranchOp, BranchToOp, bool, bool) = match mnemonic {
+		"beq" => (AsmJit::beq, AsmJit::beq_to, false, false),
+		"bne" => (AsmJit::bne, AsmJit::bne_to, false, false),
+		"blt" => (AsmJit::blt, AsmJit::blt_to, false, false),
+		"bge" => (AsmJit::bge, AsmJit::bge_to, false, false),
+		"bltu" => (AsmJit::bltu, AsmJit::bltu_to, false, false),
+		"bgeu" => (AsmJit::bgeu, AsmJit::bgeu_to, false, false),
+		"bgt" => (AsmJit::blt, AsmJit::blt_to, true, false),
+		"ble" => (AsmJit::bge, AsmJit::bge_to, true, false),
+		"bgtu" => (AsmJit::bltu, AsmJit::bltu_to, true, false),
+		"bleu" => (AsmJit::bgeu, AsmJit::bgeu_to, true, false),
+		"beqz" => (AsmJit::beq, AsmJit::beq_to, false, true),
+		"bnez" => (AsmJit::bne, AsmJit::bne_to, false, true),
+		"bltz" => (AsmJit::blt, AsmJit::blt_to, false, true),
+		"bgez" => (AsmJit::bge, AsmJit::bge_to, false, true),
+		"blez" => (AsmJit::bge, AsmJit::bge_to, true, true),
+		"bgtz" => (AsmJit::blt, AsmJit::blt_to, true, true),
+		_ => return None
+	};
+	Some((op, op_to, swap, zero))
+}
+
+impl<'a> Assembler<'a> {
+	fn statement(&mut self, text: &str) -> Result<(), String> {
+		let mut text = text.trim();
+		// any number of labels can come first
+		while let Some(colon) = text.find(':') {
+			let name = text[.. colon].trim();
+			if numeric_label(name).is_none() && ! is_identifier(name) {
+				break;
+			}
+			self.define_label(name)?;
+			text = text[colon + 1 ..].trim();
+		}
+		if text.is_empty() {
+			return Ok(());
+		}
+		let (mnemonic, operands) = match text.find(char::is_whitespace) {
+			Some(split) => (&text[.. split], &text[split ..]),
+			None => (text, "")
+		};
+		let mnemonic = mnemonic.to_ascii_lowercase();
+		let operands = split_operands(operands);
+		if mnemonic.starts_with('.') {
+			self.directive(mnemonic.as_str(), operands.as_slice())
+		} else {
+			self.instruction(mnemonic.as_str(), operands.as_slice())
+		}
+	}
+
+	fn define_label(&mut self, name: &str) -> Result<(), String> {
+		let name = match numeric_label(name) {
+			Some(number) => {
+				let count = self.numeric_labels.entry(number).or_insert(0);
+				*count += 1;
+				format!("{}:{}", number, *count - 1)
+			},
+			None => name.to_string()
+		};
+		if self.jit.label_address(name.as_str()).is_some() || self.constants.contains_key(name.as_str()) {
+			return Err(format!("{} is already defined", name));
+		}
+		self.jit.label(name.as_str());
+		Ok(())
+	}
+
+	// the label text refers to, or None if it is a value
+	fn label_name(&self, text: &str) -> Result<Option<String>, String> {
+		if text.len() > 1 && (text.ends_with('f') || text.ends_with('b')) {
+			if let Some(number) = numeric_label(&text[.. text.len() - 1]) {
+				let count = self.numeric_labels.get(&number).cloned().unwrap_or(0);
+				if text.ends_with('f') {
+					return Ok(Some(format!("{}:{}", number, count)));
+				}
+				if count == 0 {
+					return Err(format!("no label {} before {}", number, text));
+				}
+				return Ok(Some(format!("{}:{}", number, count - 1)));
+			}
+		}
+		if is_identifier(text) && ! self.constants.contains_key(text) {
+			Ok(Some(text.to_string()))
+		} else {
+			Ok(None)
+		}
+	}
+
+	fn refer(&mut self, name: &str, bits: u32) {
+		self.references.push(LabelReference {
+			line: self.line,
+			name: name.to_string(),
+			address: self.jit.current_address(),
+			bits,
+		});
+	}
+
+	fn target(&mut self, text: &str, bits: u32) -> Result<Target, String> {
+		match self.label_name(text)? {
+			Some(name) => {
+				self.refer(name.as_str(), bits);
+				Ok(Target::Label(name))
+			},
+			None => {
+				let offset = self.value(text)? - self.jit.current_address() as i64;
+				Ok(Target::Offset(check_offset(offset, bits)?))
+			}
+		}
+	}
+
+	// sums and differences of numbers, characters, constants and bound labels
+	fn value(&self, text: &str) -> Result<i64, String> {
+		let text = text.trim();
+		if let Some(inner) = text.strip_prefix("%hi(").and_then(|text| text.strip_suffix(')')) {
+			return Ok(((self.value(inner)? + 0x800) >> 12) & 0xF_FFFF);
+		}
+		if let Some(inner) = text.strip_prefix("%lo(").and_then(|text| text.strip_suffix(')')) {
+			return Ok(((self.value(inner)? & 0xFFF) ^ 0x800) - 0x800);
+		}
+		let mut total = 0;
+		let mut negate = false;
+		let mut rest = text;
+		loop {
+			rest = rest.trim_start();
+			while let Some(after) = rest.strip_prefix('-') {
+				negate = ! negate;
+				rest = after.trim_start();
+			}
+			// a character literal can hold a sign
+			let end = match rest.strip_prefix('\'') {
+				Some(literal) => literal.find('\'').map(|end| end + 2).unwrap_or(rest.len()),
+				None => rest.find(&['+', '-'][..]).unwrap_or(rest.len())
+			};
+			let term = self.term(rest[.. end].trim())?;
+			total += if negate { -term } else { term };
+			rest = rest[end ..].trim_start();
+			match rest.chars().next() {
+				Some('+') => negate = false,
+				Some('-') => negate = true,
+				Some(_) => return Err(format!("malformed expression {}", text)),
+				None => return Ok(total)
+			}
+			rest = &rest[1 ..];
+		}
+	}
+
+	fn term(&self, text: &str) -> Result<i64, String> {
+		if let Some(value) = parse_number(text) {
+			return Ok(value);
+		}
+		if text.len() >= 3 && text.starts_with('\'') && text.ends_with('\'') {
+			let data = parse_string(format!("\"{}\"", &text[1 .. text.len() - 1]).as_str())?;
+			if data.len() == 1 {
+				return Ok(data[0] as i64);
+			}
+		}
+		if let Some(value) = self.constants.get(text) {
+			return Ok(*value);
+		}
+		if let Some(Some(address)) = self.label_name(text).ok().flatten().map(|name| self.jit.label_address(name.as_str())) {
+			return Ok(address as i64);
+		}
+		if text.is_empty() {
+			return Err("missing value".to_string());
+		}
+		Err(format!("{} is not a number or a defined symbol", text))
+	}
+
+	fn immediate(&self, text: &str, min: i64, max: i64) -> Result<i64, String> {
+		check_range(self.value(text)?, min, max)
+	}
+
+	// offset(base), where the offset can be left out
+	fn memory(&self, text: &str) -> Result<(i32, u32), String> {
+		let open = text.find('(');
+		match open {
+			Some(open) if text.ends_with(')') => {
+				let offset_text = text[.. open].trim();
+				let offset = if offset_text.is_empty() { 0 } else { self.immediate(offset_text, -2048, 2047)? };
+				Ok((offset as i32, gpr(text[open + 1 .. text.len() - 1].trim())?))
+			},
+			_ => Err(format!("{} is not a memory operand", text))
+		}
+	}
+
+	fn atomic_address(&self, text: &str) -> Result<u32, String> {
+		match self.memory(text)? {
+			(0, base) => Ok(base),
+			_ => Err(format!("{} can't have an offset", text))
+		}
+	}
+
+	fn csr(&self, text: &str) -> Result<u32, String> {
+		if is_identifier(text) && ! self.constants.contains_key(text) {
+			return match (0 .. 0x1000).find(|csr| csr_name(*csr) == text) {
+				Some(csr) => Ok(csr),
+				None => Err(format!("{} is not a csr", text))
+			};
+		}
+		Ok(self.immediate(text, 0, 0xFFF)? as u32)
+	}
+
+	fn instruction(&mut self, mnemonic: &str, operands: &[&str]) -> Result<(), String> {
+		if let Some((op, kinds)) = reg_reg_op(mnemonic) {
+			expect_operands(operands, 3)?;
+			op(self.jit, reg(kinds[0], operands[0])?, reg(kinds[1], operands[1])?, reg(kinds[2], operands[2])?);
+			return Ok(());
+		}
+		if let Some((op, kinds)) = reg_op(mnemonic) {
+			expect_operands(operands, 2)?;
+			op(self.jit, reg(kinds[0], operands[0])?, reg(kinds[1], operands[1])?);
+			return Ok(());
+		}
+		if let Some(op) = fp_rounding_reg_reg_op(mnemonic) {
+			let (operands, rm) = rounding_mode(operands, 3, FpRm::Dynamic)?;
+			expect_operands(operands, 3)?;
+			op(self.jit, fpr(operands[0])?, fpr(operands[1])?, fpr(operands[2])?, rm);
+			return Ok(());
+		}
+		if let Some((op, kinds, default)) = fp_rounding_op(mnemonic) {
+			let (operands, rm) = rounding_mode(operands, 2, default)?;
+			expect_operands(operands, 2)?;
+			op(self.jit, reg(kinds[0], operands[0])?, reg(kinds[1], operands[1])?, rm);
+			return Ok(());
+		}
+		if let Some(op) = fused_op(mnemonic) {
+			let (operands, rm) = rounding_mode(operands, 4, FpRm::Dynamic)?;
+			expect_operands(operands, 4)?;
+			op(self.jit, fpr(operands[0])?, fpr(operands[1])?, fpr(operands[2])?, fpr(operands[3])?, rm);
+			return Ok(());
+		}
+		if let Some(op) = imm_op(mnemonic) {
+			expect_operands(operands, 3)?;
+			let (rd, rs, immediate) = (gpr(operands[0])?, gpr(operands[1])?, self.immediate(operands[2], -2048, 2047)?);
+			op(self.jit, rd, rs, immediate as i32);
+			return Ok(());
+		}
+		if let Some(op) = shift_op(mnemonic) {
+			expect_operands(operands, 3)?;
+			let (rd, rs, shift) = (gpr(operands[0])?, gpr(operands[1])?, self.immediate(operands[2], 0, 31)?);
+			op(self.jit, rd, rs, shift as u32);
+			return Ok(());
+		}
+		if let Some((op, kind)) = memory_op(mnemonic) {
+			expect_operands(operands, 2)?;
+			let data = reg(kind, operands[0])?;
+			let (offset, base) = self.memory(operands[1])?;
+			op(self.jit, data, base, offset);
+			return Ok(());
+		}
+		let (atomic_mnemonic, aq, rl) = atomic_ordering(mnemonic);
+		if atomic_mnemonic == "lr.w" {
+			expect_operands(operands, 2)?;
+			self.jit.lr_w_aqrl(gpr(operands[0])?, self.atomic_address(operands[1])?, aq, rl);
+			return Ok(());
+		}
+		if let Some(op) = atomic_op(atomic_mnemonic) {
+			expect_operands(operands, 3)?;
+			op(self.jit, gpr(operands[0])?, gpr(operands[1])?, self.atomic_address(operands[2])?, aq, rl);
+			return Ok(());
+		}
+		if let Some((op, op_to, swap, zero)) = branch_op(mnemonic) {
+			let (rs1, rs2, target) = if zero {
+				expect_operands(operands, 2)?;
+				(gpr(operands[0])?, 0, operands[1])
+			} else {
+				expect_operands(operands, 3)?;
+				(gpr(operands[0])?, gpr(operands[1])?, operands[2])
+			};
+			let (rs1, rs2) = if swap { (rs2, rs1) } else { (rs1, rs2) };
+			match self.target(target, 13)? {
+				Target::Label(name) => op_to(self.jit, rs1, rs2, name.as_str()),
+				Target::Offset(offset) => op(self.jit, rs1, rs2, offset)
+			};
+			return Ok(());
+		}
+		if mnemonic.starts_with("c.") {
+			return self.compressed_instruction(mnemonic, operands);
+		}
+		match mnemonic {
+			"nop" => {
+				expect_operands(operands, 0)?;
+				self.jit.addi(0, 0, 0);
+			},
+			"lui" | "auipc" => {
+				expect_operands(operands, 2)?;
+				let rd = gpr(operands[0])?;
+				let immediate = (self.immediate(operands[1], -0x8_0000, 0xF_FFFF)? & 0xF_FFFF) as i32;
+				if mnemonic == "lui" {
+					self.jit.lui(rd, immediate);
+				} else {
+					self.jit.auipc(rd, immediate);
+				}
+			},
+			"li" => {
+				expect_operands(operands, 2)?;
+				let rd = gpr(operands[0])?;
+				let value = self.immediate(operands[1], i32::MIN as i64, u32::MAX as i64)?;
+				self.jit.li(rd, value as i32);
+			},
+			"la" => {
+				expect_operands(operands, 2)?;
+				let rd = gpr(operands[0])?;
+				match self.label_name(operands[1])? {
+					Some(name) => {
+						self.refer(name.as_str(), 32);
+						self.jit.la(rd, name.as_str());
+					},
+					None => {
+						let value = self.immediate(operands[1], i32::MIN as i64, u32::MAX as i64)?;
+						self.jit.li(rd, value as i32);
+					}
+				}
+			},
+			"jal" | "j" => {
+				let (rd, target) = match (mnemonic, operands.len()) {
+					("j", 1) => (0, operands[0]),
+					("jal", 1) => (1, operands[0]),
+					("jal", 2) => (gpr(operands[0])?, operands[1]),
+					_ => return Err(format!("wrong number of operands for {}", mnemonic))
+				};
+				match self.target(target, 21)? {
+					Target::Label(name) => self.jit.jal_to(rd, name.as_str()),
+					Target::Offset(offset) => self.jit.jal(rd, offset)
+				};
+			},
+			"call" => {
+				expect_operands(operands, 1)?;
+				match self.target(operands[0], 32)? {
+					Target::Label(name) => {
+						self.jit.call(name.as_str());
+					},
+					Target::Offset(offset) => {
+						let upper = offset.wrapping_add(0x800) >> 12;
+						self.jit.auipc(1, upper & 0xF_FFFF);
+						self.jit.jalr(1, 1, offset.wrapping_sub(upper << 12));
+					}
+				}
+			},
+			"tail" => {
+				expect_operands(operands, 1)?;
+				match self.target(operands[0], 32)? {
+					Target::Label(name) => {
+						self.jit.tail(name.as_str());
+					},
+					Target::Offset(offset) => {
+						let upper = offset.wrapping_add(0x800) >> 12;
+						self.jit.auipc(6, upper & 0xF_FFFF);
+						self.jit.jalr(0, 6, offset.wrapping_sub(upper << 12));
+					}
+				}
+			},
+			"jalr" => {
+				let (rd, rs, offset) = match operands.len() {
+					1 => (1, gpr(operands[0])?, 0),
+					2 if operands[1].contains('(') => {
+						let (offset, base) = self.memory(operands[1])?;
+						(gpr(operands[0])?, base, offset)
+					},
+					2 => (gpr(operands[0])?, gpr(operands[1])?, 0),
+					3 => (gpr(operands[0])?, gpr(operands[1])?, self.immediate(operands[2], -2048, 2047)? as i32),
+					_ => return Err("wrong number of operands for jalr".to_string())
+				};
+				self.jit.jalr(rd, rs, offset);
+			},
+			"jr" => {
+				expect_operands(operands, 1)?;
+				self.jit.jr(gpr(operands[0])?);
+			},
+			"ret" => {
+				expect_operands(operands, 0)?;
+				self.jit.ret();
+			},
+			"fence" => {
+				let (predecessor, successor) = match operands.len() {
+					0 => (0b1111, 0b1111),
+					2 => (fence_set(operands[0])?, fence_set(operands[1])?),
+					_ => return Err("wrong number of operands for fence".to_string())
+				};
+				self.jit.fence(predecessor, successor);
+			},
+			"fence.i" | "ecall" | "ebreak" | "mret" | "sret" | "wfi" => {
+				expect_operands(operands, 0)?;
+				match mnemonic {
+					"fence.i" => self.jit.fence_i(),
+					"ecall" => self.jit.ecall(),
+					"ebreak" => self.jit.ebreak(),
+					"mret" => self.jit.mret(),
+					"sret" => self.jit.sret(),
+					_ => self.jit.wfi()
+				};
+			},
+			"sfence.vma" => {
+				let (rs1, rs2) = match operands.len() {
+					0 => (0, 0),
+					1 => (gpr(operands[0])?, 0),
+					2 => (gpr(operands[0])?, gpr(operands[1])?),
+					_ => return Err("wrong number of operands for sfence.vma".to_string())
+				};
+				self.jit.sfence_vma(rs1, rs2);
+			},
+			"csrrw" | "csrrs" | "csrrc" => {
+				expect_operands(operands, 3)?;
+				let (rd, csr, rs) = (gpr(operands[0])?, self.csr(operands[1])?, gpr(operands[2])?);
+				match mnemonic {
+					"csrrw" => self.jit.csrrw(rd, csr, rs),
+					"csrrs" => self.jit.csrrs(rd, csr, rs),
+					_ => self.jit.csrrc(rd, csr, rs)
+				};
+			},
+			"csrrwi" | "csrrsi" | "csrrci" => {
+				expect_operands(operands, 3)?;
+				let (rd, csr, immediate) = (gpr(operands[0])?, self.csr(operands[1])?, self.immediate(operands[2], 0, 31)? as u32);
+				match mnemonic {
+					"csrrwi" => self.jit.csrrwi(rd, csr, immediate),
+					"csrrsi" => self.jit.csrrsi(rd, csr, immediate),
+					_ => self.jit.csrrci(rd, csr, immediate)
+				};
+			},
+			"csrr" => {
+				expect_operands(operands, 2)?;
+				let (rd, csr) = (gpr(operands[0])?, self.csr(operands[1])?);
+				self.jit.csrr(rd, csr);
+			},
+			"csrw" | "csrs" | "csrc" => {
+				expect_operands(operands, 2)?;
+				let (csr, rs) = (self.csr(operands[0])?, gpr(operands[1])?);
+				match mnemonic {
+					"csrw" => self.jit.csrw(csr, rs),
+					"csrs" => self.jit.csrs(csr, rs),
+					_ => self.jit.csrc(csr, rs)
+				};
+			},
+			"csrwi" | "csrsi" | "csrci" => {
+				expect_operands(operands, 2)?;
+				let (csr, immediate) = (self.csr(operands[0])?, self.immediate(operands[1], 0, 31)? as u32);
+				match mnemonic {
+					"csrwi" => self.jit.csrrwi(0, csr, immediate),
+					"csrsi" => self.jit.csrrsi(0, csr, immediate),
+					_ => self.jit.csrrci(0, csr, immediate)
+				};
+			},
+			"frcsr" | "frrm" | "frflags" => {
+				expect_operands(operands, 1)?;
+				self.jit.csrr(gpr(operands[0])?, fp_csr(mnemonic));
+			},
+			"fscsr" | "fsrm" | "fsflags" => {
+				let (rd, rs) = match operands.len() {
+					1 => (0, gpr(operands[0])?),
+					2 => (gpr(operands[0])?, gpr(operands[1])?),
+					_ => return Err(format!("wrong number of operands for {}", mnemonic))
+				};
+				self.jit.csrrw(rd, fp_csr(mnemonic), rs);
+			},
+			"fsrmi" | "fsflagsi" => {
+				let (rd, immediate) = match operands.len() {
+					1 => (0, operands[0]),
+					2 => (gpr(operands[0])?, operands[1]),
+					_ => return Err(format!("wrong number of operands for {}", mnemonic))
+				};
+				let immediate = self.immediate(immediate, 0, 31)? as u32;
+				self.jit.csrrwi(rd, fp_csr(mnemonic), immediate);
+			},
+			_ => return Err(format!("unknown instruction {}", mnemonic))
+		}
+		Ok(())
+	}
+
+	fn compressed_instruction(&mut self, mnemonic: &str, operands: &[&str]) -> Result<(), String> {
+		match mnemonic {
+			"c.nop" | "c.ebreak" => {
+				expect_operands(operands, 0)?;
+				if mnemonic == "c.nop" {
+					self.jit.c_nop();
+				} else {
+					self.jit.c_ebreak();
+				}
+			},
+			"c.addi4spn" => {
+				expect_operands(operands, 3)?;
+				let rd = compressed_reg(Reg::X, operands[0])?;
+				if gpr(operands[1])? != 2 {
+					return Err("c.addi4spn adds to sp".to_string());
+				}
+				let immediate = check_scaled(self.value(operands[2])?, 4, 1024)?;
+				if immediate == 0 {
+					return Err("c.addi4spn can't add 0".to_string());
+				}
+				self.jit.c_addi4spn(rd, immediate);
+			},
+			"c.lw" | "c.sw" | "c.flw" | "c.fsw" | "c.fld" | "c.fsd" => {
+				expect_operands(operands, 2)?;
+				let kind = if mnemonic.starts_with("c.f") { Reg::F } else { Reg::X };
+				let data = compressed_reg(kind, operands[0])?;
+				let (offset, base) = self.memory(operands[1])?;
+				let base = compressed_reg(Reg::X, REG_NAMES[base as usize])?;
+				let scale = if mnemonic.ends_with('d') { 8 } else { 4 };
+				let offset = check_scaled(offset as i64, scale, scale * 32)?;
+				match mnemonic {
+					"c.lw" => self.jit.c_lw(data, base, offset),
+					"c.sw" => self.jit.c_sw(data, base, offset),
+					"c.flw" => self.jit.c_flw(data, base, offset),
+					"c.fsw" => self.jit.c_fsw(data, base, offset),
+					"c.fld" => self.jit.c_fld(data, base, offset),
+					_ => self.jit.c_fsd(data, base, offset)
+				};
+			},
+			"c.addi" | "c.li" => {
+				expect_operands(operands, 2)?;
+				let rd = gpr(operands[0])?;
+				let immediate = self.immediate(operands[1], -32, 31)? as i32;
+				if mnemonic == "c.addi" {
+					self.jit.c_addi(rd, immediate);
+				} else {
+					self.jit.c_li(rd, immediate);
+				}
+			},
+			"c.addi16sp" => {
+				expect_operands(operands, 2)?;
+				if gpr(operands[0])? != 2 {
+					return Err("c.addi16sp adds to sp".to_string());
+				}
+				let immediate = self.immediate(operands[1], -512, 496)?;
+				if immediate == 0 || immediate % 16 != 0 {
+					return Err(format!("{} is not a non zero multiple of 16", immediate));
+				}
+				self.jit.c_addi16sp(immediate as i32);
+			},
+			"c.lui" => {
+				expect_operands(operands, 2)?;
+				let rd = gpr(operands[0])?;
+				if rd == 0 || rd == 2 {
+					return Err(format!("c.lui can't write {}", operands[0]));
+				}
+				// the 6 bit immediate is sign extended into the upper 20 bits
+				let immediate = self.immediate(operands[1], 1, 0xF_FFFF)?;
+				if immediate > 31 && immediate < 0xF_FFE0 {
+					return Err(format!("{} is out of range 1 to 31 and 0xfffe0 to 0xfffff", immediate));
+				}
+				self.jit.c_lui(rd, immediate as i32);
+			},
+			"c.srli" | "c.srai" | "c.andi" => {
+				expect_operands(operands, 2)?;
+				let rd = compressed_reg(Reg::X, operands[0])?;
+				match mnemonic {
+					"c.andi" => self.jit.c_andi(rd, self.immediate(operands[1], -32, 31)? as i32),
+					"c.srli" => self.jit.c_srli(rd, self.immediate(operands[1], 1, 31)? as u32),
+					_ => self.jit.c_srai(rd, self.immediate(operands[1], 1, 31)? as u32)
+				};
+			},
+			"c.sub" | "c.xor" | "c.or" | "c.and" => {
+				expect_operands(operands, 2)?;
+				let (rd, rs2) = (compressed_reg(Reg::X, operands[0])?, compressed_reg(Reg::X, operands[1])?);
+				match mnemonic {
+					"c.sub" => self.jit.c_sub(rd, rs2),
+					"c.xor" => self.jit.c_xor(rd, rs2),
+					"c.or" => self.jit.c_or(rd, rs2),
+					_ => self.jit.c_and(rd, rs2)
+				};
+			},
+			"c.j" | "c.jal" => {
+				expect_operands(operands, 1)?;
+				match (self.target(operands[0], 12)?, mnemonic) {
+					(Target::Label(name), "c.j") => self.jit.c_j_to(name.as_str()),
+					(Target::Label(name), _) => self.jit.c_jal_to(name.as_str()),
+					(Target::Offset(offset), "c.j") => self.jit.c_j(offset),
+					(Target::Offset(offset), _) => self.jit.c_jal(offset)
+				};
+			},
+			"c.beqz" | "c.bnez" => {
+				expect_operands(operands, 2)?;
+				let rs1 = compressed_reg(Reg::X, operands[0])?;
+				match (self.target(operands[1], 9)?, mnemonic) {
+					(Target::Label(name), "c.beqz") => self.jit.c_beqz_to(rs1, name.as_str()),
+					(Target::Label(name), _) => self.jit.c_bnez_to(rs1, name.as_str()),
+					(Target::Offset(offset), "c.beqz") => self.jit.c_beqz(rs1, offset),
+					(Target::Offset(offset), _) => self.jit.c_bnez(rs1, offset)
+				};
+			},
+			"c.slli" => {
+				expect_operands(operands, 2)?;
+				let rd = gpr(operands[0])?;
+				self.jit.c_slli(rd, self.immediate(operands[1], 1, 31)? as u32);
+			},
+			"c.lwsp" | "c.flwsp" | "c.fldsp" | "c.swsp" | "c.fswsp" | "c.fsdsp" => {
+				expect_operands(operands, 2)?;
+				let kind = if mnemonic.starts_with("c.f") { Reg::F } else { Reg::X };
+				let data = reg(kind, operands[0])?;
+				let (offset, base) = self.memory(operands[1])?;
+				if base != 2 {
+					return Err(format!("{} is relative to sp", mnemonic));
+				}
+				let scale = if mnemonic.ends_with("dsp") { 8 } else { 4 };
+				let offset = check_scaled(offset as i64, scale, scale * 64)?;
+				match mnemonic {
+					"c.lwsp" if data == 0 => return Err("c.lwsp can't load zero".to_string()),
+					"c.lwsp" => self.jit.c_lwsp(data, offset),
+					"c.flwsp" => self.jit.c_flwsp(data, offset),
+					"c.fldsp" => self.jit.c_fldsp(data, offset),
+					"c.swsp" => self.jit.c_swsp(data, offset),
+					"c.fswsp" => self.jit.c_fswsp(data, offset),
+					_ => self.jit.c_fsdsp(data, offset)
+				};
+			},
+			"c.jr" | "c.jalr" => {
+				expect_operands(operands, 1)?;
+				let rs1 = gpr(operands[0])?;
+				if rs1 == 0 {
+					return Err(format!("{} can't jump through zero", mnemonic));
+				}
+				if mnemonic == "c.jr" {
+					self.jit.c_jr(rs1);
+				} else {
+					self.jit.c_jalr(rs1);
+				}
+			},
+			"c.mv" | "c.add" => {
+				expect_operands(operands, 2)?;
+				let (rd, rs2) = (gpr(operands[0])?, gpr(operands[1])?);
+				if rs2 == 0 {
+					return Err(format!("{} can't take zero as a source", mnemonic));
+				}
+				if mnemonic == "c.mv" {
+					self.jit.c_mv(rd, rs2);
+				} else {
+					self.jit.c_add(rd, rs2);
+				}
+			},
+			_ => return Err(format!("unknown instruction {}", mnemonic))
+		}
+		Ok(())
+	}
+
+	fn directive(&mut self, directive: &str, operands: &[&str]) -> Result<(), String> {
+		match directive {
+			".byte" => {
+				for operand in operands {
+					let value = self.immediate(operand, -0x80, 0xFF)?;
+					self.jit.data_u8(value as u8);
+				}
+			},
+			".half" | ".short" | ".2byte" => {
+				for operand in operands {
+					let value = self.immediate(operand, -0x8000, 0xFFFF)?;
+					self.jit.data_u16(value as u16);
+				}
+			},
+			".word" | ".long" | ".4byte" => {
+				for operand in operands {
+					match self.label_name(operand)? {
+						Some(name) => {
+							self.refer(name.as_str(), 32);
+							self.jit.data_label(name.as_str());
+						},
+						None => {
+							let value = self.immediate(operand, i32::MIN as i64, u32::MAX as i64)?;
+							self.jit.data_u32(value as u32);
+						}
+					}
+				}
+			},
+			".dword" | ".quad" | ".8byte" => {
+				for operand in operands {
+					let value = self.value(operand)?;
+					self.jit.data_u32(value as u32);
+					self.jit.data_u32((value >> 32) as u32);
+				}
+			},
+			".ascii" | ".asciz" | ".string" => {
+				for operand in operands {
+					let data = parse_string(operand)?;
+					self.jit.data_bytes(data.as_slice());
+					if directive != ".ascii" {
+						self.jit.data_u8(0);
+					}
+				}
+			},
+			".zero" | ".space" | ".skip" => {
+				let (size, fill) = match operands.len() {
+					1 => (self.immediate(operands[0], 0, 0x100_0000)?, 0),
+					2 => (self.immediate(operands[0], 0, 0x100_0000)?, self.immediate(operands[1], -0x80, 0xFF)?),
+					_ => return Err(format!("wrong number of operands for {}", directive))
+				};
+				self.jit.data_bytes(vec![fill as u8; size as usize].as_slice());
+			},
+			// on RISC-V .align is a power of two, like .p2align
+			".align" | ".p2align" => {
+				expect_operands(operands, 1)?;
+				let shift = self.immediate(operands[0], 0, 16)?;
+				self.jit.align(1 << shift);
+			},
+			".balign" => {
+				expect_operands(operands, 1)?;
+				let alignment = self.immediate(operands[0], 1, 0x1_0000)?;
+				if (alignment & (alignment - 1)) != 0 {
+					return Err(format!("{} is not a power of two", alignment));
+				}
+				self.jit.align(alignment as u32);
+			},
+			".equ" | ".set" => {
+				expect_operands(operands, 2)?;
+				if ! is_identifier(operands[0]) || self.jit.label_address(operands[0]).is_some() {
+					return Err(format!("{} can't be used as a constant name", operands[0]));
+				}
+				let value = self.value(operands[1])?;
+				self.constants.insert(operands[0].to_string(), value);
+			},
+			// everything is assembled into one run of code, so sections and symbol attributes don't mean anything
+			".text" | ".data" | ".rodata" | ".bss" | ".section" | ".globl" | ".global" | ".local" | ".weak" | ".type" | ".size" |
+			".file" | ".ident" | ".option" | ".attribute" | ".cfi_startproc" | ".cfi_endproc" => {},
+			_ => return Err(format!("unknown directive {}", directive))
+		}
+		Ok(())
+	}
+
+	fn finish(self) -> Result<(), AsmError> {
+		for reference in self.references.iter() {
+			let error = match self.jit.label_address(reference.name.as_str()) {
+				Some(address) => check_offset(address.wrapping_sub(reference.address) as i32 as i64, reference.bits).err(),
+				None => {
+					let name = reference.name.split(':').next().unwrap_or("");
+					Some(format!("label {} is never defined", name))
+				}
+			};
+			if let Some(message) = error {
+				return Err(AsmError {
+					line: reference.line,
+					message,
+				});
+			}
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn words(source: &str) -> Vec<u32> {
+		assemble(source, 0).unwrap().chunks(4).map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]])).collect()
+	}
+
+	fn halfwords(source: &str) -> Vec<u16> {
+		assemble(source, 0).unwrap().chunks(2).map(|half| u16::from_le_bytes([half[0], half[1]])).collect()
+	}
+
+	// expected words are from llvm-mc -triple=riscv32 -mattr=+a,+c,+d
+	#[test]
+	fn encodes_known_words() {
+		assert_eq!(words("addi a0, a1, -1"), vec![0xfff58513]);
+		assert_eq!(words("fcvt.w.s a0, fa0, rtz"), vec![0xc0051553]);
+		assert_eq!(words("fadd.s fa0, fa1, fa2, rtz"), vec![0x00c59553]);
+		assert_eq!(words("fmadd.d fa0, fa1, fa2, fa3, rup"), vec![0x6ac5b543]);
+		assert_eq!(words("lr.w.aq a0, (a2)"), vec![0x1406252f]);
+		assert_eq!(words("sc.w.rl a0, a1, (a2)"), vec![0x1ab6252f]);
+		assert_eq!(words("amoadd.w.aqrl a0, a1, (a2)"), vec![0x06b6252f]);
+		assert_eq!(words("frcsr a0; fscsr a1; frrm a0; fsflags a1; fsrmi 1"), vec![0x00302573, 0x00359073, 0x00202573, 0x00159073, 0x0020d073]);
+		assert_eq!(words("tail 1f\n nop\n 1: nop"), vec![0x00000317, 0x00c30067, 0x00000013, 0x00000013]);
+	}
+
+	#[test]
+	fn encodes_compressed_forms() {
+		assert_eq!(halfwords("c.addi4spn s0, sp, 16"), vec![0x0800]);
+		assert_eq!(halfwords("c.lw a0, 4(a1)"), vec![0x41c8]);
+	}
+
+	#[test]
+	fn rejects_bad_rounding_modes() {
+		assert_eq!(assemble("fadd.s fa0, fa1, fa2, foo", 0).unwrap_err().message, "foo is not a rounding mode");
+		assert!(assemble("fsgnj.s fa0, fa1, fa2, rtz", 0).is_err());
+		assert_eq!(assemble("nop\nfcvt.w.s a0, fa0, up", 0).unwrap_err().line, 2);
+	}
+}
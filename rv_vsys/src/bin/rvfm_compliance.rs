@@ -0,0 +1,139 @@
+use std::env::args;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::process::exit;
+
+use rv_vsys::{load_elf, load_elf_symbols, Cpu, CpuWakeupHandle, FlatMemIO, MemIO, MemReadResult, NullInterruptBus};
+
+// Runs a riscv-tests or riscv-arch-test image on a single hart with nothing but flat ram behind it.
+// The run ends when the image writes tohost, or the word at end_signature for images without a tohost,
+// and the words from begin_signature to end_signature are then dumped one per line.
+
+const RAM_BASE: u32 = 0x8000_0000;
+const RAM_SIZE: u32 = 0x0100_0000;
+
+const DEFAULT_MAX_INSTRUCTIONS: u64 = 100_000_000;
+
+fn write_signature<W: Write>(out: &mut W, mio: &FlatMemIO, begin: u32, end: u32) -> io::Result<()> {
+	let mut addr = begin;
+	while addr < end {
+		match mio.read_32(addr) {
+			MemReadResult::Ok(value) => writeln!(out, "{:08x}", value)?,
+			_ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("signature word {:#010x} is outside of ram", addr)))
+		}
+		addr += 4;
+	}
+	out.flush()
+}
+
+fn usage() -> ! {
+	eprintln!("usage: rvfm_compliance [--signature <file>] [--max-instructions <count>] <elf file>");
+	exit(2);
+}
+
+fn main() {
+	let mut path = None;
+	let mut signature_path = None;
+	let mut max_instructions = DEFAULT_MAX_INSTRUCTIONS;
+	let mut args = args().skip(1);
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--signature" => {
+				match args.next() {
+					Some(file) => signature_path = Some(file),
+					None => usage()
+				}
+			},
+			"--max-instructions" => {
+				match args.next().and_then(|value| value.parse::<u64>().ok()) {
+					Some(count) => max_instructions = count,
+					None => {
+						eprintln!("--max-instructions requires an instruction count");
+						exit(2);
+					}
+				}
+			},
+			_ if path.is_none() && ! arg.starts_with("--") => path = Some(arg),
+			_ => usage()
+		}
+	}
+	let path = match path {
+		Some(path) => path,
+		None => usage()
+	};
+	let elf_data = match fs::read(path.as_str()) {
+		Ok(data) => data,
+		Err(error) => {
+			eprintln!("{}: {}", path, error);
+			exit(2);
+		}
+	};
+	let mut mio = FlatMemIO::new(RAM_BASE, RAM_SIZE);
+	let loaded = load_elf(elf_data.as_slice(), &mut mio, 0).and_then(|entry| Ok((entry, load_elf_symbols(elf_data.as_slice())?)));
+	let (entry, symbols) = match loaded {
+		Ok(loaded) => loaded,
+		Err(error) => {
+			eprintln!("{}: {}", path, error);
+			exit(2);
+		}
+	};
+	let signature = match (symbols.get("begin_signature"), symbols.get("end_signature")) {
+		(Some(&begin), Some(&end)) => Some((begin, end)),
+		_ => None
+	};
+	let (marker, marker_name) = match (symbols.get("tohost"), symbols.get("end_signature")) {
+		(Some(&tohost), _) => (tohost, "tohost"),
+		(None, Some(&end)) => (end, "end_signature"),
+		(None, None) => {
+			eprintln!("{}: no tohost or end_signature symbol to stop at", path);
+			exit(2);
+		}
+	};
+	// the test is over once the word changes from what the image left there
+	let initial_marker = match mio.read_32(marker) {
+		MemReadResult::Ok(value) => value,
+		_ => {
+			eprintln!("{}: {} at {:#010x} is outside of ram", path, marker_name, marker);
+			exit(2);
+		}
+	};
+	let mut cpu = Cpu::new(mio.clone(), NullInterruptBus, CpuWakeupHandle::new(), 0);
+	cpu.reset(entry);
+	let mut executed = 0u64;
+	let mut result = None;
+	while result.is_none() && executed < max_instructions {
+		cpu.step();
+		executed += 1;
+		match mio.read_32(marker) {
+			MemReadResult::Ok(value) if value != initial_marker => result = Some(value),
+			_ => {}
+		}
+	}
+	let result = match result {
+		Some(value) => value,
+		None => {
+			eprintln!("{}: timed out after {} instructions at pc {:#010x}", path, executed, cpu.get_pc());
+			exit(1);
+		}
+	};
+	if let Some((begin, end)) = signature {
+		let written = match &signature_path {
+			Some(signature_path) => File::create(signature_path).and_then(|file| write_signature(&mut BufWriter::new(file), &mio, begin, end)),
+			None => write_signature(&mut io::stdout(), &mio, begin, end)
+		};
+		if let Err(error) = written {
+			eprintln!("{}: failed to write signature: {}", path, error);
+			exit(2);
+		}
+	}
+	// riscv-tests report pass through tohost as 1, and a failure as the failing test number shifted up with
+	// the low bit set, while images ending at end_signature are only judged by their signature
+	if marker_name != "tohost" {
+		eprintln!("{}: reached end_signature after {} instructions", path, executed);
+	} else if result != 1 {
+		eprintln!("{}: failed test {} after {} instructions", path, result >> 1, executed);
+		exit(1);
+	} else {
+		eprintln!("{}: passed after {} instructions", path, executed);
+	}
+}
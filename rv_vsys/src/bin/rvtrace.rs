@@ -0,0 +1,80 @@
+use std::env::args;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::process::exit;
+
+use rv_vsys::{disassemble, TraceEvent, TraceReader, TraceRegWrite, REG_NAMES};
+
+fn print_trace(path: &str, hart_filter: Option<u32>) -> io::Result<()> {
+	let mut reader = TraceReader::new(BufReader::new(File::open(path)?))?;
+	let stdout = io::stdout();
+	let mut out = BufWriter::new(stdout.lock());
+	while let Some(event) = reader.next_event()? {
+		match event {
+			TraceEvent::Retire { hart_id, pc, opcode, length, reg_write, mem_addr } => {
+				if hart_filter.is_some_and(|hart| hart != hart_id) {
+					continue;
+				}
+				let mut effects = String::new();
+				match reg_write {
+					Some(TraceRegWrite::Gpr(reg, value)) => effects += format!("  {} <= {:#010x}", REG_NAMES[reg as usize], value).as_str(),
+					// a boxed single has the upper half all ones
+					Some(TraceRegWrite::Fpr(reg, bits)) if bits >> 32 == 0xFFFF_FFFF => effects += format!("  f{} <= {:#010x} ({})", reg, bits as u32, f32::from_bits(bits as u32)).as_str(),
+					Some(TraceRegWrite::Fpr(reg, bits)) => effects += format!("  f{} <= {:#018x} ({})", reg, bits, f64::from_bits(bits)).as_str(),
+					None => {}
+				}
+				if let Some(address) = mem_addr {
+					effects += format!("  mem {:#010x}", address).as_str();
+				}
+				let opcode_text = if length == 2 { format!("{:08x}c", opcode) } else { format!("{:08x} ", opcode) };
+				let line = format!("{} {:#010x}: {}  {: <36}{}", hart_id, pc, opcode_text, disassemble(opcode, pc), effects);
+				writeln!(out, "{}", line.trim_end())?;
+			},
+			TraceEvent::Trap { hart_id, cause, epc, tval } => {
+				if hart_filter.is_some_and(|hart| hart != hart_id) {
+					continue;
+				}
+				writeln!(out, "{} trap: mcause {:#010x} mepc {:#010x} mtval {:#010x}", hart_id, cause, epc, tval)?;
+			},
+		}
+	}
+	Ok(())
+}
+
+fn main() {
+	let mut path = None;
+	let mut hart_filter = None;
+	let mut args = args().skip(1);
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--hart" => {
+				match args.next().and_then(|value| value.parse::<u32>().ok()) {
+					Some(hart) => hart_filter = Some(hart),
+					None => {
+						eprintln!("--hart requires a hart number");
+						exit(1);
+					}
+				}
+			},
+			_ if path.is_none() && ! arg.starts_with("--") => path = Some(arg),
+			_ => {
+				eprintln!("usage: rvtrace [--hart <id>] <trace file>");
+				exit(1);
+			}
+		}
+	}
+	let path = match path {
+		Some(path) => path,
+		None => {
+			eprintln!("usage: rvtrace [--hart <id>] <trace file>");
+			exit(1);
+		}
+	};
+	if let Err(error) = print_trace(path.as_str(), hart_filter) {
+		// a closed pipe (e.g. piping into head) isn't worth reporting
+		if error.kind() != io::ErrorKind::BrokenPipe {
+			eprintln!("{}: {}", path, error);
+			exit(1);
+		}
+	}
+}
@@ -0,0 +1,144 @@
+use crate::{expand_compressed, is_compressed, MTimer, MemIO, MemReadResult, Op, Opcode};
+
+// Per-hart cache of pre-decoded basic blocks. Blocks never cross a code page, end at the
+// first control flow instruction, and are tagged with the page's write cycle at decode time,
+// so any write to the page (from any hart or peripheral) invalidates them on next entry.
+
+pub const CODE_PAGE_SIZE: u32 = 0x1000;
+
+const BLOCK_CACHE_SIZE: usize = 0x800;
+const MAX_BLOCK_INSTRUCTIONS: usize = 64;
+
+#[derive(Clone, Copy)]
+pub struct DecodedInstruction {
+	pub opcode_value: u32,
+	pub length: u32,
+}
+
+struct CachedBlock {
+	start_pc: u32,
+	write_cycle: usize,
+	instructions: Vec<DecodedInstruction>,
+}
+
+struct BlockCursor {
+	slot: usize,
+	index: usize,
+	next_pc: u32,
+}
+
+pub struct BlockCache {
+	blocks: Vec<Option<CachedBlock>>,
+	cursor: Option<BlockCursor>,
+}
+
+fn ends_block(opcode: &Opcode) -> bool {
+	matches!(opcode.op(), Op::Branch | Op::Jal | Op::Jalr | Op::System | Op::Fence)
+}
+
+impl BlockCache {
+	pub fn new() -> Self {
+		let mut blocks = Vec::with_capacity(BLOCK_CACHE_SIZE);
+		for _ in 0 .. BLOCK_CACHE_SIZE {
+			blocks.push(None);
+		}
+		BlockCache {
+			blocks,
+			cursor: None,
+		}
+	}
+
+	pub fn flush(&mut self) {
+		for block in self.blocks.iter_mut() {
+			*block = None;
+		}
+		self.cursor = None;
+	}
+
+	// forces the write cycle of the next block to be re-checked before executing it
+	pub fn break_block(&mut self) {
+		self.cursor = None;
+	}
+
+	fn slot(pc: u32) -> usize {
+		((pc >> 1) as usize) & (BLOCK_CACHE_SIZE - 1)
+	}
+
+	// returns the decoded instruction at pc, or None if it has to be fetched and decoded the slow way
+	pub fn fetch<Timer: MTimer, MIO: MemIO<Timer>>(&mut self, mio: &MIO, pc: u32) -> Option<DecodedInstruction> {
+		if let Some(cursor) = &mut self.cursor {
+			if cursor.next_pc == pc {
+				if let Some(block) = &self.blocks[cursor.slot] {
+					if let Some(instruction) = block.instructions.get(cursor.index) {
+						cursor.index += 1;
+						cursor.next_pc = pc.wrapping_add(instruction.length);
+						return Some(*instruction);
+					}
+				}
+			}
+			self.cursor = None;
+		}
+		let write_cycle = mio.code_write_cycle(pc)?;
+		let slot = Self::slot(pc);
+		let cached = match &self.blocks[slot] {
+			Some(block) => block.start_pc == pc && block.write_cycle == write_cycle,
+			None => false
+		};
+		if ! cached {
+			let instructions = decode_block(mio, pc, write_cycle)?;
+			self.blocks[slot] = Some(CachedBlock {
+				start_pc: pc,
+				write_cycle,
+				instructions,
+			});
+		}
+		let instruction = self.blocks[slot].as_ref().unwrap().instructions[0];
+		self.cursor = Some(BlockCursor {
+			slot,
+			index: 1,
+			next_pc: pc.wrapping_add(instruction.length),
+		});
+		Some(instruction)
+	}
+}
+
+// decodes the basic block starting at start_pc, as long as the page's write cycle doesn't change underneath us
+pub fn decode_block<Timer: MTimer, MIO: MemIO<Timer>>(mio: &MIO, start_pc: u32, write_cycle: usize) -> Option<Vec<DecodedInstruction>> {
+	let page_end = (start_pc & !(CODE_PAGE_SIZE - 1)).wrapping_add(CODE_PAGE_SIZE);
+	let mut instructions = Vec::new();
+	let mut pc = start_pc;
+	while instructions.len() < MAX_BLOCK_INSTRUCTIONS {
+		// leave anything that straddles the end of the page to the slow path
+		if page_end.wrapping_sub(pc) < 4 {
+			break;
+		}
+		let fetch_value = match mio.read_32_ifetch(pc) {
+			MemReadResult::Ok(value) => value,
+			_ => break
+		};
+		let instruction = if is_compressed(fetch_value) {
+			match expand_compressed(fetch_value) {
+				Some(opcode_value) => DecodedInstruction {
+					opcode_value,
+					length: 2,
+				},
+				None => break
+			}
+		} else {
+			DecodedInstruction {
+				opcode_value: fetch_value,
+				length: 4,
+			}
+		};
+		instructions.push(instruction);
+		pc = pc.wrapping_add(instruction.length);
+		if ends_block(&Opcode::new(instruction.opcode_value)) {
+			break;
+		}
+	}
+	// a write which raced with decoding means the block may be stale already
+	if instructions.is_empty() || mio.code_write_cycle(start_pc) != Some(write_cycle) {
+		return None;
+	}
+	Some(instructions)
+}
@@ -0,0 +1,270 @@
+use std::{collections::HashSet, sync::{Arc, atomic::{AtomicBool, AtomicUsize, Ordering}, mpsc::Sender}, time::Duration};
+use parking_lot::{Condvar, Mutex};
+
+use crate::CpuKillHandle;
+
+// Breakpoints and watchpoints checked by Cpu::run_loop. The host edits them through a
+// CpuBreakpointHandle from any thread, and the hart picks up changes at the start of its next
+// period. A hit stops the hart inside run_loop, reports it to the hit sender, and parks the hart
+// until the host resumes it.
+
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WatchKind {
+	Read,
+	Write,
+	ReadWrite,
+	Execute,
+}
+
+impl WatchKind {
+	// as an access, ReadWrite is an atomic read-modify-write
+	fn triggered_by(self, access: WatchKind) -> bool {
+		match (self, access) {
+			(WatchKind::Execute, access) | (access, WatchKind::Execute) => self == access,
+			(WatchKind::ReadWrite, _) | (_, WatchKind::ReadWrite) => true,
+			(kind, access) => kind == access
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Watchpoint {
+	pub address: u32,
+	pub length: u32,
+	pub kind: WatchKind,
+}
+
+impl Watchpoint {
+	pub fn new(address: u32, length: u32, kind: WatchKind) -> Self {
+		Watchpoint {
+			address,
+			length,
+			kind,
+		}
+	}
+
+	fn overlaps(&self, address: u32, size: u32) -> bool {
+		let start = self.address as u64;
+		let end = start + self.length as u64;
+		(address as u64) < end && address as u64 + size as u64 > start
+	}
+
+	pub(crate) fn is_hit_by(&self, address: u32, size: u32, access: WatchKind) -> bool {
+		self.kind.triggered_by(access) && self.overlaps(address, size)
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakpointHit {
+	// stopped before the instruction at pc
+	Breakpoint { hart_id: u32, pc: u32 },
+	// data watchpoints stop after the instruction at pc has made the access,
+	// execute watchpoints stop before it like a breakpoint
+	Watchpoint { hart_id: u32, pc: u32, address: u32, watchpoint: Watchpoint },
+	// stopped by CpuBreakpointHandle::pause, before the instruction at pc
+	Paused { hart_id: u32, pc: u32 },
+}
+
+struct BreakpointSets {
+	breakpoints: HashSet<u32>,
+	watchpoints: Vec<Watchpoint>,
+}
+
+struct BreakpointShared {
+	sets: Mutex<BreakpointSets>,
+	generation: AtomicUsize,
+	pause_requested: AtomicBool,
+	paused: Mutex<bool>,
+	resume_cond: Condvar,
+	hit_sender: Mutex<Option<Sender<BreakpointHit>>>,
+}
+
+#[derive(Clone)]
+pub struct CpuBreakpointHandle {
+	shared: Arc<BreakpointShared>,
+}
+
+impl CpuBreakpointHandle {
+	fn new() -> Self {
+		CpuBreakpointHandle {
+			shared: Arc::new(BreakpointShared {
+				sets: Mutex::new(BreakpointSets {
+					breakpoints: HashSet::new(),
+					watchpoints: Vec::new(),
+				}),
+				generation: AtomicUsize::new(0),
+				pause_requested: AtomicBool::new(false),
+				paused: Mutex::new(false),
+				resume_cond: Condvar::new(),
+				hit_sender: Mutex::new(None),
+			})
+		}
+	}
+
+	fn edit_sets<R>(&self, edit: impl FnOnce(&mut BreakpointSets) -> R) -> R {
+		let result = edit(&mut self.shared.sets.lock());
+		self.shared.generation.fetch_add(1, Ordering::SeqCst);
+		result
+	}
+
+	pub fn add_breakpoint(&self, pc: u32) -> bool {
+		self.edit_sets(|sets| sets.breakpoints.insert(pc))
+	}
+
+	pub fn remove_breakpoint(&self, pc: u32) -> bool {
+		self.edit_sets(|sets| sets.breakpoints.remove(&pc))
+	}
+
+	pub fn breakpoints(&self) -> Vec<u32> {
+		self.shared.sets.lock().breakpoints.iter().cloned().collect()
+	}
+
+	pub fn add_watchpoint(&self, watchpoint: Watchpoint) -> bool {
+		self.edit_sets(|sets| {
+			if sets.watchpoints.contains(&watchpoint) {
+				false
+			} else {
+				sets.watchpoints.push(watchpoint);
+				true
+			}
+		})
+	}
+
+	pub fn remove_watchpoint(&self, watchpoint: Watchpoint) -> bool {
+		self.edit_sets(|sets| {
+			let count = sets.watchpoints.len();
+			sets.watchpoints.retain(|existing| *existing != watchpoint);
+			sets.watchpoints.len() != count
+		})
+	}
+
+	pub fn watchpoints(&self) -> Vec<Watchpoint> {
+		self.shared.sets.lock().watchpoints.clone()
+	}
+
+	pub fn clear(&self) {
+		self.edit_sets(|sets| {
+			sets.breakpoints.clear();
+			sets.watchpoints.clear();
+		});
+	}
+
+	// every hit is sent here as the hart stops, hits with no sender are dropped
+	pub fn set_hit_sender(&self, sender: Option<Sender<BreakpointHit>>) {
+		*self.shared.hit_sender.lock() = sender;
+	}
+
+	// stops the hart at the start of its next period
+	pub fn pause(&self) {
+		self.shared.pause_requested.store(true, Ordering::SeqCst);
+	}
+
+	pub fn resume(&self) {
+		self.shared.pause_requested.store(false, Ordering::SeqCst);
+		let mut paused = self.shared.paused.lock();
+		*paused = false;
+		self.shared.resume_cond.notify_all();
+	}
+
+	pub fn is_paused(&self) -> bool {
+		*self.shared.paused.lock()
+	}
+}
+
+// the hart's own copy of the sets, so checking them doesn't lock
+pub(crate) struct HartBreakpoints {
+	handle: CpuBreakpointHandle,
+	generation: usize,
+	breakpoints: HashSet<u32>,
+	watchpoints: Vec<Watchpoint>,
+	// the pc the hart was last stopped at before executing, which mustn't stop it again on resume
+	resume_pc: Option<u32>,
+	pending_hit: Option<BreakpointHit>,
+}
+
+impl HartBreakpoints {
+	pub fn new() -> Self {
+		HartBreakpoints {
+			handle: CpuBreakpointHandle::new(),
+			generation: 0,
+			breakpoints: HashSet::new(),
+			watchpoints: Vec::new(),
+			resume_pc: None,
+			pending_hit: None,
+		}
+	}
+
+	pub fn handle(&self) -> CpuBreakpointHandle {
+		self.handle.clone()
+	}
+
+	pub fn sync(&mut self) {
+		let generation = self.handle.shared.generation.load(Ordering::SeqCst);
+		if generation != self.generation {
+			let sets = self.handle.shared.sets.lock();
+			self.breakpoints = sets.breakpoints.clone();
+			self.watchpoints = sets.watchpoints.clone();
+			self.generation = generation;
+		}
+	}
+
+	pub fn is_active(&self) -> bool {
+		! self.breakpoints.is_empty() || ! self.watchpoints.is_empty()
+	}
+
+	pub fn take_pause_request(&mut self, hart_id: u32, pc: u32) -> Option<BreakpointHit> {
+		if self.handle.shared.pause_requested.swap(false, Ordering::SeqCst) {
+			Some(BreakpointHit::Paused { hart_id, pc })
+		} else {
+			None
+		}
+	}
+
+	// checked before executing the instruction at pc
+	pub fn check_pc(&mut self, hart_id: u32, pc: u32) -> Option<BreakpointHit> {
+		if self.resume_pc.take() == Some(pc) {
+			return None;
+		}
+		if self.breakpoints.contains(&pc) {
+			return Some(BreakpointHit::Breakpoint { hart_id, pc });
+		}
+		self.watchpoints.iter()
+			.find(|watchpoint| watchpoint.kind == WatchKind::Execute && watchpoint.overlaps(pc, 1))
+			.map(|watchpoint| BreakpointHit::Watchpoint { hart_id, pc, address: pc, watchpoint: *watchpoint })
+	}
+
+	// records the first watchpoint hit by the instruction at pc, reported once it completes
+	pub fn check_access(&mut self, hart_id: u32, pc: u32, address: u32, size: u32, access: WatchKind) {
+		if self.watchpoints.is_empty() || self.pending_hit.is_some() {
+			return;
+		}
+		self.pending_hit = self.watchpoints.iter()
+			.find(|watchpoint| watchpoint.is_hit_by(address, size, access))
+			.map(|watchpoint| BreakpointHit::Watchpoint { hart_id, pc, address, watchpoint: *watchpoint });
+	}
+
+	pub fn take_hit(&mut self) -> Option<BreakpointHit> {
+		self.pending_hit.take()
+	}
+
+	// reports the hit and parks the hart until it's resumed or killed
+	pub fn stop(&mut self, hit: BreakpointHit, kill_handle: &CpuKillHandle) {
+		self.resume_pc = match hit {
+			BreakpointHit::Breakpoint { pc, .. } => Some(pc),
+			BreakpointHit::Watchpoint { pc, watchpoint, .. } if watchpoint.kind == WatchKind::Execute => Some(pc),
+			_ => None
+		};
+		let shared = &self.handle.shared;
+		let mut paused = shared.paused.lock();
+		*paused = true;
+		if let Some(sender) = &*shared.hit_sender.lock() {
+			let _ = sender.send(hit);
+		}
+		while *paused && ! kill_handle.is_kill_requested() {
+			shared.resume_cond.wait_for(&mut paused, PAUSE_POLL_INTERVAL);
+		}
+		*paused = false;
+	}
+}
@@ -0,0 +1,342 @@
+use std::sync::{Arc, atomic::{AtomicU64, Ordering}};
+use std::time::{Duration, Instant};
+use std::fmt::{self, Debug, Formatter};
+use parking_lot::{Condvar, Mutex};
+
+// Machine wide time source. In real mode time follows the host clock. In virtual mode it
+// only moves as the harts retire instructions, at a nominal instruction rate, so two runs of
+// the same program see the same time and the same periodic events. The running harts finish
+// each scheduling period together and time moves on by the period once the last of them gets
+// there, so every hart sees the same time at the same point of its own instruction stream,
+// however the host scheduled their threads.
+// The instruction rate is also the harts' clock speed, and can change while the machine runs
+// without either time or the cycle count jumping.
+
+pub const DEFAULT_INSTRUCTIONS_PER_SECOND: u64 = 20_000_000;
+
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+// how often harts waiting on the others check whether they've been asked to stop
+const LOCKSTEP_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClockMode {
+	Real,
+	Virtual,
+}
+
+struct VirtualTimer {
+	period_nanos: u64,
+	period: u64,
+	next: u64,
+	callback: Box<dyn FnMut() + Send>,
+}
+
+// cycles and nanoseconds both count on from where they were at the last rate change
+struct ClockRate {
+	instructions_per_second: u64,
+	base_cycles: u64,
+	base_nanos: u64,
+}
+
+impl ClockRate {
+	fn cycles_to_nanos(&self, cycles: u64) -> u64 {
+		self.base_nanos + ((cycles - self.base_cycles) as u128 * NANOS_PER_SECOND / self.instructions_per_second as u128) as u64
+	}
+
+	fn nanos_to_cycles(&self, nanos: u64) -> u64 {
+		self.base_cycles + ((nanos - self.base_nanos) as u128 * self.instructions_per_second as u128 / NANOS_PER_SECOND) as u64
+	}
+}
+
+// harts join at a period boundary, so when a hart starts doesn't depend on the host either
+struct Lockstep {
+	period: u64,
+	running: usize,
+	arrived: usize,
+	joining: usize,
+	cycles: u64,
+}
+
+struct ClockShared {
+	mode: ClockMode,
+	rate: Mutex<ClockRate>,
+	start: Instant,
+	cycles: AtomicU64,
+	timers: Mutex<Vec<VirtualTimer>>,
+	lockstep: Mutex<Lockstep>,
+	lockstep_cond: Condvar,
+}
+
+#[derive(Clone)]
+pub struct MachineClock {
+	shared: Arc<ClockShared>,
+}
+
+impl Debug for MachineClock {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		f.debug_struct("MachineClock")
+			.field("mode", &self.shared.mode)
+			.field("cycles", &self.cycles())
+			.finish()
+	}
+}
+
+impl MachineClock {
+	pub fn new(mode: ClockMode, instructions_per_second: u64) -> Self {
+		MachineClock {
+			shared: Arc::new(ClockShared {
+				mode,
+				rate: Mutex::new(ClockRate {
+					instructions_per_second: instructions_per_second.max(1),
+					base_cycles: 0,
+					base_nanos: 0,
+				}),
+				start: Instant::now(),
+				cycles: AtomicU64::new(0),
+				timers: Mutex::new(Vec::new()),
+				lockstep: Mutex::new(Lockstep {
+					period: 0,
+					running: 0,
+					arrived: 0,
+					joining: 0,
+					cycles: 0,
+				}),
+				lockstep_cond: Condvar::new(),
+			})
+		}
+	}
+
+	pub fn mode(&self) -> ClockMode {
+		self.shared.mode
+	}
+
+	pub fn is_virtual(&self) -> bool {
+		self.shared.mode == ClockMode::Virtual
+	}
+
+	pub fn instructions_per_second(&self) -> u64 {
+		self.shared.rate.lock().instructions_per_second
+	}
+
+	// virtual timers keep their periods in time, and whatever is left of the current one
+	pub fn set_instructions_per_second(&self, instructions_per_second: u64) {
+		let instructions_per_second = instructions_per_second.max(1);
+		let (now, old_rate) = {
+			let mut rate = self.shared.rate.lock();
+			let (cycles, nanos) = self.now(&rate);
+			let old_rate = rate.instructions_per_second;
+			*rate = ClockRate {
+				instructions_per_second,
+				base_cycles: cycles,
+				base_nanos: nanos,
+			};
+			(cycles, old_rate)
+		};
+		let mut timers = self.shared.timers.lock();
+		for timer in timers.iter_mut() {
+			let remaining = timer.next.saturating_sub(now) as u128 * instructions_per_second as u128 / old_rate as u128;
+			timer.period = (timer.period_nanos as u128 * instructions_per_second as u128 / NANOS_PER_SECOND).max(1) as u64;
+			timer.next = now + remaining as u64;
+		}
+	}
+
+	fn now(&self, rate: &ClockRate) -> (u64, u64) {
+		match self.shared.mode {
+			ClockMode::Real => {
+				let nanos = (Instant::now() - self.shared.start).as_nanos() as u64;
+				(rate.nanos_to_cycles(nanos), nanos)
+			},
+			ClockMode::Virtual => {
+				let cycles = self.shared.cycles.load(Ordering::SeqCst);
+				(cycles, rate.cycles_to_nanos(cycles))
+			}
+		}
+	}
+
+	// machine cycles, one per instruction at the nominal rate
+	pub fn cycles(&self) -> u64 {
+		match self.shared.mode {
+			ClockMode::Real => self.now(&self.shared.rate.lock()).0,
+			ClockMode::Virtual => self.shared.cycles.load(Ordering::SeqCst),
+		}
+	}
+
+	pub fn nanos(&self) -> u64 {
+		match self.shared.mode {
+			ClockMode::Real => (Instant::now() - self.shared.start).as_nanos() as u64,
+			ClockMode::Virtual => self.now(&self.shared.rate.lock()).1,
+		}
+	}
+
+	pub fn millis(&self) -> u64 {
+		self.nanos() / 1_000_000
+	}
+
+	// the time a virtual clock will read once it reaches cycles, for harts part way through a period
+	pub fn cycles_to_millis(&self, cycles: u64) -> u64 {
+		self.shared.rate.lock().cycles_to_nanos(cycles) / 1_000_000
+	}
+
+	// moves virtual time forward and runs any virtual timers which came due, on the caller's thread
+	pub fn advance(&self, cycles: u64) {
+		if self.shared.mode != ClockMode::Virtual || cycles == 0 {
+			return;
+		}
+		let now = self.shared.cycles.fetch_add(cycles, Ordering::SeqCst) + cycles;
+		let mut timers = self.shared.timers.lock();
+		for timer in timers.iter_mut() {
+			while timer.next <= now {
+				(timer.callback)();
+				timer.next += timer.period;
+			}
+		}
+	}
+
+	// adds a hart to the lockstep from the start of the next period, or straight away when no others
+	// are running, and returns the period it starts in
+	pub fn join_lockstep(&self) -> u64 {
+		let mut lockstep = self.shared.lockstep.lock();
+		if lockstep.running == 0 {
+			lockstep.running = 1;
+			lockstep.period
+		} else {
+			lockstep.joining += 1;
+			lockstep.period + 1
+		}
+	}
+
+	// waits for a joining hart's first period, and returns false if stop asked it to give up first
+	pub fn wait_for_lockstep(&self, period: u64, stop: impl Fn() -> bool) -> bool {
+		let mut lockstep = self.shared.lockstep.lock();
+		while lockstep.period < period {
+			if stop() {
+				return false;
+			}
+			self.shared.lockstep_cond.wait_for(&mut lockstep, LOCKSTEP_POLL_INTERVAL);
+		}
+		true
+	}
+
+	// a running hart is done with the current period, and waits for the others to finish it too.
+	// every hart asks for a whole period of time, however much of it it spent waiting for an
+	// interrupt. returns false, without having finished the period, if stop asked it to give up.
+	pub fn end_lockstep_period(&self, cycles: u64, stop: impl Fn() -> bool) -> bool {
+		let mut lockstep = self.shared.lockstep.lock();
+		let period = lockstep.period;
+		lockstep.arrived += 1;
+		lockstep.cycles = lockstep.cycles.max(cycles);
+		if lockstep.arrived == lockstep.running {
+			self.finish_lockstep_period(&mut lockstep);
+			return true;
+		}
+		while lockstep.period == period {
+			if stop() {
+				lockstep.arrived -= 1;
+				return false;
+			}
+			self.shared.lockstep_cond.wait_for(&mut lockstep, LOCKSTEP_POLL_INTERVAL);
+		}
+		true
+	}
+
+	// takes a hart out of the lockstep, given the period it was in or about to start
+	pub fn leave_lockstep(&self, period: u64) {
+		let mut lockstep = self.shared.lockstep.lock();
+		if period > lockstep.period {
+			lockstep.joining -= 1;
+			return;
+		}
+		lockstep.running -= 1;
+		let finished = if lockstep.running == 0 {
+			lockstep.joining > 0
+		} else {
+			lockstep.arrived == lockstep.running
+		};
+		if finished {
+			self.finish_lockstep_period(&mut lockstep);
+		}
+	}
+
+	// virtual timers run with every hart held at the boundary, so they all see them at the same point
+	fn finish_lockstep_period(&self, lockstep: &mut Lockstep) {
+		self.advance(lockstep.cycles);
+		lockstep.period += 1;
+		lockstep.running += lockstep.joining;
+		lockstep.joining = 0;
+		lockstep.arrived = 0;
+		lockstep.cycles = 0;
+		self.shared.lockstep_cond.notify_all();
+	}
+
+	// periodic callback driven by virtual time. In real mode it never fires, since the
+	// host devices keep their own pace.
+	pub fn add_virtual_timer(&self, period: Duration, callback: Box<dyn FnMut() + Send>) {
+		if self.shared.mode != ClockMode::Virtual {
+			return;
+		}
+		let period_nanos = period.as_nanos() as u64;
+		let period = (period_nanos as u128 * self.instructions_per_second() as u128 / NANOS_PER_SECOND).max(1) as u64;
+		let mut timers = self.shared.timers.lock();
+		timers.push(VirtualTimer {
+			period_nanos,
+			period,
+			next: self.shared.cycles.load(Ordering::SeqCst) + period,
+			callback,
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::thread;
+
+	#[test]
+	fn lockstep_waits_for_every_running_hart() {
+		let clock = MachineClock::new(ClockMode::Virtual, 1000);
+		assert_eq!(clock.join_lockstep(), 0);
+		assert_eq!(clock.join_lockstep(), 1);
+		// the second hart only counts from the next period, so the first finishes this one alone
+		assert!(clock.end_lockstep_period(100, || false));
+		assert_eq!(clock.cycles(), 100);
+		assert!(clock.wait_for_lockstep(1, || false));
+		let other = {
+			let clock = clock.clone();
+			thread::spawn(move || clock.end_lockstep_period(100, || false))
+		};
+		while clock.shared.lockstep.lock().arrived == 0 {
+			thread::yield_now();
+		}
+		assert_eq!(clock.cycles(), 100);
+		assert!(clock.end_lockstep_period(100, || false));
+		assert!(other.join().unwrap());
+		assert_eq!((clock.cycles(), clock.millis()), (200, 200));
+	}
+
+	#[test]
+	fn lockstep_moves_on_without_harts_which_leave() {
+		let clock = MachineClock::new(ClockMode::Virtual, 1000);
+		let first = clock.join_lockstep();
+		let second = clock.join_lockstep();
+		assert!(clock.end_lockstep_period(100, || false));
+		// a hart giving up doesn't finish the period, and leaving lets the rest carry on
+		assert!(! clock.end_lockstep_period(100, || true));
+		assert_eq!(clock.cycles(), 100);
+		let joining = clock.join_lockstep();
+		clock.leave_lockstep(joining);
+		clock.leave_lockstep(second);
+		assert!(clock.end_lockstep_period(100, || false));
+		assert_eq!(clock.cycles(), 200);
+		clock.leave_lockstep(first + 2);
+		assert_eq!(clock.join_lockstep(), 2);
+	}
+
+	#[test]
+	fn real_clock_ignores_lockstep_time() {
+		let clock = MachineClock::new(ClockMode::Real, 1000);
+		clock.join_lockstep();
+		assert!(clock.end_lockstep_period(1_000_000, || false));
+		assert!(clock.cycles() < 1_000_000);
+	}
+}
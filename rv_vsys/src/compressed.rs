@@ -0,0 +1,405 @@
+use crate::opcode::bitfield;
+use crate::{Op, OpImmFunct3, LoadFunct3, StoreFunct3, BranchFunct3, OpFunct3Funct7};
+
+// RV32C: every compressed instruction is expanded into the equivalent 32 bit
+// instruction, so the interpreter only ever has to decode the base encodings.
+
+const REG_RA: u32 = 1;
+const REG_SP: u32 = 2;
+
+const FP_WIDTH_32: u32 = 0b010;
+const FP_WIDTH_64: u32 = 0b011;
+
+const EBREAK: u32 = 0x0010_0073;
+
+pub fn is_compressed(value: u32) -> bool {
+	(value & 0b11) != 0b11
+}
+
+pub fn instruction_length(value: u32) -> u32 {
+	if is_compressed(value) {
+		2
+	} else {
+		4
+	}
+}
+
+fn sign_extend(value: u32, bits: u32) -> u32 {
+	let shift = 32 - bits;
+	(((value << shift) as i32) >> shift) as u32
+}
+
+// rd', rs1', rs2' - the 3 bit register fields address x8 - x15
+fn creg(value: u32, src_bit: u32) -> u32 {
+	bitfield(value, 3, src_bit, 0) + 8
+}
+
+fn r_type(funct7: u32, rs2: u32, rs1: u32, funct3: u32, rd: u32, op: Op) -> u32 {
+	(funct7 << 25) | (rs2 << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | op.to_raw()
+}
+
+fn i_type(imm: u32, rs1: u32, funct3: u32, rd: u32, op: Op) -> u32 {
+	((imm & 0xFFF) << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | op.to_raw()
+}
+
+fn s_type(imm: u32, rs2: u32, rs1: u32, funct3: u32, op: Op) -> u32 {
+	bitfield(imm, 7, 5, 25) | (rs2 << 20) | (rs1 << 15) | (funct3 << 12) | bitfield(imm, 5, 0, 7) | op.to_raw()
+}
+
+fn b_type(imm: u32, rs2: u32, rs1: u32, funct3: u32) -> u32 {
+	bitfield(imm, 1, 12, 31) |
+	bitfield(imm, 6, 5, 25) |
+	(rs2 << 20) |
+	(rs1 << 15) |
+	(funct3 << 12) |
+	bitfield(imm, 4, 1, 8) |
+	bitfield(imm, 1, 11, 7) |
+	Op::Branch.to_raw()
+}
+
+fn j_type(imm: u32, rd: u32) -> u32 {
+	bitfield(imm, 1, 20, 31) |
+	bitfield(imm, 10, 1, 21) |
+	bitfield(imm, 1, 11, 20) |
+	bitfield(imm, 8, 12, 12) |
+	(rd << 7) |
+	Op::Jal.to_raw()
+}
+
+fn u_type(imm: u32, rd: u32, op: Op) -> u32 {
+	(imm & 0xFFFF_F000) | (rd << 7) | op.to_raw()
+}
+
+fn op_imm(funct3: OpImmFunct3, rd: u32, rs1: u32, imm: u32) -> u32 {
+	i_type(imm, rs1, funct3 as u32, rd, Op::OpImm)
+}
+
+fn op(funct: OpFunct3Funct7, rd: u32, rs1: u32, rs2: u32) -> u32 {
+	r_type(funct.imm7(), rs2, rs1, funct.imm3(), rd, Op::Op)
+}
+
+// CL / CS format offsets
+fn cl_word_offset(value: u32) -> u32 {
+	bitfield(value, 3, 10, 3) |
+	bitfield(value, 1, 6, 2) |
+	bitfield(value, 1, 5, 6)
+}
+
+fn cl_double_offset(value: u32) -> u32 {
+	bitfield(value, 3, 10, 3) |
+	bitfield(value, 2, 5, 6)
+}
+
+// CI format stack pointer relative load offsets
+fn ci_word_sp_offset(value: u32) -> u32 {
+	bitfield(value, 1, 12, 5) |
+	bitfield(value, 3, 4, 2) |
+	bitfield(value, 2, 2, 6)
+}
+
+fn ci_double_sp_offset(value: u32) -> u32 {
+	bitfield(value, 1, 12, 5) |
+	bitfield(value, 2, 5, 3) |
+	bitfield(value, 3, 2, 6)
+}
+
+// CSS format stack pointer relative store offsets
+fn css_word_sp_offset(value: u32) -> u32 {
+	bitfield(value, 4, 9, 2) |
+	bitfield(value, 2, 7, 6)
+}
+
+fn css_double_sp_offset(value: u32) -> u32 {
+	bitfield(value, 3, 10, 3) |
+	bitfield(value, 3, 7, 6)
+}
+
+fn ci_imm(value: u32) -> u32 {
+	sign_extend(bitfield(value, 5, 2, 0) | bitfield(value, 1, 12, 5), 6)
+}
+
+fn cj_offset(value: u32) -> u32 {
+	sign_extend(
+		bitfield(value, 3, 3, 1) |
+		bitfield(value, 1, 11, 4) |
+		bitfield(value, 1, 2, 5) |
+		bitfield(value, 1, 7, 6) |
+		bitfield(value, 1, 6, 7) |
+		bitfield(value, 2, 9, 8) |
+		bitfield(value, 1, 8, 10) |
+		bitfield(value, 1, 12, 11),
+		12
+	)
+}
+
+fn cb_offset(value: u32) -> u32 {
+	sign_extend(
+		bitfield(value, 2, 3, 1) |
+		bitfield(value, 2, 10, 3) |
+		bitfield(value, 1, 2, 5) |
+		bitfield(value, 2, 5, 6) |
+		bitfield(value, 1, 12, 8),
+		9
+	)
+}
+
+// Expands a 16 bit compressed instruction into its 32 bit equivalent.
+// Returns None for illegal and reserved encodings.
+pub fn expand_compressed(value: u32) -> Option<u32> {
+	let value = value & 0xFFFF;
+	let funct3 = bitfield(value, 3, 13, 0);
+	let rd = bitfield(value, 5, 7, 0);
+	let rs2 = bitfield(value, 5, 2, 0);
+	match value & 0b11 {
+		0b00 => {
+			let rd_c = creg(value, 2);
+			let rs1_c = creg(value, 7);
+			match funct3 {
+				// c.addi4spn
+				0b000 => {
+					let imm =
+						bitfield(value, 1, 6, 2) |
+						bitfield(value, 1, 5, 3) |
+						bitfield(value, 2, 11, 4) |
+						bitfield(value, 4, 7, 6);
+					if imm == 0 {
+						return None;
+					}
+					Some(op_imm(OpImmFunct3::AddI, rd_c, REG_SP, imm))
+				},
+				// c.fld
+				0b001 => Some(i_type(cl_double_offset(value), rs1_c, FP_WIDTH_64, rd_c, Op::LoadFp)),
+				// c.lw
+				0b010 => Some(i_type(cl_word_offset(value), rs1_c, LoadFunct3::Word.to_raw(), rd_c, Op::Load)),
+				// c.flw
+				0b011 => Some(i_type(cl_word_offset(value), rs1_c, FP_WIDTH_32, rd_c, Op::LoadFp)),
+				// c.fsd
+				0b101 => Some(s_type(cl_double_offset(value), rd_c, rs1_c, FP_WIDTH_64, Op::StoreFp)),
+				// c.sw
+				0b110 => Some(s_type(cl_word_offset(value), rd_c, rs1_c, StoreFunct3::Word.to_raw(), Op::Store)),
+				// c.fsw
+				0b111 => Some(s_type(cl_word_offset(value), rd_c, rs1_c, FP_WIDTH_32, Op::StoreFp)),
+				_ => None
+			}
+		},
+		0b01 => {
+			match funct3 {
+				// c.addi (c.nop when rd = x0)
+				0b000 => Some(op_imm(OpImmFunct3::AddI, rd, rd, ci_imm(value))),
+				// c.jal
+				0b001 => Some(j_type(cj_offset(value), REG_RA)),
+				// c.li
+				0b010 => Some(op_imm(OpImmFunct3::AddI, rd, 0, ci_imm(value))),
+				0b011 => {
+					if rd == REG_SP {
+						// c.addi16sp
+						let imm = sign_extend(
+							bitfield(value, 1, 6, 4) |
+							bitfield(value, 1, 2, 5) |
+							bitfield(value, 1, 5, 6) |
+							bitfield(value, 2, 3, 7) |
+							bitfield(value, 1, 12, 9),
+							10
+						);
+						if imm == 0 {
+							return None;
+						}
+						Some(op_imm(OpImmFunct3::AddI, REG_SP, REG_SP, imm))
+					} else {
+						// c.lui
+						let imm = ci_imm(value) << 12;
+						if imm == 0 {
+							return None;
+						}
+						Some(u_type(imm, rd, Op::Lui))
+					}
+				},
+				0b100 => {
+					let rd_c = creg(value, 7);
+					let rs2_c = creg(value, 2);
+					match bitfield(value, 2, 10, 0) {
+						// c.srli
+						0b00 => {
+							if (value & 0x1000) != 0 {
+								return None;
+							}
+							Some(op_imm(OpImmFunct3::SrxI, rd_c, rd_c, rs2))
+						},
+						// c.srai
+						0b01 => {
+							if (value & 0x1000) != 0 {
+								return None;
+							}
+							Some(op_imm(OpImmFunct3::SrxI, rd_c, rd_c, rs2 | 0x400))
+						},
+						// c.andi
+						0b10 => Some(op_imm(OpImmFunct3::AndI, rd_c, rd_c, ci_imm(value))),
+						_ => {
+							if (value & 0x1000) != 0 {
+								return None;
+							}
+							match bitfield(value, 2, 5, 0) {
+								0b00 => Some(op(OpFunct3Funct7::Sub, rd_c, rd_c, rs2_c)),
+								0b01 => Some(op(OpFunct3Funct7::Xor, rd_c, rd_c, rs2_c)),
+								0b10 => Some(op(OpFunct3Funct7::Or, rd_c, rd_c, rs2_c)),
+								_ => Some(op(OpFunct3Funct7::And, rd_c, rd_c, rs2_c)),
+							}
+						}
+					}
+				},
+				// c.j
+				0b101 => Some(j_type(cj_offset(value), 0)),
+				// c.beqz
+				0b110 => Some(b_type(cb_offset(value), 0, creg(value, 7), BranchFunct3::Eq.to_raw())),
+				// c.bnez
+				_ => Some(b_type(cb_offset(value), 0, creg(value, 7), BranchFunct3::NEq.to_raw())),
+			}
+		},
+		0b10 => {
+			match funct3 {
+				// c.slli
+				0b000 => {
+					if (value & 0x1000) != 0 {
+						return None;
+					}
+					Some(op_imm(OpImmFunct3::SllI, rd, rd, rs2))
+				},
+				// c.fldsp
+				0b001 => Some(i_type(ci_double_sp_offset(value), REG_SP, FP_WIDTH_64, rd, Op::LoadFp)),
+				// c.lwsp
+				0b010 => {
+					if rd == 0 {
+						return None;
+					}
+					Some(i_type(ci_word_sp_offset(value), REG_SP, LoadFunct3::Word.to_raw(), rd, Op::Load))
+				},
+				// c.flwsp
+				0b011 => Some(i_type(ci_word_sp_offset(value), REG_SP, FP_WIDTH_32, rd, Op::LoadFp)),
+				0b100 => {
+					if (value & 0x1000) == 0 {
+						if rs2 == 0 {
+							// c.jr
+							if rd == 0 {
+								return None;
+							}
+							Some(i_type(0, rd, 0, 0, Op::Jalr))
+						} else {
+							// c.mv
+							Some(op(OpFunct3Funct7::Add, rd, 0, rs2))
+						}
+					} else {
+						if rs2 == 0 {
+							if rd == 0 {
+								// c.ebreak
+								Some(EBREAK)
+							} else {
+								// c.jalr
+								Some(i_type(0, rd, 0, REG_RA, Op::Jalr))
+							}
+						} else {
+							// c.add
+							Some(op(OpFunct3Funct7::Add, rd, rd, rs2))
+						}
+					}
+				},
+				// c.fsdsp
+				0b101 => Some(s_type(css_double_sp_offset(value), rs2, REG_SP, FP_WIDTH_64, Op::StoreFp)),
+				// c.swsp
+				0b110 => Some(s_type(css_word_sp_offset(value), rs2, REG_SP, StoreFunct3::Word.to_raw(), Op::Store)),
+				// c.fswsp
+				_ => Some(s_type(css_word_sp_offset(value), rs2, REG_SP, FP_WIDTH_32, Op::StoreFp)),
+			}
+		},
+		_ => None
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// (compressed, expanded) pairs, as encoded by an independent assembler
+	fn check(pairs: &[(u32, u32)]) {
+		for &(compressed, expanded) in pairs {
+			assert!(is_compressed(compressed));
+			assert_eq!(expand_compressed(compressed), Some(expanded), "expanding {:#06x}", compressed);
+		}
+	}
+
+	#[test]
+	fn quadrant_0_expands() {
+		check(&[
+			(0x0800, 0x0101_0413), // c.addi4spn s0, sp, 16
+			(0x2488, 0x0084_B507), // c.fld fa0, 8(s1)
+			(0x41C8, 0x0045_A503), // c.lw a0, 4(a1)
+			(0x622C, 0x0406_2587), // c.flw fa1, 64(a2)
+			(0xBFE0, 0x0E87_BC27), // c.fsd fs0, 248(a5)
+			(0xDF7C, 0x06F7_2E23), // c.sw a5, 124(a4)
+			(0xE004, 0x0094_2027), // c.fsw fs1, 0(s0)
+		]);
+	}
+
+	#[test]
+	fn quadrant_1_expands() {
+		check(&[
+			(0x0001, 0x0000_0013), // c.nop
+			(0x1501, 0xFE05_0513), // c.addi a0, -32
+			(0x3001, 0x801F_F0EF), // c.jal -2048
+			(0x437D, 0x01F0_0313), // c.li t1, 31
+			(0x7101, 0xE001_0113), // c.addi16sp sp, -512
+			(0x7905, 0xFFFE_1937), // c.lui s2, 0xfffe1
+			(0x817D, 0x01F5_5513), // c.srli a0, 31
+			(0x8785, 0x4017_D793), // c.srai a5, 1
+			(0x98FD, 0xFFF4_F493), // c.andi s1, -1
+			(0x8D0D, 0x40B5_0533), // c.sub a0, a1
+			(0x8C25, 0x0094_4433), // c.xor s0, s1
+			(0x8E55, 0x00D6_6633), // c.or a2, a3
+			(0x8F7D, 0x00F7_7733), // c.and a4, a5
+			(0xAFFD, 0x7FE0_006F), // c.j 2046
+			(0xD001, 0xF004_00E3), // c.beqz s0, -256
+			(0xEFFD, 0x0E07_9F63), // c.bnez a5, 254
+		]);
+	}
+
+	#[test]
+	fn quadrant_2_expands() {
+		check(&[
+			(0x02FE, 0x01F2_9293), // c.slli t0, 31
+			(0x307E, 0x1F81_3007), // c.fldsp ft0, 504(sp)
+			(0x50FE, 0x0FC1_2083), // c.lwsp ra, 252(sp)
+			(0x6092, 0x0041_2087), // c.flwsp ft1, 4(sp)
+			(0x8082, 0x0000_8067), // c.jr ra
+			(0x857E, 0x01F0_0533), // c.mv a0, t6
+			(0x9002, 0x0010_0073), // c.ebreak
+			(0x9282, 0x0002_80E7), // c.jalr t0
+			(0x99D2, 0x0149_89B3), // c.add s3, s4
+			(0xA46E, 0x01B1_3427), // c.fsdsp fs11, 8(sp)
+			(0xC006, 0x0011_2023), // c.swsp ra, 0(sp)
+			(0xFF8A, 0x0E21_2E27), // c.fswsp ft2, 252(sp)
+		]);
+	}
+
+	#[test]
+	fn only_the_low_halfword_is_expanded() {
+		assert_eq!(expand_compressed(0xFFFF_0001), Some(0x0000_0013));
+	}
+
+	#[test]
+	fn illegal_and_reserved_encodings_are_rejected() {
+		for &illegal in &[
+			0x0000, // all zero
+			0x0004, // c.addi4spn with a zero immediate
+			0x8000, // reserved in quadrant 0
+			0x6101, // c.addi16sp with a zero immediate
+			0x6081, // c.lui with a zero immediate
+			0x9105, // c.srli with shamt[5] set
+			0x9505, // c.srai with shamt[5] set
+			0x9D0D, // c.subw, rv64 only
+			0x1296, // c.slli with shamt[5] set
+			0x4002, // c.lwsp into x0
+			0x8002, // c.jr through x0
+		] {
+			assert_eq!(expand_compressed(illegal), None, "expanding {:#06x}", illegal);
+		}
+	}
+}
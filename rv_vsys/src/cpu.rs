@@ -1,10 +1,21 @@
 #![allow(dead_code)]
-use crate::{AtomicFunct7, AtomicSizeFunct3, BranchFunct3, FCvtType, FMvXWClassFunct3, FpCmpFunct3, FpFunct7, FpMinMaxFunct3, FpRm, FpSignFunct3, InterruptBus, LoadFunct3, MTimer, MemIO, MemReadResult, MemWriteResult, Op, OpFunct3Funct7, OpImmFunct3, Opcode, FpFormatFunct3, StoreFunct3, SystemFunct3, SystemIntFunct7};
-use std::{sync::{Arc, atomic::{AtomicBool, Ordering}}, time::{Duration, Instant}};
-use num::Signed;
+use crate::block_cache::BlockCache;
+use crate::trace::{TraceRecorder, TraceRegWrite};
+use crate::clock::{ClockMode, MachineClock, DEFAULT_INSTRUCTIONS_PER_SECOND};
+use crate::pmp::{Pmp, PmpAccess, PMP_ENTRY_COUNT};
+use crate::mmu::{Mmu, PageAccessMode, TranslateError, PAGE_SIZE};
+use crate::hpm::{HpmCounters, HpmEvent, HPM_COUNTER_BASE, HPM_COUNTER_COUNT, HPM_EVENT_COUNT};
+use crate::cpu_state::CpuState;
+use crate::breakpoint::{CpuBreakpointHandle, HartBreakpoints, WatchKind, Watchpoint};
+use crate::replay::{HartReplay, ReplayInput, ReplayStop};
+use crate::semihosting::{Semihosting, SemihostingResult, SEMIHOSTING_ENTRY, SEMIHOSTING_EXIT};
+#[cfg(all(feature = "x86_64_jit", target_arch = "x86_64", unix))]
+use crate::jit_x86_64::JitCache;
+use crate::{expand_compressed, is_compressed, DebugAdapter, DebugStep, AtomicFunct7, AtomicSizeFunct3, BranchFunct3, FCvtType, FMvXWClassFunct3, FpCmpFunct3, FpFormatFunct2, FpFunct7, FpMinMaxFunct3, FpRm, FpSignFunct3, InterruptBus, InterruptLevels, LoadFunct3, MTimer, MemAccessEvent, MemIO, MemReadResult, MemWriteResult, Op, OpFunct3Funct7, OpImmFunct3, OpImmShiftFunct, Opcode, FpFormatFunct3, StoreFunct3, SystemFunct3, SystemIntFunct7};
+use std::{collections::HashSet, num::FpCategory, sync::{Arc, atomic::{AtomicBool, AtomicU64, Ordering}}, time::{Duration, Instant}};
 use parking_lot::{Condvar, Mutex};
 
-const REG_NAMES: [&str; 32] = [
+pub const REG_NAMES: [&str; 32] = [
 	"zero",
 	"ra",
 	"sp",
@@ -74,24 +85,72 @@ const REG_NAMES_PAD: [&str; 32] = [
 	"t6  ",
 ];
 
+const MSTATUS_SIE: u32 = 1 << 1;
 const MSTATUS_MIE: u32 = 1 << 3;
+const MSTATUS_SPIE: u32 = 1 << 5;
 const MSTATUS_MPIE: u32 = 1 << 7;
-const MSTATUS_MPP: u32 = 3 << 11;
+const MSTATUS_SPP: u32 = 1 << 8;
+const MSTATUS_MPP_SHIFT: u32 = 11;
+const MSTATUS_MPP: u32 = 3 << MSTATUS_MPP_SHIFT;
 const MSTATUS_FS_MASK: u32 = 3 << 13;
 const MSTATUS_FS_OFF: u32 = 0b00 << 13;
 const MSTATUS_FS_INITIAL: u32 = 0b01 << 13;
 const MSTATUS_FS_CLEAN: u32 = 0b10 << 13;
 const MSTATUS_FS_DIRTY: u32 = 0b11 << 13;
+const MSTATUS_MPRV: u32 = 1 << 17;
+const MSTATUS_SUM: u32 = 1 << 18;
+const MSTATUS_MXR: u32 = 1 << 19;
+const MSTATUS_TVM: u32 = 1 << 20;
+const MSTATUS_TW: u32 = 1 << 21;
+const MSTATUS_TSR: u32 = 1 << 22;
 const MSTATUS_SD: u32 = 1 << 31;
 
+// the parts of mstatus which sstatus shows
+const SSTATUS_MASK: u32 = MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP | MSTATUS_FS_MASK | MSTATUS_SUM | MSTATUS_MXR | MSTATUS_SD;
+
+const MIE_SSIE: u32 = 1 << 1;
 const MIE_MSIE: u32 = 1 << 3;
+const MIE_STIE: u32 = 1 << 5;
 const MIE_MTIE: u32 = 1 << 7;
+const MIE_SEIE: u32 = 1 << 9;
 const MIE_MEIE: u32 = 1 << 11;
 
+const MIP_SSIP: u32 = 1 << 1;
 const MIP_MSIP: u32 = 1 << 3;
+const MIP_STIP: u32 = 1 << 5;
 const MIP_MTIP: u32 = 1 << 7;
+const MIP_SEIP: u32 = 1 << 9;
 const MIP_MEIP: u32 = 1 << 11;
 
+// only the supervisor interrupts can be delegated, and every exception but an ecall from machine mode
+const MIDELEG_MASK: u32 = MIP_SSIP | MIP_STIP | MIP_SEIP;
+const MEDELEG_MASK: u32 = 0xB3FF;
+
+// interrupt causes from the highest priority down
+const INTERRUPT_PRIORITY: [u32; 6] = [11, 3, 7, 9, 1, 5];
+
+const ECALL: u32 = 0x0000_0073;
+const EBREAK: u32 = 0x0010_0073;
+const SRET: u32 = 0x1020_0073;
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+enum Privilege {
+	User = 0,
+	Supervisor = 1,
+	Machine = 3,
+}
+
+impl Privilege {
+	fn from_raw(raw: u32) -> Option<Self> {
+		match raw {
+			0 => Some(Privilege::User),
+			1 => Some(Privilege::Supervisor),
+			3 => Some(Privilege::Machine),
+			_ => None
+		}
+	}
+}
+
 #[derive(Clone, Copy, Debug)]
 enum Exception {
 	InstructionMisaligned(u32),
@@ -102,7 +161,54 @@ enum Exception {
 	LoadAccessFault{instr_addr: u32, load_addr: u32},
 	StoreAddressMisaligned{instr_addr: u32, store_addr: u32},
 	StoreAccessFault{instr_addr: u32, store_addr: u32},
-	ECall(u32),
+	ECallFromUser(u32),
+	ECallFromSupervisor(u32),
+	ECallFromMachine(u32),
+	InstructionPageFault{instr_addr: u32, fault_addr: u32},
+	LoadPageFault{instr_addr: u32, load_addr: u32},
+	StorePageFault{instr_addr: u32, store_addr: u32},
+}
+
+impl Exception {
+	// (cause, tval, epc)
+	fn to_trap(self) -> (u32, u32, u32) {
+		match self {
+			Exception::InstructionMisaligned(pc) => (0, pc, pc),
+			Exception::InstructionAccessFault(pc) => (1, pc, pc),
+			Exception::IllegalInstruction{op, addr} => (2, op, addr),
+			Exception::Breakpoint(pc) => (3, pc, pc),
+			Exception::LoadAddressMisaligned{instr_addr, load_addr} => (4, load_addr, instr_addr),
+			Exception::LoadAccessFault{instr_addr, load_addr} => (5, load_addr, instr_addr),
+			Exception::StoreAddressMisaligned{instr_addr, store_addr} => (6, store_addr, instr_addr),
+			Exception::StoreAccessFault{instr_addr, store_addr} => (7, store_addr, instr_addr),
+			Exception::ECallFromUser(pc) => (8, 0, pc),
+			Exception::ECallFromSupervisor(pc) => (9, 0, pc),
+			Exception::ECallFromMachine(pc) => (11, 0, pc),
+			Exception::InstructionPageFault{instr_addr, fault_addr} => (12, fault_addr, instr_addr),
+			Exception::LoadPageFault{instr_addr, load_addr} => (13, load_addr, instr_addr),
+			Exception::StorePageFault{instr_addr, store_addr} => (15, store_addr, instr_addr),
+		}
+	}
+	
+	fn from_trap(cause: u32, tval: u32, epc: u32) -> Option<Self> {
+		match cause {
+			0 => Some(Exception::InstructionMisaligned(epc)),
+			1 => Some(Exception::InstructionAccessFault(epc)),
+			2 => Some(Exception::IllegalInstruction{op: tval, addr: epc}),
+			3 => Some(Exception::Breakpoint(epc)),
+			4 => Some(Exception::LoadAddressMisaligned{instr_addr: epc, load_addr: tval}),
+			5 => Some(Exception::LoadAccessFault{instr_addr: epc, load_addr: tval}),
+			6 => Some(Exception::StoreAddressMisaligned{instr_addr: epc, store_addr: tval}),
+			7 => Some(Exception::StoreAccessFault{instr_addr: epc, store_addr: tval}),
+			8 => Some(Exception::ECallFromUser(epc)),
+			9 => Some(Exception::ECallFromSupervisor(epc)),
+			11 => Some(Exception::ECallFromMachine(epc)),
+			12 => Some(Exception::InstructionPageFault{instr_addr: epc, fault_addr: tval}),
+			13 => Some(Exception::LoadPageFault{instr_addr: epc, load_addr: tval}),
+			15 => Some(Exception::StorePageFault{instr_addr: epc, store_addr: tval}),
+			_ => None
+		}
+	}
 }
 
 enum InterruptType {
@@ -123,6 +229,17 @@ struct TrapCSRs {
 	mcause: u32,
 	mtval: u32,
 	mip: u32,
+	
+	medeleg: u32,
+	mideleg: u32,
+	
+	// sstatus, sie and sip are views of the machine registers
+	stvec: u32,
+	scounteren: u32,
+	sscratch: u32,
+	sepc: u32,
+	scause: u32,
+	stval: u32,
 }
 
 impl TrapCSRs {
@@ -137,6 +254,16 @@ impl TrapCSRs {
 			mcause: 0,
 			mtval: 0,
 			mip: 0,
+			
+			medeleg: 0,
+			mideleg: 0,
+			
+			stvec: 0,
+			scounteren: 0,
+			sscratch: 0,
+			sepc: 0,
+			scause: 0,
+			stval: 0,
 		}
 	}
 	
@@ -145,12 +272,21 @@ impl TrapCSRs {
 	}
 }
 
+const FENCE_I_FUNCT3: u32 = 0b001;
+
 const FFLAG_INEXACT: u32 = 0x01;
 const FFLAG_UNDERFLOW: u32 = 0x02;
 const FFLAG_OVERFLOW: u32 = 0x04;
 const FFLAG_DIVIDE_BY_ZERO: u32 = 0x08;
 const FFLAG_INVALID: u32 = 0x10;
 
+const FP_NAN_BOX: u64 = 0xFFFF_FFFF_0000_0000;
+const FP_CANONICAL_NAN_32: u32 = 0x7FC0_0000;
+
+// a hart which falls behind runs at most this many periods back to back to make up for it
+const MAX_CATCH_UP_PERIODS: u32 = 8;
+const SPEED_MEASURE_INTERVAL: Duration = Duration::from_secs(1);
+
 struct PendingInt {
 	pc: u32,
 	cause: u32,
@@ -160,17 +296,25 @@ struct PendingInt {
 
 pub struct Cpu <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus> {
 	xr: [u32; 31],
-	fr: [f32; 32],
+	fr: [u64; 32],
 	pc: u32,
 	pub mio: MIO,
 	pub int_bus: IntBus,
 	csr_instrret: u64,
-	csr_cycle: u64,
-	csr_time_start: Instant,
+	period_start_instret: u64,
+	period_instructions: u64,
+	clock: MachineClock,
+	lockstep_period: Option<u64>,
 	hart_id: u32,
 	trap_csrs: TrapCSRs,
+	privilege: Privilege,
+	pmp: Pmp,
+	mmu: Mmu,
+	hpm: HpmCounters,
 	pending_exception: Option<Exception>,
 	waiting_for_interrupt: bool,
+	// stopped by a semihosting exit, which only a reset or a loaded state undoes
+	halted: bool,
 	wakeup_handle: CpuWakeupHandle,
 	fcsr: u32,
 	lr_write_cycle: usize,
@@ -178,6 +322,53 @@ pub struct Cpu <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus> {
 	timer: Arc<Timer>,
 	live: Arc<AtomicBool>,
 	kill_handle: CpuKillHandle,
+	speed_handle: CpuSpeedHandle,
+	debug_adapter: Option<Box<dyn DebugAdapter<Timer, MIO, IntBus> + Send>>,
+	block_cache: BlockCache,
+	#[cfg(all(feature = "x86_64_jit", target_arch = "x86_64", unix))]
+	jit: Option<JitCache>,
+	single_step: bool,
+	trace: Option<TraceRecorder>,
+	breakpoints: HartBreakpoints,
+	replay: Option<HartReplay>,
+	last_mem_access: Option<(u32, u32, WatchKind)>,
+	emulate_misaligned: bool,
+	semihosting: Option<Semihosting>,
+}
+
+// fclass result bit for either format
+fn fp_class(category: FpCategory, negative: bool, quiet: bool) -> u32 {
+	1 << match (category, negative) {
+		(FpCategory::Infinite, true) => 0,
+		(FpCategory::Normal, true) => 1,
+		(FpCategory::Subnormal, true) => 2,
+		(FpCategory::Zero, true) => 3,
+		(FpCategory::Zero, false) => 4,
+		(FpCategory::Subnormal, false) => 5,
+		(FpCategory::Normal, false) => 6,
+		(FpCategory::Infinite, false) => 7,
+		(FpCategory::Nan, _) => if quiet { 9 } else { 8 },
+	}
+}
+
+// fcvt.w and fcvt.wu results and flags: NaN and anything out of range after rounding saturate and
+// raise invalid, and a value which had to be rounded raises inexact
+fn fp_to_integer(value: f64, rounded: f64, signed: bool) -> (u32, u32) {
+	let (min, max, nan_result) = if signed {
+		(i32::MIN as f64, i32::MAX as f64, i32::MAX as u32)
+	} else {
+		(0.0, u32::MAX as f64, u32::MAX)
+	};
+	if value.is_nan() {
+		(nan_result, FFLAG_INVALID)
+	} else if rounded < min {
+		(if signed { i32::MIN as u32 } else { 0 }, FFLAG_INVALID)
+	} else if rounded > max {
+		(if signed { i32::MAX as u32 } else { u32::MAX }, FFLAG_INVALID)
+	} else {
+		let result = if signed { rounded as i32 as u32 } else { rounded as u32 };
+		(result, if rounded != value { FFLAG_INEXACT } else { 0 })
+	}
 }
 
 #[derive(Debug, Clone)]
@@ -256,6 +447,33 @@ impl CpuKillHandle {
 	}
 }
 
+// the instruction rate a hart actually achieved, measured by run_loop about once a second
+#[derive(Debug, Clone)]
+pub struct CpuSpeedHandle {
+	instructions_per_second: Arc<AtomicU64>,
+}
+
+impl CpuSpeedHandle {
+	fn new() -> Self {
+		CpuSpeedHandle {
+			instructions_per_second: Arc::new(AtomicU64::new(0)),
+		}
+	}
+	
+	pub fn effective_instructions_per_second(&self) -> u64 {
+		self.instructions_per_second.load(Ordering::SeqCst)
+	}
+	
+	pub fn effective_mips(&self) -> f64 {
+		self.effective_instructions_per_second() as f64 / 1_000_000.0
+	}
+	
+	fn record(&self, retired: u64, elapsed: Duration) {
+		let nanos = elapsed.as_nanos().max(1);
+		self.instructions_per_second.store((retired as u128 * 1_000_000_000 / nanos) as u64, Ordering::SeqCst);
+	}
+}
+
 impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, IntBus> {
 	pub fn new(mut mio: MIO, int_bus: IntBus, wakeup_handle: CpuWakeupHandle, id: u32) -> Self {
 		mio.set_hart_id(id);
@@ -263,24 +481,43 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 		let live = Arc::new(AtomicBool::new(false));
 		Cpu {
 			xr: [0; 31],
-			fr: [0f32; 32],
+			fr: [0; 32],
 			pc: 0,
 			mio: mio,
 			int_bus: int_bus,
 			csr_instrret: 0,
-			csr_cycle: 0,
-			csr_time_start: Instant::now(),
+			period_start_instret: 0,
+			period_instructions: u64::MAX,
+			clock: MachineClock::new(ClockMode::Real, DEFAULT_INSTRUCTIONS_PER_SECOND),
+			lockstep_period: None,
 			hart_id: id,
 			trap_csrs: TrapCSRs::new(),
+			privilege: Privilege::Machine,
+			pmp: Pmp::new(),
+			mmu: Mmu::new(),
+			hpm: HpmCounters::new(),
 			pending_exception: None,
 			waiting_for_interrupt: false,
+			halted: false,
 			wakeup_handle: wakeup_handle,
 			fcsr: 0,
 			lr_write_cycle: 0,
 			lr_write_key: 0xFFFF_FFFF,
 			timer,
 			live: live.clone(),
-			kill_handle: CpuKillHandle::new(live)
+			kill_handle: CpuKillHandle::new(live),
+			speed_handle: CpuSpeedHandle::new(),
+			debug_adapter: None,
+			block_cache: BlockCache::new(),
+			#[cfg(all(feature = "x86_64_jit", target_arch = "x86_64", unix))]
+			jit: JitCache::new(),
+			single_step: false,
+			trace: None,
+			breakpoints: HartBreakpoints::new(),
+			replay: None,
+			last_mem_access: None,
+			emulate_misaligned: false,
+			semihosting: None,
 		}
 	}
 
@@ -290,20 +527,238 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 			self.xr[i] = 0;
 		}
 		for i in 0 .. 32 {
-			self.fr[i] = 0f32;
+			self.fr[i] = 0;
 		}
 		self.pc = pc;
 		self.trap_csrs.reset();
+		self.privilege = Privilege::Machine;
+		self.pmp.reset();
+		self.mmu.reset();
+		self.hpm.reset();
 		self.pending_exception = None;
 		self.waiting_for_interrupt = false;
+		self.halted = false;
 		self.lr_write_cycle = 0;
 		self.lr_write_key = 0xFFFF_FFFF;
+		self.flush_code_caches();
+		self.discard_replay_history();
+	}
+	
+	fn flush_code_caches(&mut self) {
+		self.block_cache.flush();
+		#[cfg(all(feature = "x86_64_jit", target_arch = "x86_64", unix))]
+		{
+			if let Some(jit) = &mut self.jit {
+				jit.flush();
+			}
+		}
+	}
+	
+	// leaves every block to the interpreter, which the translated code is checked against
+	#[cfg(all(test, feature = "x86_64_jit", target_arch = "x86_64", unix))]
+	pub(crate) fn disable_jit(&mut self) {
+		self.jit = None;
 	}
 	
 	pub fn get_kill_handle(&self) -> CpuKillHandle {
 		self.kill_handle.clone()
 	}
 	
+	pub fn get_speed_handle(&self) -> CpuSpeedHandle {
+		self.speed_handle.clone()
+	}
+	
+	pub fn get_breakpoint_handle(&self) -> CpuBreakpointHandle {
+		self.breakpoints.handle()
+	}
+	
+	pub fn set_debug_adapter(&mut self, adapter: Option<Box<dyn DebugAdapter<Timer, MIO, IntBus> + Send>>) {
+		self.debug_adapter = adapter;
+	}
+	
+	// the running harts advance a virtual clock together as they retire instructions, or hart 0 on its
+	// own while a debugger is attached
+	pub fn set_clock(&mut self, clock: MachineClock) {
+		self.clock = clock;
+	}
+	
+	// has a hart about to be started join a virtual clock's lockstep now, so it starts at a point in the
+	// starting hart's instructions rather than whenever its thread gets going
+	pub fn join_clock_lockstep(&mut self) {
+		if self.clock.is_virtual() && self.lockstep_period.is_none() {
+			self.lockstep_period = Some(self.clock.join_lockstep());
+		}
+	}
+	
+	pub fn set_trace_recorder(&mut self, recorder: Option<TraceRecorder>) {
+		self.trace = recorder;
+	}
+	
+	// splits loads and stores the bus rejects as misaligned into byte accesses, instead of trapping
+	pub fn set_misaligned_emulation(&mut self, emulate: bool) {
+		self.emulate_misaligned = emulate;
+	}
+	
+	// services semihosting calls from machine mode on the host, instead of taking them as breakpoints
+	pub fn set_semihosting(&mut self, semihosting: Option<Semihosting>) {
+		self.semihosting = semihosting;
+	}
+	
+	// keeps enough of the hart's history to step at least history steps back, see replay.rs
+	pub fn enable_replay(&mut self, history: u64) {
+		self.replay = Some(HartReplay::new(history));
+	}
+	
+	// true while the hart is behind where it was recorded up to, and running from the replay log
+	pub fn is_replaying(&self) -> bool {
+		self.replay.as_ref().is_some_and(|replay| replay.is_replaying())
+	}
+	
+	// for state changed from outside of the hart, which the history before it can't replay over
+	pub fn discard_replay_history(&mut self) {
+		if let Some(replay) = &mut self.replay {
+			replay.restart();
+		}
+	}
+	
+	pub fn get_hart_id(&self) -> u32 {
+		self.hart_id
+	}
+	
+	pub fn get_pc(&self) -> u32 {
+		self.pc
+	}
+	
+	pub fn set_pc(&mut self, pc: u32) {
+		self.pc = pc;
+	}
+	
+	// only consistent between steps, so from a debug adapter or while the run loop isn't running
+	pub fn save_state(&self) -> CpuState {
+		let mut gpr = [0; 32];
+		gpr[1 ..].copy_from_slice(&self.xr);
+		let mut pmp_cfg = [0; PMP_ENTRY_COUNT / 4];
+		for (reg, cfg) in pmp_cfg.iter_mut().enumerate() {
+			*cfg = self.pmp.read_cfg(reg);
+		}
+		let mut pmp_addr = [0; PMP_ENTRY_COUNT];
+		for (index, addr) in pmp_addr.iter_mut().enumerate() {
+			*addr = self.pmp.read_addr(index);
+		}
+		let tallies = self.hpm_tallies();
+		let mut hpm_events = [0; HPM_COUNTER_COUNT];
+		let mut hpm_counters = [0; HPM_COUNTER_COUNT];
+		for index in 0 .. HPM_COUNTER_COUNT {
+			hpm_events[index] = self.hpm.event(index);
+			hpm_counters[index] = self.hpm.read(index, &tallies);
+		}
+		CpuState {
+			gpr,
+			fpr: self.fr,
+			pc: self.pc,
+			privilege: self.privilege as u32,
+			mstatus: self.trap_csrs.mstatus,
+			mie: self.trap_csrs.mie,
+			mtvec: self.trap_csrs.mtvec,
+			mscratch: self.trap_csrs.mscratch,
+			mepc: self.trap_csrs.mepc,
+			mcause: self.trap_csrs.mcause,
+			mtval: self.trap_csrs.mtval,
+			mip: self.trap_csrs.mip,
+			fcsr: self.fcsr,
+			instret: self.csr_instrret,
+			pmp_cfg,
+			pmp_addr,
+			hpm_events,
+			hpm_counters,
+			hpm_inhibit: self.hpm.inhibit(),
+			counter_enable: self.hpm.counter_enable(),
+			waiting_for_interrupt: self.waiting_for_interrupt,
+			pending_exception: self.pending_exception.map(|exception| exception.to_trap()),
+			lr_write_cycle: self.lr_write_cycle as u64,
+			lr_write_key: self.lr_write_key,
+			medeleg: self.trap_csrs.medeleg,
+			mideleg: self.trap_csrs.mideleg,
+			stvec: self.trap_csrs.stvec,
+			scounteren: self.trap_csrs.scounteren,
+			sscratch: self.trap_csrs.sscratch,
+			sepc: self.trap_csrs.sepc,
+			scause: self.trap_csrs.scause,
+			stval: self.trap_csrs.stval,
+			satp: self.mmu.read_satp(),
+		}
+	}
+	
+	pub fn load_state(&mut self, state: &CpuState) -> Result<(), String> {
+		self.restore_state(state)?;
+		self.discard_replay_history();
+		Ok(())
+	}
+	
+	fn restore_state(&mut self, state: &CpuState) -> Result<(), String> {
+		let privilege = match Privilege::from_raw(state.privilege) {
+			Some(privilege) => privilege,
+			None => return Err(format!("unsupported privilege level {}", state.privilege))
+		};
+		let pending_exception = match state.pending_exception {
+			Some((cause, tval, epc)) => match Exception::from_trap(cause, tval, epc) {
+				Some(exception) => Some(exception),
+				None => return Err(format!("unsupported pending exception cause {}", cause))
+			},
+			None => None
+		};
+		self.step_break();
+		self.xr.copy_from_slice(&state.gpr[1 ..]);
+		self.fr = state.fpr;
+		self.pc = state.pc;
+		self.privilege = privilege;
+		self.trap_csrs.mstatus = state.mstatus;
+		self.trap_csrs.mie = state.mie;
+		self.trap_csrs.mtvec = state.mtvec;
+		self.trap_csrs.mscratch = state.mscratch;
+		self.trap_csrs.mepc = state.mepc;
+		self.trap_csrs.mcause = state.mcause;
+		self.trap_csrs.mtval = state.mtval;
+		self.trap_csrs.mip = state.mip;
+		self.trap_csrs.medeleg = state.medeleg;
+		self.trap_csrs.mideleg = state.mideleg;
+		self.trap_csrs.stvec = state.stvec;
+		self.trap_csrs.scounteren = state.scounteren;
+		self.trap_csrs.sscratch = state.sscratch;
+		self.trap_csrs.sepc = state.sepc;
+		self.trap_csrs.scause = state.scause;
+		self.trap_csrs.stval = state.stval;
+		self.mmu.write_satp(state.satp);
+		self.fcsr = state.fcsr;
+		// keep what's been retired since the clock last advanced, so time doesn't jump
+		let unclocked = self.csr_instrret - self.period_start_instret;
+		self.csr_instrret = state.instret;
+		self.period_start_instret = state.instret.saturating_sub(unclocked);
+		// addresses before configs, since a locked config also locks its address
+		self.pmp.reset();
+		for index in 0 .. PMP_ENTRY_COUNT {
+			self.pmp.write_addr(index, state.pmp_addr[index]);
+		}
+		for reg in 0 .. state.pmp_cfg.len() {
+			self.pmp.write_cfg(reg, state.pmp_cfg[reg]);
+		}
+		let tallies = self.hpm_tallies();
+		self.hpm.write_inhibit(0, &tallies);
+		for index in 0 .. HPM_COUNTER_COUNT {
+			self.hpm.write_event(index, state.hpm_events[index], &tallies);
+			self.hpm.write(index, state.hpm_counters[index], &tallies);
+		}
+		self.hpm.write_inhibit(state.hpm_inhibit, &tallies);
+		self.hpm.write_counter_enable(state.counter_enable);
+		self.waiting_for_interrupt = state.waiting_for_interrupt;
+		self.halted = false;
+		self.pending_exception = pending_exception;
+		self.lr_write_cycle = state.lr_write_cycle as usize;
+		self.lr_write_key = state.lr_write_key;
+		self.flush_code_caches();
+		Ok(())
+	}
+	
 	pub fn check_timer(&mut self) {
 		if self.timer.check_timer() {
 			self.trap_csrs.mip |= MIP_MTIP;
@@ -312,129 +767,565 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 		}
 	}
 	
-	pub fn run_loop(&mut self, inst_per_period: u32, period_length: Duration) {
+	// runs the hart in periods of period_length, each with as many instructions as the clock speed allows.
+	// waits for the end of each period, or catches up with a bounded burst of periods when behind.
+	pub fn run_loop(&mut self, period_length: Duration) {
 		self.kill_handle.cpu_set_live();
-		let mut loop_t = Instant::now();
-		loop {
-			self.check_timer();
-			if self.int_bus.poll_interrupts(self.hart_id) {
-				self.signal_external_interrupt();
+		let mut debug_adapter = self.debug_adapter.take();
+		// the debugger has to see every instruction, so don't run native blocks
+		self.single_step = debug_adapter.is_some();
+		if let Some(adapter) = &mut debug_adapter {
+			adapter.debug_begin(self.hart_id);
+		}
+		// harts parked by a debugger can't be held to the others' periods
+		if self.clock.is_virtual() && debug_adapter.is_none() {
+			self.join_clock_lockstep();
+		}
+		if let Some(period) = self.lockstep_period {
+			let kill_handle = &self.kill_handle;
+			if debug_adapter.is_some() || ! self.clock.wait_for_lockstep(period, || kill_handle.is_kill_requested()) {
+				self.clock.leave_lockstep(period);
+				self.lockstep_period = None;
 			}
-			'period_loop: for _ in 0 .. inst_per_period {
-				if ! self.step() {
-					break 'period_loop;
+		}
+		let mut period_deadline = Instant::now() + period_length;
+		let mut speed_window_start = Instant::now();
+		let mut speed_window_retired = 0;
+		loop {
+			let inst_per_period = (self.clock.instructions_per_second() as u128 * period_length.as_nanos() / 1_000_000_000).max(1) as u64;
+			// while replaying, interrupts come from the log instead
+			if ! self.is_replaying() {
+				let mip = self.trap_csrs.mip;
+				self.check_timer();
+				match self.int_bus.poll_interrupt_levels(self.hart_id) {
+					Some(levels) => self.set_interrupt_levels(levels),
+					None => if self.int_bus.poll_interrupts(self.hart_id) {
+						self.signal_external_interrupt();
+					}
+				}
+				if self.trap_csrs.mip != mip {
+					if let Some(replay) = &mut self.replay {
+						replay.record_interrupt(self.trap_csrs.mip);
+					}
 				}
 			}
-			if cfg!(feature = "cpu_debug") { 
-				if let Some(exception) = self.pending_exception {
-					print!("exception @{:#010x}: {:?}", self.pc, exception);
+			let mut debug_kill = false;
+			self.breakpoints.sync();
+			let check_breakpoints = self.breakpoints.is_active();
+			let mut breakpoint_hit = self.breakpoints.take_pause_request(self.hart_id, self.pc);
+			self.period_start_instret = self.csr_instrret;
+			self.period_instructions = inst_per_period;
+			let period_first_instret = self.csr_instrret;
+			let period_end_instret = self.csr_instrret + inst_per_period;
+			'period_loop: while breakpoint_hit.is_none() && self.csr_instrret < period_end_instret {
+				if let Some(adapter) = &mut debug_adapter {
+					match adapter.debug_step_poll(self) {
+						DebugStep::Continue => {},
+						DebugStep::Pause => break 'period_loop,
+						DebugStep::Kill => {
+							debug_kill = true;
+							break 'period_loop;
+						}
+					}
+				}
+				if check_breakpoints {
+					breakpoint_hit = self.breakpoints.check_pc(self.hart_id, self.pc);
+					if breakpoint_hit.is_some() {
+						break 'period_loop;
+					}
+				}
+				let running = self.step();
+				if check_breakpoints {
+					breakpoint_hit = self.breakpoints.take_hit();
+				}
+				if ! running {
+					break 'period_loop;
 				}
 			}
+			speed_window_retired += self.csr_instrret.wrapping_sub(period_first_instret);
 			self.step_break();
-			if self.kill_handle.is_kill_requested() {
+			self.advance_clock(inst_per_period);
+			if let Some(trace) = &mut self.trace {
+				trace.flush();
+			}
+			if let Some(hit) = breakpoint_hit {
+				self.breakpoints.stop(hit, &self.kill_handle);
+			}
+			if debug_kill || self.kill_handle.is_kill_requested() {
+				if let Some(adapter) = &mut debug_adapter {
+					adapter.debug_end(self.hart_id);
+				}
+				self.debug_adapter = debug_adapter;
+				if let Some(period) = self.lockstep_period.take() {
+					self.clock.leave_lockstep(period);
+				}
+				self.period_instructions = u64::MAX;
+				self.speed_handle.record(0, period_length);
 				self.kill_handle.cpu_broadcast_dead();
 				return;
 			}
-			let current_t = Instant::now();
-			let period_time_elapsed = current_t - loop_t;
-			if period_time_elapsed < period_length {
-				self.wakeup_handle.cpu_wait(Instant::now() + (period_length - period_time_elapsed));
+			if Instant::now() < period_deadline {
+				self.wakeup_handle.cpu_wait(period_deadline);
+			}
+			let now = Instant::now();
+			let catch_up_limit = period_length * MAX_CATCH_UP_PERIODS;
+			period_deadline = if now < period_deadline {
+				// woken early by an interrupt, which doesn't earn the hart extra time later
+				now
+			} else if now - period_deadline > catch_up_limit {
+				// too far behind (a host stall, or stopped in the debugger), so the rest is dropped
+				now - catch_up_limit
+			} else {
+				period_deadline
+			} + period_length;
+			if now - speed_window_start >= SPEED_MEASURE_INTERVAL {
+				self.speed_handle.record(speed_window_retired, now - speed_window_start);
+				speed_window_start = now;
+				speed_window_retired = 0;
 			}
-			loop_t = Instant::now();
 		}
 	}
 	
-	fn get_trap_vector_addr(&self, vector: u32) -> u32 {
-		match self.trap_csrs.mtvec & 3 {
-			0 => self.trap_csrs.mtvec,
-			1 => (self.trap_csrs.mtvec & !3u32) + 4 * vector,
-			_ => panic!("unimplemented mtvec!")
+	fn advance_clock(&mut self, inst_per_period: u64) {
+		if let Some(period) = self.lockstep_period {
+			let kill_handle = &self.kill_handle;
+			if self.clock.end_lockstep_period(inst_per_period, || kill_handle.is_kill_requested()) {
+				self.lockstep_period = Some(period + 1);
+			}
+			self.period_start_instret = self.csr_instrret;
+			return;
 		}
+		if self.hart_id != 0 {
+			return;
+		}
+		// replayed instructions were clocked when they first ran
+		if self.is_replaying() {
+			self.period_start_instret = self.csr_instrret;
+			return;
+		}
+		let retired = self.csr_instrret - self.period_start_instret;
+		// a hart waiting for an interrupt idles through the rest of its period
+		let cycles = if self.waiting_for_interrupt || self.halted {
+			retired.max(inst_per_period)
+		} else {
+			retired
+		};
+		self.period_start_instret = self.csr_instrret;
+		self.clock.advance(cycles);
 	}
 	
-	fn handle_interrupts(&mut self, ) {
-		if let Some(exception) = self.pending_exception {
-			let (cause, tval, pc) = match exception {
-				Exception::InstructionMisaligned(pc) => (0, pc, pc),
-				Exception::InstructionAccessFault(pc) => (1, pc, pc),
-				Exception::IllegalInstruction{op, addr} => (2, op, addr),
-				Exception::Breakpoint(pc) => (3, pc, pc),
-				Exception::LoadAddressMisaligned{instr_addr, load_addr} => (4, load_addr, instr_addr),
-				Exception::LoadAccessFault{instr_addr, load_addr} => (5, load_addr, instr_addr),
-				Exception::StoreAddressMisaligned{instr_addr, store_addr} => (6, store_addr, instr_addr),
-				Exception::StoreAccessFault{instr_addr, store_addr} => (7, store_addr, instr_addr),
-				Exception::ECall(pc) => (11, 0, pc),
-			};
-			self.trap_csrs.mepc = pc;
+	fn get_trap_vector_addr(tvec: u32, vector: u32) -> u32 {
+		// the reserved modes can only come from a loaded state, and are taken as direct
+		match tvec & 3 {
+			1 => (tvec & !3u32) + 4 * vector,
+			_ => tvec & !3u32
+		}
+	}
+	
+	// traps below machine mode go to supervisor mode instead when their cause is delegated
+	fn take_trap(&mut self, cause: u32, tval: u32, epc: u32, vector: u32) {
+		let delegation = if cause & 0x8000_0000 != 0 { self.trap_csrs.mideleg } else { self.trap_csrs.medeleg };
+		self.trace_trap(cause, epc, tval);
+		if self.privilege < Privilege::Machine && delegation & (1 << (cause & 0x1F)) != 0 {
+			self.trap_csrs.sepc = epc;
+			self.trap_csrs.scause = cause;
+			self.trap_csrs.stval = tval;
+			self.pc = Self::get_trap_vector_addr(self.trap_csrs.stvec, vector);
+			let ie_before = (self.trap_csrs.mstatus & MSTATUS_SIE) != 0;
+			self.trap_csrs.mstatus &= !(MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP);
+			self.trap_csrs.mstatus |= if ie_before {MSTATUS_SPIE} else {0};
+			self.trap_csrs.mstatus |= if self.privilege == Privilege::Supervisor {MSTATUS_SPP} else {0};
+			self.privilege = Privilege::Supervisor;
+		} else {
+			self.trap_csrs.mepc = epc;
 			self.trap_csrs.mcause = cause;
 			self.trap_csrs.mtval = tval;
-			self.pc = self.get_trap_vector_addr(0);
+			self.pc = Self::get_trap_vector_addr(self.trap_csrs.mtvec, vector);
 			let ie_before = (self.trap_csrs.mstatus & MSTATUS_MIE) != 0;
-			self.trap_csrs.mstatus &= !(MSTATUS_MIE | MSTATUS_MPIE);
+			self.trap_csrs.mstatus &= !(MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP);
 			self.trap_csrs.mstatus |= if ie_before {MSTATUS_MPIE} else {0};
+			self.trap_csrs.mstatus |= (self.privilege as u32) << MSTATUS_MPP_SHIFT;
+			self.privilege = Privilege::Machine;
+		}
+		self.hpm.count(HpmEvent::Trap);
+	}
+	
+	fn handle_interrupts(&mut self, ) {
+		if let Some(exception) = self.pending_exception {
+			let (cause, tval, pc) = exception.to_trap();
+			self.take_trap(cause, tval, pc, 0);
 			self.pending_exception = None;
 		}
-		if (self.trap_csrs.mstatus & MSTATUS_MIE) != 0 {
-			let pending_interrupt_bits = self.trap_csrs.mip & self.trap_csrs.mie;
-			if pending_interrupt_bits & MIP_MEIP != 0 {
-				self.trap_csrs.mepc = self.pc;
-				self.trap_csrs.mcause = 0x8000_000B;
-				self.pc = self.get_trap_vector_addr(11);
-				self.trap_csrs.mtval = 0;
-				let ie_before = (self.trap_csrs.mstatus & MSTATUS_MIE) != 0;
-				self.trap_csrs.mstatus &= !(MSTATUS_MIE | MSTATUS_MPIE);
-				self.trap_csrs.mstatus |= if ie_before {MSTATUS_MPIE} else {0};
-				self.pending_exception = None;
-				self.waiting_for_interrupt = false;
-			} else if pending_interrupt_bits & MIP_MSIP != 0 {
-				self.trap_csrs.mepc = self.pc;
-				self.trap_csrs.mcause = 0x8000_0003;
-				self.pc = self.get_trap_vector_addr(3);
-				self.trap_csrs.mtval = 0;
-				let ie_before = (self.trap_csrs.mstatus & MSTATUS_MIE) != 0;
-				self.trap_csrs.mstatus &= !(MSTATUS_MIE | MSTATUS_MPIE);
-				self.trap_csrs.mstatus |= if ie_before {MSTATUS_MPIE} else {0};
-				self.pending_exception = None;
-				self.waiting_for_interrupt = false;
-			} else if pending_interrupt_bits & MIP_MTIP != 0 {
-				self.trap_csrs.mepc = self.pc;
-				self.trap_csrs.mcause = 0x8000_0007;
-				self.pc = self.get_trap_vector_addr(7);
-				self.trap_csrs.mtval = 0;
-				let ie_before = (self.trap_csrs.mstatus & MSTATUS_MIE) != 0;
-				self.trap_csrs.mstatus &= !(MSTATUS_MIE | MSTATUS_MPIE);
-				self.trap_csrs.mstatus |= if ie_before {MSTATUS_MPIE} else {0};
-				self.pending_exception = None;
-				self.waiting_for_interrupt = false;
-			}
+		let pending_interrupt_bits = self.trap_csrs.mip & self.trap_csrs.mie;
+		if pending_interrupt_bits == 0 {
+			return;
+		}
+		// interrupts for a higher privilege than the hart is running at are always enabled,
+		// and delegated interrupts are never taken in machine mode
+		let machine_enabled = self.privilege < Privilege::Machine || (self.trap_csrs.mstatus & MSTATUS_MIE) != 0;
+		let supervisor_enabled = self.privilege < Privilege::Supervisor || (self.privilege == Privilege::Supervisor && (self.trap_csrs.mstatus & MSTATUS_SIE) != 0);
+		let enabled_bits = 
+			(if machine_enabled { pending_interrupt_bits & !self.trap_csrs.mideleg } else { 0 }) |
+			(if supervisor_enabled { pending_interrupt_bits & self.trap_csrs.mideleg } else { 0 });
+		// machine interrupts come before any delegated one
+		let machine_bits = enabled_bits & !self.trap_csrs.mideleg;
+		let interrupt_bits = if machine_bits != 0 { machine_bits } else { enabled_bits };
+		if let Some(interrupt) = INTERRUPT_PRIORITY.iter().cloned().find(|interrupt| interrupt_bits & (1 << interrupt) != 0) {
+			let pc = self.pc;
+			self.take_trap(0x8000_0000 | interrupt, 0, pc, interrupt);
+			self.pending_exception = None;
+			self.waiting_for_interrupt = false;
 		}
 	}
 	
 	pub fn signal_external_interrupt(&mut self) {
 		self.trap_csrs.mip |= MIP_MEIP;
 	}
+	
+	fn set_interrupt_levels(&mut self, levels: InterruptLevels) {
+		let mut mip = self.trap_csrs.mip & !(MIP_MSIP | MIP_MEIP | MIP_SEIP);
+		if levels.machine_software {
+			mip |= MIP_MSIP;
+		}
+		if levels.machine_external {
+			mip |= MIP_MEIP;
+		}
+		if levels.supervisor_external {
+			mip |= MIP_SEIP;
+		}
+		self.trap_csrs.mip = mip;
+	}
 
 	pub fn step(&mut self) -> bool {
+		self.last_mem_access = None;
+		if self.halted {
+			return false;
+		}
+		if self.replay.is_some() {
+			self.replay_begin_step();
+		}
 		if self.trap_csrs.mip != 0 || self.pending_exception.is_some() {
 			self.handle_interrupts();
 		}
 		if self.waiting_for_interrupt {
 			return false;
 		}
+		if let Some(replay) = &mut self.replay {
+			replay.advance();
+		}
+		#[cfg(all(feature = "x86_64_jit", target_arch = "x86_64", unix))]
+		{
+			if self.native_blocks_allowed() {
+				if let Some(jit) = &mut self.jit {
+					if let Some((next_pc, executed)) = jit.execute(&mut self.xr, &mut self.mio, self.pc) {
+						self.pc = next_pc;
+						// a block which couldn't get past its first instruction leaves it to the interpreter
+						if executed != 0 {
+							self.csr_instrret += executed as u64;
+							self.block_cache.break_block();
+							return true;
+						}
+					}
+				}
+			}
+		}
 		let pc = self.pc;
-		let opcode_value = match self.mio.read_32_ifetch(pc) {
+		let (opcode_value, inst_length) = match self.fetch(pc) {
+			Some(instruction) => instruction,
+			None => return false
+		};
+		if let Some(trace) = &mut self.trace {
+			trace.begin_instruction(pc, opcode_value, inst_length);
+		}
+		let result = self.execute(opcode_value, inst_length);
+		let retired = self.pending_exception.is_none();
+		if retired {
+			self.csr_instrret += 1;
+		}
+		if let Some(trace) = &mut self.trace {
+			trace.end_instruction(retired);
+		}
+		result
+	}
+	
+	// native blocks don't report individual instructions to the trace or performance counters,
+	// and don't check memory protection or translate loads and stores
+	fn native_blocks_allowed(&self) -> bool {
+		! self.single_step && self.trace.is_none() && ! self.pmp_enforced() && self.trap_csrs.mstatus & MSTATUS_MPRV == 0 && ! self.hpm.is_counting() && ! self.breakpoints.is_active() && self.replay.is_none()
+	}
+	
+	fn replay_position(&self) -> u64 {
+		self.replay.as_ref().map_or(0, |replay| replay.position())
+	}
+	
+	// takes a checkpoint when one is due while recording
+	fn replay_begin_step(&mut self) {
+		self.replay_catch_up();
+		if self.replay.as_ref().is_some_and(|replay| replay.checkpoint_due()) {
+			let state = self.save_state();
+			if let Some(replay) = &mut self.replay {
+				replay.push_checkpoint(state);
+			}
+		}
+	}
+	
+	// applies the interrupts logged for this step while replaying, and goes back to recording at the end of the log
+	fn replay_catch_up(&mut self) {
+		if let Some(replay) = &mut self.replay {
+			while let Some(mip) = replay.next_interrupt() {
+				self.trap_csrs.mip = mip;
+			}
+			replay.catch_up();
+		}
+	}
+	
+	// restores the latest checkpoint at or before position and replays up to it, false if it's outside of the history
+	fn rewind_to(&mut self, position: u64) -> bool {
+		if ! self.replay_pages_unchanged() {
+			self.discard_replay_history();
+			return false;
+		}
+		let state = match self.replay.as_mut().and_then(|replay| replay.rewind(position)) {
+			Some(state) => state,
+			None => return false
+		};
+		if self.restore_state(&state).is_err() {
+			return false;
+		}
+		// replayed instructions were traced when they first ran
+		let trace = self.trace.take();
+		while self.replay_position() < position {
+			let before = self.replay_position();
+			self.step();
+			if self.replay_position() == before {
+				// waiting for an interrupt the log doesn't have
+				break;
+			}
+		}
+		self.trace = trace;
+		self.replay_catch_up();
+		self.period_start_instret = self.csr_instrret;
+		self.last_mem_access = None;
+		self.breakpoints.take_hit();
+		self.step_break();
+		true
+	}
+	
+	// code and page tables are replayed as they are now, which is only as they were if none were written since
+	fn replay_pages_unchanged(&self) -> bool {
+		match &self.replay {
+			Some(replay) => replay.read_pages().all(|(page, write_cycle)| self.mio.code_write_cycle(page * PAGE_SIZE) == Some(write_cycle)),
+			None => true
+		}
+	}
+	
+	fn replay_note_page_read(&mut self, address: u32) {
+		if let Some(replay) = &mut self.replay {
+			replay.record_page_read(address / PAGE_SIZE, self.mio.code_write_cycle(address));
+		}
+	}
+	
+	// moves the hart back one step, false once there's no history left
+	pub fn step_back(&mut self) -> bool {
+		let position = self.replay_position();
+		position != 0 && self.rewind_to(position - 1)
+	}
+	
+	// runs the hart backwards to the last breakpoint or watchpoint hit before where it is now,
+	// or to the start of its history if there isn't one
+	pub fn reverse_continue(&mut self, breakpoints: &HashSet<u32>, watchpoints: &[Watchpoint]) -> ReplayStop {
+		let mut range_end = self.replay_position();
+		while let Some(checkpoint) = self.replay.as_ref().and_then(|replay| replay.latest_checkpoint_before(range_end)) {
+			self.rewind_to(checkpoint);
+			let trace = self.trace.take();
+			let mut last_hit = None;
+			while self.replay_position() < range_end {
+				let position = self.replay_position();
+				let pc = self.pc;
+				if breakpoints.contains(&pc) {
+					last_hit = Some((position, ReplayStop::Breakpoint { pc }));
+				}
+				self.step();
+				if let Some((address, size, access)) = self.last_mem_access {
+					if let Some(watchpoint) = watchpoints.iter().find(|watchpoint| watchpoint.is_hit_by(address, size, access)) {
+						last_hit = Some((position, ReplayStop::Watchpoint { pc, address, watchpoint: *watchpoint }));
+					}
+				}
+				if self.replay_position() == position {
+					break;
+				}
+			}
+			self.trace = trace;
+			if let Some((position, stop)) = last_hit {
+				self.rewind_to(position);
+				return stop;
+			}
+			range_end = checkpoint;
+		}
+		if let Some(oldest) = self.replay.as_ref().and_then(|replay| replay.oldest_position()) {
+			self.rewind_to(oldest);
+		}
+		ReplayStop::HistoryStart { pc: self.pc }
+	}
+	
+	// memory as the hart left it, which while replaying is from before the stores it has yet to replay
+	pub fn debug_read_8(&self, address: u32) -> MemReadResult<u8> {
+		let address = match self.debug_physical_address(address) {
+			Some(address) => address,
+			None => return MemReadResult::ErrUnmapped
+		};
+		match self.replay.as_ref().and_then(|replay| replay.rewound_byte(address)) {
+			Some(value) => MemReadResult::Ok(value),
+			None => self.mio.read_8(address)
+		}
+	}
+	
+	pub fn debug_write_8(&mut self, address: u32, value: u8) -> MemWriteResult {
+		match self.debug_physical_address(address) {
+			Some(address) => self.mio.write_8(address, value),
+			None => MemWriteResult::ErrUnmapped
+		}
+	}
+	
+	// the debugger sees memory through the hart's translation, ignoring page permissions and
+	// without touching the TLB or the accessed and dirty bits
+	fn debug_physical_address(&self, address: u32) -> Option<u32> {
+		if ! self.translating() {
+			return Some(address);
+		}
+		let leaf = self.mmu.walk(address, |pte_address| match self.mio.read_32(pte_address) {
+			MemReadResult::Ok(pte) => Some(pte),
+			_ => None
+		}).ok()?;
+		Some(leaf.physical_address(address))
+	}
+	
+	// the data the last step accessed, as (address, size, access)
+	pub fn last_mem_access(&self) -> Option<(u32, u32, WatchKind)> {
+		self.last_mem_access
+	}
+	
+	// returns the expanded opcode at pc and its length in bytes
+	fn fetch(&mut self, pc: u32) -> Option<(u32, u32)> {
+		if self.translating() {
+			return self.fetch_translated(pc);
+		}
+		if ! self.pmp_enforced() {
+			return self.fetch_decode(pc, pc);
+		}
+		// the low half decides whether there is a high half, so it's checked on its own first
+		if self.pmp_check(pc, 2, PmpAccess::Execute) {
+			let (opcode_value, inst_length) = self.fetch_decode(pc, pc)?;
+			if inst_length == 2 || self.pmp_check(pc.wrapping_add(2), 2, PmpAccess::Execute) {
+				return Some((opcode_value, inst_length));
+			}
+		}
+		self.pending_exception = Some(Exception::InstructionAccessFault(pc));
+		None
+	}
+	
+	// the block cache works on physical addresses, which are contiguous up to the end of a page,
+	// so only an instruction straddling two pages has its halves fetched apart
+	fn fetch_translated(&mut self, pc: u32) -> Option<(u32, u32)> {
+		let address = self.fetch_address(pc, pc)?;
+		if pc & (PAGE_SIZE - 1) != PAGE_SIZE - 2 {
+			let (opcode_value, inst_length) = self.fetch_decode(pc, address)?;
+			if inst_length == 4 && ! self.pmp_check(address + 2, 2, PmpAccess::Execute) {
+				self.pending_exception = Some(Exception::InstructionAccessFault(pc));
+				return None;
+			}
+			return Some((opcode_value, inst_length));
+		}
+		let low = match self.mio.read_32_ifetch(address - 2) {
+			MemReadResult::Ok(value) => value >> 16,
+			_ => {
+				self.pending_exception = Some(Exception::InstructionAccessFault(pc));
+				return None;
+			}
+		};
+		if is_compressed(low) {
+			return match expand_compressed(low) {
+				Some(expanded_value) => Some((expanded_value, 2)),
+				None => {
+					self.pending_exception = Some(Exception::IllegalInstruction{
+						op: low,
+						addr: pc
+					});
+					None
+				}
+			};
+		}
+		let high_address = self.fetch_address(pc, pc.wrapping_add(2))?;
+		self.replay_note_page_read(high_address);
+		match self.mio.read_32_ifetch(high_address) {
+			MemReadResult::Ok(value) => Some((low | value << 16, 4)),
+			_ => {
+				self.pending_exception = Some(Exception::InstructionAccessFault(pc));
+				None
+			}
+		}
+	}
+	
+	// the physical address of the instruction bytes at address, which are part of the instruction at pc
+	fn fetch_address(&mut self, pc: u32, address: u32) -> Option<u32> {
+		let physical = match self.translate(address, PmpAccess::Execute) {
+			Ok(physical) => physical,
+			Err(TranslateError::PageFault) => {
+				self.pending_exception = Some(Exception::InstructionPageFault{
+					instr_addr: pc,
+					fault_addr: address
+				});
+				return None;
+			},
+			Err(TranslateError::AccessFault) => {
+				self.pending_exception = Some(Exception::InstructionAccessFault(pc));
+				return None;
+			}
+		};
+		if ! self.pmp_check(physical, 2, PmpAccess::Execute) {
+			self.pending_exception = Some(Exception::InstructionAccessFault(pc));
+			return None;
+		}
+		Some(physical)
+	}
+	
+	// address is where the instruction at pc is in physical memory
+	fn fetch_decode(&mut self, pc: u32, address: u32) -> Option<(u32, u32)> {
+		self.replay_note_page_read(address);
+		if let Some(instruction) = self.block_cache.fetch(&self.mio, address) {
+			return Some((instruction.opcode_value, instruction.length));
+		}
+		let fetch_value = match self.mio.read_32_ifetch(address) {
 			MemReadResult::Ok(value) => value,
-			_ => return false
+			_ => {
+				self.pending_exception = Some(Exception::InstructionAccessFault(pc));
+				return None;
+			}
 		};
-		if cfg!(feature = "cpu_debug") { print!("step @{:#010x}: {:02x} {:02x} {:02x} {:02x}  | ", pc, opcode_value & 0xFF, (opcode_value >> 8) & 0xFF, (opcode_value >> 16) & 0xFF, opcode_value >> 24); }
+		if is_compressed(fetch_value) {
+			match expand_compressed(fetch_value) {
+				Some(expanded_value) => Some((expanded_value, 2)),
+				None => {
+					self.pending_exception = Some(Exception::IllegalInstruction{
+						op: fetch_value & 0xFFFF,
+						addr: pc
+					});
+					None
+				}
+			}
+		} else {
+			Some((fetch_value, 4))
+		}
+	}
+	
+	fn execute(&mut self, opcode_value: u32, inst_length: u32) -> bool {
 		let opcode = Opcode::new(opcode_value);
 		match opcode.op() {
 			Op::Lui => {
 				let imm = opcode.u_imm() << 12;
 				let rd = opcode.rd();
 				self.set_gpr(rd, imm);
-				self.pc += 4;
-				if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <=  {:#010x}     .", format!("lui {}, {:#07x}", REG_NAMES[rd as usize], opcode.u_imm()), REG_NAMES[rd as usize], imm); }
+				self.pc += inst_length;
 			},
 			Op::Auipc => {
 				let imm = opcode.u_imm() << 12;
@@ -442,26 +1333,23 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 				let val = imm.wrapping_add(pc);
 				let rd = opcode.rd();
 				self.set_gpr(rd, val);
-				self.pc += 4;
-				if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <=  {:#010x}     .", format!("auipc {}, imm: {:#07x}", REG_NAMES[rd as usize], opcode.u_imm()), REG_NAMES[rd as usize], val); }
+				self.pc += inst_length;
 			}
 			Op::Jal => {
 				let imm = opcode.j_imm_signed();
-				let return_addr = self.pc + 4;
+				let return_addr = self.pc + inst_length;
 				let rd = opcode.rd();
 				self.set_gpr(rd, return_addr);
 				self.pc = self.pc.wrapping_add(imm as u32);
-				if cfg!(feature = "cpu_debug") { println!("{: <50} # pc           <=  {:#010x}     {: <12} <= {:#010x}", format!("jal {}, {:#010x}", REG_NAMES[rd as usize], self.pc), self.pc, REG_NAMES[rd as usize], return_addr); }
 			},
 			Op::Jalr => {
 				let imm = opcode.i_imm_signed();
 				let rs1 = opcode.rs1();
 				let rd = opcode.rd();
 				let base = self.get_gpr(rs1);
-				let return_addr = self.pc + 4;
+				let return_addr = self.pc + inst_length;
 				self.set_gpr(rd, return_addr);
-				self.pc = base.wrapping_add(imm as u32);
-				if cfg!(feature = "cpu_debug") { println!("{: <50} # pc           <=  {:#010x}     {: <12} <= {:#010x}", format!("jalr {}, {}({})", REG_NAMES[rd as usize], if imm == 0 {"".to_string()} else {format!("{:#03x}", imm).to_string()}, REG_NAMES[rs1 as usize]), self.pc, REG_NAMES[rd as usize], return_addr); }
+				self.pc = base.wrapping_add(imm as u32) & !1;
 			},
 			Op::OpImm => {
 				let op_funct = opcode.funct3_op_imm();
@@ -473,7 +1361,6 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 						let src_val = self.get_gpr(rs1) as i32;
 						let dst_val = src_val.wrapping_add(imm);
 						self.set_gpr(rd, dst_val as u32);
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <=  {:#010x}     .", format!("addi {}, {}, {}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], imm), REG_NAMES[rd as usize], dst_val as u32); }
 					},
 					OpImmFunct3::SltI => {
 						let imm = opcode.i_imm_signed();
@@ -482,7 +1369,6 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 						let src_val = self.get_gpr(rs1) as i32;
 						let dst_val = if src_val < imm { 1 } else { 0 };
 						self.set_gpr(rd, dst_val as u32);
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <= {}      .", format!("slti {}, {}, {}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], imm), REG_NAMES[rd as usize], dst_val); }
 					},
 					OpImmFunct3::SltIU => {
 						let imm = opcode.i_imm_signed() as u32;
@@ -491,7 +1377,6 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 						let src_val = self.get_gpr(rs1);
 						let dst_val = if src_val < imm { 1 } else { 0 };
 						self.set_gpr(rd, dst_val);
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <= {}      .", format!("sltiu {}, {}, {:#010x}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], imm), REG_NAMES[rd as usize], dst_val); }
 					},
 					OpImmFunct3::XorI => {
 						let imm = opcode.i_imm_signed() as u32;
@@ -500,7 +1385,6 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 						let src_val = self.get_gpr(rs1);
 						let dst_val = src_val ^ imm;
 						self.set_gpr(rd, dst_val);
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <= {}      .", format!("xori {}, {}, {:#010x}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], imm), REG_NAMES[rd as usize], dst_val); }
 					},
 					OpImmFunct3::OrI => {
 						let imm = opcode.i_imm_signed() as u32;
@@ -509,7 +1393,6 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 						let src_val = self.get_gpr(rs1);
 						let dst_val = src_val | imm;
 						self.set_gpr(rd, dst_val);
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <= {}      .", format!("ori {}, {}, {:#010x}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], imm), REG_NAMES[rd as usize], dst_val); }
 					},
 					OpImmFunct3::AndI => {
 						let imm = opcode.i_imm_signed() as u32;
@@ -518,35 +1401,44 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 						let src_val = self.get_gpr(rs1);
 						let dst_val = src_val & imm;
 						self.set_gpr(rd, dst_val);
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <= {}      .", format!("andi {}, {}, {:#010x}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], imm), REG_NAMES[rd as usize], dst_val); }
 					},
-					OpImmFunct3::SllI => {
+					OpImmFunct3::SllI | OpImmFunct3::SrxI => {
 						let rd = opcode.rd();
 						let shift = opcode.shamt();
 						let rs1 = opcode.rs1();
 						let src_val = self.get_gpr(rs1);
-						let dst_val = src_val << shift;
+						let dst_val = match opcode.funct_op_imm_shift() {
+							OpImmShiftFunct::SllI => src_val << shift,
+							OpImmShiftFunct::SrlI => src_val >> shift,
+							OpImmShiftFunct::SraI => ((src_val as i32) >> shift) as u32,
+							OpImmShiftFunct::RorI => src_val.rotate_right(shift),
+							OpImmShiftFunct::Clz => src_val.leading_zeros(),
+							OpImmShiftFunct::Ctz => src_val.trailing_zeros(),
+							OpImmShiftFunct::CPop => src_val.count_ones(),
+							OpImmShiftFunct::SextB => ((src_val as i8) as i32) as u32,
+							OpImmShiftFunct::SextH => ((src_val as i16) as i32) as u32,
+							OpImmShiftFunct::Rev8 => src_val.swap_bytes(),
+							OpImmShiftFunct::OrcB => {
+								let mut value = 0;
+								for byte in 0 .. 4 {
+									if src_val & (0xFF << (byte * 8)) != 0 {
+										value |= 0xFF << (byte * 8);
+									}
+								}
+								value
+							},
+							OpImmShiftFunct::BSetI => src_val | (1 << shift),
+							OpImmShiftFunct::BClrI => src_val & !(1 << shift),
+							OpImmShiftFunct::BInvI => src_val ^ (1 << shift),
+							OpImmShiftFunct::BExtI => (src_val >> shift) & 1,
+							OpImmShiftFunct::Unknown => {
+								return self.illegal_instruction(opcode);
+							},
+						};
 						self.set_gpr(rd, dst_val);
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <= {}      .", format!("slli {}, {}, {}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], shift), REG_NAMES[rd as usize], dst_val); }
-					},
-					OpImmFunct3::SrxI => {
-						let rd = opcode.rd();
-						let shift = opcode.shamt();
-						let rs1 = opcode.rs1();
-						if opcode.srxi_is_arithmetic() {
-							let src_val = self.get_gpr(rs1);
-							let dst_val = src_val >> shift;
-							self.set_gpr(rd, dst_val);
-							if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <= {}      .", format!("srli {}, {}, {:#03x}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], shift), REG_NAMES[rd as usize], dst_val); }
-						} else {
-							let src_val = self.get_gpr(rs1) as i32;
-							let dst_val = src_val >> shift;
-							self.set_gpr(rd, dst_val as u32);
-							if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <= {}      .", format!("srai {}, {}, {:#03x}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], shift), REG_NAMES[rd as usize], dst_val); }
-						}
 					},
 				}
-				self.pc += 4;
+				self.pc += inst_length;
 			},
 			Op::Store => {
 				let store_type = opcode.funct3_store();
@@ -555,75 +1447,18 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 				let offset = opcode.s_imm_signed();
 				let address = self.get_gpr(base).wrapping_add(offset as u32);
 				let value = self.get_gpr(src);
-				match store_type {
-					StoreFunct3::Byte => {
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # ({:#010x}) <= {:#04x}        .", format!("sb {}({}), {}", if offset != 0 { format!("{}", offset).to_string() } else { "".to_string() }, REG_NAMES[base as usize], REG_NAMES[src as usize]), address, value as u8); }
-						match self.mio.write_8(address, value as u8) {
-							MemWriteResult::Ok => {
-							},
-							MemWriteResult::ErrAlignment => {
-								self.pending_exception = Some(Exception::StoreAddressMisaligned{
-									instr_addr: self.pc,
-									store_addr: address
-								});
-								return false;
-							},
-							_ => {
-								self.pending_exception = Some(Exception::StoreAccessFault{
-									instr_addr: self.pc,
-									store_addr: address
-								});
-								return false;
-							}
-						}
-					},
-					StoreFunct3::Half => {
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # ({:#010x}) <= {:#06x}        .", format!("sh {}({}), {}", if offset != 0 { format!("{}", offset as i32).to_string() } else { "".to_string() }, REG_NAMES[base as usize], REG_NAMES[src as usize]), address, value as u16); }
-						match self.mio.write_16(address, value as u16) {
-							MemWriteResult::Ok => {
-							},
-							MemWriteResult::ErrAlignment => {
-								self.pending_exception = Some(Exception::StoreAddressMisaligned{
-									instr_addr: self.pc,
-									store_addr: address
-								});
-								return false;
-							},
-							_ => {
-								self.pending_exception = Some(Exception::StoreAccessFault {
-									instr_addr: self.pc,
-									store_addr: address
-								});
-								return false;
-							}
-						}
-					},
-					StoreFunct3::Word => {
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # ({:#010x}) <=  {:#010x}     .", format!("sw {}({}), {}", if offset != 0 { format!("{}", offset).to_string() } else { "".to_string() }, REG_NAMES[base as usize], REG_NAMES[src as usize]), address, value); }
-						match self.mio.write_32(address, value) {
-							MemWriteResult::Ok => {
-							},
-							MemWriteResult::ErrAlignment => {
-								self.pending_exception = Some(Exception::StoreAddressMisaligned {
-									instr_addr: self.pc,
-									store_addr: address
-								});
-								return false;
-							},
-							_ => {
-								self.pending_exception = Some(Exception::StoreAccessFault {
-									instr_addr: self.pc,
-									store_addr: address
-								});
-								return false;
-							}
-						}
-					},
+				let stored = match store_type {
+					StoreFunct3::Byte => self.store_8(address, value as u8),
+					StoreFunct3::Half => self.store_16(address, value as u16),
+					StoreFunct3::Word => self.store_32(address, value),
 					StoreFunct3::Unknown => {
 						return self.illegal_instruction(opcode);
 					}
+				};
+				if ! stored {
+					return false;
 				}
-				self.pc += 4;
+				self.pc += inst_length;
 			},
 			Op::Load => {
 				let load_type = opcode.funct3_load();
@@ -631,127 +1466,21 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 				let base = opcode.rs1();
 				let offset = opcode.i_imm_signed();
 				let address = self.get_gpr(base).wrapping_add(offset as u32);
-				match load_type {
-					LoadFunct3::Byte => {
-						let load_result = self.mio.read_8(address);
-						match load_result {
-							MemReadResult::Ok(value) => {
-								self.set_gpr(dest, ((value as i8) as i32) as u32);
-								if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <=  {:#010x}     .", format!("lb {}, {}({})", REG_NAMES[dest as usize], if offset != 0 { format!("{}", offset).to_string() } else { "".to_string() }, REG_NAMES[base as usize]), REG_NAMES[dest as usize], value as i8 as i32); }
-							},
-							MemReadResult::ErrAlignment => {
-								self.pending_exception = Some(Exception::LoadAddressMisaligned{
-									instr_addr: self.pc,
-									load_addr: address
-								});
-								return false;
-							}
-							_ => {
-								self.pending_exception = Some(Exception::LoadAccessFault{
-									instr_addr: self.pc,
-									load_addr: address
-								});
-								return false;
-							}
-						}
-					},
-					LoadFunct3::Half => {
-						let load_result = self.mio.read_16(address);
-						match load_result {
-							MemReadResult::Ok(value) => {
-								self.set_gpr(dest, ((value as i16) as i32) as u32);
-								if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <= ({:#010x})    .", format!("lh {}, {}({})", REG_NAMES[dest as usize], if offset != 0 { format!("{}", offset).to_string() } else { "".to_string() }, REG_NAMES[base as usize]), REG_NAMES[dest as usize], value as i16 as i32); }
-							},
-							MemReadResult::ErrAlignment => {
-								self.pending_exception = Some(Exception::LoadAddressMisaligned{
-									instr_addr: self.pc,
-									load_addr: address
-								});
-								return false;
-							}
-							_ => {
-								self.pending_exception = Some(Exception::LoadAccessFault{
-									instr_addr: self.pc,
-									load_addr: address
-								});
-								return false;
-							}
-						}
-					},
-					LoadFunct3::Word => {
-						let load_result = self.mio.read_32(address);
-						match load_result {
-							MemReadResult::Ok(value) => {
-								self.set_gpr(dest, value as u32);
-								if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <= ({:#010x})    .", format!("lw {}, {}({})", REG_NAMES[dest as usize], if offset != 0 { format!("{}", offset).to_string() } else { "".to_string() }, REG_NAMES[base as usize]), REG_NAMES[dest as usize], value as i32); }
-							},
-							MemReadResult::ErrAlignment => {
-								self.pending_exception = Some(Exception::LoadAddressMisaligned{
-									instr_addr: self.pc,
-									load_addr: address
-								});
-								return false;
-							}
-							_ => {
-								self.pending_exception = Some(Exception::LoadAccessFault{
-									instr_addr: self.pc,
-									load_addr: address
-								});
-								return false;
-							}
-						}
-					},
-					LoadFunct3::ByteUnsigned => {
-						let load_result = self.mio.read_8(address);
-						match load_result {
-							MemReadResult::Ok(value) => {
-								self.set_gpr(dest, value as u32);
-								if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <= ({:#010x})    .", format!("lbu {}, {}({})", REG_NAMES[dest as usize], if offset != 0 { format!("{}", offset).to_string() } else { "".to_string() }, REG_NAMES[base as usize]), REG_NAMES[dest as usize], value); }
-							},
-							MemReadResult::ErrAlignment => {
-								self.pending_exception = Some(Exception::LoadAddressMisaligned{
-									instr_addr: self.pc,
-									load_addr: address
-								});
-								return false;
-							}
-							_ => {
-								self.pending_exception = Some(Exception::LoadAccessFault{
-									instr_addr: self.pc,
-									load_addr: address
-								});
-								return false;
-							}
-						}
-					},
-					LoadFunct3::HalfUnsigned => {
-						let load_result = self.mio.read_16(address);
-						match load_result {
-							MemReadResult::Ok(value) => {
-								self.set_gpr(dest, value as u32);
-								if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <= ({:#010x})    .", format!("lhu {}, {}({})", REG_NAMES[dest as usize], if offset != 0 { format!("{}", offset).to_string() } else { "".to_string() }, REG_NAMES[base as usize]), REG_NAMES[dest as usize], value); }
-							},
-							MemReadResult::ErrAlignment => {
-								self.pending_exception = Some(Exception::LoadAddressMisaligned{
-									instr_addr: self.pc,
-									load_addr: address
-								});
-								return false;
-							}
-							_ => {
-								self.pending_exception = Some(Exception::LoadAccessFault{
-									instr_addr: self.pc,
-									load_addr: address
-								});
-								return false;
-							}
-						}
-					},
+				let loaded = match load_type {
+					LoadFunct3::Byte => self.load_8(address).map(|value| ((value as i8) as i32) as u32),
+					LoadFunct3::Half => self.load_16(address).map(|value| ((value as i16) as i32) as u32),
+					LoadFunct3::Word => self.load_32(address),
+					LoadFunct3::ByteUnsigned => self.load_8(address).map(|value| value as u32),
+					LoadFunct3::HalfUnsigned => self.load_16(address).map(|value| value as u32),
 					LoadFunct3::Unknown => {
 						return self.illegal_instruction(opcode);
 					},
+				};
+				match loaded {
+					Some(value) => self.set_gpr(dest, value),
+					None => return false
 				}
-				self.pc += 4;
+				self.pc += inst_length;
 			},
 			Op::Op => {
 				let rd: u32 = opcode.rd();
@@ -762,85 +1491,135 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 				let op_funct = opcode.funct3funct7_op();
 				let value = match op_funct {
 					OpFunct3Funct7::Add => {
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <=  {:#010x}     .", format!("add {}, {}, {}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], REG_NAMES[rs2 as usize]), REG_NAMES[rd as usize], s1_value.wrapping_add(s2_value)); }
 						s1_value.wrapping_add(s2_value)
 					},
 					OpFunct3Funct7::Sub => {
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <=  {:#010x}     .", format!("sub {}, {}, {}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], REG_NAMES[rs2 as usize]), REG_NAMES[rd as usize], s1_value.wrapping_sub(s2_value)); }
 						s1_value.wrapping_sub(s2_value)
 					}
 					OpFunct3Funct7::Sll => {
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <=  {:#010x}     .", format!("sll {}, {}, {}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], REG_NAMES[rs2 as usize]), REG_NAMES[rd as usize], s1_value << (s2_value & 0x1F)); }
 						s1_value << (s2_value & 0x1F)
 					},
 					OpFunct3Funct7::Slt => {
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <=  {:#010x}     .", format!("slt {}, {}, {}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], REG_NAMES[rs2 as usize]), REG_NAMES[rd as usize], if (s1_value as i32) < (s2_value as i32) { 1 } else { 0 }); }
 						if (s1_value as i32) < (s2_value as i32) { 1 } else { 0 }
 					},
 					OpFunct3Funct7::SltU => {
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <=  {:#010x}     .", format!("sltu {}, {}, {}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], REG_NAMES[rs2 as usize]), REG_NAMES[rd as usize], if s1_value < s2_value { 1 } else { 0 }); }
 						if s1_value < s2_value { 1 } else { 0 }
 					},
 					OpFunct3Funct7::Xor => {
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <=  {:#010x}     .", format!("xor {}, {}, {}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], REG_NAMES[rs2 as usize]), REG_NAMES[rd as usize], s1_value ^ s2_value); }
 						s1_value ^ s2_value
 					},
 					OpFunct3Funct7::Sra => {
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <=  {:#010x}     .", format!("sra {}, {}, {}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], REG_NAMES[rs2 as usize]), REG_NAMES[rd as usize], (s1_value as i32 >> (s2_value & 0x1F)) as u32); }
 						(s1_value as i32 >> (s2_value & 0x1F)) as u32
 					},
 					OpFunct3Funct7::Srl => {
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <=  {:#010x}     .", format!("srl {}, {}, {}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], REG_NAMES[rs2 as usize]), REG_NAMES[rd as usize], s1_value >> (s2_value & 0x1F)); }
 						s1_value >> (s2_value & 0x1F)
 					},
 					OpFunct3Funct7::Or => {
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <=  {:#010x}     .", format!("or {}, {}, {}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], REG_NAMES[rs2 as usize]), REG_NAMES[rd as usize], s1_value | s2_value); }
 						s1_value | s2_value
 					},
 					OpFunct3Funct7::And => {
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <=  {:#010x}     .", format!("and {}, {}, {}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], REG_NAMES[rs2 as usize]), REG_NAMES[rd as usize], s1_value & s2_value); }
 						s1_value & s2_value
 					},
 					OpFunct3Funct7::Mul => {
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <=  {:#010x}     .", format!("mul {}, {}, {}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], REG_NAMES[rs2 as usize]), REG_NAMES[rd as usize], s1_value * s2_value); }
-						s1_value * s2_value
+						s1_value.wrapping_mul(s2_value)
 					},
 					OpFunct3Funct7::MulH => {
 						let mult = ((s1_value as i32) as i64) * ((s2_value as i32) as i64);
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <=  {:#010x}     .", format!("mulh {}, {}, {}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], REG_NAMES[rs2 as usize]), REG_NAMES[rd as usize], (mult >> 32) as u32); }
 						(mult >> 32) as u32
 					},
 					OpFunct3Funct7::MulHSU => {
-						let mult = (s1_value as u64) as i64 * (s2_value as i64);
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <=  {:#010x}     .", format!("mulhsu {}, {}, {}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], REG_NAMES[rs2 as usize]), REG_NAMES[rd as usize], (mult >> 32) as u32); }
+						let mult = ((s1_value as i32) as i64) * (s2_value as i64);
 						(mult >> 32) as u32
 					},
 					OpFunct3Funct7::MulHU => {
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <=  {:#010x}     .", format!("mulhu {}, {}, {}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], REG_NAMES[rs2 as usize]), REG_NAMES[rd as usize], (((s1_value as u64) * (s2_value as u64)) >> 32) as u32); }
 						(((s1_value as u64) * (s2_value as u64)) >> 32) as u32
 					},
+					// division by zero doesn't trap: the quotient is all ones and the remainder is the dividend
 					OpFunct3Funct7::Div => {
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <=  {:#010x}     .", format!("div {}, {}, {}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], REG_NAMES[rs2 as usize]), REG_NAMES[rd as usize], ((s1_value as i32) / (s2_value as i32)) as u32); }
-						((s1_value as i32) / (s2_value as i32)) as u32
+						if s2_value == 0 {
+							0xFFFF_FFFF
+						} else {
+							(s1_value as i32).wrapping_div(s2_value as i32) as u32
+						}
 					},
 					OpFunct3Funct7::DivU => {
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <=  {:#010x}     .", format!("divu {}, {}, {}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], REG_NAMES[rs2 as usize]), REG_NAMES[rd as usize], s1_value / s2_value); }
-						s1_value / s2_value
+						s1_value.checked_div(s2_value).unwrap_or(0xFFFF_FFFF)
 					},
 					OpFunct3Funct7::Rem => {
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <=  {:#010x}     .", format!("rem {}, {}, {}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], REG_NAMES[rs2 as usize]), REG_NAMES[rd as usize], ((s1_value as i32) % (s2_value as i32)) as u32); }
-						((s1_value as i32) % (s2_value as i32)) as u32
+						if s2_value == 0 {
+							s1_value
+						} else {
+							(s1_value as i32).wrapping_rem(s2_value as i32) as u32
+						}
 					},
 					OpFunct3Funct7::RemU => {
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # {: <12} <=  {:#010x}     .", format!("remu {}, {}, {}", REG_NAMES[rd as usize], REG_NAMES[rs1 as usize], REG_NAMES[rs2 as usize]), REG_NAMES[rd as usize], s1_value % s2_value); }
-						s1_value % s2_value
+						if s2_value == 0 {
+							s1_value
+						} else {
+							s1_value % s2_value
+						}
+					},
+					OpFunct3Funct7::Sh1Add => {
+						(s1_value << 1).wrapping_add(s2_value)
+					},
+					OpFunct3Funct7::Sh2Add => {
+						(s1_value << 2).wrapping_add(s2_value)
+					},
+					OpFunct3Funct7::Sh3Add => {
+						(s1_value << 3).wrapping_add(s2_value)
+					},
+					OpFunct3Funct7::AndN => {
+						s1_value & !s2_value
+					},
+					OpFunct3Funct7::OrN => {
+						s1_value | !s2_value
+					},
+					OpFunct3Funct7::XNor => {
+						!(s1_value ^ s2_value)
+					},
+					OpFunct3Funct7::Min => {
+						(s1_value as i32).min(s2_value as i32) as u32
+					},
+					OpFunct3Funct7::MinU => {
+						s1_value.min(s2_value)
+					},
+					OpFunct3Funct7::Max => {
+						(s1_value as i32).max(s2_value as i32) as u32
+					},
+					OpFunct3Funct7::MaxU => {
+						s1_value.max(s2_value)
+					},
+					OpFunct3Funct7::Rol => {
+						s1_value.rotate_left(s2_value & 0x1F)
+					},
+					OpFunct3Funct7::Ror => {
+						s1_value.rotate_right(s2_value & 0x1F)
+					},
+					OpFunct3Funct7::ZextH => {
+						// zext.h is only defined with rs2 as x0
+						if rs2 != 0 {
+							return self.illegal_instruction(opcode);
+						}
+						s1_value & 0xFFFF
+					},
+					OpFunct3Funct7::BSet => {
+						s1_value | (1 << (s2_value & 0x1F))
+					},
+					OpFunct3Funct7::BClr => {
+						s1_value & !(1 << (s2_value & 0x1F))
+					},
+					OpFunct3Funct7::BInv => {
+						s1_value ^ (1 << (s2_value & 0x1F))
+					},
+					OpFunct3Funct7::BExt => {
+						(s1_value >> (s2_value & 0x1F)) & 1
 					},
 					OpFunct3Funct7::Unknown => {
 						return self.illegal_instruction(opcode);
 					},
 				};
 				self.set_gpr(rd, value);
-				self.pc += 4;
+				self.pc += inst_length;
 			},
 			Op::Branch => {
 				let branch_type = opcode.funct3_branch();
@@ -852,36 +1631,31 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 				let branch_addr = self.pc.wrapping_add(offset as u32);
 				if match branch_type {
 					BranchFunct3::Eq => {
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # pc           <=  {:#010x}     .", format!("beq {}, {}, {:#010x}", REG_NAMES[rs1 as usize], REG_NAMES[rs2 as usize], branch_addr), if s1_value == s2_value {branch_addr} else {self.pc + 4}); }
 						s1_value == s2_value
 					},
 					BranchFunct3::NEq => {
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # pc           <=  {:#010x}     .", format!("bne {}, {}, {:#010x}", REG_NAMES[rs1 as usize], REG_NAMES[rs2 as usize], branch_addr), if s1_value != s2_value {branch_addr} else {self.pc + 4}); }
 						s1_value != s2_value
 					},
 					BranchFunct3::Lt => {
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # pc           <=  {:#010x}     .", format!("blt {}, {}, {:#010x}", REG_NAMES[rs1 as usize], REG_NAMES[rs2 as usize], branch_addr), if (s1_value as i32) < (s2_value as i32) {branch_addr} else {self.pc + 4}); }
 						(s1_value as i32) < (s2_value as i32)
 					},
 					BranchFunct3::GEq => {
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # pc           <=  {:#010x}     .", format!("bge {}, {}, {:#010x}", REG_NAMES[rs1 as usize], REG_NAMES[rs2 as usize], branch_addr), if (s1_value as i32) >= (s2_value as i32) {branch_addr} else {self.pc + 4}); }
 						(s1_value as i32) >= (s2_value as i32)
 					},
 					BranchFunct3::LtU => {
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # pc           <=  {:#010x}     .", format!("bltu {}, {}, {:#010x}", REG_NAMES[rs1 as usize], REG_NAMES[rs2 as usize], branch_addr), if s1_value < s2_value {branch_addr} else {self.pc + 4}); }
 						s1_value < s2_value
 					},
 					BranchFunct3::GEqU => {
-						if cfg!(feature = "cpu_debug") { println!("{: <50} # pc           <=  {:#010x}     .", format!("bgeu {}, {}, {:#010x}", REG_NAMES[rs1 as usize], REG_NAMES[rs2 as usize], branch_addr), if s1_value >= s2_value {branch_addr} else {self.pc + 4}); }
 						s1_value >= s2_value
 					},
 					BranchFunct3::Unknown => {
 						return self.illegal_instruction(opcode);
 					},
 				} {
-					self.pc = self.pc.wrapping_add(offset as u32);
+					self.pc = branch_addr;
+					self.hpm.count(HpmEvent::BranchTaken);
 				} else {
-					self.pc += 4;
+					self.pc += inst_length;
 				}
 			},
 			Op::LoadFp => {
@@ -892,32 +1666,24 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 				let width = opcode.funct3_fpformat();
 				match width {
 					FpFormatFunct3::Width32 => {
-						let value = match self.mio.read_32(address) {
-							MemReadResult::Ok(value) => {
-								f32::from_bits(value)
-							},
-							MemReadResult::ErrAlignment => {
-								self.pending_exception = Some(Exception::LoadAddressMisaligned{
-									instr_addr: self.pc,
-									load_addr: address
-								});
-								return false;
-							},
-							_ => {
-								self.pending_exception = Some(Exception::LoadAccessFault{
-									instr_addr: self.pc,
-									load_addr: address
-								});
-								return false;
-							}
+						let value = match self.load_32(address) {
+							Some(value) => f32::from_bits(value),
+							None => return false
 						};
 						self.set_fpr(rd, value);
 					},
+					FpFormatFunct3::Width64 => {
+						let value = match self.load_64(address) {
+							Some(value) => value,
+							None => return false
+						};
+						self.set_fpr_bits(rd, value);
+					},
 					FpFormatFunct3::Unknown => {
 						return self.illegal_instruction(opcode);
 					},
 				}
-				self.pc += 4;
+				self.pc += inst_length;
 			},
 			Op::StoreFp => {
 				let rs = opcode.rs2();
@@ -927,56 +1693,106 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 				let width = opcode.funct3_fpformat();
 				match width {
 					FpFormatFunct3::Width32 => {
-						let value = self.get_fpr(rs);
-						let value_raw = f32::to_bits(value);
-						match self.mio.write_32(address, value_raw) {
-							MemWriteResult::Ok => {},
-							MemWriteResult::ErrAlignment => {
-								self.pending_exception = Some(Exception::StoreAddressMisaligned{
-									instr_addr: self.pc,
-									store_addr: address
-								});
-								return false;
-							},
-							_ => {
-								self.pending_exception = Some(Exception::StoreAccessFault{
-									instr_addr: self.pc,
-									store_addr: address
-								});
-								return false;
-							}
+						// fsw stores the low half as is, whether or not it holds a boxed single
+						let value_raw = self.get_fpr_bits(rs) as u32;
+						if ! self.store_32(address, value_raw) {
+							return false;
+						}
+					},
+					FpFormatFunct3::Width64 => {
+						let value_raw = self.get_fpr_bits(rs);
+						if ! self.store_64(address, value_raw) {
+							return false;
 						}
 					},
 					FpFormatFunct3::Unknown => {
 						return self.illegal_instruction(opcode);
 					},
 				}
-				self.pc += 4;
+				self.pc += inst_length;
 			},
 			Op::System => {
 				let funct = opcode.funct3_system();
 				let rd: u32 = opcode.rd();
 				let rs1: u32 = opcode.rs1();
 				let csr = opcode.i_imm();
+				let csr_write = match funct {
+					SystemFunct3::Int | SystemFunct3::Unknown => None,
+					SystemFunct3::CsrRW | SystemFunct3::CsrRWI => Some(true),
+					_ => Some(rs1 != 0),
+				};
+				if let Some(csr_write) = csr_write {
+					if ! self.csr_accessible(csr, csr_write) {
+						return self.illegal_instruction(opcode);
+					}
+				}
 				match funct {
 					SystemFunct3::Int => {
+						match opcode.value {
+							ECALL => {
+								self.pending_exception = Some(match self.privilege {
+									Privilege::User => Exception::ECallFromUser(self.pc),
+									Privilege::Supervisor => Exception::ECallFromSupervisor(self.pc),
+									Privilege::Machine => Exception::ECallFromMachine(self.pc),
+								});
+								return false;
+							},
+							// sret shares its funct7 with wfi
+							SRET => {
+								if self.privilege == Privilege::User || (self.privilege == Privilege::Supervisor && self.trap_csrs.mstatus & MSTATUS_TSR != 0) {
+									return self.illegal_instruction(opcode);
+								}
+								self.pc = self.trap_csrs.sepc;
+								self.privilege = if self.trap_csrs.mstatus & MSTATUS_SPP != 0 {
+									Privilege::Supervisor
+								} else {
+									Privilege::User
+								};
+								let ie_before = self.trap_csrs.mstatus & MSTATUS_SPIE != 0;
+								// returning below machine mode clears mprv
+								self.trap_csrs.mstatus &= !(MSTATUS_SIE | MSTATUS_SPP | MSTATUS_MPRV);
+								self.trap_csrs.mstatus |= MSTATUS_SPIE | if ie_before {MSTATUS_SIE} else {0};
+								return true;
+							},
+							EBREAK => {
+								if inst_length == 4 && self.is_semihosting_call() {
+									return self.semihosting_call();
+								}
+								self.pending_exception = Some(Exception::Breakpoint(self.pc));
+								return false;
+							},
+							_ => {}
+						}
 						let ifunct = opcode.funct7_system_int();
 						match ifunct {
 							SystemIntFunct7::WaitForInterrupt => {
-								if cfg!(feature = "cpu_debug") { println!("{: <50} # .                               .", format!("wfi")); }
-								self.pc += 4;
+								if self.privilege == Privilege::User || (self.privilege == Privilege::Supervisor && self.trap_csrs.mstatus & MSTATUS_TW != 0) {
+									return self.illegal_instruction(opcode);
+								}
+								self.pc += inst_length;
 								self.waiting_for_interrupt = true;
 								return false;
 							},
 							SystemIntFunct7::MRet => {
+								if self.privilege < Privilege::Machine {
+									return self.illegal_instruction(opcode);
+								}
 								self.pc = self.trap_csrs.mepc;
-								self.trap_csrs.mstatus &= !MSTATUS_MIE;
-								self.trap_csrs.mstatus |= if self.trap_csrs.mstatus & MSTATUS_MPIE != 0 {
-									MSTATUS_MIE
-								} else {
-									0
-								};
-								if cfg!(feature = "cpu_debug") { println!("{: <50} # pc           <=  {:#010x}     mstatus      <=  {:032b}", format!("mret"), self.pc, self.trap_csrs.mstatus); }
+								self.privilege = Privilege::from_raw((self.trap_csrs.mstatus & MSTATUS_MPP) >> MSTATUS_MPP_SHIFT).unwrap_or(Privilege::User);
+								let ie_before = self.trap_csrs.mstatus & MSTATUS_MPIE != 0;
+								self.trap_csrs.mstatus &= !(MSTATUS_MIE | MSTATUS_MPP);
+								self.trap_csrs.mstatus |= MSTATUS_MPIE | if ie_before {MSTATUS_MIE} else {0};
+								self.trap_csrs.mstatus |= (Privilege::User as u32) << MSTATUS_MPP_SHIFT;
+								if self.privilege < Privilege::Machine {
+									self.trap_csrs.mstatus &= !MSTATUS_MPRV;
+								}
+							},
+							SystemIntFunct7::SFenceVma => {
+								if self.privilege == Privilege::User || (self.privilege == Privilege::Supervisor && self.trap_csrs.mstatus & MSTATUS_TVM != 0) {
+									return self.illegal_instruction(opcode);
+								}
+								self.mmu.flush();
+								self.pc += inst_length;
 							},
 							SystemIntFunct7::Unknown => {
 								return self.illegal_instruction(opcode);
@@ -984,28 +1800,18 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 						}
 					},
 					SystemFunct3::CsrRW => {
-						if cfg!(feature = "cpu_debug") { println!("csrrw"); }
-						let csr_value = if rd != 0 {
+						if rd != 0 {
 							let csr_value = self.read_csr(csr);
 							self.set_gpr(rd, csr_value);
-							csr_value
-						} else {0};
-						let rs1_value = self.get_gpr(rs1);
-						self.pc += 4;
-						if cfg!(feature = "cpu_debug") { 
-							if rd == 0 {
-								println!("{: <50} # CSR {:#05x}   <=  {:#010x} .", format!("csrrw {}, {:#05x}, {}", REG_NAMES[rd as usize], csr, REG_NAMES[rs1 as usize]), csr, rs1_value);
-							} else {
-								println!("{: <50} # {}           <=  {:#010x} . CSR {:#05x}   <=  {:010x} ", format!("csrrw {}, {:#05x}, {}", REG_NAMES[rd as usize], csr, REG_NAMES[rs1 as usize]), REG_NAMES[rd as usize], csr_value, csr, rs1_value);
-							}
 						}
+						let rs1_value = self.get_gpr(rs1);
+						self.pc += inst_length;
 						return self.write_csr(csr, rs1_value);
 					},
 					SystemFunct3::CsrRS => {
-						if cfg!(feature = "cpu_debug") { println!("csrrs"); }
 						let csr_value_old = self.read_csr(csr);
 						self.set_gpr(rd, csr_value_old);
-						self.pc += 4;
+						self.pc += inst_length;
 						if rs1 != 0 {
 							let rs1_value = self.get_gpr(rs1);
 							let updated_value = csr_value_old | rs1_value;
@@ -1013,10 +1819,9 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 						}
 					},
 					SystemFunct3::CsrRC => {
-						if cfg!(feature = "cpu_debug") { println!("csrrc"); }
 						let csr_value_old = self.read_csr(csr);
 						self.set_gpr(rd, csr_value_old);
-						self.pc += 4;
+						self.pc += inst_length;
 						if rs1 != 0 {
 							let rs1_value = self.get_gpr(rs1);
 							let updated_value = csr_value_old & !rs1_value;
@@ -1024,49 +1829,31 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 						}
 					},
 					SystemFunct3::CsrRWI => {
-						let csr_value = if rd != 0 {
+						if rd != 0 {
 							let csr_value_old = self.read_csr(csr);
 							self.set_gpr(rd, csr_value_old);
-							csr_value_old
-						} else { 0 };
-						self.pc += 4;
-						let csr_value_new = if (rs1 & 0x10) != 0 {
-							rs1 & 0xFFFFFFF0
-						} else {
-							rs1
-						};
-						if cfg!(feature = "cpu_debug") { 
-							if rd == 0 {
-								println!("{: <50} # CSR {:#05x}    <=  {:010x}     .", format!("csrrwi {}, {:#05x}, {:#010x}", REG_NAMES[rd as usize], csr, csr_value_new), csr, csr_value_new);
-							} else {
-								println!("{: <50} # {}            <=  {:010x}     CSR {:#05x}    <=  {:010x}     ", format!("csrrwi {}, {:#05x}, {}", REG_NAMES[rd as usize], csr, csr_value_new), REG_NAMES[rd as usize], csr_value, csr, csr_value_new);
-							}
 						}
-						return self.write_csr(csr, csr_value_new);
+						self.pc += inst_length;
+						// the rs1 field is the zero extended immediate
+						return self.write_csr(csr, rs1);
 					},
 					SystemFunct3::CsrRSI => {
-						if cfg!(feature = "cpu_debug") { println!("csrrsi"); }
 						let csr_value_old = self.read_csr(csr);
-						let csr_bits_new = if (rs1 & 0x10) != 0 {
-							rs1 & 0xFFFFFFF0
-						} else {
-							rs1
-						};
-						self.pc += 4;
-						let csr_value_new = csr_value_old | csr_bits_new;
-						return self.write_csr(csr, csr_value_new);
+						self.set_gpr(rd, csr_value_old);
+						self.pc += inst_length;
+						if rs1 != 0 {
+							let csr_value_new = csr_value_old | rs1;
+							return self.write_csr(csr, csr_value_new);
+						}
 					},
 					SystemFunct3::CsrRCI => {
-						if cfg!(feature = "cpu_debug") { println!("csrrci"); }
 						let csr_value_old = self.read_csr(csr);
-						let csr_bits_new = if (rs1 & 0x10) != 0 {
-							rs1 & 0xFFFFFFF0
-						} else {
-							rs1
-						};
-						self.pc += 4;
-						let csr_value_new = csr_value_old & !csr_bits_new;
-						return self.write_csr(csr, csr_value_new);
+						self.set_gpr(rd, csr_value_old);
+						self.pc += inst_length;
+						if rs1 != 0 {
+							let csr_value_new = csr_value_old & !rs1;
+							return self.write_csr(csr, csr_value_new);
+						}
 					},
 					SystemFunct3::Unknown => {
 						return self.illegal_instruction(opcode);
@@ -1079,15 +1866,23 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 				let rs1 = opcode.rs1();
 				let rs2 = opcode.rs2();
 				let rs3 = opcode.rs3();
-				let fp_format = opcode.funct3_fpformat();
+				let fp_format = opcode.funct2_fpformat();
 				match fp_format {
-					FpFormatFunct3::Width32 => {
+					FpFormatFunct2::Single => {
 						let a = self.get_fpr(rs1);
 						let b = self.get_fpr(rs2);
 						let c = self.get_fpr(rs3);
-						let result = a * b + c;
+						let result = a.mul_add(b, c);
 						self.set_fpr(rd, result);
-						self.pc += 4;
+						self.pc += inst_length;
+					},
+					FpFormatFunct2::Double => {
+						let a = self.get_fpr_d(rs1);
+						let b = self.get_fpr_d(rs2);
+						let c = self.get_fpr_d(rs3);
+						let result = a.mul_add(b, c);
+						self.set_fpr_d(rd, result);
+						self.pc += inst_length;
 					},
 					_ => {
 						return self.illegal_instruction(opcode);
@@ -1100,15 +1895,23 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 				let rs1 = opcode.rs1();
 				let rs2 = opcode.rs2();
 				let rs3 = opcode.rs3();
-				let fp_format = opcode.funct3_fpformat();
+				let fp_format = opcode.funct2_fpformat();
 				match fp_format {
-					FpFormatFunct3::Width32 => {
+					FpFormatFunct2::Single => {
 						let a = self.get_fpr(rs1);
 						let b = self.get_fpr(rs2);
 						let c = self.get_fpr(rs3);
-						let result = a * b - c;
+						let result = a.mul_add(b, -c);
 						self.set_fpr(rd, result);
-						self.pc += 4;
+						self.pc += inst_length;
+					},
+					FpFormatFunct2::Double => {
+						let a = self.get_fpr_d(rs1);
+						let b = self.get_fpr_d(rs2);
+						let c = self.get_fpr_d(rs3);
+						let result = a.mul_add(b, -c);
+						self.set_fpr_d(rd, result);
+						self.pc += inst_length;
 					},
 					_ => {
 						return self.illegal_instruction(opcode);
@@ -1121,15 +1924,23 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 				let rs1 = opcode.rs1();
 				let rs2 = opcode.rs2();
 				let rs3 = opcode.rs3();
-				let fp_format = opcode.funct3_fpformat();
+				let fp_format = opcode.funct2_fpformat();
 				match fp_format {
-					FpFormatFunct3::Width32 => {
+					FpFormatFunct2::Single => {
 						let a = self.get_fpr(rs1);
 						let b = self.get_fpr(rs2);
 						let c = self.get_fpr(rs3);
-						let result = - (a * b) - c;
+						let result = (-a).mul_add(b, -c);
 						self.set_fpr(rd, result);
-						self.pc += 4;
+						self.pc += inst_length;
+					},
+					FpFormatFunct2::Double => {
+						let a = self.get_fpr_d(rs1);
+						let b = self.get_fpr_d(rs2);
+						let c = self.get_fpr_d(rs3);
+						let result = (-a).mul_add(b, -c);
+						self.set_fpr_d(rd, result);
+						self.pc += inst_length;
 					},
 					_ => {
 						return self.illegal_instruction(opcode);
@@ -1142,15 +1953,23 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 				let rs1 = opcode.rs1();
 				let rs2 = opcode.rs2();
 				let rs3 = opcode.rs3();
-				let fp_format = opcode.funct3_fpformat();
+				let fp_format = opcode.funct2_fpformat();
 				match fp_format {
-					FpFormatFunct3::Width32 => {
+					FpFormatFunct2::Single => {
 						let a = self.get_fpr(rs1);
 						let b = self.get_fpr(rs2);
 						let c = self.get_fpr(rs3);
-						let result = - (a * b) + c;
+						let result = (-a).mul_add(b, c);
 						self.set_fpr(rd, result);
-						self.pc += 4;
+						self.pc += inst_length;
+					},
+					FpFormatFunct2::Double => {
+						let a = self.get_fpr_d(rs1);
+						let b = self.get_fpr_d(rs2);
+						let c = self.get_fpr_d(rs3);
+						let result = (-a).mul_add(b, c);
+						self.set_fpr_d(rd, result);
+						self.pc += inst_length;
 					},
 					_ => {
 						return self.illegal_instruction(opcode);
@@ -1169,34 +1988,34 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 						let b = self.get_fpr(rs2);
 						let result = a + b;
 						self.set_fpr(rd, result);
-						self.pc += 4;
+						self.pc += inst_length;
 					},
 					FpFunct7::Sub_S => {
 						let a = self.get_fpr(rs1);
 						let b = self.get_fpr(rs2);
 						let result = a - b;
 						self.set_fpr(rd, result);
-						self.pc += 4;
+						self.pc += inst_length;
 					},
 					FpFunct7::Mul_S => {
 						let a = self.get_fpr(rs1);
 						let b = self.get_fpr(rs2);
 						let result = a * b;
 						self.set_fpr(rd, result);
-						self.pc += 4;
+						self.pc += inst_length;
 					},
 					FpFunct7::Div_S => {
 						let a = self.get_fpr(rs1);
 						let b = self.get_fpr(rs2);
 						let result = a / b;
 						self.set_fpr(rd, result);
-						self.pc += 4;
+						self.pc += inst_length;
 					},
 					FpFunct7::Sqrt_S => {
 						let x = self.get_fpr(rs1);
 						let result = f32::sqrt(x);
 						self.set_fpr(rd, result);
-						self.pc += 4;
+						self.pc += inst_length;
 					},
 					FpFunct7::Sign_S => {
 						let sign_source = opcode.funct3_fpsign();
@@ -1229,7 +2048,7 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 							},
 						};
 						self.set_fpr(rd, result);
-						self.pc += 4;
+						self.pc += inst_length;
 					},
 					FpFunct7::MinMax_S => {
 						let a = self.get_fpr(rs1);
@@ -1243,43 +2062,32 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 							}
 						};
 						self.set_fpr(rd, result);
-						self.pc += 4;
+						self.pc += inst_length;
 					},
-					FpFunct7::CvtW_S => {
+					FpFunct7::CvtW_S | FpFunct7::CvtW_D => {
 						let conversion = opcode.rs2_fcvtws();
-						let fp_val = self.get_fpr(rs1);
-						let mut rm = opcode.fp_rm();
-						if let FpRm::Dynamic = rm {
-							rm = FpRm::from_raw((self.fcsr & 0xFF) >> 5)
-						}
-						let fp_val_rounded = match rm {
-							FpRm::ToNearestTieEven => f32::round(fp_val),
-							FpRm::ToNearestTieMaxMagnitude => f32::round(fp_val),
-							FpRm::ToZero => if fp_val >= 0.0 {
-								f32::floor(fp_val)
-							} else {
-								f32::ceil(fp_val)
-							},
-							FpRm::Up => f32::ceil(fp_val),
-							FpRm::Down => f32::floor(fp_val),
-							FpRm::Unknown | FpRm::Dynamic => {
+						// every single is exactly representable as a double, so both formats round the same way
+						let fp_val = if let FpFunct7::CvtW_S = funct7 {
+							self.get_fpr(rs1) as f64
+						} else {
+							self.get_fpr_d(rs1)
+						};
+						let fp_val_rounded = match self.round_for_conversion(&opcode, fp_val) {
+							Some(value) => value,
+							None => {
 								return self.illegal_instruction(opcode);
 							}
 						};
-						match conversion {
-							FCvtType::Signed => {
-								let i32_val = fp_val_rounded as i32;
-								self.set_gpr(rd, i32_val as u32);
-							},
-							FCvtType::Unsigned => {
-								let u32_val = fp_val_rounded as u32;
-								self.set_gpr(rd, u32_val);
-							},
+						let (result, flags) = match conversion {
+							FCvtType::Signed => fp_to_integer(fp_val, fp_val_rounded, true),
+							FCvtType::Unsigned => fp_to_integer(fp_val, fp_val_rounded, false),
 							FCvtType::Unknown => {
 								return self.illegal_instruction(opcode);
 							}
-						}
-						self.pc += 4;
+						};
+						self.set_gpr(rd, result);
+						self.fcsr |= flags;
+						self.pc += inst_length;
 					},
 					FpFunct7::MvXWClass_S => {
 						let sub_op = opcode.funct3_fmvxwclass();
@@ -1288,8 +2096,8 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 								if rs2 != 0 {
 									return self.illegal_instruction(opcode);
 								}
-								let f_val = self.get_fpr(rs1);
-								let i_val = f_val.to_bits();
+								// like fsw, the move takes the low half of the register whether or not it's a boxed single
+								let i_val = self.get_fpr_bits(rs1) as u32;
 								self.set_gpr(rd, i_val);
 							},
 							FMvXWClassFunct3::Class => {
@@ -1297,38 +2105,14 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 									return self.illegal_instruction(opcode);
 								}
 								let val = self.get_fpr(rs1);
-								let result = 1 << match val {
-									_ if val == f32::NEG_INFINITY => 0,
-									_ if val == -0.0 => 3,
-									_ if val == 0.0 => 4,
-									_ if val == f32::INFINITY => 7,
-									v => {
-										if v.is_nan() {
-											8
-										} else {
-											if v.is_normal() {
-												if v.is_negative() {
-													1
-												} else {
-													6
-												}
-											} else {
-												if v.is_negative() {
-													2
-												} else {
-													5
-												}
-											}
-										}
-									}
-								};
+								let result = fp_class(val.classify(), val.is_sign_negative(), val.to_bits() & 0x0040_0000 != 0);
 								self.set_gpr(rd, result);
 							},
 							FMvXWClassFunct3::Unknown => {
 								return self.illegal_instruction(opcode);
 							}
 						}
-						self.pc += 4;
+						self.pc += inst_length;
 					},
 					FpFunct7::Cmp_S => {
 						let a = self.get_fpr(rs1);
@@ -1343,7 +2127,7 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 							}
 						};
 						self.set_gpr(rd, if result {1} else {0});
-						self.pc += 4;
+						self.pc += inst_length;
 					},
 					FpFunct7::CvtS_W => {
 						let conversion = opcode.rs2_fcvtws();
@@ -1361,7 +2145,7 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 							}
 						};
 						self.set_fpr(rd, result);
-						self.pc += 4;
+						self.pc += inst_length;
 					},
 					FpFunct7::MvWX_S => {
 						if rs2 != 0 {
@@ -1370,38 +2154,165 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 						let i_val = self.get_gpr(rs1);
 						let f_val = f32::from_bits(i_val);
 						self.set_fpr(rd, f_val);
-						self.pc += 4;
+						self.pc += inst_length;
 					},
-					FpFunct7::Unknown => {
-						return self.illegal_instruction(opcode);
-					}
-				}
-			},
-			Op::Fence => {
-				self.pc += 4; // No-op since our memory and peripherals are sequentially consistent :)	
-			},
-			Op::Atomic => {
-				let atomic_op = opcode.funct7_atomic();
-				let size = opcode.funct3_atomicsize();
-				let rd = opcode.rd();
-				let rs1 = opcode.rs1();
-				let rs2 = opcode.rs1();
-				match atomic_op {
-					AtomicFunct7::LoadReserve => {
+					FpFunct7::Add_D => {
+						let a = self.get_fpr_d(rs1);
+						let b = self.get_fpr_d(rs2);
+						let result = a + b;
+						self.set_fpr_d(rd, result);
+						self.pc += inst_length;
+					},
+					FpFunct7::Sub_D => {
+						let a = self.get_fpr_d(rs1);
+						let b = self.get_fpr_d(rs2);
+						let result = a - b;
+						self.set_fpr_d(rd, result);
+						self.pc += inst_length;
+					},
+					FpFunct7::Mul_D => {
+						let a = self.get_fpr_d(rs1);
+						let b = self.get_fpr_d(rs2);
+						let result = a * b;
+						self.set_fpr_d(rd, result);
+						self.pc += inst_length;
+					},
+					FpFunct7::Div_D => {
+						let a = self.get_fpr_d(rs1);
+						let b = self.get_fpr_d(rs2);
+						let result = a / b;
+						self.set_fpr_d(rd, result);
+						self.pc += inst_length;
+					},
+					FpFunct7::Sqrt_D => {
 						if rs2 != 0 {
 							return self.illegal_instruction(opcode);
 						}
-						match size {
-							AtomicSizeFunct3::Word => {
-								let load_addr = self.get_gpr(rs1);
-								let (load_result, write_cycle, write_key) = self.mio.read_32_ll(load_addr);
-								match load_result {
-									MemReadResult::Ok(value) => {
-										self.write_csr(rd, value);
-										self.lr_write_cycle = write_cycle;
-										self.lr_write_key = write_key;
-									},
-									MemReadResult::ErrAlignment => {
+						let x = self.get_fpr_d(rs1);
+						let result = f64::sqrt(x);
+						self.set_fpr_d(rd, result);
+						self.pc += inst_length;
+					},
+					FpFunct7::Sign_D => {
+						let a = self.get_fpr_d(rs1);
+						let b = self.get_fpr_d(rs2);
+						let result = match opcode.funct3_fpsign() {
+							FpSignFunct3::SignFromRs2 => a.copysign(b),
+							FpSignFunct3::SignFromNotRs2 => a.copysign(-b),
+							FpSignFunct3::SignFromRs1XorRs2 => if b.is_sign_negative() { -a } else { a },
+							FpSignFunct3::Unknown => {
+								return self.illegal_instruction(opcode);
+							},
+						};
+						self.set_fpr_d(rd, result);
+						self.pc += inst_length;
+					},
+					FpFunct7::MinMax_D => {
+						let a = self.get_fpr_d(rs1);
+						let b = self.get_fpr_d(rs2);
+						let result = match opcode.funct3_fpminmax() {
+							FpMinMaxFunct3::Min => f64::min(a, b),
+							FpMinMaxFunct3::Max => f64::max(a, b),
+							FpMinMaxFunct3::Unknown => {
+								return self.illegal_instruction(opcode);
+							}
+						};
+						self.set_fpr_d(rd, result);
+						self.pc += inst_length;
+					},
+					FpFunct7::CvtS_D => {
+						if rs2 != 1 {
+							return self.illegal_instruction(opcode);
+						}
+						let result = self.get_fpr_d(rs1) as f32;
+						self.set_fpr(rd, result);
+						self.pc += inst_length;
+					},
+					FpFunct7::CvtD_S => {
+						if rs2 != 0 {
+							return self.illegal_instruction(opcode);
+						}
+						let result = self.get_fpr(rs1) as f64;
+						self.set_fpr_d(rd, result);
+						self.pc += inst_length;
+					},
+					FpFunct7::Class_D => {
+						// RV32 has no fmv.x.d, so fclass.d is the only op here
+						if rs2 != 0 || opcode.funct3() != 0b001 {
+							return self.illegal_instruction(opcode);
+						}
+						let val = self.get_fpr_d(rs1);
+						let result = fp_class(val.classify(), val.is_sign_negative(), val.to_bits() & 0x0008_0000_0000_0000 != 0);
+						self.set_gpr(rd, result);
+						self.pc += inst_length;
+					},
+					FpFunct7::Cmp_D => {
+						let a = self.get_fpr_d(rs1);
+						let b = self.get_fpr_d(rs2);
+						let result = match opcode.funct3_fpcmp() {
+							FpCmpFunct3::Eq => a == b,
+							FpCmpFunct3::Lt => a < b,
+							FpCmpFunct3::LEq => a <= b,
+							FpCmpFunct3::Unknown => {
+								return self.illegal_instruction(opcode);
+							}
+						};
+						self.set_gpr(rd, if result {1} else {0});
+						self.pc += inst_length;
+					},
+					FpFunct7::CvtD_W => {
+						// every 32 bit integer is exact as a double, so the rounding mode doesn't matter
+						let result = match opcode.rs2_fcvtws() {
+							FCvtType::Signed => (self.get_gpr(rs1) as i32) as f64,
+							FCvtType::Unsigned => self.get_gpr(rs1) as f64,
+							FCvtType::Unknown => {
+								return self.illegal_instruction(opcode);
+							}
+						};
+						self.set_fpr_d(rd, result);
+						self.pc += inst_length;
+					},
+					FpFunct7::Unknown => {
+						return self.illegal_instruction(opcode);
+					}
+				}
+			},
+			Op::Fence => {
+				if opcode.funct3() == FENCE_I_FUNCT3 {
+					// fence.i: instruction writes are only guaranteed visible to this hart after a flush
+					self.flush_code_caches();
+				}
+				self.pc += inst_length; // No-op since our memory and peripherals are sequentially consistent :)	
+			},
+			Op::Atomic => {
+				let atomic_op = opcode.funct7_atomic();
+				let size = opcode.funct3_atomicsize();
+				let rd = opcode.rd();
+				let rs1 = opcode.rs1();
+				let rs2 = opcode.rs2();
+				let access = if let AtomicFunct7::LoadReserve = atomic_op { PmpAccess::Read } else { PmpAccess::Write };
+				// the bus sees the physical address, everything the program sees keeps the virtual one
+				let physical = match self.data_address(self.get_gpr(rs1), 4, access) {
+					Some(physical) => physical,
+					None => return false
+				};
+				match atomic_op {
+					AtomicFunct7::LoadReserve => {
+						if rs2 != 0 {
+							return self.illegal_instruction(opcode);
+						}
+						match size {
+							AtomicSizeFunct3::Word => {
+								let load_addr = self.get_gpr(rs1);
+								self.record_mem_access(load_addr, 4, WatchKind::Read);
+								let (load_result, write_cycle, write_key) = self.bus_read_32_ll(physical);
+								match load_result {
+									MemReadResult::Ok(value) => {
+										self.set_gpr(rd, value);
+										self.lr_write_cycle = write_cycle;
+										self.lr_write_key = write_key;
+									},
+									MemReadResult::ErrAlignment => {
 										self.pending_exception = Some(Exception::LoadAddressMisaligned {
 											instr_addr: self.pc,
 											load_addr: load_addr
@@ -1424,13 +2335,15 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 					},
 					AtomicFunct7::StoreConditional => {
 						let store_addr = self.get_gpr(rs1);
+						self.record_mem_access(store_addr, 4, WatchKind::Write);
 						let store_value = self.get_gpr(rs2);
 						let write_key = self.lr_write_key;
 						self.lr_write_key = 0xFFFF_FFFF;
-						if let Some(write_result) = self.mio.write_32_cs(store_addr, store_value, self.lr_write_cycle, write_key) {
+						if let Some(write_result) = self.bus_write_32_cs(physical, store_value, self.lr_write_cycle, write_key) {
 							match write_result {
 								MemWriteResult::Ok => {
 									self.set_gpr(rd, 0);
+									self.hpm.count(HpmEvent::Store);
 								},
 								MemWriteResult::ErrAlignment => {
 									self.pending_exception = Some(Exception::StoreAddressMisaligned {
@@ -1449,6 +2362,7 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 							}
 						} else {
 							self.set_gpr(rd, 1);
+							self.hpm.count(HpmEvent::StoreConditionalFail);
 						}
 					},
 					AtomicFunct7::Swap => {
@@ -1460,7 +2374,8 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 							});
 							return false;
 						}
-						match self.mio.lock_for_modify(swap_addr) {
+						self.record_mem_access(swap_addr, 4, WatchKind::ReadWrite);
+						match self.bus_lock_for_modify(physical) {
 							MemWriteResult::Ok => {},
 							_ => {
 								self.pending_exception = Some(Exception::StoreAccessFault {
@@ -1471,9 +2386,9 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 							}
 						}
 						let new_value = self.get_gpr(rs2);
-						let old_value = self.mio.read_32(swap_addr).unwrap();
+						let old_value = self.bus_read_32(physical).unwrap();
 						self.set_gpr(rd, old_value);
-						self.mio.write_32(swap_addr, new_value);
+						self.bus_write_32(physical, new_value);
 					},
 					AtomicFunct7::Add => {
 						let swap_addr = self.get_gpr(rs1);
@@ -1484,7 +2399,8 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 							});
 							return false;
 						}
-						match self.mio.lock_for_modify(swap_addr) {
+						self.record_mem_access(swap_addr, 4, WatchKind::ReadWrite);
+						match self.bus_lock_for_modify(physical) {
 							MemWriteResult::Ok => {},
 							_ => {
 								self.pending_exception = Some(Exception::StoreAccessFault {
@@ -1495,9 +2411,9 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 							}
 						}
 						let new_value = self.get_gpr(rs2);
-						let old_value = self.mio.read_32(swap_addr).unwrap();
+						let old_value = self.bus_read_32(physical).unwrap();
 						self.set_gpr(rd, old_value);
-						self.mio.write_32(swap_addr, old_value.wrapping_add(new_value)).unwrap();
+						self.bus_write_32(physical, old_value.wrapping_add(new_value)).unwrap();
 						
 					},
 					AtomicFunct7::Xor => {
@@ -1517,7 +2433,8 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 							});
 							return false;
 						}
-						match self.mio.lock_for_modify(swap_addr) {
+						self.record_mem_access(swap_addr, 4, WatchKind::ReadWrite);
+						match self.bus_lock_for_modify(physical) {
 							MemWriteResult::Ok => {},
 							_ => {
 								self.pending_exception = Some(Exception::StoreAccessFault {
@@ -1528,9 +2445,9 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 							}
 						}
 						let new_value = self.get_gpr(rs2);
-						let old_value = self.mio.read_32(swap_addr).unwrap();
+						let old_value = self.bus_read_32(physical).unwrap();
 						self.set_gpr(rd, old_value);
-						self.mio.write_32(swap_addr, old_value ^ new_value).unwrap();
+						self.bus_write_32(physical, old_value ^ new_value).unwrap();
 					},
 					AtomicFunct7::And => {
 						let swap_addr = self.get_gpr(rs1);
@@ -1549,7 +2466,8 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 							});
 							return false;
 						}
-						match self.mio.lock_for_modify(swap_addr) {
+						self.record_mem_access(swap_addr, 4, WatchKind::ReadWrite);
+						match self.bus_lock_for_modify(physical) {
 							MemWriteResult::Ok => {},
 							_ => {
 								self.pending_exception = Some(Exception::StoreAccessFault {
@@ -1560,9 +2478,9 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 							}
 						}
 						let new_value = self.get_gpr(rs2);
-						let old_value = self.mio.read_32(swap_addr).unwrap();
+						let old_value = self.bus_read_32(physical).unwrap();
 						self.set_gpr(rd, old_value);
-						self.mio.write_32(swap_addr, old_value & new_value).unwrap();
+						self.bus_write_32(physical, old_value & new_value).unwrap();
 					},
 					AtomicFunct7::Or => {
 						let swap_addr = self.get_gpr(rs1);
@@ -1581,7 +2499,8 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 							});
 							return false;
 						}
-						match self.mio.lock_for_modify(swap_addr) {
+						self.record_mem_access(swap_addr, 4, WatchKind::ReadWrite);
+						match self.bus_lock_for_modify(physical) {
 							MemWriteResult::Ok => {},
 							_ => {
 								self.pending_exception = Some(Exception::StoreAccessFault {
@@ -1592,9 +2511,9 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 							}
 						}
 						let new_value = self.get_gpr(rs2);
-						let old_value = self.mio.read_32(swap_addr).unwrap();
+						let old_value = self.bus_read_32(physical).unwrap();
 						self.set_gpr(rd, old_value);
-						self.mio.write_32(swap_addr, old_value | new_value).unwrap();
+						self.bus_write_32(physical, old_value | new_value).unwrap();
 					},
 					AtomicFunct7::Min => {
 						let swap_addr = self.get_gpr(rs1);
@@ -1613,7 +2532,8 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 							});
 							return false;
 						}
-						match self.mio.lock_for_modify(swap_addr) {
+						self.record_mem_access(swap_addr, 4, WatchKind::ReadWrite);
+						match self.bus_lock_for_modify(physical) {
 							MemWriteResult::Ok => {},
 							_ => {
 								self.pending_exception = Some(Exception::StoreAccessFault {
@@ -1624,9 +2544,9 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 							}
 						}
 						let new_value = self.get_gpr(rs2);
-						let old_value = self.mio.read_32(swap_addr).unwrap();
+						let old_value = self.bus_read_32(physical).unwrap();
 						self.set_gpr(rd, old_value);
-						self.mio.write_32(swap_addr, (old_value as i32).min(new_value as i32) as u32).unwrap();
+						self.bus_write_32(physical, (old_value as i32).min(new_value as i32) as u32).unwrap();
 					},
 					AtomicFunct7::Max => {
 						let swap_addr = self.get_gpr(rs1);
@@ -1645,7 +2565,8 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 							});
 							return false;
 						}
-						match self.mio.lock_for_modify(swap_addr) {
+						self.record_mem_access(swap_addr, 4, WatchKind::ReadWrite);
+						match self.bus_lock_for_modify(physical) {
 							MemWriteResult::Ok => {},
 							_ => {
 								self.pending_exception = Some(Exception::StoreAccessFault {
@@ -1656,9 +2577,9 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 							}
 						}
 						let new_value = self.get_gpr(rs2);
-						let old_value = self.mio.read_32(swap_addr).unwrap();
+						let old_value = self.bus_read_32(physical).unwrap();
 						self.set_gpr(rd, old_value);
-						self.mio.write_32(swap_addr, (old_value as i32).max(new_value as i32) as u32).unwrap();
+						self.bus_write_32(physical, (old_value as i32).max(new_value as i32) as u32).unwrap();
 					},
 					AtomicFunct7::MinU => {
 						let swap_addr = self.get_gpr(rs1);
@@ -1677,7 +2598,8 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 							});
 							return false;
 						}
-						match self.mio.lock_for_modify(swap_addr) {
+						self.record_mem_access(swap_addr, 4, WatchKind::ReadWrite);
+						match self.bus_lock_for_modify(physical) {
 							MemWriteResult::Ok => {},
 							_ => {
 								self.pending_exception = Some(Exception::StoreAccessFault {
@@ -1688,9 +2610,9 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 							}
 						}
 						let new_value = self.get_gpr(rs2);
-						let old_value = self.mio.read_32(swap_addr).unwrap();
+						let old_value = self.bus_read_32(physical).unwrap();
 						self.set_gpr(rd, old_value);
-						self.mio.write_32(swap_addr, old_value.min(new_value)).unwrap();
+						self.bus_write_32(physical, old_value.min(new_value)).unwrap();
 					},
 					AtomicFunct7::MaxU => {
 						let swap_addr = self.get_gpr(rs1);
@@ -1709,7 +2631,8 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 							});
 							return false;
 						}
-						match self.mio.lock_for_modify(swap_addr) {
+						self.record_mem_access(swap_addr, 4, WatchKind::ReadWrite);
+						match self.bus_lock_for_modify(physical) {
 							MemWriteResult::Ok => {},
 							_ => {
 								self.pending_exception = Some(Exception::StoreAccessFault {
@@ -1720,15 +2643,24 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 							}
 						}
 						let new_value = self.get_gpr(rs2);
-						let old_value = self.mio.read_32(swap_addr).unwrap();
+						let old_value = self.bus_read_32(physical).unwrap();
 						self.set_gpr(rd, old_value);
-						self.mio.write_32(swap_addr, old_value.max(new_value)).unwrap();
+						self.bus_write_32(physical, old_value.max(new_value)).unwrap();
 					},
 					AtomicFunct7::Unknown => {
 						return self.illegal_instruction(opcode);
 					},
 				}
-				self.pc += 4;
+				match atomic_op {
+					AtomicFunct7::LoadReserve => self.hpm.count(HpmEvent::Load),
+					// counted where it succeeds
+					AtomicFunct7::StoreConditional => {},
+					_ => {
+						self.hpm.count(HpmEvent::Load);
+						self.hpm.count(HpmEvent::Store);
+					}
+				}
+				self.pc += inst_length;
 			},
 			Op::Unknown => {
 				return self.illegal_instruction(opcode);
@@ -1738,7 +2670,6 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 	}
 	
 	fn illegal_instruction(&mut self, opcode: Opcode) -> bool {
-		if cfg!(feature = "cpu_debug") { println!("unknown opcode: {:#08}", opcode.value); }
 		self.pending_exception = Some(Exception::IllegalInstruction{
 			op: opcode.value,
 			addr: self.pc
@@ -1746,17 +2677,591 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 		return false
 	}
 	
+	fn load_result<T>(&mut self, address: u32, result: MemReadResult<T>) -> Option<T> {
+		self.record_mem_access(address, std::mem::size_of::<T>() as u32, WatchKind::Read);
+		match result {
+			MemReadResult::Ok(value) => {
+				self.hpm.count(HpmEvent::Load);
+				Some(value)
+			},
+			MemReadResult::ErrAlignment => {
+				self.pending_exception = Some(Exception::LoadAddressMisaligned{
+					instr_addr: self.pc,
+					load_addr: address
+				});
+				None
+			},
+			_ => {
+				self.pending_exception = Some(Exception::LoadAccessFault{
+					instr_addr: self.pc,
+					load_addr: address
+				});
+				None
+			}
+		}
+	}
+	
+	fn store_result(&mut self, address: u32, size: u32, result: MemWriteResult) -> bool {
+		self.record_mem_access(address, size, WatchKind::Write);
+		match result {
+			MemWriteResult::Ok => {
+				self.hpm.count(HpmEvent::Store);
+				true
+			},
+			MemWriteResult::ErrAlignment => {
+				self.pending_exception = Some(Exception::StoreAddressMisaligned{
+					instr_addr: self.pc,
+					store_addr: address
+				});
+				false
+			},
+			_ => {
+				self.pending_exception = Some(Exception::StoreAccessFault{
+					instr_addr: self.pc,
+					store_addr: address
+				});
+				false
+			}
+		}
+	}
+	
+	// the bus as the hart sees it, which while replaying is the log. loads and atomics are logged by
+	// value, and stores with the value they replaced
+	fn bus_read_8(&mut self, address: u32) -> MemReadResult<u8> {
+		if let Some(input) = self.replay.as_mut().and_then(|replay| replay.next_input()) {
+			return input.to_read(|value| value as u8);
+		}
+		let result = self.mio.read_8(address);
+		if let Some(replay) = &mut self.replay {
+			replay.record_input(ReplayInput::from_read(&result));
+		}
+		result
+	}
+	
+	fn bus_read_16(&mut self, address: u32) -> MemReadResult<u16> {
+		if let Some(input) = self.replay.as_mut().and_then(|replay| replay.next_input()) {
+			return input.to_read(|value| value as u16);
+		}
+		let result = self.mio.read_16(address);
+		if let Some(replay) = &mut self.replay {
+			replay.record_input(ReplayInput::from_read(&result));
+		}
+		result
+	}
+	
+	fn bus_read_32(&mut self, address: u32) -> MemReadResult<u32> {
+		if let Some(input) = self.replay.as_mut().and_then(|replay| replay.next_input()) {
+			return input.to_read(|value| value);
+		}
+		let result = self.mio.read_32(address);
+		if let Some(replay) = &mut self.replay {
+			replay.record_input(ReplayInput::from_read(&result));
+		}
+		result
+	}
+	
+	// the reservation is logged too, so a store conditional made live after replay catches up still matches it
+	fn bus_read_32_ll(&mut self, address: u32) -> (MemReadResult<u32>, usize, u32) {
+		if let Some(replay) = &mut self.replay {
+			if let Some(input) = replay.next_input() {
+				let mut reservation = [0u32; 3];
+				for value in reservation.iter_mut() {
+					if let Some(ReplayInput::Value(logged)) = replay.next_input() {
+						*value = logged;
+					}
+				}
+				let write_cycle = ((reservation[1] as u64) << 32 | reservation[0] as u64) as usize;
+				return (input.to_read(|value| value), write_cycle, reservation[2]);
+			}
+		}
+		let (result, write_cycle, write_key) = self.mio.read_32_ll(address);
+		if let Some(replay) = &mut self.replay {
+			replay.record_input(ReplayInput::from_read(&result));
+			replay.record_input(ReplayInput::Value(write_cycle as u32));
+			replay.record_input(ReplayInput::Value((write_cycle as u64 >> 32) as u32));
+			replay.record_input(ReplayInput::Value(write_key));
+		}
+		(result, write_cycle, write_key)
+	}
+	
+	fn bus_lock_for_modify(&mut self, address: u32) -> MemWriteResult {
+		if let Some(input) = self.replay.as_mut().and_then(|replay| replay.next_input()) {
+			return input.to_write();
+		}
+		let result = self.mio.lock_for_modify(address);
+		if let Some(replay) = &mut self.replay {
+			replay.record_input(ReplayInput::from_write(&result));
+		}
+		result
+	}
+	
+	// peripherals can't be read without side effects, so only memory keeps the value a store replaced
+	fn replaced_value(&self, address: u32, size: u32) -> Option<u32> {
+		if self.replay.is_none() || ! self.mio.is_plain_memory(address, size) {
+			return None;
+		}
+		let result = match size {
+			1 => ReplayInput::from_read(&self.mio.read_8(address)),
+			2 => ReplayInput::from_read(&self.mio.read_16(address)),
+			_ => ReplayInput::from_read(&self.mio.read_32(address)),
+		};
+		match result {
+			ReplayInput::Value(value) => Some(value),
+			_ => None
+		}
+	}
+	
+	fn record_store(&mut self, address: u32, size: u32, old_value: Option<u32>, result: &MemWriteResult) {
+		if let Some(replay) = &mut self.replay {
+			replay.record_store(address, size, old_value, result);
+		}
+	}
+	
+	fn bus_write_8(&mut self, address: u32, value: u8) -> MemWriteResult {
+		if let Some(result) = self.replay.as_mut().and_then(|replay| replay.next_store()) {
+			return result;
+		}
+		let old_value = self.replaced_value(address, 1);
+		let result = self.mio.write_8(address, value);
+		self.record_store(address, 1, old_value, &result);
+		result
+	}
+	
+	fn bus_write_16(&mut self, address: u32, value: u16) -> MemWriteResult {
+		if let Some(result) = self.replay.as_mut().and_then(|replay| replay.next_store()) {
+			return result;
+		}
+		let old_value = self.replaced_value(address, 2);
+		let result = self.mio.write_16(address, value);
+		self.record_store(address, 2, old_value, &result);
+		result
+	}
+	
+	fn bus_write_32(&mut self, address: u32, value: u32) -> MemWriteResult {
+		if let Some(result) = self.replay.as_mut().and_then(|replay| replay.next_store()) {
+			return result;
+		}
+		let old_value = self.replaced_value(address, 4);
+		let result = self.mio.write_32(address, value);
+		self.record_store(address, 4, old_value, &result);
+		result
+	}
+	
+	// logged as whether the reservation held, followed by the store if it did
+	fn bus_write_32_cs(&mut self, address: u32, value: u32, ll_cycle: usize, page_key: u32) -> Option<MemWriteResult> {
+		if let Some(input) = self.replay.as_mut().and_then(|replay| replay.next_input()) {
+			if input != ReplayInput::Value(1) {
+				return None;
+			}
+			return self.replay.as_mut().and_then(|replay| replay.next_store());
+		}
+		let old_value = self.replaced_value(address, 4);
+		let result = self.mio.write_32_cs(address, value, ll_cycle, page_key);
+		if let Some(replay) = &mut self.replay {
+			replay.record_input(ReplayInput::Value(result.is_some() as u32));
+		}
+		if let Some(result) = &result {
+			self.record_store(address, 4, old_value, result);
+		}
+		result
+	}
+	
+	fn load_8(&mut self, address: u32) -> Option<u8> {
+		let physical = self.data_address(address, 1, PmpAccess::Read)?;
+		let result = self.bus_read_8(physical);
+		self.load_result(address, result)
+	}
+	
+	fn load_16(&mut self, address: u32) -> Option<u16> {
+		if self.crosses_page(address, 2) {
+			if self.emulate_misaligned {
+				return self.load_bytes(address, 2).map(|value| value as u16);
+			}
+			return self.load_result(address, MemReadResult::ErrAlignment);
+		}
+		let physical = self.data_address(address, 2, PmpAccess::Read)?;
+		match self.bus_read_16(physical) {
+			MemReadResult::ErrAlignment if self.emulate_misaligned => self.load_bytes(address, 2).map(|value| value as u16),
+			result => self.load_result(address, result)
+		}
+	}
+	
+	fn load_32(&mut self, address: u32) -> Option<u32> {
+		self.load_word(address)
+	}
+	
+	fn load_word(&mut self, address: u32) -> Option<u32> {
+		if self.crosses_page(address, 4) {
+			if self.emulate_misaligned {
+				return self.load_bytes(address, 4);
+			}
+			return self.load_result(address, MemReadResult::ErrAlignment);
+		}
+		let physical = self.data_address(address, 4, PmpAccess::Read)?;
+		match self.bus_read_32(physical) {
+			MemReadResult::ErrAlignment if self.emulate_misaligned => self.load_bytes(address, 4),
+			result => self.load_result(address, result)
+		}
+	}
+	
+	// a misaligned access the bus rejected, or one which crosses into another page, made a byte at a time
+	fn load_bytes(&mut self, address: u32, size: u32) -> Option<u32> {
+		let mut value = 0;
+		for i in 0 .. size {
+			let byte_address = address.wrapping_add(i);
+			let physical = self.data_address(byte_address, 1, PmpAccess::Read)?;
+			let result = self.bus_read_8(physical);
+			value |= (self.load_result(byte_address, result)? as u32) << (i * 8);
+		}
+		if let Some(trace) = &mut self.trace {
+			trace.record_mem_access(address);
+		}
+		Some(value)
+	}
+	
+	// the bus has no 64 bit access, so doubles move as two words, low word first
+	fn load_64(&mut self, address: u32) -> Option<u64> {
+		if self.data_pmp_enforced() && ! self.data_translating() && ! self.pmp_check_access(address, 8, PmpAccess::Read) {
+			return None;
+		}
+		let low = self.load_word(address)?;
+		let high = self.load_word(address.wrapping_add(4))?;
+		if let Some(trace) = &mut self.trace {
+			trace.record_mem_access(address);
+		}
+		Some(low as u64 | (high as u64) << 32)
+	}
+	
+	fn store_8(&mut self, address: u32, value: u8) -> bool {
+		let physical = match self.data_address(address, 1, PmpAccess::Write) {
+			Some(physical) => physical,
+			None => return false
+		};
+		let result = self.bus_write_8(physical, value);
+		self.store_result(address, 1, result)
+	}
+	
+	fn store_16(&mut self, address: u32, value: u16) -> bool {
+		if self.crosses_page(address, 2) {
+			if self.emulate_misaligned {
+				return self.store_bytes(address, 2, value as u32);
+			}
+			return self.store_result(address, 2, MemWriteResult::ErrAlignment);
+		}
+		let physical = match self.data_address(address, 2, PmpAccess::Write) {
+			Some(physical) => physical,
+			None => return false
+		};
+		match self.bus_write_16(physical, value) {
+			MemWriteResult::ErrAlignment if self.emulate_misaligned => self.store_bytes(address, 2, value as u32),
+			result => self.store_result(address, 2, result)
+		}
+	}
+	
+	fn store_32(&mut self, address: u32, value: u32) -> bool {
+		self.store_word(address, value)
+	}
+	
+	fn store_word(&mut self, address: u32, value: u32) -> bool {
+		if self.crosses_page(address, 4) {
+			if self.emulate_misaligned {
+				return self.store_bytes(address, 4, value);
+			}
+			return self.store_result(address, 4, MemWriteResult::ErrAlignment);
+		}
+		let physical = match self.data_address(address, 4, PmpAccess::Write) {
+			Some(physical) => physical,
+			None => return false
+		};
+		match self.bus_write_32(physical, value) {
+			MemWriteResult::ErrAlignment if self.emulate_misaligned => self.store_bytes(address, 4, value),
+			result => self.store_result(address, 4, result)
+		}
+	}
+	
+	// every byte is translated and checked before any is written, so a fault on a later byte leaves memory as it
+	// was. a store only partly in ram would fail on the bus part way through, so it faults up front as well.
+	fn store_bytes(&mut self, address: u32, size: u32, value: u32) -> bool {
+		let mut physical = [0u32; 4];
+		for i in 0 .. size {
+			physical[i as usize] = match self.data_address(address.wrapping_add(i), 1, PmpAccess::Write) {
+				Some(physical) => physical,
+				None => return false
+			};
+		}
+		let physical = &physical[.. size as usize];
+		let in_ram = physical.iter().filter(|&&byte| self.mio.is_plain_memory(byte, 1)).count();
+		if in_ram != 0 && in_ram != physical.len() {
+			return self.store_result(address, size, MemWriteResult::ErrUnmapped);
+		}
+		for (i, &byte) in physical.iter().enumerate() {
+			let result = self.bus_write_8(byte, (value >> (i * 8)) as u8);
+			if ! self.store_result(address.wrapping_add(i as u32), 1, result) {
+				return false;
+			}
+		}
+		if let Some(trace) = &mut self.trace {
+			trace.record_mem_access(address);
+		}
+		true
+	}
+	
+	fn store_64(&mut self, address: u32, value: u64) -> bool {
+		// checked whole so a double isn't left half stored, which paging can't promise across a page
+		if self.data_pmp_enforced() && ! self.data_translating() && ! self.pmp_check_access(address, 8, PmpAccess::Write) {
+			return false;
+		}
+		if ! self.store_word(address, value as u32) || ! self.store_word(address.wrapping_add(4), (value >> 32) as u32) {
+			return false;
+		}
+		if let Some(trace) = &mut self.trace {
+			trace.record_mem_access(address);
+		}
+		true
+	}
+	
+	fn pmp_enforced(&self) -> bool {
+		self.privilege < Privilege::Machine || self.pmp.checks_machine_mode()
+	}
+	
+	fn pmp_check(&self, address: u32, size: u32, access: PmpAccess) -> bool {
+		self.pmp.check(address, size, access, self.privilege == Privilege::Machine)
+	}
+	
+	// loads and stores from machine mode are made with mpp's privilege while mprv is set
+	fn data_privilege(&self) -> Privilege {
+		if self.privilege == Privilege::Machine && self.trap_csrs.mstatus & MSTATUS_MPRV != 0 {
+			Privilege::from_raw((self.trap_csrs.mstatus & MSTATUS_MPP) >> MSTATUS_MPP_SHIFT).unwrap_or(Privilege::User)
+		} else {
+			self.privilege
+		}
+	}
+	
+	fn data_pmp_enforced(&self) -> bool {
+		self.data_privilege() < Privilege::Machine || self.pmp.checks_machine_mode()
+	}
+	
+	fn data_pmp_check(&self, address: u32, size: u32, access: PmpAccess) -> bool {
+		self.pmp.check(address, size, access, self.data_privilege() == Privilege::Machine)
+	}
+	
+	// raises the access fault for a load or store which protection doesn't allow
+	fn pmp_check_access(&mut self, address: u32, size: u32, access: PmpAccess) -> bool {
+		if self.data_pmp_check(address, size, access) {
+			return true;
+		}
+		self.data_fault(address, access, false);
+		false
+	}
+	
+	fn data_fault(&mut self, address: u32, access: PmpAccess, page_fault: bool) {
+		if let Some(trace) = &mut self.trace {
+			trace.record_mem_access(address);
+		}
+		self.pending_exception = Some(match (access, page_fault) {
+			(PmpAccess::Read, false) => Exception::LoadAccessFault {
+				instr_addr: self.pc,
+				load_addr: address
+			},
+			(PmpAccess::Read, true) => Exception::LoadPageFault {
+				instr_addr: self.pc,
+				load_addr: address
+			},
+			(_, false) => Exception::StoreAccessFault {
+				instr_addr: self.pc,
+				store_addr: address
+			},
+			(_, true) => Exception::StorePageFault {
+				instr_addr: self.pc,
+				store_addr: address
+			},
+		});
+	}
+	
+	// paging applies to everything below machine mode once satp turns it on
+	fn translating(&self) -> bool {
+		self.privilege < Privilege::Machine && self.mmu.is_enabled()
+	}
+	
+	fn data_translating(&self) -> bool {
+		self.data_privilege() < Privilege::Machine && self.mmu.is_enabled()
+	}
+	
+	// the halves of an access crossing into another page can be anywhere in physical memory, so it's only
+	// made as bytes when misaligned accesses are emulated, and is otherwise a misaligned exception
+	fn crosses_page(&self, address: u32, size: u32) -> bool {
+		self.data_translating() && (address & (PAGE_SIZE - 1)) + size > PAGE_SIZE
+	}
+	
+	// fetches only translate below machine mode, where the data privilege is the hart's own
+	fn page_access_mode(&self) -> PageAccessMode {
+		PageAccessMode {
+			user: self.data_privilege() == Privilege::User,
+			supervisor_user_access: self.trap_csrs.mstatus & MSTATUS_SUM != 0,
+			make_executable_readable: self.trap_csrs.mstatus & MSTATUS_MXR != 0,
+		}
+	}
+	
+	// walks aren't logged for replay, only the pages they read noted, and the accessed and dirty bits a replayed
+	// walk would set are already in memory
+	fn translate(&mut self, address: u32, access: PmpAccess) -> Result<u32, TranslateError> {
+		let mode = self.page_access_mode();
+		let mio = &self.mio;
+		let pmp = &self.pmp;
+		let mut pte_addresses = [None; 2];
+		let result = self.mmu.translate(address, access, mode, |pte_address| {
+			if let Some(slot) = pte_addresses.iter_mut().find(|slot| slot.is_none()) {
+				*slot = Some(pte_address);
+			}
+			if ! pmp.check(pte_address, 4, PmpAccess::Read, false) {
+				return None;
+			}
+			match mio.read_32(pte_address) {
+				MemReadResult::Ok(pte) => Some(pte),
+				_ => None
+			}
+		});
+		for pte_address in pte_addresses.iter().flatten() {
+			self.replay_note_page_read(*pte_address);
+		}
+		let (physical, update) = result?;
+		if let Some((pte_address, pte)) = update {
+			if ! self.is_replaying() {
+				if ! self.pmp.check(pte_address, 4, PmpAccess::Write, false) {
+					return Err(TranslateError::AccessFault);
+				}
+				if let MemWriteResult::Ok = self.mio.write_32(pte_address, pte) {} else {
+					return Err(TranslateError::AccessFault);
+				}
+				if self.replay.is_some() {
+					// let go of the page so the write cycle the update bumped can be read back
+					self.mio.access_break();
+					let write_cycle = self.mio.code_write_cycle(pte_address);
+					if let Some(replay) = &mut self.replay {
+						replay.record_page_table_update(pte_address / PAGE_SIZE, write_cycle);
+					}
+				}
+			}
+		}
+		Ok(physical)
+	}
+	
+	// the physical address of a load or store which doesn't cross a page, or None with the fault raised
+	fn data_address(&mut self, address: u32, size: u32, access: PmpAccess) -> Option<u32> {
+		let physical = if self.data_translating() {
+			match self.translate(address, access) {
+				Ok(physical) => physical,
+				Err(error) => {
+					self.data_fault(address, access, error == TranslateError::PageFault);
+					return None;
+				}
+			}
+		} else {
+			address
+		};
+		if self.data_pmp_enforced() && ! self.data_pmp_check(physical, size, access) {
+			self.data_fault(address, access, false);
+			return None;
+		}
+		Some(physical)
+	}
+	
+	// csr address bits 9:8 give the lowest privilege allowed access, and 0b11 in bits 11:10 marks it read only
+	fn csr_accessible(&self, csr: u32, write: bool) -> bool {
+		if (self.privilege as u32) < (csr >> 8) & 3 {
+			return false;
+		}
+		if write && (csr >> 10) & 3 == 3 {
+			return false;
+		}
+		// supervisor mode only sees the counters mcounteren allows, and user mode those scounteren allows as well
+		match csr {
+			0xC00 ..= 0xC1F | 0xC80 ..= 0xC9F => {
+				let bit = 1 << (csr & 0x1F);
+				match self.privilege {
+					Privilege::Machine => true,
+					Privilege::Supervisor => self.hpm.counter_enable() & bit != 0,
+					Privilege::User => self.hpm.counter_enable() & self.trap_csrs.scounteren & bit != 0,
+				}
+			},
+			0x180 => self.privilege == Privilege::Machine || self.trap_csrs.mstatus & MSTATUS_TVM == 0,
+			_ => true
+		}
+	}
+	
+	fn hpm_tallies(&self) -> [u64; HPM_EVENT_COUNT] {
+		let mut tallies = self.hpm.tallies();
+		tallies[HpmEvent::PeripheralAccess as usize] = self.mio.access_event_count(MemAccessEvent::PeripheralAccess);
+		tallies[HpmEvent::PageLockContention as usize] = self.mio.access_event_count(MemAccessEvent::PageLockContention);
+		tallies
+	}
+	
+	fn record_mem_access(&mut self, address: u32, size: u32, access: WatchKind) {
+		self.last_mem_access = match self.last_mem_access {
+			// doubles are accessed a word at a time
+			Some((start, length, kind)) if kind == access && start.wrapping_add(length) == address => Some((start, length + size, kind)),
+			_ => Some((address, size, access))
+		};
+		if let Some(trace) = &mut self.trace {
+			trace.record_mem_access(address);
+		}
+		self.breakpoints.check_access(self.hart_id, self.pc, address, size, access);
+	}
+	
+	fn trace_trap(&mut self, cause: u32, epc: u32, tval: u32) {
+		if let Some(trace) = &mut self.trace {
+			trace.record_trap(cause, epc, tval);
+		}
+	}
+	
 	pub fn step_break(&mut self) {
 		self.mio.access_break();
+		self.block_cache.break_block();
 	}
 
-	fn set_gpr(&mut self, reg: u32, val: u32) {
+	// the ebreak at pc is a semihosting call if it sits between the entry and exit markers
+	fn is_semihosting_call(&self) -> bool {
+		if self.semihosting.is_none() || self.privilege != Privilege::Machine || self.pc & 3 != 0 {
+			return false;
+		}
+		matches!((self.mio.read_32(self.pc.wrapping_sub(4)), self.mio.read_32(self.pc.wrapping_add(4))),
+			(MemReadResult::Ok(SEMIHOSTING_ENTRY), MemReadResult::Ok(SEMIHOSTING_EXIT)))
+	}
+	
+	fn semihosting_call(&mut self) -> bool {
+		let operation = self.get_gpr(10);
+		let parameter = self.get_gpr(11);
+		let millis = self.clock.millis();
+		let result = match &mut self.semihosting {
+			Some(semihosting) => semihosting.call(&mut self.mio, operation, parameter, millis),
+			None => return false
+		};
+		// what the host did can't be replayed
+		self.discard_replay_history();
+		match result {
+			SemihostingResult::Return(value) => {
+				self.set_gpr(10, value);
+				self.pc += 4;
+				true
+			},
+			// the hart stays at the call until the machine is reset, interrupts can't restart it
+			SemihostingResult::Exit => {
+				self.halted = true;
+				false
+			}
+		}
+	}
+	
+	pub fn set_gpr(&mut self, reg: u32, val: u32) {
 		if reg != 0 {
 			self.xr[(reg - 1) as usize] = val;
+			if let Some(trace) = &mut self.trace {
+				trace.record_reg_write(TraceRegWrite::Gpr(reg, val));
+			}
 		}
 	}
 
-	fn get_gpr(&self, reg: u32) -> u32 {
+	pub fn get_gpr(&self, reg: u32) -> u32 {
 		if reg == 0 {
 			0
 		} else {
@@ -1782,21 +3287,93 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 		s
 	}
 	
-	fn set_fpr(&mut self, reg: u32, value: f32) {
+	// singles are NaN-boxed in the 64 bit registers: the upper half is all ones, and a single
+	// read from a register which doesn't hold a boxed value reads as the canonical NaN
+	pub fn set_fpr(&mut self, reg: u32, value: f32) {
+		self.set_fpr_bits(reg, FP_NAN_BOX | value.to_bits() as u64);
+	}
+	
+	pub fn get_fpr(&self, reg: u32) -> f32 {
+		let bits = self.fr[reg as usize];
+		if bits & FP_NAN_BOX == FP_NAN_BOX {
+			f32::from_bits(bits as u32)
+		} else {
+			f32::from_bits(FP_CANONICAL_NAN_32)
+		}
+	}
+	
+	pub fn set_fpr_d(&mut self, reg: u32, value: f64) {
+		self.set_fpr_bits(reg, value.to_bits());
+	}
+	
+	pub fn get_fpr_d(&self, reg: u32) -> f64 {
+		f64::from_bits(self.fr[reg as usize])
+	}
+	
+	pub fn set_fpr_bits(&mut self, reg: u32, value: u64) {
 		self.fr[reg as usize] = value;
+		if let Some(trace) = &mut self.trace {
+			trace.record_reg_write(TraceRegWrite::Fpr(reg, value));
+		}
 	}
 	
-	fn get_fpr(&self, reg: u32) -> f32 {
+	pub fn get_fpr_bits(&self, reg: u32) -> u64 {
 		self.fr[reg as usize]
 	}
 	
+	// rounds per the instruction's rounding mode, or frm for the dynamic mode. None if the mode is reserved.
+	fn round_for_conversion(&self, opcode: &Opcode, value: f64) -> Option<f64> {
+		let mut rm = opcode.fp_rm();
+		if let FpRm::Dynamic = rm {
+			rm = FpRm::from_raw((self.fcsr & 0xFF) >> 5)
+		}
+		match rm {
+			FpRm::ToNearestTieEven => {
+				let rounded = f64::round(value);
+				// round() breaks ties away from zero, so step back to the even neighbour
+				if (value - value.trunc()).abs() == 0.5 && rounded % 2.0 != 0.0 {
+					Some(rounded - value.signum())
+				} else {
+					Some(rounded)
+				}
+			},
+			FpRm::ToNearestTieMaxMagnitude => Some(f64::round(value)),
+			FpRm::ToZero => Some(f64::trunc(value)),
+			FpRm::Up => Some(f64::ceil(value)),
+			FpRm::Down => Some(f64::floor(value)),
+			FpRm::Unknown | FpRm::Dynamic => None
+		}
+	}
+	
 	fn get_time(&self) -> u64 {
-		let t = Instant::now() - self.csr_time_start;
-		t.as_millis() as u64
+		if self.clock.is_virtual() {
+			self.clock.cycles_to_millis(self.get_cycles())
+		} else {
+			self.clock.millis()
+		}
 	}
 	
-	fn read_csr(&mut self, csr: u32) -> u32 {
-		match csr {
+	fn get_cycles(&self) -> u64 {
+		if self.clock.is_virtual() && (self.lockstep_period.is_some() || self.hart_id == 0) {
+			// include what this hart retired since the clock last advanced, up to the rest of the period
+			self.clock.cycles() + (self.csr_instrret - self.period_start_instret).min(self.period_instructions)
+		} else {
+			self.clock.cycles()
+		}
+	}
+	
+	pub fn read_csr(&mut self, csr: u32) -> u32 {
+		// the clock and performance counters depend on the host, so they're part of the replay log
+		let logged = match csr {
+			0xB00 | 0xB80 | 0xB03 ..= 0xB1F | 0xB83 ..= 0xB9F | 0xC00 ..= 0xC01 | 0xC80 ..= 0xC81 | 0xC03 ..= 0xC1F | 0xC83 ..= 0xC9F => self.replay.is_some(),
+			_ => false
+		};
+		if logged {
+			if let Some(ReplayInput::Value(value)) = self.replay.as_mut().and_then(|replay| replay.next_input()) {
+				return value;
+			}
+		}
+		let value = match csr {
 			// FPU
 			// FFlags: floating point accrued exceptions
 			0x001 => {
@@ -1811,6 +3388,47 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 				self.fcsr
 			},
 			
+			// sstatus: the supervisor view of mstatus
+			0x100 => {
+				self.trap_csrs.mstatus & SSTATUS_MASK
+			},
+			// sie: the delegated interrupt enables
+			0x104 => {
+				self.trap_csrs.mie & self.trap_csrs.mideleg
+			},
+			// stvec: supervisor trap handler base address
+			0x105 => {
+				self.trap_csrs.stvec
+			},
+			// scounteren: counters readable from user mode, of those supervisor mode can read
+			0x106 => {
+				self.trap_csrs.scounteren
+			},
+			// sscratch: supervisor scratch register
+			0x140 => {
+				self.trap_csrs.sscratch
+			},
+			// sepc: supervisor exception program counter
+			0x141 => {
+				self.trap_csrs.sepc
+			},
+			// scause: supervisor trap cause
+			0x142 => {
+				self.trap_csrs.scause
+			},
+			// stval: supervisor bad instruction or address
+			0x143 => {
+				self.trap_csrs.stval
+			},
+			// sip: the delegated pending interrupts
+			0x144 => {
+				self.trap_csrs.mip & self.trap_csrs.mideleg
+			},
+			// satp: address translation mode and root page table
+			0x180 => {
+				self.mmu.read_satp()
+			},
+			
 			// mstatus: machine status
 			0x300 => {
 				self.trap_csrs.mstatus
@@ -1818,11 +3436,24 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 			// misa: ISA and extensions
 			0x301 => {
 				(1 << 30) | // RV32 ISA
+				(1 << ('b' as u32 - 'a' as u32)) | // "B" extension support (Zba, Zbb and Zbs)
+				(1 << ('c' as u32 - 'a' as u32)) | // "C" extension support
+				(1 << ('d' as u32 - 'a' as u32)) | // "D" extension support
 				(1 << ('i' as u32 - 'a' as u32)) | // "I" extension support
 				(1 << ('f' as u32 - 'a' as u32)) | // "F" extension support
 				(1 << ('m' as u32 - 'a' as u32)) | // "M" extension support
+				(1 << ('s' as u32 - 'a' as u32)) | // "S" supervisor mode support
+				(1 << ('u' as u32 - 'a' as u32)) | // "U" user mode support
 				(1 << ('x' as u32 - 'a' as u32))   // "X" not standard extensions implemented
 			},
+			// medeleg: exceptions delegated to supervisor mode
+			0x302 => {
+				self.trap_csrs.medeleg
+			},
+			// mideleg: interrupts delegated to supervisor mode
+			0x303 => {
+				self.trap_csrs.mideleg
+			},
 			// mie: machine interrupt enable
 			0x304 => {
 				self.trap_csrs.mie
@@ -1831,6 +3462,18 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 			0x305 => {
 				self.trap_csrs.mtvec
 			},
+			// mcounteren: counters readable from user mode
+			0x306 => {
+				self.hpm.counter_enable()
+			},
+			// mcountinhibit: stopped counters
+			0x320 => {
+				self.hpm.inhibit()
+			},
+			// mhpmevent3 - mhpmevent31: performance counter event selectors
+			0x323 ..= 0x33F => {
+				self.hpm.event((csr - 0x323) as usize)
+			},
 			
 			// mscratch: machine scratch register
 			0x340 => {
@@ -1842,7 +3485,7 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 			},
 			// mcause: machine trap cause
 			0x342 => {
-				self.trap_csrs.mepc
+				self.trap_csrs.mcause
 			},
 			// mtval: machine bad instruction or address
 			0x343 => {
@@ -1852,14 +3495,45 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 			0x344 => {
 				self.trap_csrs.mip
 			},
+			// pmpcfg0 - pmpcfg3: physical memory protection configuration
+			0x3A0 ..= 0x3A3 => {
+				self.pmp.read_cfg((csr - 0x3A0) as usize)
+			},
+			// pmpaddr0 - pmpaddr15: physical memory protection addresses
+			0x3B0 ..= 0x3BF => {
+				self.pmp.read_addr((csr - 0x3B0) as usize)
+			},
+			
+			// Machine counters
+			0xB00 => {
+				self.get_cycles() as u32
+			},
+			0xB80 => {
+				(self.get_cycles() >> 32) as u32
+			},
+			0xB02 => {
+				self.csr_instrret as u32
+			},
+			0xB82 => {
+				(self.csr_instrret >> 32) as u32
+			},
+			// mhpmcounter3 - mhpmcounter31: performance counters
+			0xB03 ..= 0xB1F => {
+				let tallies = self.hpm_tallies();
+				self.hpm.read(csr as usize - 0xB00 - HPM_COUNTER_BASE, &tallies) as u32
+			},
+			0xB83 ..= 0xB9F => {
+				let tallies = self.hpm_tallies();
+				(self.hpm.read(csr as usize - 0xB80 - HPM_COUNTER_BASE, &tallies) >> 32) as u32
+			},
 			
 			// RV32I Counters
 			// Cycle: Cycle counter
 			0xC00 => {
-				self.csr_cycle as u32
+				self.get_cycles() as u32
 			},
 			0xC80 => {
-				(self.csr_cycle >> 32) as u32
+				(self.get_cycles() >> 32) as u32
 			},
 			// Time: general timer
 			0xC01 => {
@@ -1875,6 +3549,15 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 			0xC82 => {
 				(self.csr_instrret >> 32) as u32
 			},
+			// HpmCounter: user mode aliases of the performance counters
+			0xC03 ..= 0xC1F => {
+				let tallies = self.hpm_tallies();
+				self.hpm.read(csr as usize - 0xC00 - HPM_COUNTER_BASE, &tallies) as u32
+			},
+			0xC83 ..= 0xC9F => {
+				let tallies = self.hpm_tallies();
+				(self.hpm.read(csr as usize - 0xC80 - HPM_COUNTER_BASE, &tallies) >> 32) as u32
+			},
 			
 			// Machine Vendor ID
 			0xF11 => { 0 },
@@ -1887,33 +3570,123 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 			
 			
 			_ => 0
+		};
+		if logged {
+			if let Some(replay) = &mut self.replay {
+				replay.record_input(ReplayInput::Value(value));
+			}
 		}
+		value
 	}
 	
-	fn write_csr(&mut self, csr: u32, value: u32) -> bool {
+	// sd summarizes fs, so it's never written directly
+	fn write_mstatus(&mut self, value: u32) {
+		let mut fixed_value = value & !MSTATUS_SD;
+		if (fixed_value & MSTATUS_FS_MASK) == MSTATUS_FS_DIRTY {
+			fixed_value |= MSTATUS_SD;
+		}
+		self.trap_csrs.mstatus = fixed_value;
+	}
+	
+	pub fn write_csr(&mut self, csr: u32, value: u32) -> bool {
 		match csr {
 			0xBFF => {
 				return false;
 			},
 			0x300 => {
-				let mut fixed_value = (value & (MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_FS_MASK)) | MSTATUS_MPP;
-				if (fixed_value & MSTATUS_FS_MASK) == MSTATUS_FS_DIRTY {
-					fixed_value |= MSTATUS_SD;
-				}
-				self.trap_csrs.mstatus = fixed_value;
+				// mpp only holds the privileges which exist, other values leave it unchanged
+				let mpp = match Privilege::from_raw((value & MSTATUS_MPP) >> MSTATUS_MPP_SHIFT) {
+					Some(privilege) => (privilege as u32) << MSTATUS_MPP_SHIFT,
+					None => self.trap_csrs.mstatus & MSTATUS_MPP
+				};
+				let writable = MSTATUS_SIE | MSTATUS_MIE | MSTATUS_SPIE | MSTATUS_MPIE | MSTATUS_SPP | MSTATUS_FS_MASK | MSTATUS_MPRV | MSTATUS_SUM | MSTATUS_MXR | MSTATUS_TVM | MSTATUS_TW | MSTATUS_TSR;
+				self.write_mstatus((value & writable) | mpp);
+			},
+			0x100 => {
+				let mstatus = self.trap_csrs.mstatus & !SSTATUS_MASK;
+				self.write_mstatus(mstatus | (value & SSTATUS_MASK));
+			},
+			0x104 => {
+				let mideleg = self.trap_csrs.mideleg;
+				self.trap_csrs.mie = (self.trap_csrs.mie & !mideleg) | (value & mideleg);
+			},
+			// the mode field is warl, and only direct (0) and vectored (1) exist
+			0x105 => {
+				self.trap_csrs.stvec = value & !2;
+			},
+			0x106 => {
+				self.trap_csrs.scounteren = value;
+			},
+			0x140 => {
+				self.trap_csrs.sscratch = value;
+			},
+			0x141 => {
+				self.trap_csrs.sepc = value & !1;
+			},
+			0x142 => {
+				self.trap_csrs.scause = value;
+			},
+			0x143 => {
+				self.trap_csrs.stval = value;
+			},
+			// only the supervisor software interrupt can be raised or cleared from supervisor mode
+			0x144 => {
+				let writable = MIP_SSIP & self.trap_csrs.mideleg;
+				self.trap_csrs.mip = (self.trap_csrs.mip & !writable) | (value & writable);
+			},
+			0x180 => {
+				self.mmu.write_satp(value);
+			},
+			0x302 => {
+				self.trap_csrs.medeleg = value & MEDELEG_MASK;
+			},
+			0x303 => {
+				self.trap_csrs.mideleg = value & MIDELEG_MASK;
 			},
 			0x304 => {
-				let fixed_value = value & (MIE_MSIE | MIE_MTIE | MIE_MEIE);
+				let fixed_value = value & (MIE_SSIE | MIE_MSIE | MIE_STIE | MIE_MTIE | MIE_SEIE | MIE_MEIE);
 				self.trap_csrs.mie = fixed_value;
 			},
 			0x305 => {
-				self.trap_csrs.mtvec = value;
+				self.trap_csrs.mtvec = value & !2;
+			},
+			0x306 => {
+				self.hpm.write_counter_enable(value);
+			},
+			0x320 => {
+				let tallies = self.hpm_tallies();
+				self.hpm.write_inhibit(value, &tallies);
+			},
+			0x323 ..= 0x33F => {
+				let tallies = self.hpm_tallies();
+				self.hpm.write_event((csr - 0x323) as usize, value, &tallies);
+			},
+			0xB03 ..= 0xB1F => {
+				let index = csr as usize - 0xB00 - HPM_COUNTER_BASE;
+				let tallies = self.hpm_tallies();
+				let counter = self.hpm.read(index, &tallies);
+				self.hpm.write(index, (counter & !0xFFFF_FFFF) | value as u64, &tallies);
+			},
+			0xB83 ..= 0xB9F => {
+				let index = csr as usize - 0xB80 - HPM_COUNTER_BASE;
+				let tallies = self.hpm_tallies();
+				let counter = self.hpm.read(index, &tallies);
+				self.hpm.write(index, (counter & 0xFFFF_FFFF) | (value as u64) << 32, &tallies);
 			},
 			0x340 => {
 				self.trap_csrs.mscratch = value;
 			},
+			0x341 => {
+				self.trap_csrs.mepc = value & !1;
+			},
+			0x342 => {
+				self.trap_csrs.mcause = value;
+			},
+			0x343 => {
+				self.trap_csrs.mtval = value;
+			},
 			0x344 => {
-				let fixed_value = value & (MIP_MSIP | MIP_MTIP | MIP_MEIP);
+				let fixed_value = value & (MIP_SSIP | MIP_MSIP | MIP_STIP | MIP_MTIP | MIP_SEIP | MIP_MEIP);
 				self.trap_csrs.mip = fixed_value;
 			},
 			0x001 => {
@@ -1925,9 +3698,213 @@ impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus,> Cpu<Timer, MIO, I
 			0x003 => {
 				self.fcsr = value & 0xFF;
 			}
+			0x3A0 ..= 0x3A3 => {
+				self.pmp.write_cfg((csr - 0x3A0) as usize, value);
+			},
+			0x3B0 ..= 0x3BF => {
+				self.pmp.write_addr((csr - 0x3B0) as usize, value);
+			},
 			_ => {
 			}
 		}
 		true
 	}
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{assemble, FlatMemIO, ManualMTimer, NullInterruptBus};
+
+	const BASE: u32 = 0x8000_0000;
+	const DATA: u32 = 0x8000_1000;
+
+	type TestCpu = Cpu<ManualMTimer, FlatMemIO, NullInterruptBus>;
+
+	// runs source through to its end on a fresh hart, with the doublewords of data at DATA and t0 pointing at them
+	fn run(source: &str, data: &[u64]) -> (TestCpu, FlatMemIO) {
+		let mio = FlatMemIO::new(BASE, 0x2000);
+		let program = assemble(&format!("li t0, {:#x}\n{}", DATA, source), BASE).unwrap();
+		let data: Vec<u8> = data.iter().flat_map(|value| value.to_le_bytes()).collect();
+		assert!(mio.load(BASE, &program) && mio.load(DATA, &data));
+		let mut cpu = Cpu::new(mio.clone(), NullInterruptBus, CpuWakeupHandle::new(), 0);
+		cpu.reset(BASE);
+		for _ in 0 .. 100 {
+			if cpu.get_pc() == BASE + program.len() as u32 {
+				return (cpu, mio);
+			}
+			cpu.step();
+		}
+		panic!("ran off at {:#010x}", cpu.get_pc());
+	}
+
+	// the integer result and the flags raised
+	fn convert(load: &str, instruction: &str, bits: u64) -> (u32, u32) {
+		let (mut cpu, _) = run(&format!("{} f0, 0(t0)\n{}", load, instruction), &[bits]);
+		(cpu.get_gpr(10), cpu.read_csr(0x001))
+	}
+
+	fn convert_d(instruction: &str, value: f64) -> (u32, u32) {
+		convert("fld", instruction, value.to_bits())
+	}
+
+	fn convert_s(instruction: &str, value: f32) -> (u32, u32) {
+		convert("flw", instruction, value.to_bits() as u64)
+	}
+
+	#[test]
+	fn fcvt_w_saturates_nan_and_out_of_range_values() {
+		assert_eq!(convert_d("fcvt.w.d a0, f0", f64::NAN), (0x7FFF_FFFF, FFLAG_INVALID));
+		assert_eq!(convert_d("fcvt.wu.d a0, f0", f64::NAN), (0xFFFF_FFFF, FFLAG_INVALID));
+		assert_eq!(convert_s("fcvt.w.s a0, f0", f32::NAN), (0x7FFF_FFFF, FFLAG_INVALID));
+		assert_eq!(convert_s("fcvt.wu.s a0, f0", f32::NAN), (0xFFFF_FFFF, FFLAG_INVALID));
+		assert_eq!(convert_d("fcvt.w.d a0, f0", 1e10), (0x7FFF_FFFF, FFLAG_INVALID));
+		assert_eq!(convert_d("fcvt.w.d a0, f0", -1e10), (0x8000_0000, FFLAG_INVALID));
+		assert_eq!(convert_d("fcvt.wu.d a0, f0", -1.0), (0, FFLAG_INVALID));
+		assert_eq!(convert_d("fcvt.wu.d a0, f0", 4294967296.0), (0xFFFF_FFFF, FFLAG_INVALID));
+		assert_eq!(convert_s("fcvt.wu.s a0, f0", f32::NEG_INFINITY), (0, FFLAG_INVALID));
+		assert_eq!(convert_s("fcvt.w.s a0, f0", f32::INFINITY), (0x7FFF_FFFF, FFLAG_INVALID));
+	}
+
+	#[test]
+	fn fcvt_w_rounds_and_flags_inexact_results() {
+		assert_eq!(convert_d("fcvt.w.d a0, f0", 3.0), (3, 0));
+		assert_eq!(convert_d("fcvt.wu.d a0, f0", 4294967295.0), (0xFFFF_FFFF, 0));
+		assert_eq!(convert_d("fcvt.w.d a0, f0", 2.5), (2, FFLAG_INEXACT));
+		assert_eq!(convert_d("fcvt.w.d a0, f0, rdn", -2.5), (-3i32 as u32, FFLAG_INEXACT));
+		assert_eq!(convert_d("fcvt.w.d a0, f0, rtz", -2147483648.5), (0x8000_0000, FFLAG_INEXACT));
+		// rounds to zero rather than below it, so it's only inexact
+		assert_eq!(convert_d("fcvt.wu.d a0, f0, rtz", -0.25), (0, FFLAG_INEXACT));
+		assert_eq!(convert_s("fcvt.w.s a0, f0, rup", 1.25), (2, FFLAG_INEXACT));
+		assert_eq!(convert_s("fcvt.wu.s a0, f0, rmm", 6.5), (7, FFLAG_INEXACT));
+	}
+
+	#[test]
+	fn singles_are_nan_boxed() {
+		let (cpu, mio) = run("
+			li a0, 0x3FC00000
+			fmv.w.x f1, a0
+			fsd f1, 8(t0)
+			fld f2, 0(t0)
+			fadd.s f3, f2, f2
+			fmv.x.w a1, f2
+			fmv.x.w a2, f1
+			fcvt.d.s f4, f1
+			fcvt.s.d f5, f2
+		", &[1.0f64.to_bits()]);
+		// the move boxes the single, and a double read as a single is the canonical NaN
+		assert_eq!(mio.dump(DATA + 8, 8).unwrap(), 0xFFFF_FFFF_3FC0_0000u64.to_le_bytes());
+		assert_eq!(cpu.get_fpr_bits(3), 0xFFFF_FFFF_7FC0_0000);
+		// fmv.x.w takes the low half of whatever is in the register
+		assert_eq!((cpu.get_gpr(11), cpu.get_gpr(12)), (0, 0x3FC0_0000));
+		assert_eq!(cpu.get_fpr_d(4), 1.5);
+		assert_eq!(cpu.get_fpr_bits(5), 0xFFFF_FFFF_3F80_0000);
+	}
+
+	// steps source on a fresh hart with traps caught at the label trap, and returns mcause, mtval and mepc
+	fn trap(mio: &FlatMemIO, source: &str, emulate_misaligned: bool) -> (u32, u32, u32) {
+		let program = assemble(&format!("la t1, trap\ncsrw mtvec, t1\n{}\ntrap:\nj trap", source), BASE).unwrap();
+		assert!(mio.load(BASE, &program));
+		let mut cpu: TestCpu = Cpu::new(mio.clone(), NullInterruptBus, CpuWakeupHandle::new(), 0);
+		cpu.set_misaligned_emulation(emulate_misaligned);
+		cpu.reset(BASE);
+		for _ in 0 .. 100 {
+			cpu.step();
+		}
+		(cpu.read_csr(0x342), cpu.read_csr(0x343), cpu.read_csr(0x341))
+	}
+
+	#[test]
+	fn fetching_from_unmapped_space_is_an_access_fault() {
+		let mio = FlatMemIO::new(BASE, 0x2000);
+		let target = 0x9000_0000;
+		assert_eq!(trap(&mio, &format!("li t1, {:#x}\njr t1", target), false), (1, target, target));
+	}
+
+	// a word stored from supervisor mode at address, with pmp open, pages 0 - 3 and 5 - 8 mapped to themselves,
+	// page 4 unmapped, and ram ending at page 8. returns the trap cause, mtval, and the bytes of ram the store covers.
+	fn store_across_pages(address: u32, emulate_misaligned: bool) -> (u32, u32, Vec<u8>) {
+		const ROOT: u32 = BASE + 0x5000;
+		const LEAVES: u32 = BASE + 0x6000;
+		let mio = FlatMemIO::new(BASE, 0x8000);
+		// valid, and for the leaves readable, writable, executable, accessed and dirty too
+		let root = (LEAVES >> 12) << 10 | 0x01;
+		assert!(mio.load(ROOT + (BASE >> 22) * 4, &root.to_le_bytes()));
+		for page in (0 .. 9).filter(|&page| page != 4) {
+			let leaf = ((BASE >> 12) + page) << 10 | 0xCF;
+			assert!(mio.load(LEAVES + page * 4, &leaf.to_le_bytes()));
+		}
+		let (cause, value, _) = trap(&mio, &format!("
+			li t1, -1
+			csrw pmpaddr0, t1
+			li t1, 0x1F
+			csrw pmpcfg0, t1
+			li t1, {:#x}
+			csrw satp, t1
+			li t1, {:#x}
+			csrw mstatus, t1
+			la t1, supervisor
+			csrw mepc, t1
+			mret
+		supervisor:
+			li t2, 0x11223344
+			li t3, {:#x}
+			sw t2, 0(t3)
+			ecall
+		", 1 << 31 | ROOT >> 12, (Privilege::Supervisor as u32) << MSTATUS_MPP_SHIFT, address), emulate_misaligned);
+		let in_ram = (BASE + 0x8000 - address).min(4);
+		(cause, value, mio.dump(address, in_ram).unwrap())
+	}
+
+	#[test]
+	fn stores_across_pages_are_split_only_when_emulated() {
+		assert_eq!(store_across_pages(BASE + 0x1FFE, true), (9, 0, vec![0x44, 0x33, 0x22, 0x11]));
+		assert_eq!(store_across_pages(BASE + 0x1FFE, false), (6, BASE + 0x1FFE, vec![0; 4]));
+	}
+
+	#[test]
+	fn split_stores_fault_before_writing_any_byte() {
+		// the second page isn't mapped
+		assert_eq!(store_across_pages(BASE + 0x3FFE, true), (15, BASE + 0x4000, vec![0; 4]));
+		// the second page is mapped past the end of ram
+		assert_eq!(store_across_pages(BASE + 0x7FFE, true), (7, BASE + 0x7FFE, vec![0; 2]));
+	}
+
+	#[test]
+	fn patching_code_discards_the_history_before_it() {
+		let patch = assemble("addi a0, a0, 16", 0).unwrap();
+		let program = assemble(&format!("
+			li a0, 0
+			call bump
+			la s2, bump
+			li s3, {:#x}
+			sw s3, 0(s2)
+			call bump
+			j done
+		bump:
+			addi a0, a0, 1
+			ret
+		done:
+		", u32::from_le_bytes([patch[0], patch[1], patch[2], patch[3]])), BASE).unwrap();
+		let end = BASE + program.len() as u32;
+		let mio = FlatMemIO::new(BASE, 0x2000);
+		assert!(mio.load(BASE, &program));
+		let mut cpu: TestCpu = Cpu::new(mio, NullInterruptBus, CpuWakeupHandle::new(), 0);
+		cpu.reset(BASE);
+		cpu.enable_replay(1000);
+		while cpu.get_pc() != end {
+			cpu.step();
+		}
+		assert_eq!(cpu.get_gpr(10), 17);
+		let mut steps = 0;
+		while cpu.step_back() {
+			steps += 1;
+		}
+		// the first call would replay with the patched increment, so it's out of reach
+		assert!(steps > 0);
+		assert_eq!(cpu.get_gpr(10), 1);
+		while cpu.get_pc() != end {
+			cpu.step();
+		}
+		assert_eq!(cpu.get_gpr(10), 17);
+	}
+}
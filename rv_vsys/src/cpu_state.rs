@@ -0,0 +1,384 @@
+use std::io::{self, Read, Write};
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::hpm::HPM_COUNTER_COUNT;
+use crate::pmp::PMP_ENTRY_COUNT;
+
+// Architectural state of one hart, as captured between instructions by Cpu::save_state.
+// The serialized form is a header followed by every field in declaration order, little endian,
+// with bools and the pending exception flag as a byte. New fields go on the end with a version bump,
+// and are left zero when loading an older version.
+
+const STATE_MAGIC: &[u8; 4] = b"RVCS";
+const STATE_VERSION: u16 = 2;
+const STATE_HEADER_SIZE: usize = 8;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CpuState {
+	// x0 is saved as zero and ignored on load
+	pub gpr: [u32; 32],
+	pub fpr: [u64; 32],
+	pub pc: u32,
+	pub privilege: u32,
+	pub mstatus: u32,
+	pub mie: u32,
+	pub mtvec: u32,
+	pub mscratch: u32,
+	pub mepc: u32,
+	pub mcause: u32,
+	pub mtval: u32,
+	pub mip: u32,
+	pub fcsr: u32,
+	pub instret: u64,
+	pub pmp_cfg: [u32; PMP_ENTRY_COUNT / 4],
+	pub pmp_addr: [u32; PMP_ENTRY_COUNT],
+	pub hpm_events: [u32; HPM_COUNTER_COUNT],
+	pub hpm_counters: [u64; HPM_COUNTER_COUNT],
+	pub hpm_inhibit: u32,
+	pub counter_enable: u32,
+	pub waiting_for_interrupt: bool,
+	// an exception raised by the last instruction which is taken at the start of the next step,
+	// as (cause, tval, epc)
+	pub pending_exception: Option<(u32, u32, u32)>,
+	// the reservation is only meaningful to the memory interface which handed it out
+	pub lr_write_cycle: u64,
+	pub lr_write_key: u32,
+	// version 2: supervisor mode and paging
+	pub medeleg: u32,
+	pub mideleg: u32,
+	pub stvec: u32,
+	pub scounteren: u32,
+	pub sscratch: u32,
+	pub sepc: u32,
+	pub scause: u32,
+	pub stval: u32,
+	pub satp: u32,
+}
+
+fn invalid_data(message: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn push_u32(buffer: &mut Vec<u8>, value: u32) {
+	let mut bytes = [0u8; 4];
+	LittleEndian::write_u32(&mut bytes, value);
+	buffer.extend_from_slice(&bytes);
+}
+
+fn push_u64(buffer: &mut Vec<u8>, value: u64) {
+	let mut bytes = [0u8; 8];
+	LittleEndian::write_u64(&mut bytes, value);
+	buffer.extend_from_slice(&bytes);
+}
+
+struct StateReader<'a> {
+	data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+	fn take(&mut self, size: usize) -> io::Result<&'a [u8]> {
+		if self.data.len() < size {
+			return Err(invalid_data("truncated cpu state"));
+		}
+		let (taken, rest) = self.data.split_at(size);
+		self.data = rest;
+		Ok(taken)
+	}
+
+	fn take_u8(&mut self) -> io::Result<u8> {
+		Ok(self.take(1)?[0])
+	}
+
+	fn take_u32(&mut self) -> io::Result<u32> {
+		Ok(LittleEndian::read_u32(self.take(4)?))
+	}
+
+	fn take_u64(&mut self) -> io::Result<u64> {
+		Ok(LittleEndian::read_u64(self.take(8)?))
+	}
+}
+
+impl CpuState {
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut buffer = Vec::new();
+		buffer.extend_from_slice(STATE_MAGIC);
+		buffer.extend_from_slice(&[0; STATE_HEADER_SIZE - 4]);
+		LittleEndian::write_u16(&mut buffer[4 .. 6], STATE_VERSION);
+		for value in self.gpr.iter() {
+			push_u32(&mut buffer, *value);
+		}
+		for value in self.fpr.iter() {
+			push_u64(&mut buffer, *value);
+		}
+		for value in [self.pc, self.privilege, self.mstatus, self.mie, self.mtvec, self.mscratch, self.mepc, self.mcause, self.mtval, self.mip, self.fcsr].iter() {
+			push_u32(&mut buffer, *value);
+		}
+		push_u64(&mut buffer, self.instret);
+		for value in self.pmp_cfg.iter().chain(self.pmp_addr.iter()).chain(self.hpm_events.iter()) {
+			push_u32(&mut buffer, *value);
+		}
+		for value in self.hpm_counters.iter() {
+			push_u64(&mut buffer, *value);
+		}
+		push_u32(&mut buffer, self.hpm_inhibit);
+		push_u32(&mut buffer, self.counter_enable);
+		buffer.push(self.waiting_for_interrupt as u8);
+		match self.pending_exception {
+			Some((cause, tval, epc)) => {
+				buffer.push(1);
+				push_u32(&mut buffer, cause);
+				push_u32(&mut buffer, tval);
+				push_u32(&mut buffer, epc);
+			},
+			None => buffer.push(0)
+		}
+		push_u64(&mut buffer, self.lr_write_cycle);
+		push_u32(&mut buffer, self.lr_write_key);
+		for value in [self.medeleg, self.mideleg, self.stvec, self.scounteren, self.sscratch, self.sepc, self.scause, self.stval, self.satp].iter() {
+			push_u32(&mut buffer, *value);
+		}
+		buffer
+	}
+
+	pub fn from_bytes(data: &[u8]) -> io::Result<Self> {
+		if data.len() < STATE_HEADER_SIZE || &data[0 .. 4] != STATE_MAGIC {
+			return Err(invalid_data("not a cpu state"));
+		}
+		let version = LittleEndian::read_u16(&data[4 .. 6]);
+		if version == 0 || version > STATE_VERSION {
+			return Err(invalid_data(format!("unsupported cpu state version {}", version).as_str()));
+		}
+		let mut reader = StateReader {
+			data: &data[STATE_HEADER_SIZE ..],
+		};
+		let mut gpr = [0; 32];
+		for value in gpr.iter_mut() {
+			*value = reader.take_u32()?;
+		}
+		gpr[0] = 0;
+		let mut fpr = [0; 32];
+		for value in fpr.iter_mut() {
+			*value = reader.take_u64()?;
+		}
+		let pc = reader.take_u32()?;
+		let privilege = reader.take_u32()?;
+		let mstatus = reader.take_u32()?;
+		let mie = reader.take_u32()?;
+		let mtvec = reader.take_u32()?;
+		let mscratch = reader.take_u32()?;
+		let mepc = reader.take_u32()?;
+		let mcause = reader.take_u32()?;
+		let mtval = reader.take_u32()?;
+		let mip = reader.take_u32()?;
+		let fcsr = reader.take_u32()?;
+		let instret = reader.take_u64()?;
+		let mut pmp_cfg = [0; PMP_ENTRY_COUNT / 4];
+		for value in pmp_cfg.iter_mut() {
+			*value = reader.take_u32()?;
+		}
+		let mut pmp_addr = [0; PMP_ENTRY_COUNT];
+		for value in pmp_addr.iter_mut() {
+			*value = reader.take_u32()?;
+		}
+		let mut hpm_events = [0; HPM_COUNTER_COUNT];
+		for value in hpm_events.iter_mut() {
+			*value = reader.take_u32()?;
+		}
+		let mut hpm_counters = [0; HPM_COUNTER_COUNT];
+		for value in hpm_counters.iter_mut() {
+			*value = reader.take_u64()?;
+		}
+		let hpm_inhibit = reader.take_u32()?;
+		let counter_enable = reader.take_u32()?;
+		let waiting_for_interrupt = reader.take_u8()? != 0;
+		let pending_exception = match reader.take_u8()? {
+			0 => None,
+			_ => Some((reader.take_u32()?, reader.take_u32()?, reader.take_u32()?))
+		};
+		let lr_write_cycle = reader.take_u64()?;
+		let lr_write_key = reader.take_u32()?;
+		let mut supervisor = [0; 9];
+		if version >= 2 {
+			for value in supervisor.iter_mut() {
+				*value = reader.take_u32()?;
+			}
+		}
+		let [medeleg, mideleg, stvec, scounteren, sscratch, sepc, scause, stval, satp] = supervisor;
+		Ok(CpuState {
+			gpr,
+			fpr,
+			pc,
+			privilege,
+			mstatus,
+			mie,
+			mtvec,
+			mscratch,
+			mepc,
+			mcause,
+			mtval,
+			mip,
+			fcsr,
+			instret,
+			pmp_cfg,
+			pmp_addr,
+			hpm_events,
+			hpm_counters,
+			hpm_inhibit,
+			counter_enable,
+			waiting_for_interrupt,
+			pending_exception,
+			lr_write_cycle,
+			lr_write_key,
+			medeleg,
+			mideleg,
+			stvec,
+			scounteren,
+			sscratch,
+			sepc,
+			scause,
+			stval,
+			satp,
+		})
+	}
+
+	pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+		writer.write_all(self.to_bytes().as_slice())
+	}
+
+	pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+		let mut data = Vec::new();
+		reader.read_to_end(&mut data)?;
+		Self::from_bytes(data.as_slice())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{assemble, Cpu, CpuWakeupHandle, FlatMemIO, NullInterruptBus};
+
+	// every field distinct, so a field read back into the wrong place shows up
+	fn sample_state() -> CpuState {
+		let mut gpr = [0; 32];
+		for (index, value) in gpr.iter_mut().enumerate().skip(1) {
+			*value = 0x1000 + index as u32;
+		}
+		let mut fpr = [0; 32];
+		for (index, value) in fpr.iter_mut().enumerate() {
+			*value = 0xFFFF_FFFF_0000_0000 | index as u64;
+		}
+		let mut pmp_addr = [0; PMP_ENTRY_COUNT];
+		for (index, value) in pmp_addr.iter_mut().enumerate() {
+			*value = 0x2000 + index as u32;
+		}
+		let mut hpm_events = [0; HPM_COUNTER_COUNT];
+		let mut hpm_counters = [0; HPM_COUNTER_COUNT];
+		for index in 0 .. HPM_COUNTER_COUNT {
+			hpm_events[index] = index as u32 % 4;
+			hpm_counters[index] = 0x1_0000_0000 + index as u64;
+		}
+		CpuState {
+			gpr,
+			fpr,
+			pc: 0x8000_0100,
+			privilege: 1,
+			mstatus: 0x1880,
+			mie: 0x888,
+			mtvec: 0x8000_0001,
+			mscratch: 0x1234,
+			mepc: 0x8000_0200,
+			mcause: 0x8000_0007,
+			mtval: 0xBAD,
+			mip: 0x80,
+			fcsr: 0xE1,
+			instret: 0x1_2345_6789,
+			pmp_cfg: [0x1F, 0x0F00, 0x0018_0000, 0x9800_0000],
+			pmp_addr,
+			hpm_events,
+			hpm_counters,
+			hpm_inhibit: 0x18,
+			counter_enable: 0x7,
+			waiting_for_interrupt: true,
+			pending_exception: Some((13, 0x4000, 0x8000_0300)),
+			lr_write_cycle: 42,
+			lr_write_key: 3,
+			medeleg: 0xB109,
+			mideleg: 0x222,
+			stvec: 0x8000_1000,
+			scounteren: 0x5,
+			sscratch: 0x5678,
+			sepc: 0x8000_0400,
+			scause: 8,
+			stval: 0xCAFE,
+			satp: 0x8000_0042,
+		}
+	}
+
+	#[test]
+	fn bytes_round_trip() {
+		let state = sample_state();
+		assert_eq!(CpuState::from_bytes(&state.to_bytes()).unwrap(), state);
+		let mut written = Vec::new();
+		state.write_to(&mut written).unwrap();
+		assert_eq!(CpuState::read_from(&mut written.as_slice()).unwrap(), state);
+	}
+
+	#[test]
+	fn version_1_loads_without_supervisor_state() {
+		let state = sample_state();
+		let mut bytes = state.to_bytes();
+		LittleEndian::write_u16(&mut bytes[4 .. 6], 1);
+		bytes.truncate(bytes.len() - 9 * 4);
+		let loaded = CpuState::from_bytes(&bytes).unwrap();
+		assert_eq!(loaded, CpuState {
+			medeleg: 0,
+			mideleg: 0,
+			stvec: 0,
+			scounteren: 0,
+			sscratch: 0,
+			sepc: 0,
+			scause: 0,
+			stval: 0,
+			satp: 0,
+			..state
+		});
+	}
+
+	#[test]
+	fn bad_data_is_rejected() {
+		let bytes = sample_state().to_bytes();
+		assert!(CpuState::from_bytes(&bytes[.. bytes.len() - 1]).is_err());
+		let mut newer = bytes.clone();
+		LittleEndian::write_u16(&mut newer[4 .. 6], STATE_VERSION + 1);
+		assert!(CpuState::from_bytes(&newer).is_err());
+		let mut other = bytes;
+		other[0 .. 4].copy_from_slice(b"RVTR");
+		assert!(CpuState::from_bytes(&other).is_err());
+	}
+
+	#[test]
+	fn cpu_resumes_from_a_saved_state() {
+		let base = 0x8000_0000;
+		let program = assemble("
+			li a0, 1
+			addi a0, a0, 1
+			addi a0, a0, 1
+			csrw mscratch, a0
+		", base).unwrap();
+		let mio = FlatMemIO::new(base, 0x1000);
+		assert!(mio.load(base, &program));
+		let mut cpu = Cpu::new(mio.clone(), NullInterruptBus, CpuWakeupHandle::new(), 0);
+		cpu.reset(base);
+		cpu.step();
+		cpu.step();
+		let state = CpuState::from_bytes(&cpu.save_state().to_bytes()).unwrap();
+		let mut resumed = Cpu::new(mio, NullInterruptBus, CpuWakeupHandle::new(), 0);
+		resumed.reset(0);
+		resumed.load_state(&state).unwrap();
+		assert_eq!(resumed.save_state(), state);
+		resumed.step();
+		resumed.step();
+		assert_eq!(resumed.get_gpr(10), 3);
+		assert_eq!(resumed.read_csr(0x340), 3);
+	}
+}
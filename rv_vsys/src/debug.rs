@@ -1,11 +1,14 @@
-enum DebugStep {
+use crate::{Cpu, InterruptBus, MTimer, MemIO};
+
+pub enum DebugStep {
 	Pause,
 	Continue,
 	Kill,
 }
 
+// polled by Cpu::run_loop before every instruction while attached to a hart
 pub trait DebugAdapter<Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus> {
-	fn debug_begin(hart_id: u32);
-	fn debug_end(hart_id: u32);
-	fn debug_step_poll(cpu: &mut Cpu<Timer, MIO, IntBus>) -> DebugStep;
+	fn debug_begin(&mut self, hart_id: u32);
+	fn debug_end(&mut self, hart_id: u32);
+	fn debug_step_poll(&mut self, cpu: &mut Cpu<Timer, MIO, IntBus>) -> DebugStep;
 }
@@ -0,0 +1,410 @@
+use crate::cpu::REG_NAMES;
+use crate::opcode::bitfield;
+use crate::{expand_compressed, is_compressed, AtomicFunct7, AtomicSizeFunct3, BranchFunct3, FCvtType, FMvXWClassFunct3, FpCmpFunct3, FpFormatFunct3, FpFormatFunct2, FpFunct7, FpMinMaxFunct3, FpSignFunct3, LoadFunct3, Op, OpFunct3Funct7, OpImmFunct3, OpImmShiftFunct, Opcode, StoreFunct3, SystemFunct3, SystemIntFunct7};
+
+pub(crate) const FP_REG_NAMES: [&str; 32] = [
+	"ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7",
+	"fs0", "fs1", "fa0", "fa1", "fa2", "fa3", "fa4", "fa5",
+	"fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7",
+	"fs8", "fs9", "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+];
+
+const ECALL: u32 = 0x0000_0073;
+const EBREAK: u32 = 0x0010_0073;
+const SRET: u32 = 0x1020_0073;
+
+fn x(reg: u32) -> &'static str {
+	REG_NAMES[reg as usize]
+}
+
+fn f(reg: u32) -> &'static str {
+	FP_REG_NAMES[reg as usize]
+}
+
+pub(crate) fn csr_name(csr: u32) -> String {
+	let name = match csr {
+		0x001 => "fflags",
+		0x002 => "frm",
+		0x003 => "fcsr",
+		0x100 => "sstatus",
+		0x104 => "sie",
+		0x105 => "stvec",
+		0x106 => "scounteren",
+		0x140 => "sscratch",
+		0x141 => "sepc",
+		0x142 => "scause",
+		0x143 => "stval",
+		0x144 => "sip",
+		0x180 => "satp",
+		0x300 => "mstatus",
+		0x301 => "misa",
+		0x302 => "medeleg",
+		0x303 => "mideleg",
+		0x304 => "mie",
+		0x305 => "mtvec",
+		0x306 => "mcounteren",
+		0x320 => "mcountinhibit",
+		0x340 => "mscratch",
+		0x341 => "mepc",
+		0x342 => "mcause",
+		0x343 => "mtval",
+		0x344 => "mip",
+		0xB00 => "mcycle",
+		0xB02 => "minstret",
+		0xB80 => "mcycleh",
+		0xB82 => "minstreth",
+		0xC00 => "cycle",
+		0xC01 => "time",
+		0xC02 => "instret",
+		0xC80 => "cycleh",
+		0xC81 => "timeh",
+		0xC82 => "instreth",
+		0xF11 => "mvendorid",
+		0xF12 => "marchid",
+		0xF13 => "mimpid",
+		0xF14 => "mhartid",
+		0x3A0 ..= 0x3A3 => return format!("pmpcfg{}", csr - 0x3A0),
+		0x3B0 ..= 0x3BF => return format!("pmpaddr{}", csr - 0x3B0),
+		0x323 ..= 0x33F => return format!("mhpmevent{}", csr - 0x320),
+		0xB03 ..= 0xB1F => return format!("mhpmcounter{}", csr - 0xB00),
+		0xB83 ..= 0xB9F => return format!("mhpmcounter{}h", csr - 0xB80),
+		0xC03 ..= 0xC1F => return format!("hpmcounter{}", csr - 0xC00),
+		0xC83 ..= 0xC9F => return format!("hpmcounter{}h", csr - 0xC80),
+		_ => return format!("{:#05x}", csr)
+	};
+	name.to_string()
+}
+
+fn rounding_mode_suffix(rm: u32) -> &'static str {
+	match rm {
+		0b000 => ", rne",
+		0b001 => ", rtz",
+		0b010 => ", rdn",
+		0b011 => ", rup",
+		0b100 => ", rmm",
+		0b111 => "",
+		_ => ", ?"
+	}
+}
+
+fn fence_set(bits: u32) -> String {
+	let mut set = String::new();
+	for (bit, name) in [(8, 'i'), (4, 'o'), (2, 'r'), (1, 'w')].iter() {
+		if bits & bit != 0 {
+			set.push(*name);
+		}
+	}
+	if set.is_empty() {
+		set.push('0');
+	}
+	set
+}
+
+fn target<F: Fn(u32) -> Option<String>>(addr: u32, symbol_lookup: &F) -> String {
+	match symbol_lookup(addr) {
+		Some(symbol) => format!("{:#010x} <{}>", addr, symbol),
+		None => format!("{:#010x}", addr)
+	}
+}
+
+fn unknown(value: u32) -> String {
+	format!(".word {:#010x}", value)
+}
+
+pub fn disassemble(opcode: u32, pc: u32) -> String {
+	disassemble_with_symbols(opcode, pc, |_| None)
+}
+
+// symbol_lookup maps a branch or jump target to a name to show alongside the address
+pub fn disassemble_with_symbols<F: Fn(u32) -> Option<String>>(opcode: u32, pc: u32, symbol_lookup: F) -> String {
+	if is_compressed(opcode) {
+		return match expand_compressed(opcode) {
+			Some(expanded) => disassemble_expanded(expanded, pc, &symbol_lookup),
+			None => format!(".half {:#06x}", opcode & 0xFFFF)
+		};
+	}
+	disassemble_expanded(opcode, pc, &symbol_lookup)
+}
+
+fn disassemble_expanded<F: Fn(u32) -> Option<String>>(value: u32, pc: u32, symbol_lookup: &F) -> String {
+	let opcode = Opcode::new(value);
+	let rd = opcode.rd();
+	let rs1 = opcode.rs1();
+	let rs2 = opcode.rs2();
+	match opcode.op() {
+		Op::Lui => format!("lui {}, {:#x}", x(rd), opcode.u_imm()),
+		Op::Auipc => format!("auipc {}, {:#x}", x(rd), opcode.u_imm()),
+		Op::Jal => format!("jal {}, {}", x(rd), target(pc.wrapping_add(opcode.j_imm_signed() as u32), symbol_lookup)),
+		Op::Jalr => format!("jalr {}, {}({})", x(rd), opcode.i_imm_signed(), x(rs1)),
+		Op::Branch => {
+			let mnemonic = match opcode.funct3_branch() {
+				BranchFunct3::Eq => "beq",
+				BranchFunct3::NEq => "bne",
+				BranchFunct3::Lt => "blt",
+				BranchFunct3::GEq => "bge",
+				BranchFunct3::LtU => "bltu",
+				BranchFunct3::GEqU => "bgeu",
+				BranchFunct3::Unknown => return unknown(value),
+			};
+			format!("{} {}, {}, {}", mnemonic, x(rs1), x(rs2), target(pc.wrapping_add(opcode.b_imm_signed() as u32), symbol_lookup))
+		},
+		Op::Load => {
+			let mnemonic = match opcode.funct3_load() {
+				LoadFunct3::Byte => "lb",
+				LoadFunct3::Half => "lh",
+				LoadFunct3::Word => "lw",
+				LoadFunct3::ByteUnsigned => "lbu",
+				LoadFunct3::HalfUnsigned => "lhu",
+				LoadFunct3::Unknown => return unknown(value),
+			};
+			format!("{} {}, {}({})", mnemonic, x(rd), opcode.i_imm_signed(), x(rs1))
+		},
+		Op::Store => {
+			let mnemonic = match opcode.funct3_store() {
+				StoreFunct3::Byte => "sb",
+				StoreFunct3::Half => "sh",
+				StoreFunct3::Word => "sw",
+				StoreFunct3::Unknown => return unknown(value),
+			};
+			format!("{} {}, {}({})", mnemonic, x(rs2), opcode.s_imm_signed(), x(rs1))
+		},
+		Op::OpImm => {
+			match opcode.funct3_op_imm() {
+				OpImmFunct3::AddI => format!("addi {}, {}, {}", x(rd), x(rs1), opcode.i_imm_signed()),
+				OpImmFunct3::SltI => format!("slti {}, {}, {}", x(rd), x(rs1), opcode.i_imm_signed()),
+				OpImmFunct3::SltIU => format!("sltiu {}, {}, {}", x(rd), x(rs1), opcode.i_imm_signed()),
+				OpImmFunct3::XorI => format!("xori {}, {}, {}", x(rd), x(rs1), opcode.i_imm_signed()),
+				OpImmFunct3::OrI => format!("ori {}, {}, {}", x(rd), x(rs1), opcode.i_imm_signed()),
+				OpImmFunct3::AndI => format!("andi {}, {}, {}", x(rd), x(rs1), opcode.i_imm_signed()),
+				OpImmFunct3::SllI | OpImmFunct3::SrxI => {
+					let (mnemonic, has_shamt) = match opcode.funct_op_imm_shift() {
+						OpImmShiftFunct::SllI => ("slli", true),
+						OpImmShiftFunct::SrlI => ("srli", true),
+						OpImmShiftFunct::SraI => ("srai", true),
+						OpImmShiftFunct::RorI => ("rori", true),
+						OpImmShiftFunct::BSetI => ("bseti", true),
+						OpImmShiftFunct::BClrI => ("bclri", true),
+						OpImmShiftFunct::BInvI => ("binvi", true),
+						OpImmShiftFunct::BExtI => ("bexti", true),
+						OpImmShiftFunct::Clz => ("clz", false),
+						OpImmShiftFunct::Ctz => ("ctz", false),
+						OpImmShiftFunct::CPop => ("cpop", false),
+						OpImmShiftFunct::SextB => ("sext.b", false),
+						OpImmShiftFunct::SextH => ("sext.h", false),
+						OpImmShiftFunct::Rev8 => ("rev8", false),
+						OpImmShiftFunct::OrcB => ("orc.b", false),
+						OpImmShiftFunct::Unknown => return unknown(value),
+					};
+					if has_shamt {
+						format!("{} {}, {}, {}", mnemonic, x(rd), x(rs1), opcode.shamt())
+					} else {
+						format!("{} {}, {}", mnemonic, x(rd), x(rs1))
+					}
+				},
+			}
+		},
+		Op::Op => {
+			let mnemonic = match opcode.funct3funct7_op() {
+				OpFunct3Funct7::Add => "add",
+				OpFunct3Funct7::Sub => "sub",
+				OpFunct3Funct7::Sll => "sll",
+				OpFunct3Funct7::Slt => "slt",
+				OpFunct3Funct7::SltU => "sltu",
+				OpFunct3Funct7::Xor => "xor",
+				OpFunct3Funct7::Srl => "srl",
+				OpFunct3Funct7::Sra => "sra",
+				OpFunct3Funct7::Or => "or",
+				OpFunct3Funct7::And => "and",
+				OpFunct3Funct7::Mul => "mul",
+				OpFunct3Funct7::MulH => "mulh",
+				OpFunct3Funct7::MulHSU => "mulhsu",
+				OpFunct3Funct7::MulHU => "mulhu",
+				OpFunct3Funct7::Div => "div",
+				OpFunct3Funct7::DivU => "divu",
+				OpFunct3Funct7::Rem => "rem",
+				OpFunct3Funct7::RemU => "remu",
+				OpFunct3Funct7::Sh1Add => "sh1add",
+				OpFunct3Funct7::Sh2Add => "sh2add",
+				OpFunct3Funct7::Sh3Add => "sh3add",
+				OpFunct3Funct7::AndN => "andn",
+				OpFunct3Funct7::OrN => "orn",
+				OpFunct3Funct7::XNor => "xnor",
+				OpFunct3Funct7::Min => "min",
+				OpFunct3Funct7::MinU => "minu",
+				OpFunct3Funct7::Max => "max",
+				OpFunct3Funct7::MaxU => "maxu",
+				OpFunct3Funct7::Rol => "rol",
+				OpFunct3Funct7::Ror => "ror",
+				OpFunct3Funct7::BSet => "bset",
+				OpFunct3Funct7::BClr => "bclr",
+				OpFunct3Funct7::BInv => "binv",
+				OpFunct3Funct7::BExt => "bext",
+				OpFunct3Funct7::ZextH => {
+					if rs2 != 0 {
+						return unknown(value);
+					}
+					return format!("zext.h {}, {}", x(rd), x(rs1));
+				},
+				OpFunct3Funct7::Unknown => return unknown(value),
+			};
+			format!("{} {}, {}, {}", mnemonic, x(rd), x(rs1), x(rs2))
+		},
+		Op::Fence => {
+			match opcode.funct3() {
+				0b000 => format!("fence {}, {}", fence_set(bitfield(value, 4, 24, 0)), fence_set(bitfield(value, 4, 20, 0))),
+				0b001 => "fence.i".to_string(),
+				_ => unknown(value)
+			}
+		},
+		Op::Atomic => {
+			if let AtomicSizeFunct3::Unknown = opcode.funct3_atomicsize() {
+				return unknown(value);
+			}
+			// funct7 holds funct5 plus the aq / rl ordering bits
+			let ordering = match opcode.funct7() & 0b11 {
+				0b10 => ".aq",
+				0b01 => ".rl",
+				0b11 => ".aqrl",
+				_ => ""
+			};
+			let mnemonic = match opcode.funct7_atomic() {
+				AtomicFunct7::LoadReserve => return format!("lr.w{} {}, ({})", ordering, x(rd), x(rs1)),
+				AtomicFunct7::StoreConditional => "sc.w",
+				AtomicFunct7::Swap => "amoswap.w",
+				AtomicFunct7::Add => "amoadd.w",
+				AtomicFunct7::Xor => "amoxor.w",
+				AtomicFunct7::And => "amoand.w",
+				AtomicFunct7::Or => "amoor.w",
+				AtomicFunct7::Min => "amomin.w",
+				AtomicFunct7::Max => "amomax.w",
+				AtomicFunct7::MinU => "amominu.w",
+				AtomicFunct7::MaxU => "amomaxu.w",
+				AtomicFunct7::Unknown => return unknown(value),
+			};
+			format!("{}{} {}, {}, ({})", mnemonic, ordering, x(rd), x(rs2), x(rs1))
+		},
+		Op::System => {
+			let csr = opcode.i_imm();
+			match opcode.funct3_system() {
+				SystemFunct3::Int => {
+					match value {
+						ECALL => return "ecall".to_string(),
+						EBREAK => return "ebreak".to_string(),
+						SRET => return "sret".to_string(),
+						_ => {}
+					}
+					match opcode.funct7_system_int() {
+						SystemIntFunct7::WaitForInterrupt => "wfi".to_string(),
+						SystemIntFunct7::SFenceVma => format!("sfence.vma {}, {}", x(rs1), x(rs2)),
+						SystemIntFunct7::MRet => "mret".to_string(),
+						SystemIntFunct7::Unknown => unknown(value),
+					}
+				},
+				SystemFunct3::CsrRW => format!("csrrw {}, {}, {}", x(rd), csr_name(csr), x(rs1)),
+				SystemFunct3::CsrRS => format!("csrrs {}, {}, {}", x(rd), csr_name(csr), x(rs1)),
+				SystemFunct3::CsrRC => format!("csrrc {}, {}, {}", x(rd), csr_name(csr), x(rs1)),
+				SystemFunct3::CsrRWI => format!("csrrwi {}, {}, {}", x(rd), csr_name(csr), rs1),
+				SystemFunct3::CsrRSI => format!("csrrsi {}, {}, {}", x(rd), csr_name(csr), rs1),
+				SystemFunct3::CsrRCI => format!("csrrci {}, {}, {}", x(rd), csr_name(csr), rs1),
+				SystemFunct3::Unknown => unknown(value),
+			}
+		},
+		Op::LoadFp => {
+			match opcode.funct3_fpformat() {
+				FpFormatFunct3::Width32 => format!("flw {}, {}({})", f(rd), opcode.i_imm_signed(), x(rs1)),
+				FpFormatFunct3::Width64 => format!("fld {}, {}({})", f(rd), opcode.i_imm_signed(), x(rs1)),
+				FpFormatFunct3::Unknown => unknown(value),
+			}
+		},
+		Op::StoreFp => {
+			match opcode.funct3_fpformat() {
+				FpFormatFunct3::Width32 => format!("fsw {}, {}({})", f(rs2), opcode.s_imm_signed(), x(rs1)),
+				FpFormatFunct3::Width64 => format!("fsd {}, {}({})", f(rs2), opcode.s_imm_signed(), x(rs1)),
+				FpFormatFunct3::Unknown => unknown(value),
+			}
+		},
+		Op::MAdd | Op::MSub | Op::NMSub | Op::NMAdd => {
+			let format = match opcode.funct2_fpformat() {
+				FpFormatFunct2::Single => "s",
+				FpFormatFunct2::Double => "d",
+				FpFormatFunct2::Unknown => return unknown(value),
+			};
+			let mnemonic = match opcode.op() {
+				Op::MAdd => "fmadd",
+				Op::MSub => "fmsub",
+				Op::NMSub => "fnmsub",
+				_ => "fnmadd",
+			};
+			format!("{}.{} {}, {}, {}, {}{}", mnemonic, format, f(rd), f(rs1), f(rs2), f(opcode.rs3()), rounding_mode_suffix(opcode.funct3()))
+		},
+		Op::OpFp => {
+			let rm = rounding_mode_suffix(opcode.funct3());
+			// the low two bits of funct7 give the format
+			let format = if opcode.funct7() & 1 != 0 { "d" } else { "s" };
+			match opcode.funct7_fp() {
+				FpFunct7::Add_S | FpFunct7::Add_D => format!("fadd.{} {}, {}, {}{}", format, f(rd), f(rs1), f(rs2), rm),
+				FpFunct7::Sub_S | FpFunct7::Sub_D => format!("fsub.{} {}, {}, {}{}", format, f(rd), f(rs1), f(rs2), rm),
+				FpFunct7::Mul_S | FpFunct7::Mul_D => format!("fmul.{} {}, {}, {}{}", format, f(rd), f(rs1), f(rs2), rm),
+				FpFunct7::Div_S | FpFunct7::Div_D => format!("fdiv.{} {}, {}, {}{}", format, f(rd), f(rs1), f(rs2), rm),
+				FpFunct7::Sqrt_S | FpFunct7::Sqrt_D => format!("fsqrt.{} {}, {}{}", format, f(rd), f(rs1), rm),
+				FpFunct7::Sign_S | FpFunct7::Sign_D => {
+					let mnemonic = match opcode.funct3_fpsign() {
+						FpSignFunct3::SignFromRs2 => "fsgnj",
+						FpSignFunct3::SignFromNotRs2 => "fsgnjn",
+						FpSignFunct3::SignFromRs1XorRs2 => "fsgnjx",
+						FpSignFunct3::Unknown => return unknown(value),
+					};
+					format!("{}.{} {}, {}, {}", mnemonic, format, f(rd), f(rs1), f(rs2))
+				},
+				FpFunct7::MinMax_S | FpFunct7::MinMax_D => {
+					let mnemonic = match opcode.funct3_fpminmax() {
+						FpMinMaxFunct3::Min => "fmin",
+						FpMinMaxFunct3::Max => "fmax",
+						FpMinMaxFunct3::Unknown => return unknown(value),
+					};
+					format!("{}.{} {}, {}, {}", mnemonic, format, f(rd), f(rs1), f(rs2))
+				},
+				FpFunct7::CvtW_S | FpFunct7::CvtW_D => {
+					let mnemonic = match opcode.rs2_fcvtws() {
+						FCvtType::Signed => "fcvt.w",
+						FCvtType::Unsigned => "fcvt.wu",
+						FCvtType::Unknown => return unknown(value),
+					};
+					format!("{}.{} {}, {}{}", mnemonic, format, x(rd), f(rs1), rm)
+				},
+				FpFunct7::MvXWClass_S => {
+					match opcode.funct3_fmvxwclass() {
+						FMvXWClassFunct3::MvXW => format!("fmv.x.w {}, {}", x(rd), f(rs1)),
+						FMvXWClassFunct3::Class => format!("fclass.s {}, {}", x(rd), f(rs1)),
+						FMvXWClassFunct3::Unknown => unknown(value),
+					}
+				},
+				FpFunct7::Cmp_S | FpFunct7::Cmp_D => {
+					let mnemonic = match opcode.funct3_fpcmp() {
+						FpCmpFunct3::Eq => "feq",
+						FpCmpFunct3::Lt => "flt",
+						FpCmpFunct3::LEq => "fle",
+						FpCmpFunct3::Unknown => return unknown(value),
+					};
+					format!("{}.{} {}, {}, {}", mnemonic, format, x(rd), f(rs1), f(rs2))
+				},
+				FpFunct7::CvtS_W | FpFunct7::CvtD_W => {
+					let mnemonic = match opcode.rs2_fcvtws() {
+						FCvtType::Signed => "w",
+						FCvtType::Unsigned => "wu",
+						FCvtType::Unknown => return unknown(value),
+					};
+					format!("fcvt.{}.{} {}, {}{}", format, mnemonic, f(rd), x(rs1), rm)
+				},
+				FpFunct7::MvWX_S => format!("fmv.w.x {}, {}", f(rd), x(rs1)),
+				FpFunct7::CvtS_D if rs2 == 1 => format!("fcvt.s.d {}, {}{}", f(rd), f(rs1), rm),
+				FpFunct7::CvtD_S if rs2 == 0 => format!("fcvt.d.s {}, {}{}", f(rd), f(rs1), rm),
+				FpFunct7::CvtS_D | FpFunct7::CvtD_S => unknown(value),
+				FpFunct7::Class_D if opcode.funct3() == 0b001 => format!("fclass.d {}, {}", x(rd), f(rs1)),
+				FpFunct7::Class_D => unknown(value),
+				FpFunct7::Unknown => unknown(value),
+			}
+		},
+		Op::Unknown => unknown(value),
+	}
+}
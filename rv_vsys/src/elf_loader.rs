@@ -1,5 +1,5 @@
 use mem::size_of;
-use rv_vsys::{MemIO, MTimer};
+use crate::{MemIO, MemWriteResult, MTimer};
 use std::{collections::HashMap, fmt::Display, fmt, mem};
 use bytemuck::{Pod, Zeroable, from_bytes};
 
@@ -101,6 +101,7 @@ impl ElfVersion {
 	}
 }
 
+#[allow(dead_code)]
 #[derive(Debug)]
 struct ElfHeader {
 	ident: [u8; EI_NIDENT],
@@ -233,6 +234,7 @@ impl ElfSectionType {
 	}
 }
 
+#[allow(dead_code)]
 #[derive(Debug)]
 struct ElfSectionHeader {
 	name_index: u32,
@@ -314,6 +316,7 @@ impl ElfStringTable {
 	}
 }
 
+#[allow(dead_code)]
 #[derive(Debug)]
 enum ElfProgramHeaderType {
 	Null,
@@ -532,6 +535,7 @@ impl ElfSymbolSection {
 	}
 }
 
+#[allow(dead_code)]
 #[derive(Debug)]
 struct ElfSymbol {
 	binding: ElfSymbolBinding,
@@ -575,9 +579,9 @@ fn symbol_temporary_name(sym_raw: &ElfSymbolRaw) -> String {
 		ElfSymbolSection::Proc(val) => format!("p{:04x}_", val).to_string(),
 		ElfSymbolSection::Unknown => "unknown_".to_string()
 	};
-	unsafe {
-		format!("{}{}{:08x}", sym_type_name, sym_section_name, sym_raw.value).to_string()
-	}
+	// copied out, as formatting takes a reference and the packed field may be unaligned
+	let value = u32::from_le(sym_raw.value);
+	format!("{}{}{:08x}", sym_type_name, sym_section_name, value)
 }
 
 #[allow(dead_code)]
@@ -718,13 +722,13 @@ fn load_program_header<Timer: MTimer, Mem: MemIO<Timer>>(file_data: &[u8], progr
 			}
 			for i in 0 .. f_size {
 				match mio.write_8(m_addr + i, file_data[(offset + i) as usize]) {
-					rv_vsys::MemWriteResult::Ok => {},
+					MemWriteResult::Ok => {},
 					_ => return Err(format!("failed to write to address {:#010x}", m_addr + i).to_string())
 				}
 			}
 			for i in 0 .. z_size {
 				match mio.write_8(zm_addr + i, 0) {
-					rv_vsys::MemWriteResult::Ok => {},
+					MemWriteResult::Ok => {},
 					_ => return Err(format!("failed to write zero at address {:#010x}", m_addr + i).to_string())
 				}
 			}
@@ -793,4 +797,31 @@ pub fn load_elf<Timer: MTimer, Mem: MemIO<Timer>>(file_data: &[u8], mio: &mut Me
 	}*/
 	mio.access_break();
 	Ok(header.entry)
+}
+
+// symbol values by name, for tools which need to find labels in an image (e.g. tohost)
+pub fn load_elf_symbols(file_data: &[u8]) -> Result<HashMap<String, u32>, String> {
+	let header_size = mem::size_of::<ElfHeaderRaw>();
+	if file_data.len() < header_size {
+		return Err("Elf file too small to be Elf format".to_string())
+	};
+	let raw_header: ElfHeaderRaw = *from_bytes(&file_data[0..header_size]);
+	let header: ElfHeader = ElfHeader::from_raw(&raw_header);
+	match check_header(&header) {
+		Ok(()) => {},
+		Err(error_string) => return Err(error_string)
+	}
+	let section_headers = match load_section_headers(file_data, &header) {
+		Ok(headers) => headers,
+		Err(error_string) => return Err(error_string)
+	};
+	let string_tables = match collect_string_tables(file_data, &header, &section_headers) {
+		Ok(string_tables) => string_tables,
+		Err(error_string) => return Err(error_string)
+	};
+	let symbol_table = match load_symbol_tables(file_data, &section_headers, &string_tables) {
+		Ok(symbol_table) => symbol_table,
+		Err(error_string) => return Err(error_string)
+	};
+	Ok(symbol_table.into_iter().map(|(name, symbol)| (name, symbol.value)).collect())
 }
\ No newline at end of file
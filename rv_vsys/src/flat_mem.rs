@@ -0,0 +1,282 @@
+use std::sync::Arc;
+use parking_lot::Mutex;
+
+use crate::{MemIO, MemReadResult, MemWriteResult, ManualMTimer};
+
+// Single hart memory interface with one block of ram and nothing else mapped, for running a Cpu
+// in tools and tests without a whole machine around it. Clones share the same ram, so a handle
+// kept outside of the Cpu can load images and inspect results.
+
+const PAGE_SIZE: u32 = 0x1000;
+
+struct FlatMemory {
+	ram: Box<[u8]>,
+	// bumped whenever a page is written, for instruction caches and reservations
+	page_write_cycles: Box<[usize]>,
+	write_cycle: usize,
+}
+
+#[derive(Clone)]
+pub struct FlatMemIO {
+	base: u32,
+	size: u32,
+	memory: Arc<Mutex<FlatMemory>>,
+	timer: Arc<ManualMTimer>,
+}
+
+impl FlatMemIO {
+	pub fn new(base: u32, size: u32) -> Self {
+		let page_count = (size as usize).div_ceil(PAGE_SIZE as usize);
+		FlatMemIO {
+			base,
+			size,
+			memory: Arc::new(Mutex::new(FlatMemory {
+				ram: vec![0; size as usize].into_boxed_slice(),
+				page_write_cycles: vec![0; page_count].into_boxed_slice(),
+				write_cycle: 0,
+			})),
+			timer: Arc::new(ManualMTimer::new()),
+		}
+	}
+
+	pub fn base(&self) -> u32 {
+		self.base
+	}
+
+	pub fn size(&self) -> u32 {
+		self.size
+	}
+
+	pub fn timer(&self) -> Arc<ManualMTimer> {
+		self.timer.clone()
+	}
+
+	fn offset(&self, addr: u32, size: u32) -> Option<usize> {
+		let offset = addr.wrapping_sub(self.base);
+		if offset as u64 + size as u64 <= self.size as u64 {
+			Some(offset as usize)
+		} else {
+			None
+		}
+	}
+
+	// copies data into ram at addr, false if any of it would fall outside
+	pub fn load(&self, addr: u32, data: &[u8]) -> bool {
+		if data.len() as u64 > self.size as u64 {
+			return false;
+		}
+		match self.offset(addr, data.len() as u32) {
+			Some(offset) => {
+				let mut memory = self.memory.lock();
+				memory.ram[offset .. offset + data.len()].copy_from_slice(data);
+				Self::mark_written(&mut memory, offset, data.len());
+				true
+			},
+			None => false
+		}
+	}
+
+	pub fn dump(&self, addr: u32, length: u32) -> Option<Vec<u8>> {
+		let offset = self.offset(addr, length)?;
+		let memory = self.memory.lock();
+		Some(memory.ram[offset .. offset + length as usize].to_vec())
+	}
+
+	fn mark_written(memory: &mut FlatMemory, offset: usize, length: usize) {
+		if length == 0 {
+			return;
+		}
+		memory.write_cycle += 1;
+		let cycle = memory.write_cycle;
+		for page in offset / PAGE_SIZE as usize ..= (offset + length - 1) / PAGE_SIZE as usize {
+			memory.page_write_cycles[page] = cycle;
+		}
+	}
+
+	fn read(&self, addr: u32, size: u32) -> Option<u32> {
+		let offset = self.offset(addr, size)?;
+		let memory = self.memory.lock();
+		let mut value = 0;
+		for i in 0 .. size as usize {
+			value |= (memory.ram[offset + i] as u32) << (i * 8);
+		}
+		Some(value)
+	}
+
+	fn write(&mut self, addr: u32, size: u32, value: u32) -> MemWriteResult {
+		let offset = match self.offset(addr, size) {
+			Some(offset) => offset,
+			None => return MemWriteResult::ErrUnmapped
+		};
+		let mut memory = self.memory.lock();
+		for i in 0 .. size as usize {
+			memory.ram[offset + i] = (value >> (i * 8)) as u8;
+		}
+		Self::mark_written(&mut memory, offset, size as usize);
+		MemWriteResult::Ok
+	}
+}
+
+impl MemIO<ManualMTimer> for FlatMemIO {
+	fn read_8(&self, addr: u32) -> MemReadResult<u8> {
+		match self.read(addr, 1) {
+			Some(value) => MemReadResult::Ok(value as u8),
+			None => MemReadResult::ErrUnmapped
+		}
+	}
+
+	fn read_16(&self, addr: u32) -> MemReadResult<u16> {
+		match self.read(addr, 2) {
+			Some(value) => MemReadResult::Ok(value as u16),
+			None => MemReadResult::ErrUnmapped
+		}
+	}
+
+	fn read_32(&self, addr: u32) -> MemReadResult<u32> {
+		match self.read(addr, 4) {
+			Some(value) => MemReadResult::Ok(value),
+			None => MemReadResult::ErrUnmapped
+		}
+	}
+
+	// a compressed instruction in the last halfword of ram has nothing after it to fetch
+	fn read_32_ifetch(&self, addr: u32) -> MemReadResult<u32> {
+		let low = match self.read(addr, 2) {
+			Some(low) => low,
+			None => return MemReadResult::ErrUnmapped
+		};
+		if low & 3 != 3 {
+			return MemReadResult::Ok(low);
+		}
+		match self.read(addr.wrapping_add(2), 2) {
+			Some(high) => MemReadResult::Ok(low | high << 16),
+			None => MemReadResult::ErrUnmapped
+		}
+	}
+
+	// the reservation is the page's write cycle, so any write to the page breaks it
+	fn read_32_ll(&self, addr: u32) -> (MemReadResult<u32>, usize, u32) {
+		if (addr & 0b11) != 0 {
+			return (MemReadResult::ErrAlignment, 0, 0);
+		}
+		let offset = match self.offset(addr, 4) {
+			Some(offset) => offset,
+			None => return (MemReadResult::ErrUnmapped, 0, 0)
+		};
+		let page = offset / PAGE_SIZE as usize;
+		let write_cycle = self.memory.lock().page_write_cycles[page];
+		(self.read_32(addr), write_cycle, page as u32)
+	}
+
+	fn lock_for_modify(&mut self, addr: u32) -> MemWriteResult {
+		match self.offset(addr, 4) {
+			Some(_) => MemWriteResult::Ok,
+			None => MemWriteResult::ErrUnmapped
+		}
+	}
+
+	fn write_8(&mut self, addr: u32, value: u8) -> MemWriteResult {
+		self.write(addr, 1, value as u32)
+	}
+
+	fn write_16(&mut self, addr: u32, value: u16) -> MemWriteResult {
+		self.write(addr, 2, value as u32)
+	}
+
+	fn write_32(&mut self, addr: u32, value: u32) -> MemWriteResult {
+		self.write(addr, 4, value)
+	}
+
+	fn write_32_cs(&mut self, addr: u32, value: u32, ll_cycle: usize, page_key: u32) -> Option<MemWriteResult> {
+		if (addr & 0b11) != 0 {
+			return Some(MemWriteResult::ErrAlignment);
+		}
+		let offset = self.offset(addr, 4)?;
+		if offset / PAGE_SIZE as usize != page_key as usize || self.memory.lock().page_write_cycles[page_key as usize] != ll_cycle {
+			return None;
+		}
+		Some(self.write(addr, 4, value))
+	}
+
+	fn access_break(&mut self) {}
+
+	fn code_write_cycle(&self, addr: u32) -> Option<usize> {
+		let offset = self.offset(addr, 1)?;
+		Some(self.memory.lock().page_write_cycles[offset / PAGE_SIZE as usize])
+	}
+
+	fn is_plain_memory(&self, addr: u32, size: u32) -> bool {
+		self.offset(addr, size).is_some()
+	}
+
+	fn set_hart_id(&mut self, _id: u32) {}
+
+	fn get_mtimer(&self, _hart_id: u32) -> Option<Arc<ManualMTimer>> {
+		Some(self.timer.clone())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{assemble, Cpu, CpuWakeupHandle, MTimer, NullInterruptBus};
+
+	const BASE: u32 = 0x8000_0000;
+
+	#[test]
+	fn cpu_runs_from_flat_memory() {
+		let mio = FlatMemIO::new(BASE, 0x2000);
+		let program = assemble("
+			li a0, 5
+			li a1, 7
+			add a2, a0, a1
+			li t0, 0x80001000
+			sw a2, 0(t0)
+			lw a3, 0(t0)
+		", BASE).unwrap();
+		assert!(mio.load(BASE, &program));
+		let mut cpu = Cpu::new(mio.clone(), NullInterruptBus, CpuWakeupHandle::new(), 0);
+		cpu.reset(BASE);
+		for _ in 0 .. 6 {
+			cpu.step();
+		}
+		assert_eq!(cpu.get_pc(), BASE + program.len() as u32);
+		assert_eq!(cpu.get_gpr(13), 12);
+		assert!(matches!(mio.read_32(0x8000_1000), MemReadResult::Ok(12)));
+	}
+
+	#[test]
+	fn accesses_outside_of_ram_are_unmapped() {
+		let mut mio = FlatMemIO::new(BASE, 0x1000);
+		assert!(matches!(mio.read_32(BASE + 0xFFC), MemReadResult::Ok(0)));
+		assert!(matches!(mio.read_32(BASE + 0xFFE), MemReadResult::ErrUnmapped));
+		assert!(matches!(mio.read_8(BASE - 1), MemReadResult::ErrUnmapped));
+		assert!(matches!(mio.write_32(BASE + 0x1000, 1), MemWriteResult::ErrUnmapped));
+		assert!(! mio.load(BASE + 0xFFF, &[1, 2]));
+	}
+
+	#[test]
+	fn compressed_instruction_runs_from_the_last_halfword() {
+		let mio = FlatMemIO::new(BASE, 0x1000);
+		let end = BASE + 0x1000;
+		assert!(mio.load(end - 2, &assemble("c.nop", end - 2).unwrap()));
+		let mut cpu = Cpu::new(mio.clone(), NullInterruptBus, CpuWakeupHandle::new(), 0);
+		cpu.reset(end - 2);
+		cpu.step();
+		assert_eq!(cpu.get_pc(), end);
+		// while a full size instruction there runs off the end of ram
+		assert!(mio.load(end - 2, &[0x13, 0x00]));
+		assert!(matches!(mio.read_32_ifetch(end - 2), MemReadResult::ErrUnmapped));
+	}
+
+	#[test]
+	fn timer_fires_at_compare() {
+		let mio = FlatMemIO::new(BASE, 0x1000);
+		let timer = mio.timer();
+		timer.set_compare(10);
+		timer.advance(9);
+		assert!(! timer.check_timer());
+		timer.advance(1);
+		assert!(timer.check_timer());
+	}
+}
@@ -0,0 +1,1017 @@
+use std::{collections::{HashSet, VecDeque}, io::{self, ErrorKind, Read, Write}, net::{TcpListener, TcpStream}, sync::{Arc, atomic::{AtomicBool, AtomicUsize, Ordering}, mpsc::{self, Receiver, Sender}}, thread::{self, JoinHandle}, time::Duration};
+#[cfg(unix)]
+use std::{os::unix::net::{UnixListener, UnixStream}, path::Path};
+use parking_lot::{Condvar, Mutex, MutexGuard};
+
+use crate::{assemble, Cpu, DebugAdapter, DebugStep, InterruptBus, MTimer, MemIO, MemReadResult, MemWriteResult, ReplayStop, WatchKind, Watchpoint};
+
+// GDB remote serial protocol stub. Every hart gets a GdbHartAdapter which is polled from
+// Cpu::run_loop, and parks the hart while the debugger has it stopped. Register and memory
+// requests are executed on the hart's own thread, through its own MemIO. Reverse step and
+// continue are run on the hart's thread too, while it stays parked, for harts with replay enabled.
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+const REG_PC: u32 = 32;
+const REG_F0: u32 = 33;
+const REG_CSR_BASE: u32 = 65;
+const GPR_PACKET_COUNT: usize = 33;
+
+const HART_POLL_INTERVAL: Duration = Duration::from_millis(10);
+const HART_REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+const HART_HALT_TIMEOUT: Duration = Duration::from_millis(500);
+
+const PACKET_SIZE: usize = 0x4000;
+
+const DEBUG_CSRS: [(u32, &str); 23] = [
+	(0x001, "fflags"),
+	(0x002, "frm"),
+	(0x003, "fcsr"),
+	(0x100, "sstatus"),
+	(0x104, "sie"),
+	(0x105, "stvec"),
+	(0x140, "sscratch"),
+	(0x141, "sepc"),
+	(0x142, "scause"),
+	(0x143, "stval"),
+	(0x144, "sip"),
+	(0x180, "satp"),
+	(0x300, "mstatus"),
+	(0x301, "misa"),
+	(0x302, "medeleg"),
+	(0x303, "mideleg"),
+	(0x304, "mie"),
+	(0x305, "mtvec"),
+	(0x340, "mscratch"),
+	(0x341, "mepc"),
+	(0x342, "mcause"),
+	(0x343, "mtval"),
+	(0x344, "mip"),
+];
+
+const GPR_NAMES: [&str; 32] = [
+	"zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2",
+	"fp", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+	"a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7",
+	"s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+];
+
+const FPR_NAMES: [&str; 32] = [
+	"ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7",
+	"fs0", "fs1", "fa0", "fa1", "fa2", "fa3", "fa4", "fa5",
+	"fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7",
+	"fs8", "fs9", "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+];
+
+fn target_description() -> String {
+	let mut xml = String::new();
+	xml += "<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n<target version=\"1.0\">\n<architecture>riscv:rv32</architecture>\n";
+	xml += "<feature name=\"org.gnu.gdb.riscv.cpu\">\n";
+	for (i, name) in GPR_NAMES.iter().enumerate() {
+		let reg_type = match i {
+			1 => "code_ptr",
+			2 | 8 => "data_ptr",
+			_ => "int",
+		};
+		xml += format!("<reg name=\"{}\" bitsize=\"32\" type=\"{}\" regnum=\"{}\"/>\n", name, reg_type, i).as_str();
+	}
+	xml += format!("<reg name=\"pc\" bitsize=\"32\" type=\"code_ptr\" regnum=\"{}\"/>\n", REG_PC).as_str();
+	xml += "</feature>\n<feature name=\"org.gnu.gdb.riscv.fpu\">\n";
+	for (i, name) in FPR_NAMES.iter().enumerate() {
+		xml += format!("<reg name=\"{}\" bitsize=\"64\" type=\"ieee_double\" regnum=\"{}\"/>\n", name, REG_F0 + i as u32).as_str();
+	}
+	for (csr, name) in DEBUG_CSRS.iter().take(3) {
+		xml += format!("<reg name=\"{}\" bitsize=\"32\" type=\"int\" regnum=\"{}\"/>\n", name, REG_CSR_BASE + csr).as_str();
+	}
+	xml += "</feature>\n<feature name=\"org.gnu.gdb.riscv.csr\">\n";
+	for (csr, name) in DEBUG_CSRS.iter().skip(3) {
+		xml += format!("<reg name=\"{}\" bitsize=\"32\" type=\"int\" regnum=\"{}\"/>\n", name, REG_CSR_BASE + csr).as_str();
+	}
+	xml += "</feature>\n</target>\n";
+	xml
+}
+
+enum HartCommand {
+	ReadRegisters,
+	WriteRegisters(Vec<u32>),
+	ReadRegister(u32),
+	WriteRegister(u32, u64),
+	ReadMemory(u32, u32),
+	WriteMemory(u32, Vec<u8>),
+}
+
+enum HartReply {
+	Registers(Vec<u32>),
+	Register(Option<u64>),
+	Memory(Vec<u8>),
+	Done(bool),
+}
+
+#[derive(Clone, Copy)]
+enum Resume {
+	Continue,
+	Step,
+	ReverseContinue,
+	ReverseStep,
+}
+
+#[derive(Clone, Copy)]
+enum StopReason {
+	Breakpoint,
+	Step,
+	Watchpoint(WatchKind, u32),
+	HistoryStart,
+}
+
+// the stop reply fields gdb expects ahead of the thread
+fn stop_detail(reason: StopReason) -> String {
+	match reason {
+		StopReason::Watchpoint(WatchKind::Write, address) => format!("watch:{:x};", address),
+		StopReason::Watchpoint(WatchKind::Read, address) => format!("rwatch:{:x};", address),
+		StopReason::Watchpoint(_, address) => format!("awatch:{:x};", address),
+		StopReason::HistoryStart => "replaylog:begin;".to_string(),
+		StopReason::Breakpoint | StopReason::Step => String::new(),
+	}
+}
+
+struct HartDebugState {
+	attached: bool,
+	halted: bool,
+	halt_requested: bool,
+	resume: Option<Resume>,
+	commands: VecDeque<(HartCommand, Sender<HartReply>)>,
+}
+
+struct HartDebugShared {
+	hart_id: u32,
+	state: Mutex<HartDebugState>,
+	cond: Condvar,
+	attention: AtomicBool,
+	breakpoints: Mutex<HashSet<u32>>,
+	watchpoints: Mutex<Vec<Watchpoint>>,
+	breakpoint_generation: AtomicUsize,
+}
+
+impl HartDebugShared {
+	fn new(hart_id: u32) -> Self {
+		Self {
+			hart_id,
+			state: Mutex::new(HartDebugState {
+				attached: false,
+				halted: false,
+				halt_requested: false,
+				resume: None,
+				commands: VecDeque::new(),
+			}),
+			cond: Condvar::new(),
+			attention: AtomicBool::new(false),
+			breakpoints: Mutex::new(HashSet::new()),
+			watchpoints: Mutex::new(Vec::new()),
+			breakpoint_generation: AtomicUsize::new(0),
+		}
+	}
+
+	fn request_halt(&self) {
+		let mut state = self.state.lock();
+		state.halt_requested = true;
+		self.attention.store(true, Ordering::SeqCst);
+		self.cond.notify_all();
+	}
+
+	fn wait_halted(&self) -> bool {
+		let mut state = self.state.lock();
+		let mut waited = Duration::from_millis(0);
+		while state.attached && ! state.halted {
+			if waited >= HART_HALT_TIMEOUT {
+				return false;
+			}
+			self.cond.wait_for(&mut state, HART_POLL_INTERVAL);
+			waited += HART_POLL_INTERVAL;
+		}
+		state.halted
+	}
+
+	fn resume(&self, resume: Option<Resume>) {
+		let mut state = self.state.lock();
+		state.halt_requested = false;
+		self.attention.store(false, Ordering::SeqCst);
+		if state.halted {
+			state.resume = Some(resume.unwrap_or(Resume::Continue));
+		}
+		self.cond.notify_all();
+	}
+
+	fn is_halted(&self) -> bool {
+		let state = self.state.lock();
+		state.attached && state.halted
+	}
+
+	fn is_attached(&self) -> bool {
+		self.state.lock().attached
+	}
+
+	fn request(&self, command: HartCommand) -> Option<HartReply> {
+		let (reply_sender, reply_receiver) = mpsc::channel();
+		{
+			let mut state = self.state.lock();
+			if ! (state.attached && state.halted) {
+				return None;
+			}
+			state.commands.push_back((command, reply_sender));
+			self.cond.notify_all();
+		}
+		reply_receiver.recv_timeout(HART_REPLY_TIMEOUT).ok()
+	}
+
+	fn set_breakpoint(&self, addr: u32, set: bool) {
+		let mut breakpoints = self.breakpoints.lock();
+		if set {
+			breakpoints.insert(addr);
+		} else {
+			breakpoints.remove(&addr);
+		}
+		self.breakpoint_generation.fetch_add(1, Ordering::SeqCst);
+	}
+
+	fn set_watchpoint(&self, watchpoint: Watchpoint, set: bool) {
+		let mut watchpoints = self.watchpoints.lock();
+		watchpoints.retain(|existing| *existing != watchpoint);
+		if set {
+			watchpoints.push(watchpoint);
+		}
+		self.breakpoint_generation.fetch_add(1, Ordering::SeqCst);
+	}
+
+	fn clear_breakpoints(&self) {
+		self.breakpoints.lock().clear();
+		self.watchpoints.lock().clear();
+		self.breakpoint_generation.fetch_add(1, Ordering::SeqCst);
+	}
+}
+
+fn execute_command<Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus>(cpu: &mut Cpu<Timer, MIO, IntBus>, command: HartCommand) -> HartReply {
+	match command {
+		HartCommand::ReadRegisters => {
+			let mut registers = Vec::with_capacity(GPR_PACKET_COUNT);
+			for i in 0 .. 32 {
+				registers.push(cpu.get_gpr(i));
+			}
+			registers.push(cpu.get_pc());
+			HartReply::Registers(registers)
+		},
+		// a replaying hart has to follow its log, so it can't be changed until it's caught up
+		HartCommand::WriteRegisters(_) | HartCommand::WriteRegister(..) | HartCommand::WriteMemory(..) if cpu.is_replaying() => HartReply::Done(false),
+		HartCommand::WriteRegisters(registers) => {
+			for (i, value) in registers.iter().enumerate().take(GPR_PACKET_COUNT) {
+				write_register(cpu, i as u32, *value as u64);
+			}
+			cpu.discard_replay_history();
+			HartReply::Done(true)
+		},
+		HartCommand::ReadRegister(reg) => HartReply::Register(read_register(cpu, reg)),
+		HartCommand::WriteRegister(reg, value) => {
+			let written = write_register(cpu, reg, value);
+			cpu.discard_replay_history();
+			HartReply::Done(written)
+		},
+		HartCommand::ReadMemory(addr, length) => {
+			let mut data = Vec::with_capacity(length as usize);
+			for i in 0 .. length {
+				match cpu.debug_read_8(addr.wrapping_add(i)) {
+					MemReadResult::Ok(value) => data.push(value),
+					_ => break
+				}
+			}
+			cpu.step_break();
+			HartReply::Memory(data)
+		},
+		HartCommand::WriteMemory(addr, data) => {
+			let mut ok = true;
+			for (i, value) in data.iter().enumerate() {
+				match cpu.debug_write_8(addr.wrapping_add(i as u32), *value) {
+					MemWriteResult::Ok => {},
+					_ => {
+						ok = false;
+						break;
+					}
+				}
+			}
+			cpu.step_break();
+			HartReply::Done(ok)
+		},
+	}
+}
+
+fn is_fpr(reg: u32) -> bool {
+	(REG_F0 .. REG_F0 + 32).contains(&reg)
+}
+
+// the float registers are 64 bits wide, everything else is 32
+fn register_size(reg: u32) -> usize {
+	if is_fpr(reg) { 8 } else { 4 }
+}
+
+fn read_register<Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus>(cpu: &mut Cpu<Timer, MIO, IntBus>, reg: u32) -> Option<u64> {
+	match reg {
+		0 ..= 31 => Some(cpu.get_gpr(reg) as u64),
+		REG_PC => Some(cpu.get_pc() as u64),
+		_ if is_fpr(reg) => Some(cpu.get_fpr_bits(reg - REG_F0)),
+		_ if reg >= REG_CSR_BASE && DEBUG_CSRS.iter().any(|(csr, _)| *csr == reg - REG_CSR_BASE) => Some(cpu.read_csr(reg - REG_CSR_BASE) as u64),
+		_ => None
+	}
+}
+
+fn write_register<Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus>(cpu: &mut Cpu<Timer, MIO, IntBus>, reg: u32, value: u64) -> bool {
+	match reg {
+		0 ..= 31 => cpu.set_gpr(reg, value as u32),
+		REG_PC => cpu.set_pc(value as u32),
+		_ if is_fpr(reg) => cpu.set_fpr_bits(reg - REG_F0, value),
+		_ if reg >= REG_CSR_BASE && DEBUG_CSRS.iter().any(|(csr, _)| *csr == reg - REG_CSR_BASE) => {
+			cpu.write_csr(reg - REG_CSR_BASE, value as u32);
+		},
+		_ => return false
+	}
+	true
+}
+
+fn reverse<Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus>(cpu: &mut Cpu<Timer, MIO, IntBus>, resume: Resume, breakpoints: &HashSet<u32>, watchpoints: &[Watchpoint]) -> StopReason {
+	if let Resume::ReverseStep = resume {
+		return if cpu.step_back() { StopReason::Step } else { StopReason::HistoryStart };
+	}
+	match cpu.reverse_continue(breakpoints, watchpoints) {
+		ReplayStop::Breakpoint { .. } => StopReason::Breakpoint,
+		ReplayStop::Watchpoint { address, watchpoint, .. } => StopReason::Watchpoint(watchpoint.kind, address),
+		ReplayStop::HistoryStart { .. } => StopReason::HistoryStart,
+	}
+}
+
+pub struct GdbHartAdapter {
+	shared: Arc<HartDebugShared>,
+	stop_sender: Sender<(u32, StopReason)>,
+	breakpoints: HashSet<u32>,
+	watchpoints: Vec<Watchpoint>,
+	breakpoint_generation: usize,
+	step_pending: bool,
+	resume_pc: Option<u32>,
+}
+
+impl GdbHartAdapter {
+	fn halt<Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus>(&mut self, cpu: &mut Cpu<Timer, MIO, IntBus>, reason: Option<StopReason>) -> DebugStep {
+		// drop any page locks so the other harts and peripherals aren't blocked while we're parked
+		cpu.step_break();
+		let kill_handle = cpu.get_kill_handle();
+		let mut state = self.shared.state.lock();
+		state.halted = true;
+		if let Some(reason) = reason {
+			let _ = self.stop_sender.send((self.shared.hart_id, reason));
+		}
+		self.shared.cond.notify_all();
+		let resume = loop {
+			if let Some((command, reply_sender)) = state.commands.pop_front() {
+				MutexGuard::unlocked(&mut state, || {
+					let _ = reply_sender.send(execute_command(cpu, command));
+				});
+				continue;
+			}
+			match state.resume.take() {
+				Some(resume @ Resume::ReverseStep) | Some(resume @ Resume::ReverseContinue) => {
+					// the hart stays halted, and reports where it stopped like it had run there
+					let reason = MutexGuard::unlocked(&mut state, || {
+						let breakpoints = self.shared.breakpoints.lock().clone();
+						let watchpoints = self.shared.watchpoints.lock().clone();
+						reverse(cpu, resume, &breakpoints, &watchpoints)
+					});
+					let _ = self.stop_sender.send((self.shared.hart_id, reason));
+					continue;
+				},
+				Some(resume) => break Some(resume),
+				None => {}
+			}
+			if kill_handle.is_kill_requested() {
+				break None;
+			}
+			self.shared.cond.wait_for(&mut state, HART_POLL_INTERVAL);
+		};
+		state.halted = false;
+		self.shared.cond.notify_all();
+		drop(state);
+		self.resume_pc = Some(cpu.get_pc());
+		match resume {
+			Some(Resume::Continue) => DebugStep::Continue,
+			Some(Resume::Step) => {
+				self.step_pending = true;
+				DebugStep::Continue
+			},
+			Some(Resume::ReverseContinue) | Some(Resume::ReverseStep) => unreachable!(),
+			None => DebugStep::Kill
+		}
+	}
+}
+
+impl <Timer: MTimer, MIO: MemIO<Timer>, IntBus: InterruptBus> DebugAdapter<Timer, MIO, IntBus> for GdbHartAdapter {
+	fn debug_begin(&mut self, _hart_id: u32) {
+		let mut state = self.shared.state.lock();
+		state.attached = true;
+		state.halted = false;
+		state.resume = None;
+		self.step_pending = false;
+		self.resume_pc = None;
+		self.shared.cond.notify_all();
+	}
+
+	fn debug_end(&mut self, _hart_id: u32) {
+		let mut state = self.shared.state.lock();
+		state.attached = false;
+		state.halted = false;
+		state.resume = None;
+		state.commands.clear();
+		self.shared.cond.notify_all();
+	}
+
+	fn debug_step_poll(&mut self, cpu: &mut Cpu<Timer, MIO, IntBus>) -> DebugStep {
+		if self.step_pending {
+			self.step_pending = false;
+			return self.halt(cpu, Some(StopReason::Step));
+		}
+		let generation = self.shared.breakpoint_generation.load(Ordering::SeqCst);
+		if generation != self.breakpoint_generation {
+			self.breakpoints = self.shared.breakpoints.lock().clone();
+			self.watchpoints = self.shared.watchpoints.lock().clone();
+			self.breakpoint_generation = generation;
+		}
+		// watchpoints stop after the instruction which made the access
+		if let Some((address, size, access)) = cpu.last_mem_access() {
+			if let Some(watchpoint) = self.watchpoints.iter().find(|watchpoint| watchpoint.is_hit_by(address, size, access)) {
+				let reason = StopReason::Watchpoint(watchpoint.kind, address);
+				return self.halt(cpu, Some(reason));
+			}
+		}
+		if self.shared.attention.load(Ordering::SeqCst) {
+			let halt_requested = self.shared.state.lock().halt_requested;
+			if halt_requested {
+				return self.halt(cpu, None);
+			}
+		}
+		let pc = cpu.get_pc();
+		if self.resume_pc.take() != Some(pc) && self.breakpoints.contains(&pc) {
+			return self.halt(cpu, Some(StopReason::Breakpoint));
+		}
+		DebugStep::Continue
+	}
+}
+
+trait GdbConnection: Read + Write + Send {
+	fn set_poll_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl GdbConnection for TcpStream {
+	fn set_poll_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		self.set_read_timeout(timeout)
+	}
+}
+
+#[cfg(unix)]
+impl GdbConnection for UnixStream {
+	fn set_poll_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		self.set_read_timeout(timeout)
+	}
+}
+
+pub struct GdbServer {
+	harts: Vec<Arc<HartDebugShared>>,
+	stop_sender: Sender<(u32, StopReason)>,
+	stop_receiver: Receiver<(u32, StopReason)>,
+}
+
+impl GdbServer {
+	pub fn new(hart_count: u32) -> Self {
+		let (stop_sender, stop_receiver) = mpsc::channel();
+		GdbServer {
+			harts: (0 .. hart_count).map(|hart_id| Arc::new(HartDebugShared::new(hart_id))).collect(),
+			stop_sender,
+			stop_receiver,
+		}
+	}
+
+	pub fn hart_adapter(&self, hart_id: u32) -> GdbHartAdapter {
+		GdbHartAdapter {
+			shared: self.harts[hart_id as usize].clone(),
+			stop_sender: self.stop_sender.clone(),
+			breakpoints: HashSet::new(),
+			watchpoints: Vec::new(),
+			breakpoint_generation: 0,
+			step_pending: false,
+			resume_pc: None,
+		}
+	}
+
+	pub fn listen_tcp(self, address: &str) -> io::Result<JoinHandle<()>> {
+		let listener = TcpListener::bind(address)?;
+		Ok(thread::spawn(move || {
+			for stream in listener.incoming().flatten() {
+				let _ = stream.set_nodelay(true);
+				self.serve(Box::new(stream));
+			}
+		}))
+	}
+
+	#[cfg(unix)]
+	pub fn listen_unix<P: AsRef<Path>>(self, path: P) -> io::Result<JoinHandle<()>> {
+		let _ = std::fs::remove_file(path.as_ref());
+		let listener = UnixListener::bind(path)?;
+		Ok(thread::spawn(move || {
+			for stream in listener.incoming().flatten() {
+				self.serve(Box::new(stream));
+			}
+		}))
+	}
+
+	fn serve(&self, connection: Box<dyn GdbConnection>) {
+		// gdb expects the target to be stopped when it attaches
+		self.halt_all();
+		let first_hart = self.harts.iter().position(|hart| hart.is_attached()).unwrap_or(0) as u32;
+		let mut session = GdbSession {
+			server: self,
+			connection,
+			input: Vec::new(),
+			no_ack: false,
+			running: false,
+			general_hart: first_hart,
+			last_stop: (first_hart, SIGTRAP),
+			last_stop_detail: String::new(),
+		};
+		let _ = session.run();
+		for hart in self.harts.iter() {
+			hart.clear_breakpoints();
+		}
+		self.resume_all();
+	}
+
+	fn halt_all(&self) {
+		for hart in self.harts.iter() {
+			hart.request_halt();
+		}
+		for hart in self.harts.iter() {
+			hart.wait_halted();
+		}
+	}
+
+	fn resume_all(&self) {
+		while self.stop_receiver.try_recv().is_ok() {}
+		for hart in self.harts.iter() {
+			hart.resume(None);
+		}
+	}
+}
+
+enum Packet {
+	Data(String),
+	Interrupt,
+}
+
+struct GdbSession<'a> {
+	server: &'a GdbServer,
+	connection: Box<dyn GdbConnection>,
+	input: Vec<u8>,
+	no_ack: bool,
+	running: bool,
+	general_hart: u32,
+	last_stop: (u32, u8),
+	last_stop_detail: String,
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+	u32::from_str_radix(text, 16).ok()
+}
+
+fn hex_digit(digit: u8) -> Option<u8> {
+	(digit as char).to_digit(16).map(|value| value as u8)
+}
+
+// decoded from the bytes, since a packet can hold anything the client sent and slicing text could split a character
+fn decode_hex_bytes(text: &str) -> Option<Vec<u8>> {
+	let text = text.as_bytes();
+	if text.len() & 1 != 0 {
+		return None;
+	}
+	text.chunks(2).map(|pair| Some(hex_digit(pair[0])? << 4 | hex_digit(pair[1])?)).collect()
+}
+
+fn encode_hex_bytes(data: &[u8]) -> String {
+	data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn encode_register(value: Option<u64>, size: usize) -> String {
+	match value {
+		Some(value) => encode_hex_bytes(&value.to_le_bytes()[.. size]),
+		None => "xx".repeat(size)
+	}
+}
+
+fn decode_register(text: &str) -> Option<u64> {
+	let bytes = decode_hex_bytes(text)?;
+	if bytes.len() != 4 && bytes.len() != 8 {
+		return None;
+	}
+	let mut value = [0u8; 8];
+	value[.. bytes.len()].copy_from_slice(&bytes);
+	Some(u64::from_le_bytes(value))
+}
+
+impl <'a> GdbSession<'a> {
+	fn run(&mut self) -> io::Result<()> {
+		loop {
+			let packet = if self.running {
+				if let Ok((hart_id, reason)) = self.server.stop_receiver.try_recv() {
+					self.server.halt_all();
+					self.running = false;
+					self.general_hart = hart_id;
+					self.last_stop = (hart_id, SIGTRAP);
+					self.last_stop_detail = stop_detail(reason);
+					let reply = self.stop_reply();
+					self.send_packet(reply.as_str())?;
+					continue;
+				}
+				match self.read_packet(Some(HART_POLL_INTERVAL))? {
+					Some(Packet::Interrupt) => {
+						self.server.halt_all();
+						self.running = false;
+						self.last_stop = (self.general_hart, SIGINT);
+						self.last_stop_detail.clear();
+						let reply = self.stop_reply();
+						self.send_packet(reply.as_str())?;
+						continue;
+					},
+					packet => packet
+				}
+			} else {
+				self.read_packet(None)?
+			};
+			// an interrupt while halted has nothing to stop
+			if let Some(Packet::Data(packet)) = packet {
+				if ! self.handle_packet(packet.as_str())? {
+					return Ok(());
+				}
+			}
+		}
+	}
+
+	fn read_packet(&mut self, timeout: Option<Duration>) -> io::Result<Option<Packet>> {
+		loop {
+			while let Some(&byte) = self.input.first() {
+				match byte {
+					0x03 => {
+						self.input.remove(0);
+						return Ok(Some(Packet::Interrupt));
+					},
+					b'$' => {
+						let end = match self.input.iter().position(|b| *b == b'#') {
+							Some(end) if self.input.len() >= end + 3 => end,
+							_ => break
+						};
+						let packet_bytes: Vec<u8> = self.input.drain(.. end + 3).collect();
+						let body = &packet_bytes[1 .. end];
+						let checksum = std::str::from_utf8(&packet_bytes[end + 1 .. end + 3]).ok().and_then(|text| u8::from_str_radix(text, 16).ok());
+						let expected = body.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+						if ! self.no_ack {
+							if checksum == Some(expected) {
+								self.connection.write_all(b"+")?;
+							} else {
+								self.connection.write_all(b"-")?;
+								continue;
+							}
+						}
+						return Ok(Some(Packet::Data(String::from_utf8_lossy(body).to_string())));
+					},
+					_ => {
+						// acks, and anything else outside of a packet
+						self.input.remove(0);
+					}
+				}
+			}
+			self.connection.set_poll_timeout(timeout)?;
+			let mut buffer = [0u8; 1024];
+			match self.connection.read(&mut buffer) {
+				Ok(0) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "gdb connection closed")),
+				Ok(count) => self.input.extend_from_slice(&buffer[.. count]),
+				Err(error) => match error.kind() {
+					ErrorKind::WouldBlock | ErrorKind::TimedOut => return Ok(None),
+					ErrorKind::Interrupted => {},
+					_ => return Err(error)
+				}
+			}
+		}
+	}
+
+	fn send_packet(&mut self, data: &str) -> io::Result<()> {
+		let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+		let packet = format!("${}#{:02x}", data, checksum);
+		self.connection.write_all(packet.as_bytes())?;
+		self.connection.flush()
+	}
+
+	fn stop_reply(&self) -> String {
+		let (hart_id, signal) = self.last_stop;
+		format!("T{:02x}{}thread:{:x};", signal, self.last_stop_detail, hart_id + 1)
+	}
+
+	fn hart(&self, hart_id: u32) -> &HartDebugShared {
+		&self.server.harts[hart_id as usize]
+	}
+
+	fn parse_thread(&self, text: &str) -> Option<Option<u32>> {
+		// returns Some(None) for "any" / "all" threads
+		if text == "-1" || text == "0" {
+			return Some(None);
+		}
+		let thread_id = parse_hex(text)?;
+		if thread_id == 0 || thread_id as usize > self.server.harts.len() {
+			return None;
+		}
+		Some(Some(thread_id - 1))
+	}
+
+	fn memory_hart(&self) -> u32 {
+		if self.hart(self.general_hart).is_halted() {
+			return self.general_hart;
+		}
+		self.server.harts.iter().position(|hart| hart.is_halted()).unwrap_or(self.general_hart as usize) as u32
+	}
+
+	fn resume(&mut self, actions: &[(Option<u32>, Resume)]) {
+		while self.server.stop_receiver.try_recv().is_ok() {}
+		for hart in self.server.harts.iter() {
+			let action = actions.iter().find(|(thread, _)| match thread {
+				Some(hart_id) => *hart_id == hart.hart_id,
+				None => true
+			});
+			if let Some((_, resume)) = action {
+				hart.resume(Some(*resume));
+			}
+		}
+		self.running = true;
+	}
+
+	fn handle_packet(&mut self, packet: &str) -> io::Result<bool> {
+		let reply = match packet.chars().next() {
+			Some('?') => self.stop_reply(),
+			Some('g') => {
+				match self.hart(self.general_hart).request(HartCommand::ReadRegisters) {
+					Some(HartReply::Registers(registers)) => registers.iter().map(|value| encode_register(Some(*value as u64), 4)).collect(),
+					_ => (0 .. GPR_PACKET_COUNT).map(|_| encode_register(None, 4)).collect(),
+				}
+			},
+			Some('G') => {
+				let registers = decode_hex_bytes(&packet[1 ..]).filter(|bytes| bytes.len() % 4 == 0)
+					.map(|bytes| bytes.chunks(4).map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]])).collect::<Vec<u32>>());
+				match registers {
+					Some(registers) => match self.hart(self.general_hart).request(HartCommand::WriteRegisters(registers)) {
+						Some(HartReply::Done(true)) => "OK".to_string(),
+						_ => "E01".to_string()
+					},
+					None => "E22".to_string()
+				}
+			},
+			Some('p') => {
+				match parse_hex(&packet[1 ..]) {
+					Some(reg) => match self.hart(self.general_hart).request(HartCommand::ReadRegister(reg)) {
+						Some(HartReply::Register(value)) => encode_register(value, register_size(reg)),
+						_ => encode_register(None, register_size(reg))
+					},
+					None => "E22".to_string()
+				}
+			},
+			Some('P') => {
+				let mut parts = packet[1 ..].splitn(2, '=');
+				match (parts.next().and_then(parse_hex), parts.next().and_then(decode_register)) {
+					(Some(reg), Some(value)) => match self.hart(self.general_hart).request(HartCommand::WriteRegister(reg, value)) {
+						Some(HartReply::Done(true)) => "OK".to_string(),
+						_ => "E01".to_string()
+					},
+					_ => "E22".to_string()
+				}
+			},
+			Some('m') => {
+				let mut parts = packet[1 ..].splitn(2, ',');
+				match (parts.next().and_then(parse_hex), parts.next().and_then(parse_hex)) {
+					(Some(addr), Some(length)) => {
+						let length = length.min(PACKET_SIZE as u32 / 2);
+						match self.hart(self.memory_hart()).request(HartCommand::ReadMemory(addr, length)) {
+							Some(HartReply::Memory(data)) if ! data.is_empty() => encode_hex_bytes(&data),
+							_ => "E14".to_string()
+						}
+					},
+					_ => "E22".to_string()
+				}
+			},
+			Some('M') => {
+				let mut parts = packet[1 ..].splitn(2, ':');
+				let header = parts.next().unwrap_or("");
+				let mut header_parts = header.splitn(2, ',');
+				match (header_parts.next().and_then(parse_hex), header_parts.next().and_then(parse_hex), parts.next().and_then(decode_hex_bytes)) {
+					(Some(addr), Some(length), Some(data)) if data.len() == length as usize => {
+						match self.hart(self.memory_hart()).request(HartCommand::WriteMemory(addr, data)) {
+							Some(HartReply::Done(true)) => "OK".to_string(),
+							_ => "E14".to_string()
+						}
+					},
+					_ => "E22".to_string()
+				}
+			},
+			Some('c') | Some('C') => {
+				self.resume(&[(None, Resume::Continue)]);
+				return Ok(true);
+			},
+			Some('s') | Some('S') => {
+				let hart_id = self.general_hart;
+				self.resume(&[(Some(hart_id), Resume::Step)]);
+				return Ok(true);
+			},
+			// reverse step and continue only move the selected hart
+			Some('b') if packet == "bs" || packet == "bc" => {
+				let hart_id = self.general_hart;
+				let resume = if packet == "bs" { Resume::ReverseStep } else { Resume::ReverseContinue };
+				self.resume(&[(Some(hart_id), resume)]);
+				return Ok(true);
+			},
+			Some('H') => {
+				match packet.get(2 ..).and_then(|thread| self.parse_thread(thread)) {
+					Some(thread) => {
+						if packet.starts_with("Hg") {
+							if let Some(hart_id) = thread {
+								self.general_hart = hart_id;
+							}
+						}
+						"OK".to_string()
+					},
+					None => "E22".to_string()
+				}
+			},
+			Some('T') => {
+				match self.parse_thread(&packet[1 ..]) {
+					Some(_) => "OK".to_string(),
+					None => "E22".to_string()
+				}
+			},
+			Some('Z') | Some('z') => {
+				let set = packet.starts_with('Z');
+				let mut parts = packet[1 ..].split(',');
+				match (parts.next(), parts.next().and_then(parse_hex), parts.next().and_then(parse_hex)) {
+					(Some("0"), Some(addr), _) | (Some("1"), Some(addr), _) => {
+						for hart in self.server.harts.iter() {
+							hart.set_breakpoint(addr, set);
+						}
+						"OK".to_string()
+					},
+					(Some(kind @ "2"), Some(addr), Some(length)) | (Some(kind @ "3"), Some(addr), Some(length)) | (Some(kind @ "4"), Some(addr), Some(length)) => {
+						let kind = match kind {
+							"2" => WatchKind::Write,
+							"3" => WatchKind::Read,
+							_ => WatchKind::ReadWrite,
+						};
+						for hart in self.server.harts.iter() {
+							hart.set_watchpoint(Watchpoint::new(addr, length, kind), set);
+						}
+						"OK".to_string()
+					},
+					_ => String::new()
+				}
+			},
+			Some('k') => {
+				return Ok(false);
+			},
+			Some('D') => {
+				self.send_packet("OK")?;
+				return Ok(false);
+			},
+			Some('v') => {
+				if packet == "vCont?" {
+					"vCont;c;C;s;S".to_string()
+				} else if let Some(action_list) = packet.strip_prefix("vCont;") {
+					let mut actions = Vec::new();
+					for action in action_list.split(';') {
+						let mut parts = action.splitn(2, ':');
+						let resume = match parts.next().and_then(|kind| kind.chars().next()) {
+							Some('c') | Some('C') => Resume::Continue,
+							Some('s') | Some('S') => Resume::Step,
+							_ => continue
+						};
+						let thread = match parts.next() {
+							Some(thread) => match self.parse_thread(thread) {
+								Some(thread) => thread,
+								None => continue
+							},
+							None => None
+						};
+						actions.push((thread, resume));
+					}
+					self.resume(&actions);
+					return Ok(true);
+				} else {
+					String::new()
+				}
+			},
+			Some('q') | Some('Q') => self.handle_query(packet),
+			_ => String::new()
+		};
+		self.send_packet(reply.as_str())?;
+		// the reply to QStartNoAckMode is still acknowledged
+		if packet == "QStartNoAckMode" {
+			self.no_ack = true;
+		}
+		Ok(true)
+	}
+
+	// gdb's monitor command, the reply is printed on the gdb console
+	fn monitor_command(&mut self, command: &str) -> String {
+		let mut parts = command.trim().splitn(3, ' ');
+		match (parts.next(), parts.next(), parts.next()) {
+			(Some("asm"), Some(address), Some(source)) => {
+				let address = match parse_hex(address.trim_start_matches("0x")) {
+					Some(address) => address,
+					None => return format!("{} is not a hex address\n", address)
+				};
+				// gdb sends the whole command as one line, so the instructions are separated by ';'
+				let data = match assemble(source, address) {
+					Ok(data) => data,
+					Err(error) => return format!("{}\n", error.message)
+				};
+				let length = data.len();
+				match self.hart(self.memory_hart()).request(HartCommand::WriteMemory(address, data)) {
+					Some(HartReply::Done(true)) => format!("wrote {} bytes at {:#010x}\n", length, address),
+					_ => format!("failed to write to {:#010x}\n", address)
+				}
+			},
+			_ => "usage: monitor asm <address> <instruction>[; <instruction>...]\n".to_string()
+		}
+	}
+
+	fn handle_query(&mut self, packet: &str) -> String {
+		if packet.starts_with("qSupported") {
+			format!("PacketSize={:x};qXfer:features:read+;QStartNoAckMode+;vContSupported+;ReverseStep+;ReverseContinue+", PACKET_SIZE)
+		} else if packet == "QStartNoAckMode" {
+			"OK".to_string()
+		} else if packet == "qAttached" {
+			"1".to_string()
+		} else if packet == "qC" {
+			format!("QC{:x}", self.general_hart + 1)
+		} else if packet == "qfThreadInfo" {
+			let threads: Vec<String> = (1 ..= self.server.harts.len()).map(|thread| format!("{:x}", thread)).collect();
+			format!("m{}", threads.join(","))
+		} else if packet == "qsThreadInfo" {
+			"l".to_string()
+		} else if let Some(thread) = packet.strip_prefix("qThreadExtraInfo,") {
+			match self.parse_thread(thread) {
+				Some(Some(hart_id)) => {
+					let status = if self.hart(hart_id).is_attached() { "running" } else { "idle" };
+					encode_hex_bytes(format!("hart {} ({})", hart_id, status).as_bytes())
+				},
+				_ => "E22".to_string()
+			}
+		} else if let Some(command) = packet.strip_prefix("qRcmd,") {
+			match decode_hex_bytes(command).and_then(|command| String::from_utf8(command).ok()) {
+				Some(command) => encode_hex_bytes(self.monitor_command(command.as_str()).as_bytes()),
+				None => "E22".to_string()
+			}
+		} else if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+			let mut parts = range.splitn(2, ',');
+			match (parts.next().and_then(parse_hex), parts.next().and_then(parse_hex)) {
+				(Some(offset), Some(length)) => {
+					let description = target_description();
+					let start = (offset as usize).min(description.len());
+					let end = (start + length as usize).min(description.len());
+					let prefix = if end == description.len() { "l" } else { "m" };
+					format!("{}{}", prefix, &description[start .. end])
+				},
+				_ => "E22".to_string()
+			}
+		} else {
+			String::new()
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn hex_decodes_bytes_and_registers() {
+		assert_eq!(decode_hex_bytes("00ff7A"), Some(vec![0x00, 0xFF, 0x7A]));
+		assert_eq!(decode_register("78563412"), Some(0x1234_5678));
+		assert_eq!(decode_register("efcdab9078563412"), Some(0x1234_5678_90AB_CDEF));
+		assert_eq!(decode_register("785634"), None);
+	}
+
+	#[test]
+	fn hex_rejects_what_isnt_hex() {
+		assert_eq!(decode_hex_bytes("0"), None);
+		assert_eq!(decode_hex_bytes("0g"), None);
+		assert_eq!(decode_hex_bytes("+1"), None);
+		// multi byte characters from the client are refused rather than split
+		assert_eq!(decode_hex_bytes("\u{e9}"), None);
+		assert_eq!(decode_hex_bytes("0\u{e9}0"), None);
+		assert_eq!(decode_register("0000000\u{e9}"), None);
+	}
+}
@@ -0,0 +1,143 @@
+// Hardware performance monitor, mhpmcounter3 - mhpmcounter31 and their event selectors.
+// Every event is tallied per hart whether or not a counter selects it, and a counter reads as
+// its value plus how far its event's tally moved since the counter was last written, so
+// selecting an event costs nothing while the hart runs.
+
+pub const HPM_COUNTER_BASE: usize = 3;
+pub const HPM_COUNTER_COUNT: usize = 29;
+
+pub const HPM_EVENT_COUNT: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HpmEvent {
+	None = 0,
+	Load = 1,
+	Store = 2,
+	BranchTaken = 3,
+	Trap = 4,
+	PeripheralAccess = 5,
+	StoreConditionalFail = 6,
+	PageLockContention = 7,
+}
+
+impl HpmEvent {
+	// unsupported selectors read back as no event
+	pub fn from_raw(raw: u32) -> Self {
+		match raw {
+			1 => Self::Load,
+			2 => Self::Store,
+			3 => Self::BranchTaken,
+			4 => Self::Trap,
+			5 => Self::PeripheralAccess,
+			6 => Self::StoreConditionalFail,
+			7 => Self::PageLockContention,
+			_ => Self::None,
+		}
+	}
+}
+
+#[derive(Clone, Copy)]
+struct HpmCounter {
+	event: HpmEvent,
+	value: u64,
+	base: u64,
+}
+
+pub struct HpmCounters {
+	counters: [HpmCounter; HPM_COUNTER_COUNT],
+	tallies: [u64; HPM_EVENT_COUNT],
+	inhibit: u32,
+	counter_enable: u32,
+	counting: bool,
+}
+
+impl HpmCounters {
+	pub fn new() -> Self {
+		HpmCounters {
+			counters: [HpmCounter {
+				event: HpmEvent::None,
+				value: 0,
+				base: 0,
+			}; HPM_COUNTER_COUNT],
+			tallies: [0; HPM_EVENT_COUNT],
+			inhibit: 0,
+			counter_enable: 0,
+			counting: false,
+		}
+	}
+
+	pub fn reset(&mut self) {
+		*self = Self::new();
+	}
+
+	pub fn count(&mut self, event: HpmEvent) {
+		self.tallies[event as usize] += 1;
+	}
+
+	// the memory system's events are left for the caller to fill in
+	pub fn tallies(&self) -> [u64; HPM_EVENT_COUNT] {
+		self.tallies
+	}
+
+	// true while any counter is selecting an event and not inhibited
+	pub fn is_counting(&self) -> bool {
+		self.counting
+	}
+
+	fn update_counting(&mut self) {
+		self.counting = (0 .. HPM_COUNTER_COUNT).any(|index| self.counters[index].event != HpmEvent::None && ! self.is_inhibited(index));
+	}
+
+	fn is_inhibited(&self, index: usize) -> bool {
+		self.inhibit & (1 << (index + HPM_COUNTER_BASE)) != 0
+	}
+
+	pub fn event(&self, index: usize) -> u32 {
+		self.counters[index].event as u32
+	}
+
+	// tallies holds the current tally of every event, including the memory system's
+	pub fn read(&self, index: usize, tallies: &[u64; HPM_EVENT_COUNT]) -> u64 {
+		let counter = &self.counters[index];
+		if counter.event == HpmEvent::None || self.is_inhibited(index) {
+			counter.value
+		} else {
+			counter.value.wrapping_add(tallies[counter.event as usize].wrapping_sub(counter.base))
+		}
+	}
+
+	pub fn write(&mut self, index: usize, value: u64, tallies: &[u64; HPM_EVENT_COUNT]) {
+		let counter = &mut self.counters[index];
+		counter.value = value;
+		counter.base = tallies[counter.event as usize];
+	}
+
+	pub fn write_event(&mut self, index: usize, raw: u32, tallies: &[u64; HPM_EVENT_COUNT]) {
+		let value = self.read(index, tallies);
+		self.counters[index].event = HpmEvent::from_raw(raw);
+		self.write(index, value, tallies);
+		self.update_counting();
+	}
+
+	pub fn inhibit(&self) -> u32 {
+		self.inhibit
+	}
+
+	// cycle and instret follow the machine clock and the period loop, so only the hpm counters can be stopped
+	pub fn write_inhibit(&mut self, value: u32, tallies: &[u64; HPM_EVENT_COUNT]) {
+		let values: Vec<u64> = (0 .. HPM_COUNTER_COUNT).map(|index| self.read(index, tallies)).collect();
+		self.inhibit = value & !0b111;
+		for (index, value) in values.into_iter().enumerate() {
+			self.write(index, value, tallies);
+		}
+		self.update_counting();
+	}
+
+	pub fn counter_enable(&self) -> u32 {
+		self.counter_enable
+	}
+
+	pub fn write_counter_enable(&mut self, value: u32) {
+		self.counter_enable = value;
+	}
+}
@@ -1,3 +1,27 @@
+// the interrupts a bus with standard interrupt controllers drives, as levels
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct InterruptLevels {
+	pub machine_software: bool,
+	pub machine_external: bool,
+	pub supervisor_external: bool,
+}
+
 pub trait InterruptBus: Send + Clone {
+	// raises the machine external interrupt, which stays pending until the program clears it in mip
 	fn poll_interrupts(&mut self, hart_id: u32) -> bool;
+	
+	// a bus which returns levels here has the hart's mip follow them instead of latching poll_interrupts
+	fn poll_interrupt_levels(&mut self, _hart_id: u32) -> Option<InterruptLevels> {
+		None
+	}
 }
+
+// for a hart with nothing to raise external interrupts
+#[derive(Clone, Debug)]
+pub struct NullInterruptBus;
+
+impl InterruptBus for NullInterruptBus {
+	fn poll_interrupts(&mut self, _hart_id: u32) -> bool {
+		false
+	}
+}
\ No newline at end of file
@@ -0,0 +1,732 @@
+use std::ptr;
+
+use crate::block_cache::{decode_block, DecodedInstruction};
+use crate::{BranchFunct3, LoadFunct3, MTimer, MemIO, MemReadResult, MemWriteResult, Op, OpFunct3Funct7, OpImmFunct3, OpImmShiftFunct, Opcode, StoreFunct3};
+
+// Native x86-64 translation of hot basic blocks. Translated code works directly on the
+// hart's integer register file, and goes through MemIO for loads and stores. Anything it
+// can't do (MMIO, traps, LR/SC, floating point, system instructions) ends the block,
+// leaving that instruction to the interpreter.
+//
+// Blocks are called as extern "sysv64" fn(xr: *mut u32, mio: *mut MIO) -> u64, returning
+// (instructions executed << 32) | next pc.
+
+const JIT_CACHE_SIZE: usize = 0x1000;
+const JIT_HOT_THRESHOLD: u32 = 32;
+const JIT_CODE_SIZE: usize = 0x100_0000;
+
+const JIT_ACCESS_FAILED: u64 = 1 << 32;
+
+const EAX: u8 = 0;
+const ECX: u8 = 1;
+const EDX: u8 = 2;
+const ESI: u8 = 6;
+
+const CC_B: u8 = 0x2;
+const CC_AE: u8 = 0x3;
+const CC_E: u8 = 0x4;
+const CC_NE: u8 = 0x5;
+const CC_L: u8 = 0xC;
+const CC_GE: u8 = 0xD;
+
+const ALU_ADD: u8 = 0x01;
+const ALU_OR: u8 = 0x09;
+const ALU_AND: u8 = 0x21;
+const ALU_SUB: u8 = 0x29;
+const ALU_XOR: u8 = 0x31;
+const ALU_CMP: u8 = 0x39;
+
+const ALU_IMM_ADD: u8 = 0;
+const ALU_IMM_OR: u8 = 1;
+const ALU_IMM_AND: u8 = 4;
+const ALU_IMM_XOR: u8 = 6;
+const ALU_IMM_CMP: u8 = 7;
+
+const SHIFT_SHL: u8 = 4;
+const SHIFT_SHR: u8 = 5;
+const SHIFT_SAR: u8 = 7;
+
+type JitBlockFn<MIO> = extern "sysv64" fn(*mut u32, *mut MIO) -> u64;
+
+extern "sysv64" fn jit_load<Timer: MTimer, MIO: MemIO<Timer>>(mio: *mut MIO, addr: u32, funct3: u32) -> u64 {
+	let mio = unsafe { &mut *mio };
+	let load_type = LoadFunct3::from_raw(funct3);
+	let size = match load_type {
+		LoadFunct3::Byte | LoadFunct3::ByteUnsigned => 1,
+		LoadFunct3::Half | LoadFunct3::HalfUnsigned => 2,
+		_ => 4
+	};
+	if ! mio.is_plain_memory(addr, size) {
+		return JIT_ACCESS_FAILED;
+	}
+	let value = match load_type {
+		LoadFunct3::Byte => match mio.read_8(addr) {
+			MemReadResult::Ok(value) => value as i8 as i32 as u32,
+			_ => return JIT_ACCESS_FAILED
+		},
+		LoadFunct3::ByteUnsigned => match mio.read_8(addr) {
+			MemReadResult::Ok(value) => value as u32,
+			_ => return JIT_ACCESS_FAILED
+		},
+		LoadFunct3::Half => match mio.read_16(addr) {
+			MemReadResult::Ok(value) => value as i16 as i32 as u32,
+			_ => return JIT_ACCESS_FAILED
+		},
+		LoadFunct3::HalfUnsigned => match mio.read_16(addr) {
+			MemReadResult::Ok(value) => value as u32,
+			_ => return JIT_ACCESS_FAILED
+		},
+		LoadFunct3::Word => match mio.read_32(addr) {
+			MemReadResult::Ok(value) => value,
+			_ => return JIT_ACCESS_FAILED
+		},
+		LoadFunct3::Unknown => return JIT_ACCESS_FAILED
+	};
+	value as u64
+}
+
+extern "sysv64" fn jit_store<Timer: MTimer, MIO: MemIO<Timer>>(mio: *mut MIO, addr: u32, value: u32, funct3: u32) -> u64 {
+	let mio = unsafe { &mut *mio };
+	let store_type = StoreFunct3::from_raw(funct3);
+	let size = match store_type {
+		StoreFunct3::Byte => 1,
+		StoreFunct3::Half => 2,
+		_ => 4
+	};
+	if ! mio.is_plain_memory(addr, size) {
+		return JIT_ACCESS_FAILED;
+	}
+	let result = match store_type {
+		StoreFunct3::Byte => mio.write_8(addr, value as u8),
+		StoreFunct3::Half => mio.write_16(addr, value as u16),
+		StoreFunct3::Word => mio.write_32(addr, value),
+		StoreFunct3::Unknown => return JIT_ACCESS_FAILED
+	};
+	match result {
+		MemWriteResult::Ok => 0,
+		_ => JIT_ACCESS_FAILED
+	}
+}
+
+struct CodeBuffer {
+	base: *mut u8,
+	size: usize,
+	used: usize,
+}
+
+unsafe impl Send for CodeBuffer {}
+
+impl CodeBuffer {
+	fn new(size: usize) -> Option<Self> {
+		let base = unsafe {
+			libc::mmap(ptr::null_mut(), size, libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC, libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0)
+		};
+		if base == libc::MAP_FAILED {
+			return None;
+		}
+		Some(CodeBuffer {
+			base: base as *mut u8,
+			size,
+			used: 0,
+		})
+	}
+
+	fn push(&mut self, code: &[u8]) -> Option<usize> {
+		// keep block entry points 16 byte aligned
+		let offset = (self.used + 15) & !15;
+		if offset + code.len() > self.size {
+			return None;
+		}
+		unsafe {
+			ptr::copy_nonoverlapping(code.as_ptr(), self.base.add(offset), code.len());
+		}
+		self.used = offset + code.len();
+		Some(offset)
+	}
+
+	fn clear(&mut self) {
+		self.used = 0;
+	}
+}
+
+impl Drop for CodeBuffer {
+	fn drop(&mut self) {
+		unsafe {
+			libc::munmap(self.base as *mut libc::c_void, self.size);
+		}
+	}
+}
+
+struct Emitter {
+	code: Vec<u8>,
+	exit_fixups: Vec<usize>,
+}
+
+impl Emitter {
+	fn new() -> Self {
+		let mut emitter = Emitter {
+			code: Vec::new(),
+			exit_fixups: Vec::new(),
+		};
+		// push rbx; push r12; push rbp - also realigns the stack for helper calls
+		emitter.bytes(&[0x53, 0x41, 0x54, 0x55]);
+		// mov rbx, rdi (register file); mov r12, rsi (MemIO)
+		emitter.bytes(&[0x48, 0x89, 0xFB, 0x49, 0x89, 0xF4]);
+		emitter
+	}
+
+	fn bytes(&mut self, bytes: &[u8]) {
+		self.code.extend_from_slice(bytes);
+	}
+
+	fn imm32(&mut self, value: u32) {
+		self.code.extend_from_slice(&value.to_le_bytes());
+	}
+
+	fn load_gpr(&mut self, host: u8, reg: u32) {
+		if reg == 0 {
+			// xor host, host
+			self.bytes(&[0x31, 0xC0 | (host << 3) | host]);
+		} else {
+			// mov host, [rbx + disp32]
+			self.bytes(&[0x8B, 0x80 | (host << 3) | 3]);
+			self.imm32((reg - 1) * 4);
+		}
+	}
+
+	fn store_gpr(&mut self, host: u8, reg: u32) {
+		if reg != 0 {
+			// mov [rbx + disp32], host
+			self.bytes(&[0x89, 0x80 | (host << 3) | 3]);
+			self.imm32((reg - 1) * 4);
+		}
+	}
+
+	fn mov_imm(&mut self, host: u8, value: u32) {
+		self.bytes(&[0xB8 + host]);
+		self.imm32(value);
+	}
+
+	fn alu(&mut self, op: u8, dst: u8, src: u8) {
+		self.bytes(&[op, 0xC0 | (src << 3) | dst]);
+	}
+
+	fn alu_imm(&mut self, digit: u8, dst: u8, value: u32) {
+		self.bytes(&[0x81, 0xC0 | (digit << 3) | dst]);
+		self.imm32(value);
+	}
+
+	fn shift_cl(&mut self, digit: u8, dst: u8) {
+		self.bytes(&[0xD3, 0xC0 | (digit << 3) | dst]);
+	}
+
+	fn shift_imm(&mut self, digit: u8, dst: u8, amount: u32) {
+		self.bytes(&[0xC1, 0xC0 | (digit << 3) | dst, amount as u8]);
+	}
+
+	// setcc al; movzx eax, al
+	fn set_eax_cc(&mut self, cc: u8) {
+		self.bytes(&[0x0F, 0x90 | cc, 0xC0, 0x0F, 0xB6, 0xC0]);
+	}
+
+	// mov rdi, r12; mov rax, helper; call rax
+	fn call_helper(&mut self, helper: usize) {
+		self.bytes(&[0x4C, 0x89, 0xE7, 0x48, 0xB8]);
+		self.code.extend_from_slice(&(helper as u64).to_le_bytes());
+		self.bytes(&[0xFF, 0xD0]);
+	}
+
+	// leaves the block at pc if the helper reported a failed access, so the interpreter can redo it
+	fn exit_on_access_failure(&mut self, pc: u32, executed: u32) {
+		// bt rax, 32; jnc over the exit
+		self.bytes(&[0x48, 0x0F, 0xBA, 0xE0, 0x20, 0x73, 15]);
+		self.exit_to(pc, executed);
+	}
+
+	fn exit_to(&mut self, pc: u32, executed: u32) {
+		self.mov_imm(EAX, pc);
+		self.exit_with_eax(executed);
+	}
+
+	fn exit_with_eax(&mut self, executed: u32) {
+		self.mov_imm(EDX, executed);
+		self.bytes(&[0xE9]);
+		self.exit_fixups.push(self.code.len());
+		self.imm32(0);
+	}
+
+	fn finish(mut self) -> Vec<u8> {
+		let exit = self.code.len();
+		for fixup in self.exit_fixups.iter() {
+			let rel = (exit - (fixup + 4)) as u32;
+			self.code[*fixup .. *fixup + 4].copy_from_slice(&rel.to_le_bytes());
+		}
+		// shl rdx, 32; or rax, rdx; pop rbp; pop r12; pop rbx; ret
+		self.bytes(&[0x48, 0xC1, 0xE2, 0x20, 0x48, 0x09, 0xD0, 0x5D, 0x41, 0x5C, 0x5B, 0xC3]);
+		self.code
+	}
+}
+
+enum Translated {
+	Sequential,
+	Terminator,
+	Unsupported,
+}
+
+fn translate_instruction<Timer: MTimer, MIO: MemIO<Timer>>(emitter: &mut Emitter, opcode: &Opcode, pc: u32, next_pc: u32, executed: u32) -> Translated {
+	let rd = opcode.rd();
+	let rs1 = opcode.rs1();
+	let rs2 = opcode.rs2();
+	match opcode.op() {
+		Op::Lui => {
+			emitter.mov_imm(EAX, opcode.u_imm() << 12);
+			emitter.store_gpr(EAX, rd);
+		},
+		Op::Auipc => {
+			emitter.mov_imm(EAX, pc.wrapping_add(opcode.u_imm() << 12));
+			emitter.store_gpr(EAX, rd);
+		},
+		Op::OpImm => {
+			let op_funct = opcode.funct3_op_imm();
+			// the bit manipulation ops which share the shift immediate encodings are left to the interpreter
+			if let OpImmFunct3::SllI | OpImmFunct3::SrxI = op_funct {
+				match opcode.funct_op_imm_shift() {
+					OpImmShiftFunct::SllI | OpImmShiftFunct::SrlI | OpImmShiftFunct::SraI => {},
+					_ => return Translated::Unsupported,
+				}
+			}
+			if rd == 0 {
+				return Translated::Sequential;
+			}
+			let imm = opcode.i_imm_signed() as u32;
+			emitter.load_gpr(EAX, rs1);
+			match op_funct {
+				OpImmFunct3::AddI => emitter.alu_imm(ALU_IMM_ADD, EAX, imm),
+				OpImmFunct3::SltI => {
+					emitter.alu_imm(ALU_IMM_CMP, EAX, imm);
+					emitter.set_eax_cc(CC_L);
+				},
+				OpImmFunct3::SltIU => {
+					emitter.alu_imm(ALU_IMM_CMP, EAX, imm);
+					emitter.set_eax_cc(CC_B);
+				},
+				OpImmFunct3::XorI => emitter.alu_imm(ALU_IMM_XOR, EAX, imm),
+				OpImmFunct3::OrI => emitter.alu_imm(ALU_IMM_OR, EAX, imm),
+				OpImmFunct3::AndI => emitter.alu_imm(ALU_IMM_AND, EAX, imm),
+				OpImmFunct3::SllI => emitter.shift_imm(SHIFT_SHL, EAX, opcode.shamt()),
+				OpImmFunct3::SrxI => {
+					if opcode.srxi_is_arithmetic() {
+						emitter.shift_imm(SHIFT_SAR, EAX, opcode.shamt());
+					} else {
+						emitter.shift_imm(SHIFT_SHR, EAX, opcode.shamt());
+					}
+				},
+			}
+			emitter.store_gpr(EAX, rd);
+		},
+		Op::Op => {
+			let op_funct = opcode.funct3funct7_op();
+			match op_funct {
+				OpFunct3Funct7::Div |
+				OpFunct3Funct7::DivU |
+				OpFunct3Funct7::Rem |
+				OpFunct3Funct7::RemU |
+				OpFunct3Funct7::Unknown => return Translated::Unsupported,
+				// Zba, Zbb and Zbs
+				OpFunct3Funct7::Sh1Add |
+				OpFunct3Funct7::Sh2Add |
+				OpFunct3Funct7::Sh3Add |
+				OpFunct3Funct7::AndN |
+				OpFunct3Funct7::OrN |
+				OpFunct3Funct7::XNor |
+				OpFunct3Funct7::Min |
+				OpFunct3Funct7::MinU |
+				OpFunct3Funct7::Max |
+				OpFunct3Funct7::MaxU |
+				OpFunct3Funct7::Rol |
+				OpFunct3Funct7::Ror |
+				OpFunct3Funct7::ZextH |
+				OpFunct3Funct7::BSet |
+				OpFunct3Funct7::BClr |
+				OpFunct3Funct7::BInv |
+				OpFunct3Funct7::BExt => return Translated::Unsupported,
+				_ => {}
+			}
+			if rd == 0 {
+				return Translated::Sequential;
+			}
+			emitter.load_gpr(EAX, rs1);
+			emitter.load_gpr(ECX, rs2);
+			match op_funct {
+				OpFunct3Funct7::Add => emitter.alu(ALU_ADD, EAX, ECX),
+				OpFunct3Funct7::Sub => emitter.alu(ALU_SUB, EAX, ECX),
+				OpFunct3Funct7::Xor => emitter.alu(ALU_XOR, EAX, ECX),
+				OpFunct3Funct7::Or => emitter.alu(ALU_OR, EAX, ECX),
+				OpFunct3Funct7::And => emitter.alu(ALU_AND, EAX, ECX),
+				// x86 masks 32 bit shift counts in cl to 5 bits, same as RV32
+				OpFunct3Funct7::Sll => emitter.shift_cl(SHIFT_SHL, EAX),
+				OpFunct3Funct7::Srl => emitter.shift_cl(SHIFT_SHR, EAX),
+				OpFunct3Funct7::Sra => emitter.shift_cl(SHIFT_SAR, EAX),
+				OpFunct3Funct7::Slt => {
+					emitter.alu(ALU_CMP, EAX, ECX);
+					emitter.set_eax_cc(CC_L);
+				},
+				OpFunct3Funct7::SltU => {
+					emitter.alu(ALU_CMP, EAX, ECX);
+					emitter.set_eax_cc(CC_B);
+				},
+				// imul eax, ecx
+				OpFunct3Funct7::Mul => emitter.bytes(&[0x0F, 0xAF, 0xC1]),
+				OpFunct3Funct7::MulH => {
+					// movsxd rax, eax; movsxd rcx, ecx; imul rax, rcx; shr rax, 32
+					emitter.bytes(&[0x48, 0x63, 0xC0, 0x48, 0x63, 0xC9, 0x48, 0x0F, 0xAF, 0xC1, 0x48, 0xC1, 0xE8, 0x20]);
+				},
+				OpFunct3Funct7::MulHSU => {
+					// movsxd rax, eax; imul rax, rcx; shr rax, 32 - rcx is already zero extended
+					emitter.bytes(&[0x48, 0x63, 0xC0, 0x48, 0x0F, 0xAF, 0xC1, 0x48, 0xC1, 0xE8, 0x20]);
+				},
+				OpFunct3Funct7::MulHU => {
+					// imul rax, rcx; shr rax, 32 - both operands are zero extended, so the low 64 bits are the full product
+					emitter.bytes(&[0x48, 0x0F, 0xAF, 0xC1, 0x48, 0xC1, 0xE8, 0x20]);
+				},
+				_ => unreachable!()
+			}
+			emitter.store_gpr(EAX, rd);
+		},
+		Op::Load => {
+			let load_type = opcode.funct3_load();
+			if let LoadFunct3::Unknown = load_type {
+				return Translated::Unsupported;
+			}
+			emitter.load_gpr(ESI, rs1);
+			emitter.alu_imm(ALU_IMM_ADD, ESI, opcode.i_imm_signed() as u32);
+			emitter.mov_imm(EDX, load_type.to_raw());
+			emitter.call_helper(jit_load::<Timer, MIO> as *const () as usize);
+			emitter.exit_on_access_failure(pc, executed);
+			emitter.store_gpr(EAX, rd);
+		},
+		Op::Store => {
+			let store_type = opcode.funct3_store();
+			if let StoreFunct3::Unknown = store_type {
+				return Translated::Unsupported;
+			}
+			emitter.load_gpr(ESI, rs1);
+			emitter.alu_imm(ALU_IMM_ADD, ESI, opcode.s_imm_signed() as u32);
+			emitter.load_gpr(EDX, rs2);
+			emitter.mov_imm(ECX, store_type.to_raw());
+			emitter.call_helper(jit_store::<Timer, MIO> as *const () as usize);
+			emitter.exit_on_access_failure(pc, executed);
+		},
+		Op::Branch => {
+			// cmov on the inverse condition picks the fallthrough pc
+			let inverse_cc = match opcode.funct3_branch() {
+				BranchFunct3::Eq => CC_NE,
+				BranchFunct3::NEq => CC_E,
+				BranchFunct3::Lt => CC_GE,
+				BranchFunct3::GEq => CC_L,
+				BranchFunct3::LtU => CC_AE,
+				BranchFunct3::GEqU => CC_B,
+				BranchFunct3::Unknown => return Translated::Unsupported,
+			};
+			emitter.load_gpr(EAX, rs1);
+			emitter.load_gpr(ECX, rs2);
+			emitter.alu(ALU_CMP, EAX, ECX);
+			emitter.mov_imm(EAX, pc.wrapping_add(opcode.b_imm_signed() as u32));
+			emitter.mov_imm(EDX, next_pc);
+			// cmovcc eax, edx
+			emitter.bytes(&[0x0F, 0x40 | inverse_cc, 0xC2]);
+			emitter.exit_with_eax(executed + 1);
+			return Translated::Terminator;
+		},
+		Op::Jal => {
+			emitter.mov_imm(EAX, next_pc);
+			emitter.store_gpr(EAX, rd);
+			emitter.exit_to(pc.wrapping_add(opcode.j_imm_signed() as u32), executed + 1);
+			return Translated::Terminator;
+		},
+		Op::Jalr => {
+			emitter.load_gpr(EAX, rs1);
+			emitter.alu_imm(ALU_IMM_ADD, EAX, opcode.i_imm_signed() as u32);
+			emitter.alu_imm(ALU_IMM_AND, EAX, !1);
+			emitter.mov_imm(ECX, next_pc);
+			emitter.store_gpr(ECX, rd);
+			emitter.exit_with_eax(executed + 1);
+			return Translated::Terminator;
+		},
+		_ => return Translated::Unsupported
+	}
+	Translated::Sequential
+}
+
+fn translate_block<Timer: MTimer, MIO: MemIO<Timer>>(instructions: &[DecodedInstruction], start_pc: u32) -> Option<Vec<u8>> {
+	let mut emitter = Emitter::new();
+	let mut pc = start_pc;
+	let mut executed = 0;
+	for instruction in instructions.iter() {
+		let opcode = Opcode::new(instruction.opcode_value);
+		let next_pc = pc.wrapping_add(instruction.length);
+		match translate_instruction::<Timer, MIO>(&mut emitter, &opcode, pc, next_pc, executed) {
+			Translated::Sequential => {},
+			Translated::Terminator => return Some(emitter.finish()),
+			Translated::Unsupported => break,
+		}
+		pc = next_pc;
+		executed += 1;
+	}
+	if executed == 0 {
+		return None;
+	}
+	emitter.exit_to(pc, executed);
+	Some(emitter.finish())
+}
+
+#[derive(Clone, Copy)]
+enum JitBlockState {
+	Cold(u32),
+	Translated(usize),
+	Untranslatable,
+}
+
+#[derive(Clone, Copy)]
+struct JitEntry {
+	pc: u32,
+	write_cycle: usize,
+	state: JitBlockState,
+}
+
+pub struct JitCache {
+	code: CodeBuffer,
+	entries: Vec<Option<JitEntry>>,
+}
+
+impl JitCache {
+	pub fn new() -> Option<Self> {
+		Some(JitCache {
+			code: CodeBuffer::new(JIT_CODE_SIZE)?,
+			entries: vec![None; JIT_CACHE_SIZE],
+		})
+	}
+
+	pub fn flush(&mut self) {
+		for entry in self.entries.iter_mut() {
+			*entry = None;
+		}
+		self.code.clear();
+	}
+
+	// runs the translated block at pc, translating it first if it has gotten hot.
+	// returns the next pc and the number of instructions executed, or None if the block
+	// has to be interpreted.
+	pub fn execute<Timer: MTimer, MIO: MemIO<Timer>>(&mut self, xr: &mut [u32; 31], mio: &mut MIO, pc: u32) -> Option<(u32, u32)> {
+		let write_cycle = mio.code_write_cycle(pc)?;
+		let slot = ((pc >> 1) as usize) & (JIT_CACHE_SIZE - 1);
+		let state = match &self.entries[slot] {
+			Some(entry) if entry.pc == pc && entry.write_cycle == write_cycle => entry.state,
+			_ => JitBlockState::Cold(0)
+		};
+		let offset = match state {
+			JitBlockState::Translated(offset) => offset,
+			JitBlockState::Untranslatable => return None,
+			JitBlockState::Cold(count) if count + 1 < JIT_HOT_THRESHOLD => {
+				self.entries[slot] = Some(JitEntry {
+					pc,
+					write_cycle,
+					state: JitBlockState::Cold(count + 1),
+				});
+				return None;
+			},
+			JitBlockState::Cold(_) => {
+				let state = match self.translate::<Timer, MIO>(mio, pc, write_cycle) {
+					Some(offset) => JitBlockState::Translated(offset),
+					None => JitBlockState::Untranslatable,
+				};
+				self.entries[slot] = Some(JitEntry {
+					pc,
+					write_cycle,
+					state,
+				});
+				match state {
+					JitBlockState::Translated(offset) => offset,
+					_ => return None
+				}
+			}
+		};
+		let block: JitBlockFn<MIO> = unsafe { std::mem::transmute(self.code.base.add(offset)) };
+		let result = block(xr.as_mut_ptr(), mio as *mut MIO);
+		Some((result as u32, (result >> 32) as u32))
+	}
+
+	fn translate<Timer: MTimer, MIO: MemIO<Timer>>(&mut self, mio: &MIO, pc: u32, write_cycle: usize) -> Option<usize> {
+		let instructions = decode_block(mio, pc, write_cycle)?;
+		let code = translate_block::<Timer, MIO>(&instructions, pc)?;
+		if let Some(offset) = self.code.push(&code) {
+			return Some(offset);
+		}
+		// out of code space, start over
+		self.flush();
+		self.code.push(&code)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{assemble, Cpu, CpuState, CpuWakeupHandle, FlatMemIO, ManualMTimer, NullInterruptBus};
+
+	const BASE: u32 = 0x8000_0000;
+	const DATA: u32 = 0x8000_1000;
+	const RAM_SIZE: u32 = 0x2000;
+
+	// runs source through to its end with t0 pointing at DATA, returning the hart's state, ram, and the number
+	// of steps taken
+	fn run(source: &str, jit: bool) -> (CpuState, Vec<u8>, u32) {
+		let mio = FlatMemIO::new(BASE, RAM_SIZE);
+		let program = assemble(&format!("li t0, {:#x}\n{}", DATA, source), BASE).unwrap();
+		assert!(mio.load(BASE, &program));
+		let mut cpu: Cpu<ManualMTimer, FlatMemIO, NullInterruptBus> = Cpu::new(mio.clone(), NullInterruptBus, CpuWakeupHandle::new(), 0);
+		if ! jit {
+			cpu.disable_jit();
+		}
+		cpu.reset(BASE);
+		let end = BASE + program.len() as u32;
+		for steps in 0 .. 100_000 {
+			if cpu.get_pc() == end {
+				return (cpu.save_state(), mio.dump(BASE, RAM_SIZE).unwrap(), steps);
+			}
+			cpu.step();
+		}
+		panic!("ran off at {:#010x}", cpu.get_pc());
+	}
+
+	// runs source translated and interpreted, and checks both finish with the same hart and ram
+	fn differential(source: &str) -> CpuState {
+		let (state, ram, steps) = run(source, true);
+		let (expected_state, expected_ram, _) = run(source, false);
+		assert_eq!(state, expected_state);
+		assert!(ram == expected_ram, "ram differs");
+		// translated blocks run several instructions a step, so fewer steps means the jit did some of the work
+		assert!((steps as u64) < state.instret, "nothing was translated");
+		state
+	}
+
+	#[test]
+	fn alu_matches_interpreter() {
+		differential("
+			li a0, 0x12345678
+			li a1, -7
+			li a2, 100
+		loop:
+			add a3, a0, a1
+			sub a4, a0, a1
+			xor a5, a3, a4
+			or a6, a5, a1
+			and a7, a6, a0
+			sll s2, a0, a2
+			srl s3, a1, a2
+			sra s4, a1, a2
+			slt s5, a1, a0
+			sltu s6, a1, a0
+			addi a0, a7, -123
+			xori s7, a0, 0x5A5
+			ori s8, s7, 0x0F0
+			andi s9, s8, -16
+			slli s10, s9, 3
+			srli s11, s10, 5
+			srai t1, a1, 1
+			slti t2, a0, 0
+			sltiu t3, a0, 1000
+			lui t4, 0xABCDE
+			auipc t5, 0
+			add a0, a0, s11
+			add a1, a1, t4
+			addi a2, a2, -1
+			bnez a2, loop
+		");
+	}
+
+	#[test]
+	fn branches_match_interpreter() {
+		let state = differential("
+			li a0, 0
+			li a1, 200
+			li s2, -50
+		loop:
+			addi a1, a1, -1
+			beq a1, s2, skip
+			blt a1, s2, skip
+			addi a0, a0, 1
+			bge a1, zero, positive
+			addi a0, a0, 2
+		positive:
+			bltu a1, s2, unsigned
+			addi a0, a0, 4
+		unsigned:
+			bgeu a1, a0, skip
+			call bump
+		skip:
+			bne a1, zero, loop
+			j done
+		bump:
+			addi a0, a0, 8
+			ret
+		done:
+		");
+		assert_ne!(state.gpr[10], 0);
+	}
+
+	#[test]
+	fn loads_and_stores_match_interpreter() {
+		differential("
+			li a0, 0x8badf00d
+			li a2, 64
+			mv a3, t0
+		loop:
+			sw a0, 0(a3)
+			sh a0, 4(a3)
+			sb a0, 6(a3)
+			lw a4, 0(a3)
+			lh a5, 4(a3)
+			lhu a6, 4(a3)
+			lb a7, 6(a3)
+			lbu s2, 6(a3)
+			add a0, a0, a4
+			xor a0, a0, a5
+			add a0, a0, a6
+			xor a0, a0, a7
+			add a0, a0, s2
+			sb a0, 7(a3)
+			lw s3, 4(a3)
+			add a0, a0, s3
+			addi a3, a3, 8
+			addi a2, a2, -1
+			bnez a2, loop
+		");
+	}
+
+	#[test]
+	fn stores_into_translated_code_invalidate_it() {
+		// bump runs hot enough to be translated, then has its increment patched from 1 to 16. there's no fence.i,
+		// which would flush every block, so it's the store itself which has to retire the stale translation.
+		let patch = assemble("addi a0, a0, 16", 0).unwrap();
+		let patch = u32::from_le_bytes([patch[0], patch[1], patch[2], patch[3]]);
+		let state = differential(&format!("
+			li a0, 0
+			li a1, 100
+			la s2, bump
+			li s3, {:#x}
+		loop:
+			call bump
+			addi a1, a1, -1
+			li a2, 50
+			bne a1, a2, unpatched
+			sw s3, 0(s2)
+		unpatched:
+			bnez a1, loop
+			j done
+		bump:
+			addi a0, a0, 1
+			ret
+		done:
+		", patch));
+		assert_eq!(state.gpr[10], 50 + 50 * 16);
+	}
+}
@@ -1,13 +1,45 @@
 mod mem;
+mod flat_mem;
 mod cpu;
+mod cpu_state;
 mod opcode;
+mod compressed;
+mod block_cache;
+#[cfg(all(feature = "x86_64_jit", target_arch = "x86_64", unix))]
+mod jit_x86_64;
 mod asm_jit;
+mod assembler;
 mod interrupt;
 mod mtimer;
+mod debug;
+mod breakpoint;
+mod replay;
+mod gdb_stub;
+mod disasm;
+mod trace;
+mod clock;
+mod pmp;
+mod hpm;
+mod mmu;
+mod semihosting;
+mod elf_loader;
 
-pub use cpu::{Cpu, CpuWakeupHandle, CpuKillHandle};
-pub use mem::{MemIO, MemReadResult, MemWriteResult};
-pub use opcode::{Opcode, Op, OpImmFunct3, StoreFunct3, LoadFunct3, OpFunct3Funct7, BranchFunct3, FpFormatFunct3, SystemFunct3, SystemIntFunct7, FpFunct7, FpRm, FpSignFunct3, FpMinMaxFunct3, FCvtType, FMvXWClassFunct3, FpCmpFunct3, AtomicFunct7, AtomicSizeFunct3};
-pub use asm_jit::AsmJit;
-pub use interrupt::InterruptBus;
-pub use mtimer::MTimer;
+pub use cpu::{Cpu, CpuWakeupHandle, CpuKillHandle, CpuSpeedHandle, REG_NAMES};
+pub use cpu_state::CpuState;
+pub use mem::{MemIO, MemReadResult, MemWriteResult, MemAccessEvent};
+pub use flat_mem::FlatMemIO;
+pub use opcode::{Opcode, Op, OpImmFunct3, OpImmShiftFunct, StoreFunct3, LoadFunct3, OpFunct3Funct7, BranchFunct3, FpFormatFunct3, FpFormatFunct2, SystemFunct3, SystemIntFunct7, FpFunct7, FpRm, FpSignFunct3, FpMinMaxFunct3, FCvtType, FMvXWClassFunct3, FpCmpFunct3, AtomicFunct7, AtomicSizeFunct3};
+pub use compressed::{expand_compressed, is_compressed, instruction_length};
+pub use asm_jit::{AsmJit, AsmResult};
+pub use assembler::{assemble, assemble_into, AsmError};
+pub use interrupt::{InterruptBus, InterruptLevels, NullInterruptBus};
+pub use mtimer::{MTimer, ManualMTimer};
+pub use debug::{DebugAdapter, DebugStep};
+pub use breakpoint::{CpuBreakpointHandle, BreakpointHit, Watchpoint, WatchKind};
+pub use replay::ReplayStop;
+pub use semihosting::Semihosting;
+pub use gdb_stub::{GdbServer, GdbHartAdapter};
+pub use disasm::{disassemble, disassemble_with_symbols};
+pub use trace::{TraceFile, TraceRecorder, TraceReader, TraceEvent, TraceRegWrite};
+pub use elf_loader::{load_elf, load_elf_symbols};
+pub use clock::{ClockMode, MachineClock, DEFAULT_INSTRUCTIONS_PER_SECOND};
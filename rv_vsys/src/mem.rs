@@ -43,6 +43,13 @@ impl MemWriteResult {
 	}
 }
 
+// events seen by the memory system, which the performance counters can select
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MemAccessEvent {
+	PeripheralAccess,
+	PageLockContention,
+}
+
 pub trait MemIO<Timer: MTimer>: Send + Clone {
 	fn read_8(&self, addr: u32) -> MemReadResult<u8>;
 	fn read_16(&self, addr: u32) -> MemReadResult<u16>;
@@ -58,6 +65,22 @@ pub trait MemIO<Timer: MTimer>: Send + Clone {
 	
 	fn access_break(&mut self);
 	
+	// a value which changes whenever the memory backing addr may have been written, used to
+	// validate decoded instruction caches. None means code at addr must not be cached.
+	fn code_write_cycle(&self, _addr: u32) -> Option<usize> {
+		None
+	}
+	
+	// true if size bytes at addr are ordinary memory, where accesses have no side effects
+	fn is_plain_memory(&self, _addr: u32, _size: u32) -> bool {
+		false
+	}
+	
+	// running count of an event for this interface, zero for events the implementation doesn't track
+	fn access_event_count(&self, _event: MemAccessEvent) -> u64 {
+		0
+	}
+	
 	fn set_hart_id(&mut self, id: u32);
 	
 	fn get_mtimer(&self, hart_id: u32) -> Option<Arc<Timer>>;
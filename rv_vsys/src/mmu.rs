@@ -0,0 +1,281 @@
+// Sv32 virtual memory for supervisor and user mode. satp selects bare or Sv32 translation and holds
+// the root page table, with no ASID bits. Walks set the accessed and dirty bits in the page table
+// entries themselves, and the leaves they find are cached in a small direct mapped TLB, which is
+// flushed whenever satp is written or an sfence.vma is executed.
+
+use crate::pmp::PmpAccess;
+
+pub const PAGE_SIZE: u32 = 0x1000;
+const PAGE_SHIFT: u32 = 12;
+const VPN_BITS: u32 = 10;
+
+const SATP_MODE_SV32: u32 = 1 << 31;
+const SATP_PPN_MASK: u32 = 0x003F_FFFF;
+
+const PTE_V: u32 = 1 << 0;
+const PTE_R: u32 = 1 << 1;
+const PTE_W: u32 = 1 << 2;
+const PTE_X: u32 = 1 << 3;
+const PTE_U: u32 = 1 << 4;
+const PTE_A: u32 = 1 << 6;
+const PTE_D: u32 = 1 << 7;
+const PTE_PPN_SHIFT: u32 = 10;
+
+const TLB_SIZE: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TranslateError {
+	PageFault,
+	// a page table entry was outside of memory or protected from the walk
+	AccessFault,
+}
+
+// what the hart may do with a page, from its privilege and the sum and mxr bits of mstatus
+#[derive(Clone, Copy, Debug)]
+pub struct PageAccessMode {
+	pub user: bool,
+	pub supervisor_user_access: bool,
+	pub make_executable_readable: bool,
+}
+
+#[derive(Clone, Copy)]
+struct TlbEntry {
+	vpn: u32,
+	// the 4 KiB physical page, also when the leaf was a megapage
+	ppn: u32,
+	pte: u32,
+}
+
+// the leaf entry a walk ended at, and where it is in memory
+#[derive(Clone, Copy, Debug)]
+pub struct PageLeaf {
+	pub pte_address: u32,
+	pub pte: u32,
+	ppn: u32,
+}
+
+impl PageLeaf {
+	pub fn physical_address(&self, address: u32) -> u32 {
+		self.ppn << PAGE_SHIFT | address & (PAGE_SIZE - 1)
+	}
+}
+
+fn permits(pte: u32, access: PmpAccess, mode: PageAccessMode) -> bool {
+	if (pte & PTE_U != 0) != mode.user {
+		// supervisor mode reaches user pages with sum set, but never executes them
+		if mode.user || ! mode.supervisor_user_access || access == PmpAccess::Execute {
+			return false;
+		}
+	}
+	match access {
+		PmpAccess::Read => pte & PTE_R != 0 || (mode.make_executable_readable && pte & PTE_X != 0),
+		PmpAccess::Write => pte & PTE_W != 0,
+		PmpAccess::Execute => pte & PTE_X != 0,
+	}
+}
+
+#[derive(Clone)]
+pub struct Mmu {
+	satp: u32,
+	tlb: [Option<TlbEntry>; TLB_SIZE],
+}
+
+impl Mmu {
+	pub fn new() -> Self {
+		Mmu {
+			satp: 0,
+			tlb: [None; TLB_SIZE],
+		}
+	}
+
+	pub fn reset(&mut self) {
+		*self = Self::new();
+	}
+
+	pub fn read_satp(&self) -> u32 {
+		self.satp
+	}
+
+	pub fn write_satp(&mut self, value: u32) {
+		self.satp = value & (SATP_MODE_SV32 | SATP_PPN_MASK);
+		self.flush();
+	}
+
+	pub fn is_enabled(&self) -> bool {
+		self.satp & SATP_MODE_SV32 != 0
+	}
+
+	pub fn flush(&mut self) {
+		self.tlb = [None; TLB_SIZE];
+	}
+
+	// the physical address for an access, reading page table entries through read_pte. also returns
+	// an entry which has to be written back with its accessed or dirty bit set for the access to go ahead
+	pub fn translate(&mut self, address: u32, access: PmpAccess, mode: PageAccessMode, read_pte: impl FnMut(u32) -> Option<u32>) -> Result<(u32, Option<(u32, u32)>), TranslateError> {
+		let vpn = address >> PAGE_SHIFT;
+		let slot = vpn as usize & (TLB_SIZE - 1);
+		if let Some(entry) = self.tlb[slot] {
+			if entry.vpn == vpn {
+				if ! permits(entry.pte, access, mode) {
+					return Err(TranslateError::PageFault);
+				}
+				// the first store to a clean page walks again to mark it dirty
+				if access != PmpAccess::Write || entry.pte & PTE_D != 0 {
+					return Ok((entry.ppn << PAGE_SHIFT | address & (PAGE_SIZE - 1), None));
+				}
+			}
+		}
+		let leaf = self.walk(address, read_pte)?;
+		if ! permits(leaf.pte, access, mode) {
+			return Err(TranslateError::PageFault);
+		}
+		let mut pte = leaf.pte | PTE_A;
+		if access == PmpAccess::Write {
+			pte |= PTE_D;
+		}
+		self.tlb[slot] = Some(TlbEntry {
+			vpn,
+			ppn: leaf.ppn,
+			pte,
+		});
+		let update = if pte != leaf.pte { Some((leaf.pte_address, pte)) } else { None };
+		Ok((leaf.physical_address(address), update))
+	}
+
+	// finds the leaf entry for address without checking permissions or touching the TLB
+	pub fn walk(&self, address: u32, mut read_pte: impl FnMut(u32) -> Option<u32>) -> Result<PageLeaf, TranslateError> {
+		// physical addresses are 34 bits, and anything past the first 4 GiB is outside of memory
+		let mut table = ((self.satp & SATP_PPN_MASK) as u64) << PAGE_SHIFT;
+		for level in (0 .. 2).rev() {
+			let index = (address >> (PAGE_SHIFT + VPN_BITS * level)) & ((1 << VPN_BITS) - 1);
+			let pte_address = table + index as u64 * 4;
+			if pte_address > u32::MAX as u64 {
+				return Err(TranslateError::AccessFault);
+			}
+			let pte = read_pte(pte_address as u32).ok_or(TranslateError::AccessFault)?;
+			// write without read is reserved
+			if pte & PTE_V == 0 || pte & (PTE_R | PTE_W) == PTE_W {
+				return Err(TranslateError::PageFault);
+			}
+			let ppn = (pte >> PTE_PPN_SHIFT) as u64;
+			if pte & (PTE_R | PTE_X) == 0 {
+				table = ppn << PAGE_SHIFT;
+				continue;
+			}
+			let page = if level == 1 {
+				// a megapage has to be aligned to its size
+				if ppn & ((1 << VPN_BITS) - 1) != 0 {
+					return Err(TranslateError::PageFault);
+				}
+				ppn | (vpn_low(address) as u64)
+			} else {
+				ppn
+			};
+			if page > (u32::MAX >> PAGE_SHIFT) as u64 {
+				return Err(TranslateError::AccessFault);
+			}
+			return Ok(PageLeaf {
+				pte_address: pte_address as u32,
+				pte,
+				ppn: page as u32,
+			});
+		}
+		Err(TranslateError::PageFault)
+	}
+}
+
+impl Default for Mmu {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+fn vpn_low(address: u32) -> u32 {
+	(address >> PAGE_SHIFT) & ((1 << VPN_BITS) - 1)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::HashMap;
+
+	const ROOT: u32 = 0x4000;
+	const TABLE: u32 = 0x5000;
+
+	const SUPERVISOR: PageAccessMode = PageAccessMode { user: false, supervisor_user_access: false, make_executable_readable: false };
+	const USER: PageAccessMode = PageAccessMode { user: true, supervisor_user_access: false, make_executable_readable: false };
+
+	fn pte(ppn: u32, flags: u32) -> u32 {
+		ppn << PTE_PPN_SHIFT | flags
+	}
+
+	// 0x0040_0000 is a megapage at 0x0080_0000, 0x0080_0000 a misaligned megapage, and the first
+	// 4 MiB go through a second level table with user pages at 0x3000 (rx), 0x4000 (w only) and
+	// 0x5000 (x only). 0x00C0_0000 points at a table outside of memory.
+	fn page_tables() -> HashMap<u32, u32> {
+		let mut memory = HashMap::new();
+		memory.insert(ROOT, pte(TABLE >> PAGE_SHIFT, PTE_V));
+		memory.insert(ROOT + 4, pte(0x800, PTE_V | PTE_R | PTE_W));
+		memory.insert(ROOT + 8, pte(0x801, PTE_V | PTE_R | PTE_W));
+		memory.insert(ROOT + 12, pte(0xF0, PTE_V));
+		memory.insert(TABLE + 3 * 4, pte(0x9, PTE_V | PTE_R | PTE_X | PTE_U));
+		memory.insert(TABLE + 4 * 4, pte(0xA, PTE_V | PTE_W | PTE_U));
+		memory.insert(TABLE + 5 * 4, pte(0xB, PTE_V | PTE_X | PTE_U | PTE_A));
+		memory
+	}
+
+	fn sv32_mmu() -> Mmu {
+		let mut mmu = Mmu::new();
+		mmu.write_satp(SATP_MODE_SV32 | ROOT >> PAGE_SHIFT);
+		mmu
+	}
+
+	#[test]
+	fn walk_finds_pages_and_megapages() {
+		let memory = page_tables();
+		let mmu = sv32_mmu();
+		assert!(mmu.is_enabled());
+		let read_pte = |address| memory.get(&address).cloned().or(Some(0));
+		let leaf = mmu.walk(0x0041_2345, read_pte).unwrap();
+		assert_eq!((leaf.pte_address, leaf.physical_address(0x0041_2345)), (ROOT + 4, 0x0081_2345));
+		let leaf = mmu.walk(0x3ABC, read_pte).unwrap();
+		assert_eq!((leaf.pte_address, leaf.physical_address(0x3ABC)), (TABLE + 12, 0x9ABC));
+		assert_eq!(mmu.walk(0x0080_0000, read_pte).unwrap_err(), TranslateError::PageFault);
+		assert_eq!(mmu.walk(0x4000, read_pte).unwrap_err(), TranslateError::PageFault);
+		assert_eq!(mmu.walk(0x6000, read_pte).unwrap_err(), TranslateError::PageFault);
+		assert_eq!(mmu.walk(0x0100_0000, read_pte).unwrap_err(), TranslateError::PageFault);
+		let unmapped_table = |address| if address >> PAGE_SHIFT == 0xF0 { None } else { read_pte(address) };
+		assert_eq!(mmu.walk(0x00C0_0000, unmapped_table).unwrap_err(), TranslateError::AccessFault);
+	}
+
+	#[test]
+	fn translate_checks_permissions() {
+		let memory = page_tables();
+		let mut mmu = sv32_mmu();
+		let read_pte = |address| memory.get(&address).cloned().or(Some(0));
+		assert_eq!(mmu.translate(0x0040_0010, PmpAccess::Read, USER, read_pte), Err(TranslateError::PageFault));
+		assert_eq!(mmu.translate(0x0040_0010, PmpAccess::Execute, SUPERVISOR, read_pte), Err(TranslateError::PageFault));
+		assert_eq!(mmu.translate(0x3000, PmpAccess::Write, USER, read_pte), Err(TranslateError::PageFault));
+		assert_eq!(mmu.translate(0x3000, PmpAccess::Read, SUPERVISOR, read_pte), Err(TranslateError::PageFault));
+		let sum = PageAccessMode { supervisor_user_access: true, ..SUPERVISOR };
+		assert!(mmu.translate(0x3000, PmpAccess::Read, sum, read_pte).is_ok());
+		assert_eq!(mmu.translate(0x3000, PmpAccess::Execute, sum, read_pte), Err(TranslateError::PageFault));
+		assert_eq!(mmu.translate(0x5000, PmpAccess::Read, USER, read_pte), Err(TranslateError::PageFault));
+		let mxr = PageAccessMode { make_executable_readable: true, ..USER };
+		assert_eq!(mmu.translate(0x5004, PmpAccess::Read, mxr, read_pte), Ok((0xB004, None)));
+	}
+
+	#[test]
+	fn translate_sets_accessed_and_dirty() {
+		let memory = page_tables();
+		let mut mmu = sv32_mmu();
+		let read_pte = |address| memory.get(&address).cloned().or(Some(0));
+		let megapage = memory[&(ROOT + 4)];
+		assert_eq!(mmu.translate(0x0040_0010, PmpAccess::Read, SUPERVISOR, read_pte), Ok((0x0080_0010, Some((ROOT + 4, megapage | PTE_A)))));
+		// cached as accessed but clean, so the first store still has to update the entry
+		assert_eq!(mmu.translate(0x0040_0020, PmpAccess::Read, SUPERVISOR, read_pte), Ok((0x0080_0020, None)));
+		assert_eq!(mmu.translate(0x0040_0020, PmpAccess::Write, SUPERVISOR, read_pte), Ok((0x0080_0020, Some((ROOT + 4, megapage | PTE_A | PTE_D)))));
+		mmu.write_satp(0);
+		assert!(! mmu.is_enabled());
+	}
+}
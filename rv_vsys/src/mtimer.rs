@@ -1,3 +1,52 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
 pub trait MTimer {
 	fn check_timer(&self) -> bool;
+}
+
+// Timer which only moves when its owner advances it, for running a hart outside of a full machine.
+pub struct ManualMTimer {
+	time: AtomicU64,
+	compare: AtomicU64,
+}
+
+impl ManualMTimer {
+	pub fn new() -> Self {
+		ManualMTimer {
+			time: AtomicU64::new(0),
+			compare: AtomicU64::new(0xFFFF_FFFF_FFFF_FFFF),
+		}
+	}
+
+	pub fn time(&self) -> u64 {
+		self.time.load(Ordering::SeqCst)
+	}
+
+	pub fn set_time(&self, time: u64) {
+		self.time.store(time, Ordering::SeqCst);
+	}
+
+	pub fn advance(&self, ticks: u64) {
+		self.time.fetch_add(ticks, Ordering::SeqCst);
+	}
+
+	pub fn compare(&self) -> u64 {
+		self.compare.load(Ordering::SeqCst)
+	}
+
+	pub fn set_compare(&self, compare: u64) {
+		self.compare.store(compare, Ordering::SeqCst);
+	}
+}
+
+impl Default for ManualMTimer {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl MTimer for ManualMTimer {
+	fn check_timer(&self) -> bool {
+		self.time() >= self.compare()
+	}
 }
\ No newline at end of file
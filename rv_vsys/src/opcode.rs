@@ -187,6 +187,26 @@ pub enum OpFunct3Funct7 {
 	Rem,
 	RemU,
 	
+	Sh1Add,
+	Sh2Add,
+	Sh3Add,
+	
+	AndN,
+	OrN,
+	XNor,
+	Min,
+	MinU,
+	Max,
+	MaxU,
+	Rol,
+	Ror,
+	ZextH,
+	
+	BSet,
+	BClr,
+	BInv,
+	BExt,
+	
 	Unknown,
 }
 
@@ -213,6 +233,26 @@ impl OpFunct3Funct7 {
 			0b0000001_110 => Self::Rem,
 			0b0000001_111 => Self::RemU,
 			
+			0b0010000_010 => Self::Sh1Add,
+			0b0010000_100 => Self::Sh2Add,
+			0b0010000_110 => Self::Sh3Add,
+			
+			0b0100000_111 => Self::AndN,
+			0b0100000_110 => Self::OrN,
+			0b0100000_100 => Self::XNor,
+			0b0000101_100 => Self::Min,
+			0b0000101_101 => Self::MinU,
+			0b0000101_110 => Self::Max,
+			0b0000101_111 => Self::MaxU,
+			0b0110000_001 => Self::Rol,
+			0b0110000_101 => Self::Ror,
+			0b0000100_100 => Self::ZextH,
+			
+			0b0010100_001 => Self::BSet,
+			0b0100100_001 => Self::BClr,
+			0b0110100_001 => Self::BInv,
+			0b0100100_101 => Self::BExt,
+			
 			_ => Self::Unknown,
 		}
 	}
@@ -239,6 +279,26 @@ impl OpFunct3Funct7 {
 			Self::Rem    => 0b0000001_110,
 			Self::RemU   => 0b0000001_111,
 			
+			Self::Sh1Add => 0b0010000_010,
+			Self::Sh2Add => 0b0010000_100,
+			Self::Sh3Add => 0b0010000_110,
+			
+			Self::AndN   => 0b0100000_111,
+			Self::OrN    => 0b0100000_110,
+			Self::XNor   => 0b0100000_100,
+			Self::Min    => 0b0000101_100,
+			Self::MinU   => 0b0000101_101,
+			Self::Max    => 0b0000101_110,
+			Self::MaxU   => 0b0000101_111,
+			Self::Rol    => 0b0110000_001,
+			Self::Ror    => 0b0110000_101,
+			Self::ZextH  => 0b0000100_100,
+			
+			Self::BSet   => 0b0010100_001,
+			Self::BClr   => 0b0100100_001,
+			Self::BInv   => 0b0110100_001,
+			Self::BExt   => 0b0100100_101,
+			
 			Self::Unknown => 0b1111111_111,
 		}
 	}
@@ -252,10 +312,101 @@ impl OpFunct3Funct7 {
 	}
 }
 
+// The shift immediate encodings of OpImm, told apart by funct3 and the upper seven bits of the
+// immediate. The unary bit manipulation ops also use the shift amount field as part of the encoding.
+#[derive(Debug, Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub enum OpImmShiftFunct {
+	SllI,
+	SrlI,
+	SraI,
+	
+	RorI,
+	Clz,
+	Ctz,
+	CPop,
+	SextB,
+	SextH,
+	Rev8,
+	OrcB,
+	
+	BSetI,
+	BClrI,
+	BInvI,
+	BExtI,
+	
+	Unknown,
+}
+
+impl OpImmShiftFunct {
+	// raw is funct3 | imm[11:0] << 3
+	pub fn from_raw(raw: u32) -> Self {
+		let shamt = (raw >> 3) & 0x1F;
+		match (raw >> 8, raw & 0x07) {
+			(0b0000000, 0b001) => Self::SllI,
+			(0b0000000, 0b101) => Self::SrlI,
+			(0b0100000, 0b101) => Self::SraI,
+			
+			(0b0110000, 0b101) => Self::RorI,
+			(0b0110000, 0b001) => match shamt {
+				0b00000 => Self::Clz,
+				0b00001 => Self::Ctz,
+				0b00010 => Self::CPop,
+				0b00100 => Self::SextB,
+				0b00101 => Self::SextH,
+				_ => Self::Unknown,
+			},
+			(0b0110100, 0b101) if shamt == 0b11000 => Self::Rev8,
+			(0b0010100, 0b101) if shamt == 0b00111 => Self::OrcB,
+			
+			(0b0010100, 0b001) => Self::BSetI,
+			(0b0100100, 0b001) => Self::BClrI,
+			(0b0110100, 0b001) => Self::BInvI,
+			(0b0100100, 0b101) => Self::BExtI,
+			
+			_ => Self::Unknown,
+		}
+	}
+	
+	// the shift amount field is zero for the ops which take one
+	pub fn to_raw(&self) -> u32 {
+		match self {
+			Self::SllI  => 0b0000000_00000_001,
+			Self::SrlI  => 0b0000000_00000_101,
+			Self::SraI  => 0b0100000_00000_101,
+			
+			Self::RorI  => 0b0110000_00000_101,
+			Self::Clz   => 0b0110000_00000_001,
+			Self::Ctz   => 0b0110000_00001_001,
+			Self::CPop  => 0b0110000_00010_001,
+			Self::SextB => 0b0110000_00100_001,
+			Self::SextH => 0b0110000_00101_001,
+			Self::Rev8  => 0b0110100_11000_101,
+			Self::OrcB  => 0b0010100_00111_101,
+			
+			Self::BSetI => 0b0010100_00000_001,
+			Self::BClrI => 0b0100100_00000_001,
+			Self::BInvI => 0b0110100_00000_001,
+			Self::BExtI => 0b0100100_00000_101,
+			
+			Self::Unknown => 0b1111111_11111_111,
+		}
+	}
+	
+	pub fn imm3(&self) -> u32 {
+		self.to_raw() & 0x07
+	}
+	
+	pub fn imm12(&self) -> u32 {
+		self.to_raw() >> 3
+	}
+}
+
 #[derive(Debug, Clone, Copy)]
 #[allow(non_camel_case_types)]
 pub enum FpFormatFunct3 {
 	Width32,
+	Width64,
 	Unknown
 }
 
@@ -263,6 +414,7 @@ impl FpFormatFunct3 {
 	pub fn from_raw(raw: u32) -> Self {
 		match raw {
 			0b010 => Self::Width32,
+			0b011 => Self::Width64,
 			_ => Self::Unknown
 		}
 	}
@@ -270,11 +422,39 @@ impl FpFormatFunct3 {
 	pub fn to_raw(&self) -> u32 {
 		match self {
 			Self::Width32 => 0b010,
+			Self::Width64 => 0b011,
 			Self::Unknown => 0b000,
 		}
 	}
 }
 
+// Operand format of the fused multiply-add ops, in bits 26:25
+#[derive(Debug, Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub enum FpFormatFunct2 {
+	Single,
+	Double,
+	Unknown
+}
+
+impl FpFormatFunct2 {
+	pub fn from_raw(raw: u32) -> Self {
+		match raw {
+			0b00 => Self::Single,
+			0b01 => Self::Double,
+			_ => Self::Unknown
+		}
+	}
+	
+	pub fn to_raw(&self) -> u32 {
+		match self {
+			Self::Single => 0b00,
+			Self::Double => 0b01,
+			Self::Unknown => 0b11,
+		}
+	}
+}
+
 #[derive(Debug, Clone, Copy)]
 #[allow(non_camel_case_types)]
 pub enum BranchFunct3 {
@@ -357,7 +537,9 @@ impl SystemFunct3 {
 #[derive(Debug, Clone, Copy)]
 #[allow(non_camel_case_types)]
 pub enum SystemIntFunct7 {
+	// sret shares its funct7 with wfi, and is told apart by rs2
 	WaitForInterrupt,
+	SFenceVma,
 	MRet,
 	Unknown,
 }
@@ -366,6 +548,7 @@ impl SystemIntFunct7 {
 	pub fn from_raw(raw: u32) -> Self {
 		match raw {
 			0b0001000 => Self::WaitForInterrupt,
+			0b0001001 => Self::SFenceVma,
 			0b0011000 => Self::MRet,
 			_ => Self::Unknown,
 		}
@@ -374,6 +557,7 @@ impl SystemIntFunct7 {
 	pub fn to_raw(&self) -> u32 {
 		match self {
 			Self::WaitForInterrupt => 0b0001000,
+			Self::SFenceVma => 0b0001001,
 			Self::MRet => 0b0011000,
 			Self::Unknown => 0xFFFF_FFFF,
 		}
@@ -395,6 +579,20 @@ pub enum FpFunct7 {
 	Cmp_S,
 	CvtS_W,
 	MvWX_S,
+	
+	Add_D,
+	Sub_D,
+	Mul_D,
+	Div_D,
+	Sqrt_D,
+	Sign_D,
+	MinMax_D,
+	CvtS_D,
+	CvtD_S,
+	CvtW_D,
+	Class_D,
+	Cmp_D,
+	CvtD_W,
 	Unknown,
 }
 
@@ -413,11 +611,25 @@ impl FpFunct7 {
 			0b10100_00 => Self::Cmp_S,
 			0b11010_00 => Self::CvtS_W,
 			0b11110_00 => Self::MvWX_S,
+			
+			0b00000_01 => Self::Add_D,
+			0b00001_01 => Self::Sub_D,
+			0b00010_01 => Self::Mul_D,
+			0b00011_01 => Self::Div_D,
+			0b01011_01 => Self::Sqrt_D,
+			0b00100_01 => Self::Sign_D,
+			0b00101_01 => Self::MinMax_D,
+			0b01000_00 => Self::CvtS_D,
+			0b01000_01 => Self::CvtD_S,
+			0b11000_01 => Self::CvtW_D,
+			0b11100_01 => Self::Class_D,
+			0b10100_01 => Self::Cmp_D,
+			0b11010_01 => Self::CvtD_W,
 			_ => Self::Unknown,
 		}
 	}
 	
-	fn to_raw(&self) -> u32 {
+	pub fn to_raw(&self) -> u32 {
 		match self {
 			Self::Add_S       => 0b00000_00,
 			Self::Sub_S       => 0b00001_00,
@@ -431,7 +643,21 @@ impl FpFunct7 {
 			Self::Cmp_S       => 0b10100_00,
 			Self::CvtS_W      => 0b11010_00,
 			Self::MvWX_S      => 0b11110_00,
-			Self::Unknown     => 0b00000_01,
+			
+			Self::Add_D       => 0b00000_01,
+			Self::Sub_D       => 0b00001_01,
+			Self::Mul_D       => 0b00010_01,
+			Self::Div_D       => 0b00011_01,
+			Self::Sqrt_D      => 0b01011_01,
+			Self::Sign_D      => 0b00100_01,
+			Self::MinMax_D    => 0b00101_01,
+			Self::CvtS_D      => 0b01000_00,
+			Self::CvtD_S      => 0b01000_01,
+			Self::CvtW_D      => 0b11000_01,
+			Self::Class_D     => 0b11100_01,
+			Self::Cmp_D       => 0b10100_01,
+			Self::CvtD_W      => 0b11010_01,
+			Self::Unknown     => 0b11111_11,
 		}
 	}
 }
@@ -456,12 +682,12 @@ impl FpRm {
 			0b010 => Self::Down,
 			0b011 => Self::Up,
 			0b100 => Self::ToNearestTieMaxMagnitude,
-			0b111 => Self::ToZero,
+			0b111 => Self::Dynamic,
 			_ => Self::Unknown
 		}
 	}
 	
-	fn to_raw(&self) -> u32 {
+	pub fn to_raw(&self) -> u32 {
 		match self {
 			Self::ToNearestTieEven         => 0b000,
 			Self::ToZero                   => 0b001,
@@ -491,7 +717,7 @@ impl FMvXWClassFunct3 {
 		}
 	}
 	
-	fn to_raw(&self) -> u32 {
+	pub fn to_raw(&self) -> u32 {
 		match self {
 			Self::MvXW => 0b000,
 			Self::Class => 0b001,
@@ -519,7 +745,7 @@ impl FpSignFunct3 {
 		}
 	}
 	
-	fn to_raw(&self) -> u32 {
+	pub fn to_raw(&self) -> u32 {
 		match self {
 			Self::SignFromRs2 => 0b000,
 			Self::SignFromNotRs2 => 0b001,
@@ -546,7 +772,7 @@ impl FpMinMaxFunct3 {
 		}
 	}
 	
-	fn to_raw(&self) -> u32 {
+	pub fn to_raw(&self) -> u32 {
 		match self {
 			Self::Min => 0b000,
 			Self::Max => 0b001,
@@ -572,7 +798,7 @@ impl FCvtType {
 		}
 	}
 	
-	fn to_raw(&self) -> u32 {
+	pub fn to_raw(&self) -> u32 {
 		match self {
 			Self::Signed => 0b000,
 			Self::Unsigned => 0b001,
@@ -600,7 +826,7 @@ impl FpCmpFunct3 {
 		}
 	}
 	
-	fn to_raw(&self) -> u32 {
+	pub fn to_raw(&self) -> u32 {
 		match self {
 			Self::LEq => 0b000,
 			Self::Lt => 0b001,
@@ -652,7 +878,7 @@ impl AtomicFunct7 {
 			Self::Swap =>             0b00001,
 			Self::Add =>              0b00000,
 			Self::Xor =>              0b00100,
-			Self::And =>              0b00100,
+			Self::And =>              0b01100,
 			Self::Or =>               0b01000,
 			Self::Min =>              0b10000,
 			Self::Max =>              0b10100,
@@ -686,7 +912,7 @@ impl AtomicSizeFunct3 {
 	}
 }
 
-fn bitfield(x: u32, count: u32, src_bit: u32, dst_bit: u32) -> u32 {
+pub(crate) fn bitfield(x: u32, count: u32, src_bit: u32, dst_bit: u32) -> u32 {
 	let mask = (1u32 << count).wrapping_sub(1);
 	let y = x >> src_bit;
 	(y & mask) << dst_bit
@@ -771,10 +997,18 @@ impl Opcode {
 		FpFormatFunct3::from_raw(self.funct3())
 	}
 	
+	pub fn funct2_fpformat(&self) -> FpFormatFunct2 {
+		FpFormatFunct2::from_raw(bitfield(self.value, 2, 25, 0))
+	}
+	
 	pub fn funct3funct7_op(&self) -> OpFunct3Funct7 {
 		OpFunct3Funct7::from_raw(self.funct3_7())
 	}
 	
+	pub fn funct_op_imm_shift(&self) -> OpImmShiftFunct {
+		OpImmShiftFunct::from_raw(self.funct3() | self.i_imm() << 3)
+	}
+	
 	pub fn funct3_branch(&self) -> BranchFunct3 {
 		BranchFunct3::from_raw(self.funct3())
 	}
@@ -795,8 +1029,9 @@ impl Opcode {
 		FpFunct7::from_raw(self.funct7())
 	}
 	
+	// the low two bits of funct7 are the aq and rl ordering bits, which don't change the op
 	pub fn funct7_atomic(&self) -> AtomicFunct7 {
-		AtomicFunct7::from_raw(self.funct7())
+		AtomicFunct7::from_raw(self.funct7() >> 2)
 	}
 
 	pub fn rs1(&self) -> u32 {
@@ -0,0 +1,149 @@
+// Physical memory protection, with all 16 entries of the RV32 layout implemented.
+// Entries are checked in order and the first one that covers any byte of an access decides it.
+// Machine mode accesses are only checked against locked entries, and user mode accesses which
+// no entry covers fail.
+
+pub const PMP_ENTRY_COUNT: usize = 16;
+
+const PMP_CFG_R: u8 = 1 << 0;
+const PMP_CFG_W: u8 = 1 << 1;
+const PMP_CFG_X: u8 = 1 << 2;
+const PMP_CFG_A_SHIFT: u8 = 3;
+const PMP_CFG_A_MASK: u8 = 3 << PMP_CFG_A_SHIFT;
+const PMP_CFG_L: u8 = 1 << 7;
+// bits 5 and 6 are reserved and read as zero
+const PMP_CFG_WRITE_MASK: u8 = PMP_CFG_R | PMP_CFG_W | PMP_CFG_X | PMP_CFG_A_MASK | PMP_CFG_L;
+
+const PMP_A_OFF: u8 = 0;
+const PMP_A_TOR: u8 = 1;
+const PMP_A_NA4: u8 = 2;
+const PMP_A_NAPOT: u8 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PmpAccess {
+	Read,
+	Write,
+	Execute,
+}
+
+#[derive(Clone)]
+pub struct Pmp {
+	cfg: [u8; PMP_ENTRY_COUNT],
+	addr: [u32; PMP_ENTRY_COUNT],
+	any_locked: bool,
+}
+
+impl Pmp {
+	pub fn new() -> Self {
+		Pmp {
+			cfg: [0; PMP_ENTRY_COUNT],
+			addr: [0; PMP_ENTRY_COUNT],
+			any_locked: false,
+		}
+	}
+
+	pub fn reset(&mut self) {
+		*self = Self::new();
+	}
+
+	// machine mode can skip checks entirely until something is locked
+	pub fn checks_machine_mode(&self) -> bool {
+		self.any_locked
+	}
+
+	fn is_locked(&self, index: usize) -> bool {
+		self.cfg[index] & PMP_CFG_L != 0
+	}
+
+	pub fn read_cfg(&self, reg: usize) -> u32 {
+		let base = reg * 4;
+		(self.cfg[base] as u32) |
+		(self.cfg[base + 1] as u32) << 8 |
+		(self.cfg[base + 2] as u32) << 16 |
+		(self.cfg[base + 3] as u32) << 24
+	}
+
+	pub fn write_cfg(&mut self, reg: usize, value: u32) {
+		for i in 0 .. 4 {
+			let index = reg * 4 + i;
+			if self.is_locked(index) {
+				continue;
+			}
+			let mut cfg = (value >> (i * 8)) as u8 & PMP_CFG_WRITE_MASK;
+			// write without read is reserved
+			if cfg & (PMP_CFG_R | PMP_CFG_W) == PMP_CFG_W {
+				cfg &= !PMP_CFG_W;
+			}
+			self.cfg[index] = cfg;
+		}
+		self.any_locked = self.cfg.iter().any(|cfg| cfg & PMP_CFG_L != 0);
+	}
+
+	pub fn read_addr(&self, index: usize) -> u32 {
+		self.addr[index]
+	}
+
+	pub fn write_addr(&mut self, index: usize, value: u32) {
+		if self.is_locked(index) {
+			return;
+		}
+		// a locked top of range entry also locks the address below it
+		if index + 1 < PMP_ENTRY_COUNT && self.is_locked(index + 1) && (self.cfg[index + 1] & PMP_CFG_A_MASK) >> PMP_CFG_A_SHIFT == PMP_A_TOR {
+			return;
+		}
+		self.addr[index] = value;
+	}
+
+	// byte range covered by an entry, as [start, end)
+	fn entry_range(&self, index: usize) -> Option<(u64, u64)> {
+		let addr = self.addr[index] as u64;
+		match (self.cfg[index] & PMP_CFG_A_MASK) >> PMP_CFG_A_SHIFT {
+			PMP_A_OFF => None,
+			PMP_A_TOR => {
+				let start = if index == 0 { 0 } else { (self.addr[index - 1] as u64) << 2 };
+				// an empty range matches nothing
+				if start >= addr << 2 {
+					None
+				} else {
+					Some((start, addr << 2))
+				}
+			},
+			PMP_A_NA4 => Some((addr << 2, (addr << 2) + 4)),
+			PMP_A_NAPOT => {
+				let trailing_ones = self.addr[index].trailing_ones() as u64;
+				let size = 8u64 << trailing_ones;
+				let start = (addr & !((1u64 << trailing_ones) - 1)) << 2;
+				Some((start, start + size))
+			},
+			_ => unreachable!()
+		}
+	}
+
+	pub fn check(&self, address: u32, size: u32, access: PmpAccess, machine_mode: bool) -> bool {
+		let start = address as u64;
+		let end = start + size as u64;
+		for index in 0 .. PMP_ENTRY_COUNT {
+			let (entry_start, entry_end) = match self.entry_range(index) {
+				Some(range) => range,
+				None => continue
+			};
+			if end <= entry_start || start >= entry_end {
+				continue;
+			}
+			// an access which is only partly inside the matching entry fails
+			if start < entry_start || end > entry_end {
+				return false;
+			}
+			let cfg = self.cfg[index];
+			if machine_mode && cfg & PMP_CFG_L == 0 {
+				return true;
+			}
+			return cfg & match access {
+				PmpAccess::Read => PMP_CFG_R,
+				PmpAccess::Write => PMP_CFG_W,
+				PmpAccess::Execute => PMP_CFG_X,
+			} != 0;
+		}
+		machine_mode
+	}
+}
@@ -0,0 +1,350 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::{CpuState, MemReadResult, MemWriteResult, Watchpoint};
+
+// Record and replay of a hart's nondeterministic inputs, so it can be stepped backwards. While
+// recording, the hart keeps a checkpoint of its state every CHECKPOINT_INTERVAL steps, and logs
+// everything it got from outside of itself since the oldest checkpoint it still has: the result of
+// every data load, atomic and store conditional, the counter and timer csrs it read, and the
+// interrupts pending at the start of each period. Loads are logged by value, so peripheral reads,
+// data the cart loader copied in and stores from other harts all replay exactly as the hart saw
+// them, however the harts were interleaved.
+//
+// Going back restores the latest checkpoint before the target and replays forward to it with the
+// log standing in for the bus and the clock. Stores aren't made again while replaying, since memory
+// already has them, so the value each store replaced is logged to show memory as the hart left it.
+// Instructions and page table entries aren't logged but read from memory as it is now, so the write
+// cycle of each page they come from is noted while recording, and a write to one of those pages
+// discards the history from before it. Once replay reaches the point where recording stopped, the
+// hart carries on live and recording.
+
+const CHECKPOINT_INTERVAL: u64 = 250_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ReplayInput {
+	Value(u32),
+	Unmapped,
+	ReadOnly,
+	Alignment,
+	Size,
+	PeripheralError,
+}
+
+impl ReplayInput {
+	pub fn from_read<T: Copy + Into<u32>>(result: &MemReadResult<T>) -> Self {
+		match result {
+			MemReadResult::Ok(value) => ReplayInput::Value((*value).into()),
+			MemReadResult::ErrUnmapped => ReplayInput::Unmapped,
+			MemReadResult::ErrAlignment => ReplayInput::Alignment,
+			MemReadResult::ErrSize => ReplayInput::Size,
+			MemReadResult::PeripheralError => ReplayInput::PeripheralError,
+		}
+	}
+
+	pub fn to_read<T>(self, narrow: fn(u32) -> T) -> MemReadResult<T> {
+		match self {
+			ReplayInput::Value(value) => MemReadResult::Ok(narrow(value)),
+			ReplayInput::Unmapped | ReplayInput::ReadOnly => MemReadResult::ErrUnmapped,
+			ReplayInput::Alignment => MemReadResult::ErrAlignment,
+			ReplayInput::Size => MemReadResult::ErrSize,
+			ReplayInput::PeripheralError => MemReadResult::PeripheralError,
+		}
+	}
+
+	pub fn from_write(result: &MemWriteResult) -> Self {
+		match result {
+			MemWriteResult::Ok => ReplayInput::Value(0),
+			MemWriteResult::ErrUnmapped => ReplayInput::Unmapped,
+			MemWriteResult::ErrReadOnly => ReplayInput::ReadOnly,
+			MemWriteResult::ErrAlignment => ReplayInput::Alignment,
+			MemWriteResult::ErrSize => ReplayInput::Size,
+			MemWriteResult::PeripheralError => ReplayInput::PeripheralError,
+		}
+	}
+
+	pub fn to_write(self) -> MemWriteResult {
+		match self {
+			ReplayInput::Value(_) => MemWriteResult::Ok,
+			ReplayInput::Unmapped => MemWriteResult::ErrUnmapped,
+			ReplayInput::ReadOnly => MemWriteResult::ErrReadOnly,
+			ReplayInput::Alignment => MemWriteResult::ErrAlignment,
+			ReplayInput::Size => MemWriteResult::ErrSize,
+			ReplayInput::PeripheralError => MemWriteResult::PeripheralError,
+		}
+	}
+}
+
+// where Cpu::reverse_continue stopped the hart
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayStop {
+	// before the instruction at pc
+	Breakpoint { pc: u32 },
+	// before the instruction at pc, which made the access
+	Watchpoint { pc: u32, address: u32, watchpoint: Watchpoint },
+	// nothing was hit, and the hart is at the oldest checkpoint it has
+	HistoryStart { pc: u32 },
+}
+
+struct ReplayStore {
+	address: u32,
+	size: u32,
+	// None for peripherals, which can't be read without side effects
+	old_value: Option<u32>,
+	result: ReplayInput,
+}
+
+struct ReplayCheckpoint {
+	position: u64,
+	state: CpuState,
+	input: u64,
+	store: u64,
+	interrupt: u64,
+}
+
+struct ReplayCursor {
+	input: u64,
+	store: u64,
+	interrupt: u64,
+	live_position: u64,
+}
+
+// logs are indexed from when recording started, and trimmed to the oldest checkpoint
+struct ReplayLog<T> {
+	entries: VecDeque<T>,
+	base: u64,
+}
+
+impl <T> ReplayLog<T> {
+	fn new() -> Self {
+		ReplayLog {
+			entries: VecDeque::new(),
+			base: 0,
+		}
+	}
+
+	fn end(&self) -> u64 {
+		self.base + self.entries.len() as u64
+	}
+
+	fn get(&self, index: u64) -> Option<&T> {
+		self.entries.get(index.checked_sub(self.base)? as usize)
+	}
+
+	fn trim(&mut self, start: u64) {
+		while self.base < start && self.entries.pop_front().is_some() {
+			self.base += 1;
+		}
+	}
+
+	fn clear(&mut self) {
+		self.base = self.end();
+		self.entries.clear();
+	}
+}
+
+pub(crate) struct HartReplay {
+	checkpoint_interval: u64,
+	checkpoint_count: usize,
+	checkpoints: VecDeque<ReplayCheckpoint>,
+	inputs: ReplayLog<ReplayInput>,
+	stores: ReplayLog<ReplayStore>,
+	// the mip each period started with, by position
+	interrupts: ReplayLog<(u64, u32)>,
+	// the write cycle of each page instructions or page table entries were read from
+	read_pages: HashMap<u32, usize>,
+	// steps taken, counting instructions which trapped as well as those which retired
+	position: u64,
+	cursor: Option<ReplayCursor>,
+}
+
+impl HartReplay {
+	// keeps at least history steps behind the hart
+	pub fn new(history: u64) -> Self {
+		let checkpoint_interval = CHECKPOINT_INTERVAL.min(history.max(1));
+		HartReplay {
+			checkpoint_interval,
+			checkpoint_count: (history / checkpoint_interval) as usize + 2,
+			checkpoints: VecDeque::new(),
+			inputs: ReplayLog::new(),
+			stores: ReplayLog::new(),
+			interrupts: ReplayLog::new(),
+			read_pages: HashMap::new(),
+			position: 0,
+			cursor: None,
+		}
+	}
+
+	// state changed from outside of the hart, so nothing before now can be replayed
+	pub fn restart(&mut self) {
+		self.checkpoints.clear();
+		self.inputs.clear();
+		self.stores.clear();
+		self.interrupts.clear();
+		self.read_pages.clear();
+		self.cursor = None;
+	}
+
+	pub fn is_replaying(&self) -> bool {
+		self.cursor.as_ref().is_some_and(|cursor| self.position < cursor.live_position)
+	}
+
+	pub fn position(&self) -> u64 {
+		self.position
+	}
+
+	pub fn advance(&mut self) {
+		self.position += 1;
+		self.catch_up();
+	}
+
+	pub fn checkpoint_due(&self) -> bool {
+		self.cursor.is_none() && match self.checkpoints.back() {
+			Some(checkpoint) => self.position - checkpoint.position >= self.checkpoint_interval,
+			None => true
+		}
+	}
+
+	pub fn push_checkpoint(&mut self, state: CpuState) {
+		self.checkpoints.push_back(ReplayCheckpoint {
+			position: self.position,
+			state,
+			input: self.inputs.end(),
+			store: self.stores.end(),
+			interrupt: self.interrupts.end(),
+		});
+		if self.checkpoints.len() > self.checkpoint_count {
+			self.checkpoints.pop_front();
+			if let Some(oldest) = self.checkpoints.front() {
+				self.inputs.trim(oldest.input);
+				self.stores.trim(oldest.store);
+				self.interrupts.trim(oldest.interrupt);
+			}
+		}
+	}
+
+	pub fn oldest_position(&self) -> Option<u64> {
+		self.checkpoints.front().map(|checkpoint| checkpoint.position)
+	}
+
+	pub fn latest_checkpoint_before(&self, position: u64) -> Option<u64> {
+		self.checkpoints.iter().rev().map(|checkpoint| checkpoint.position).find(|checkpoint| *checkpoint < position)
+	}
+
+	// moves the log back to the latest checkpoint at or before position, and returns the state to restore
+	pub fn rewind(&mut self, position: u64) -> Option<CpuState> {
+		let checkpoint = self.checkpoints.iter().rev().find(|checkpoint| checkpoint.position <= position)?;
+		let live_position = match &self.cursor {
+			Some(cursor) => cursor.live_position,
+			None => self.position
+		};
+		if position > live_position {
+			return None;
+		}
+		self.cursor = Some(ReplayCursor {
+			input: checkpoint.input,
+			store: checkpoint.store,
+			interrupt: checkpoint.interrupt,
+			live_position,
+		});
+		self.position = checkpoint.position;
+		Some(checkpoint.state.clone())
+	}
+
+	// back to recording once replay has caught up with where it stopped
+	pub fn catch_up(&mut self) {
+		if let Some(cursor) = &self.cursor {
+			if self.position >= cursor.live_position {
+				self.cursor = None;
+			}
+		}
+	}
+
+	pub fn next_interrupt(&mut self) -> Option<u32> {
+		let cursor = self.cursor.as_mut()?;
+		match self.interrupts.get(cursor.interrupt) {
+			Some((position, mip)) if *position <= self.position => {
+				cursor.interrupt += 1;
+				Some(*mip)
+			},
+			_ => None
+		}
+	}
+
+	pub fn record_interrupt(&mut self, mip: u32) {
+		if self.cursor.is_none() {
+			self.interrupts.entries.push_back((self.position, mip));
+		}
+	}
+
+	// restarts once a page read from before has been written, since replay would read it as it is now. a page
+	// without a write cycle is either not ram or being written by the hart, whose write shows once it lets go.
+	pub fn record_page_read(&mut self, page: u32, write_cycle: Option<usize>) {
+		if self.cursor.is_some() {
+			return;
+		}
+		let write_cycle = match write_cycle {
+			Some(write_cycle) => write_cycle,
+			None => return
+		};
+		if self.read_pages.insert(page, write_cycle).is_some_and(|seen| seen != write_cycle) {
+			self.restart();
+			self.read_pages.insert(page, write_cycle);
+		}
+	}
+
+	// the hart's own accessed and dirty bit updates don't change what a replayed walk finds
+	pub fn record_page_table_update(&mut self, page: u32, write_cycle: Option<usize>) {
+		if let (Some(seen), Some(write_cycle)) = (self.read_pages.get_mut(&page), write_cycle) {
+			*seen = write_cycle;
+		}
+	}
+
+	pub fn read_pages(&self) -> impl Iterator<Item = (u32, usize)> + '_ {
+		self.read_pages.iter().map(|(page, write_cycle)| (*page, *write_cycle))
+	}
+
+	pub fn next_input(&mut self) -> Option<ReplayInput> {
+		let cursor = self.cursor.as_mut()?;
+		let input = *self.inputs.get(cursor.input)?;
+		cursor.input += 1;
+		Some(input)
+	}
+
+	pub fn record_input(&mut self, input: ReplayInput) {
+		if self.cursor.is_none() {
+			self.inputs.entries.push_back(input);
+		}
+	}
+
+	pub fn next_store(&mut self) -> Option<MemWriteResult> {
+		let cursor = self.cursor.as_mut()?;
+		let result = self.stores.get(cursor.store)?.result;
+		cursor.store += 1;
+		Some(result.to_write())
+	}
+
+	pub fn record_store(&mut self, address: u32, size: u32, old_value: Option<u32>, result: &MemWriteResult) {
+		if self.cursor.is_none() {
+			self.stores.entries.push_back(ReplayStore {
+				address,
+				size,
+				old_value,
+				result: ReplayInput::from_write(result),
+			});
+		}
+	}
+
+	// the byte at address before the first store the hart has yet to replay over it
+	pub fn rewound_byte(&self, address: u32) -> Option<u8> {
+		let cursor = self.cursor.as_ref()?;
+		let mut index = cursor.store;
+		while let Some(store) = self.stores.get(index) {
+			let offset = address.wrapping_sub(store.address);
+			if offset < store.size && store.result == ReplayInput::Value(0) {
+				return store.old_value.map(|value| (value >> (offset * 8)) as u8);
+			}
+			index += 1;
+		}
+		None
+	}
+}
@@ -0,0 +1,377 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use crate::{MTimer, MemIO, MemReadResult, MemWriteResult};
+
+// RISC-V semihosting. A machine mode ebreak between `slli zero, zero, 0x1f` and `srai zero, zero, 7`
+// is a call to the host instead of a breakpoint: a0 holds the operation, a1 the parameter (mostly
+// the address of a block of words) and the result comes back in a0. Files are opened relative to
+// a root directory on the host, and a name can't reach outside of it, absolute names included.
+// ":tt" is the console, stdin when opened for reading, stdout for writing and stderr for appending.
+
+pub(crate) const SEMIHOSTING_ENTRY: u32 = 0x01F0_1013;
+pub(crate) const SEMIHOSTING_EXIT: u32 = 0x4070_5013;
+
+const SYS_OPEN: u32 = 0x01;
+const SYS_CLOSE: u32 = 0x02;
+const SYS_WRITEC: u32 = 0x03;
+const SYS_WRITE0: u32 = 0x04;
+const SYS_WRITE: u32 = 0x05;
+const SYS_READ: u32 = 0x06;
+const SYS_ISTTY: u32 = 0x09;
+const SYS_SEEK: u32 = 0x0A;
+const SYS_FLEN: u32 = 0x0C;
+const SYS_CLOCK: u32 = 0x10;
+const SYS_ERRNO: u32 = 0x13;
+const SYS_EXIT: u32 = 0x18;
+const SYS_EXIT_EXTENDED: u32 = 0x20;
+
+const ADP_STOPPED_APPLICATION_EXIT: u32 = 0x2_0026;
+// the reasons SYS_EXIT takes in a1 itself rather than in a block
+const ADP_STOPPED_FIRST: u32 = 0x2_0000;
+const ADP_STOPPED_LAST: u32 = 0x2_0036;
+
+const CONSOLE_NAME: &[u8] = b":tt";
+// longest file name or SYS_WRITE0 string read from the hart
+const MAX_STRING_LENGTH: u32 = 0x1_0000;
+// longer reads and writes are cut short, which callers see as a partial transfer
+const MAX_TRANSFER_LENGTH: u32 = 0x10_0000;
+const EBADF: i32 = 9;
+const EINVAL: i32 = 22;
+const EACCES: i32 = 13;
+
+const RESULT_ERROR: u32 = 0xFFFF_FFFF;
+
+enum HostFile {
+	Stdin,
+	Stdout,
+	Stderr,
+	File(File),
+}
+
+pub(crate) enum SemihostingResult {
+	Return(u32),
+	Exit,
+}
+
+pub struct Semihosting {
+	root: PathBuf,
+	// handle n is files[n - 1], so no open file has handle 0
+	files: Vec<Option<HostFile>>,
+	errno: i32,
+	exit_handler: Box<dyn FnMut(i32) + Send>,
+}
+
+impl Semihosting {
+	// exit_handler gets the exit code of SYS_EXIT, and the hart stays halted at the call if it returns
+	pub fn new<P: AsRef<Path>>(root: P, exit_handler: Box<dyn FnMut(i32) + Send>) -> io::Result<Self> {
+		Ok(Semihosting {
+			root: root.as_ref().canonicalize()?,
+			files: Vec::new(),
+			errno: 0,
+			exit_handler,
+		})
+	}
+
+	pub(crate) fn call<Timer: MTimer, MIO: MemIO<Timer>>(&mut self, mio: &mut MIO, operation: u32, parameter: u32, millis: u64) -> SemihostingResult {
+		let result = match operation {
+			SYS_OPEN => self.open(mio, parameter),
+			SYS_CLOSE => self.close(mio, parameter),
+			SYS_WRITEC => match mio.read_8(parameter) {
+				MemReadResult::Ok(value) => Self::console_write(&[value]).map(|_| 0),
+				_ => Err(EINVAL)
+			},
+			SYS_WRITE0 => match read_string(mio, parameter, None) {
+				Some(string) => Self::console_write(string.as_slice()).map(|_| 0),
+				None => Err(EINVAL)
+			},
+			SYS_WRITE => self.write(mio, parameter),
+			SYS_READ => self.read(mio, parameter),
+			SYS_ISTTY => match read_words(mio, parameter, 1).map(|words| self.file(words[0])) {
+				Some(Ok(HostFile::File(_))) => Ok(0),
+				Some(Ok(_)) => Ok(1),
+				Some(Err(errno)) => Err(errno),
+				None => Err(EINVAL)
+			},
+			SYS_SEEK => self.seek(mio, parameter),
+			SYS_FLEN => match read_words(mio, parameter, 1).map(|words| self.file(words[0])) {
+				Some(Ok(HostFile::File(file))) => file.metadata().map(|metadata| metadata.len() as u32).map_err(os_errno),
+				Some(Ok(_)) => Ok(0),
+				Some(Err(errno)) => Err(errno),
+				None => Err(EINVAL)
+			},
+			SYS_CLOCK => Ok((millis / 10) as u32),
+			SYS_ERRNO => Ok(self.errno as u32),
+			SYS_EXIT | SYS_EXIT_EXTENDED => {
+				let code = match exit_reason(mio, operation, parameter) {
+					Some((ADP_STOPPED_APPLICATION_EXIT, code)) => code,
+					_ => 1
+				};
+				(self.exit_handler)(code);
+				return SemihostingResult::Exit;
+			},
+			_ => Err(EINVAL)
+		};
+		match result {
+			Ok(value) => SemihostingResult::Return(value),
+			Err(errno) => {
+				self.errno = errno;
+				SemihostingResult::Return(RESULT_ERROR)
+			}
+		}
+	}
+
+	fn file(&mut self, handle: u32) -> Result<&mut HostFile, i32> {
+		match self.files.get_mut((handle as usize).wrapping_sub(1)) {
+			Some(Some(file)) => Ok(file),
+			_ => Err(EBADF)
+		}
+	}
+
+	// the host path for a name, with . and .. resolved inside the root
+	fn sandboxed_path(&self, name: &str) -> Option<PathBuf> {
+		let mut path = self.root.clone();
+		for component in Path::new(name).components() {
+			match component {
+				Component::Normal(part) => path.push(part),
+				Component::RootDir | Component::CurDir => {},
+				Component::ParentDir | Component::Prefix(_) => return None,
+			}
+		}
+		// and symlinks inside of it can't lead out either
+		let parent = path.parent()?.canonicalize().ok()?;
+		if ! parent.starts_with(&self.root) {
+			return None;
+		}
+		// a dangling symlink can't be canonicalized, but creating the file would follow it
+		match path.symlink_metadata() {
+			Ok(metadata) if metadata.file_type().is_symlink() => match path.canonicalize() {
+				Ok(target) if target.starts_with(&self.root) => Some(path),
+				_ => None
+			},
+			_ => Some(path)
+		}
+	}
+
+	fn open<Timer: MTimer, MIO: MemIO<Timer>>(&mut self, mio: &MIO, parameter: u32) -> Result<u32, i32> {
+		let words = read_words(mio, parameter, 3).ok_or(EINVAL)?;
+		let (name_address, mode, name_length) = (words[0], words[1], words[2]);
+		let name = read_string(mio, name_address, Some(name_length)).ok_or(EINVAL)?;
+		// the modes are those of fopen, in the order r, rb, r+, r+b, w, wb, w+, w+b, a, ab, a+, a+b
+		let file = if name.as_slice() == CONSOLE_NAME {
+			match mode {
+				0 ..= 3 => HostFile::Stdin,
+				4 ..= 7 => HostFile::Stdout,
+				8 ..= 11 => HostFile::Stderr,
+				_ => return Err(EINVAL)
+			}
+		} else {
+			let mut options = OpenOptions::new();
+			match mode {
+				0 | 1 => options.read(true),
+				2 | 3 => options.read(true).write(true),
+				4 | 5 => options.write(true).create(true).truncate(true),
+				6 | 7 => options.read(true).write(true).create(true).truncate(true),
+				8 | 9 => options.append(true).create(true),
+				10 | 11 => options.read(true).append(true).create(true),
+				_ => return Err(EINVAL)
+			};
+			let name = String::from_utf8(name).map_err(|_| EINVAL)?;
+			let path = self.sandboxed_path(name.as_str()).ok_or(EACCES)?;
+			HostFile::File(options.open(path).map_err(os_errno)?)
+		};
+		let slot = match self.files.iter().position(|file| file.is_none()) {
+			Some(slot) => slot,
+			None => {
+				self.files.push(None);
+				self.files.len() - 1
+			}
+		};
+		self.files[slot] = Some(file);
+		Ok(slot as u32 + 1)
+	}
+
+	fn close<Timer: MTimer, MIO: MemIO<Timer>>(&mut self, mio: &MIO, parameter: u32) -> Result<u32, i32> {
+		let words = read_words(mio, parameter, 1).ok_or(EINVAL)?;
+		self.file(words[0])?;
+		self.files[words[0] as usize - 1] = None;
+		Ok(0)
+	}
+
+	fn console_write(data: &[u8]) -> Result<(), i32> {
+		let mut stdout = io::stdout();
+		stdout.write_all(data).and_then(|_| stdout.flush()).map_err(os_errno)
+	}
+
+	// returns the number of bytes that weren't written
+	fn write<Timer: MTimer, MIO: MemIO<Timer>>(&mut self, mio: &MIO, parameter: u32) -> Result<u32, i32> {
+		let words = read_words(mio, parameter, 3).ok_or(EINVAL)?;
+		let (buffer, length) = (words[1], words[2]);
+		let mut data = Vec::with_capacity(length.min(MAX_TRANSFER_LENGTH) as usize);
+		for i in 0 .. length.min(MAX_TRANSFER_LENGTH) {
+			match mio.read_8(buffer.wrapping_add(i)) {
+				MemReadResult::Ok(value) => data.push(value),
+				_ => return Err(EINVAL)
+			}
+		}
+		let result = match self.file(words[0])? {
+			HostFile::Stdin => return Err(EBADF),
+			HostFile::Stdout => Self::console_write(data.as_slice()),
+			HostFile::Stderr => io::stderr().write_all(data.as_slice()).map_err(os_errno),
+			HostFile::File(file) => file.write_all(data.as_slice()).map_err(os_errno),
+		};
+		result.map(|_| length - data.len() as u32)
+	}
+
+	// returns the number of bytes that weren't read, all of them at the end of the file
+	fn read<Timer: MTimer, MIO: MemIO<Timer>>(&mut self, mio: &mut MIO, parameter: u32) -> Result<u32, i32> {
+		let words = read_words(mio, parameter, 3).ok_or(EINVAL)?;
+		let (buffer, length) = (words[1], words[2]);
+		let mut data = vec![0; length.min(MAX_TRANSFER_LENGTH) as usize];
+		let count = match self.file(words[0])? {
+			HostFile::Stdin => io::stdin().read(data.as_mut_slice()).map_err(os_errno)?,
+			HostFile::Stdout | HostFile::Stderr => return Err(EBADF),
+			HostFile::File(file) => {
+				let mut count = 0;
+				while count < data.len() {
+					match file.read(&mut data[count ..]).map_err(os_errno)? {
+						0 => break,
+						read => count += read
+					}
+				}
+				count
+			}
+		};
+		for (i, value) in data[.. count].iter().enumerate() {
+			match mio.write_8(buffer.wrapping_add(i as u32), *value) {
+				MemWriteResult::Ok => {},
+				_ => return Err(EINVAL)
+			}
+		}
+		Ok(length - count as u32)
+	}
+
+	fn seek<Timer: MTimer, MIO: MemIO<Timer>>(&mut self, mio: &MIO, parameter: u32) -> Result<u32, i32> {
+		let words = read_words(mio, parameter, 2).ok_or(EINVAL)?;
+		match self.file(words[0])? {
+			HostFile::File(file) => file.seek(SeekFrom::Start(words[1] as u64)).map(|_| 0).map_err(os_errno),
+			_ => Err(EINVAL)
+		}
+	}
+}
+
+fn os_errno(error: io::Error) -> i32 {
+	error.raw_os_error().unwrap_or(EINVAL)
+}
+
+fn read_words<Timer: MTimer, MIO: MemIO<Timer>>(mio: &MIO, address: u32, count: u32) -> Option<Vec<u32>> {
+	(0 .. count).map(|i| match mio.read_32(address.wrapping_add(i * 4)) {
+		MemReadResult::Ok(value) => Some(value),
+		_ => None
+	}).collect()
+}
+
+// a string of length bytes, or up to its nul terminator without one
+fn read_string<Timer: MTimer, MIO: MemIO<Timer>>(mio: &MIO, address: u32, length: Option<u32>) -> Option<Vec<u8>> {
+	let limit = length.unwrap_or(MAX_STRING_LENGTH);
+	if limit > MAX_STRING_LENGTH {
+		return None;
+	}
+	let mut string = Vec::new();
+	for i in 0 .. limit {
+		match mio.read_8(address.wrapping_add(i)) {
+			MemReadResult::Ok(0) if length.is_none() => return Some(string),
+			MemReadResult::Ok(value) => string.push(value),
+			_ => return None
+		}
+	}
+	length.map(|_| string)
+}
+
+// SYS_EXIT takes the reason in a1 on rv32 with stock newlib, and a block of the reason and exit
+// code otherwise, as SYS_EXIT_EXTENDED always does
+fn exit_reason<Timer: MTimer, MIO: MemIO<Timer>>(mio: &MIO, operation: u32, parameter: u32) -> Option<(u32, i32)> {
+	if operation == SYS_EXIT && (ADP_STOPPED_FIRST ..= ADP_STOPPED_LAST).contains(&parameter) {
+		return Some((parameter, 0));
+	}
+	read_words(mio, parameter, 2).map(|words| (words[0], words[1] as i32))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::fs;
+
+	// a root holding inner/file, next to an outside directory holding secret
+	struct Sandbox {
+		base: PathBuf,
+		semihosting: Semihosting,
+	}
+
+	impl Sandbox {
+		fn new(name: &str) -> Self {
+			let base = std::env::temp_dir().join(format!("rv_vsys_semihosting_{}_{}", name, std::process::id()));
+			fs::create_dir_all(base.join("root/inner")).unwrap();
+			fs::create_dir_all(base.join("outside")).unwrap();
+			fs::write(base.join("root/inner/file"), b"inside").unwrap();
+			fs::write(base.join("outside/secret"), b"outside").unwrap();
+			let semihosting = Semihosting::new(base.join("root"), Box::new(|_| {})).unwrap();
+			Sandbox {
+				base,
+				semihosting,
+			}
+		}
+
+		fn path(&self, name: &str) -> Option<PathBuf> {
+			self.semihosting.sandboxed_path(name)
+		}
+
+		fn inside(&self, name: &str) -> Option<PathBuf> {
+			Some(self.semihosting.root.join(name))
+		}
+	}
+
+	impl Drop for Sandbox {
+		fn drop(&mut self) {
+			let _ = fs::remove_dir_all(&self.base);
+		}
+	}
+
+	#[test]
+	fn names_resolve_inside_the_root() {
+		let sandbox = Sandbox::new("nested");
+		assert_eq!(sandbox.path("inner/file"), sandbox.inside("inner/file"));
+		assert_eq!(sandbox.path("./inner/./new"), sandbox.inside("inner/new"));
+		assert_eq!(sandbox.path("top"), sandbox.inside("top"));
+		// an absolute name is taken from the root too
+		assert_eq!(sandbox.path("/inner/file"), sandbox.inside("inner/file"));
+		// but the directory still has to exist
+		assert_eq!(sandbox.path("missing/file"), None);
+	}
+
+	#[test]
+	fn names_cant_leave_the_root() {
+		let sandbox = Sandbox::new("escape");
+		assert_eq!(sandbox.path("../outside/secret"), None);
+		assert_eq!(sandbox.path("inner/../../outside/secret"), None);
+		assert_eq!(sandbox.path("inner/.."), None);
+		let secret = sandbox.base.join("outside/secret").canonicalize().unwrap();
+		assert_eq!(sandbox.path(secret.to_str().unwrap()), None);
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn symlinks_cant_lead_out_of_the_root() {
+		use std::os::unix::fs::symlink;
+		let sandbox = Sandbox::new("symlinks");
+		let root = &sandbox.semihosting.root;
+		symlink(sandbox.base.join("outside"), root.join("directory")).unwrap();
+		symlink(sandbox.base.join("outside/secret"), root.join("file")).unwrap();
+		symlink(sandbox.base.join("outside/new"), root.join("dangling")).unwrap();
+		symlink(root.join("inner"), root.join("shortcut")).unwrap();
+		assert_eq!(sandbox.path("directory/secret"), None);
+		assert_eq!(sandbox.path("file"), None);
+		assert_eq!(sandbox.path("dangling"), None);
+		assert_eq!(sandbox.path("shortcut/file"), sandbox.inside("shortcut/file"));
+	}
+}
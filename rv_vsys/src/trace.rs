@@ -0,0 +1,446 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::collections::HashMap;
+use std::sync::Arc;
+use parking_lot::Mutex;
+use byteorder::{ByteOrder, LittleEndian};
+
+// Binary execution trace. The file is a header followed by chunks, each tagged with the hart
+// which recorded it, so harts running on separate threads only contend once per chunk.
+// Within a hart's chunks records are in execution order:
+//
+//   flags: u8
+//   pc: u32        (FLAG_PC, only when pc isn't the fall through of the previous record)
+//   opcode: u32    (expanded, FLAG_COMPRESSED marks a 2 byte instruction)
+//   reg: u8, value: u32   (FLAG_GPR_WRITE)
+//   reg: u8, value: u64   (FLAG_FPR_WRITE, the raw register so boxed singles keep their upper half)
+//   address: u32   (FLAG_MEM)
+//
+// Trap records are FLAG_TRAP followed by cause, epc and tval.
+
+const TRACE_MAGIC: &[u8; 4] = b"RVTR";
+const TRACE_VERSION: u16 = 2;
+const TRACE_HEADER_SIZE: usize = 8;
+const CHUNK_HEADER_SIZE: usize = 8;
+const CHUNK_FLUSH_SIZE: usize = 0x10000;
+// a retired instruction with a pc, a double write and an address. a chunk is flushed once it reaches
+// CHUNK_FLUSH_SIZE, so one record past that is the most a writer puts in one, and the reader takes no more.
+const MAX_RECORD_SIZE: usize = 22;
+const MAX_CHUNK_SIZE: usize = CHUNK_FLUSH_SIZE - 1 + MAX_RECORD_SIZE;
+
+const FLAG_COMPRESSED: u8 = 0x01;
+const FLAG_PC: u8 = 0x02;
+const FLAG_GPR_WRITE: u8 = 0x04;
+const FLAG_FPR_WRITE: u8 = 0x08;
+const FLAG_MEM: u8 = 0x10;
+const FLAG_TRAP: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceRegWrite {
+	Gpr(u32, u32),
+	Fpr(u32, u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceEvent {
+	Retire {
+		hart_id: u32,
+		pc: u32,
+		opcode: u32,
+		length: u32,
+		reg_write: Option<TraceRegWrite>,
+		mem_addr: Option<u32>,
+	},
+	Trap {
+		hart_id: u32,
+		cause: u32,
+		epc: u32,
+		tval: u32,
+	},
+}
+
+#[derive(Clone)]
+pub struct TraceFile {
+	file: Arc<Mutex<File>>,
+}
+
+impl TraceFile {
+	pub fn create(path: &str) -> io::Result<Self> {
+		let mut file = File::create(path)?;
+		let mut header = [0u8; TRACE_HEADER_SIZE];
+		header[0 .. 4].copy_from_slice(TRACE_MAGIC);
+		LittleEndian::write_u16(&mut header[4 .. 6], TRACE_VERSION);
+		file.write_all(&header)?;
+		Ok(TraceFile {
+			file: Arc::new(Mutex::new(file)),
+		})
+	}
+
+	pub fn recorder(&self, hart_id: u32) -> TraceRecorder {
+		TraceRecorder {
+			file: self.file.clone(),
+			hart_id,
+			buffer: Vec::with_capacity(MAX_CHUNK_SIZE),
+			next_pc: None,
+			active: false,
+			pc: 0,
+			opcode: 0,
+			length: 0,
+			reg_write: None,
+			mem_addr: None,
+			failed: false,
+		}
+	}
+}
+
+pub struct TraceRecorder {
+	file: Arc<Mutex<File>>,
+	hart_id: u32,
+	buffer: Vec<u8>,
+	next_pc: Option<u32>,
+	active: bool,
+	pc: u32,
+	opcode: u32,
+	length: u32,
+	reg_write: Option<TraceRegWrite>,
+	mem_addr: Option<u32>,
+	failed: bool,
+}
+
+fn push_u32(buffer: &mut Vec<u8>, value: u32) {
+	let mut bytes = [0u8; 4];
+	LittleEndian::write_u32(&mut bytes, value);
+	buffer.extend_from_slice(&bytes);
+}
+
+fn push_u64(buffer: &mut Vec<u8>, value: u64) {
+	let mut bytes = [0u8; 8];
+	LittleEndian::write_u64(&mut bytes, value);
+	buffer.extend_from_slice(&bytes);
+}
+
+impl TraceRecorder {
+	pub fn begin_instruction(&mut self, pc: u32, opcode: u32, length: u32) {
+		self.active = true;
+		self.pc = pc;
+		self.opcode = opcode;
+		self.length = length;
+		self.reg_write = None;
+		self.mem_addr = None;
+	}
+
+	pub fn record_reg_write(&mut self, reg_write: TraceRegWrite) {
+		if self.active {
+			self.reg_write = Some(reg_write);
+		}
+	}
+
+	pub fn record_mem_access(&mut self, address: u32) {
+		if self.active {
+			self.mem_addr = Some(address);
+		}
+	}
+
+	// instructions which raised an exception didn't retire, the trap record covers them instead
+	pub fn end_instruction(&mut self, retired: bool) {
+		if ! self.active {
+			return;
+		}
+		self.active = false;
+		if ! retired {
+			return;
+		}
+		let mut flags = 0;
+		if self.length == 2 {
+			flags |= FLAG_COMPRESSED;
+		}
+		if self.next_pc != Some(self.pc) {
+			flags |= FLAG_PC;
+		}
+		match self.reg_write {
+			Some(TraceRegWrite::Gpr(..)) => flags |= FLAG_GPR_WRITE,
+			Some(TraceRegWrite::Fpr(..)) => flags |= FLAG_FPR_WRITE,
+			None => {}
+		}
+		if self.mem_addr.is_some() {
+			flags |= FLAG_MEM;
+		}
+		self.buffer.push(flags);
+		if flags & FLAG_PC != 0 {
+			push_u32(&mut self.buffer, self.pc);
+		}
+		push_u32(&mut self.buffer, self.opcode);
+		match self.reg_write {
+			Some(TraceRegWrite::Gpr(reg, value)) => {
+				self.buffer.push(reg as u8);
+				push_u32(&mut self.buffer, value);
+			},
+			Some(TraceRegWrite::Fpr(reg, value)) => {
+				self.buffer.push(reg as u8);
+				push_u64(&mut self.buffer, value);
+			},
+			None => {}
+		}
+		if let Some(address) = self.mem_addr {
+			push_u32(&mut self.buffer, address);
+		}
+		self.next_pc = Some(self.pc.wrapping_add(self.length));
+		self.flush_if_full();
+	}
+
+	pub fn record_trap(&mut self, cause: u32, epc: u32, tval: u32) {
+		self.buffer.push(FLAG_TRAP);
+		push_u32(&mut self.buffer, cause);
+		push_u32(&mut self.buffer, epc);
+		push_u32(&mut self.buffer, tval);
+		self.next_pc = None;
+		self.flush_if_full();
+	}
+
+	fn flush_if_full(&mut self) {
+		if self.buffer.len() >= CHUNK_FLUSH_SIZE {
+			self.flush();
+		}
+	}
+
+	pub fn flush(&mut self) {
+		if self.buffer.is_empty() || self.failed {
+			self.buffer.clear();
+			return;
+		}
+		let mut chunk_header = [0u8; CHUNK_HEADER_SIZE];
+		LittleEndian::write_u32(&mut chunk_header[0 .. 4], self.hart_id);
+		LittleEndian::write_u32(&mut chunk_header[4 .. 8], self.buffer.len() as u32);
+		let result = {
+			let mut file = self.file.lock();
+			file.write_all(&chunk_header).and_then(|_| file.write_all(&self.buffer))
+		};
+		if let Err(error) = result {
+			eprintln!("hart {}: stopped writing trace: {}", self.hart_id, error);
+			self.failed = true;
+		}
+		self.buffer.clear();
+	}
+}
+
+impl Drop for TraceRecorder {
+	fn drop(&mut self) {
+		self.flush();
+	}
+}
+
+pub struct TraceReader<R: Read> {
+	reader: R,
+	chunk: Vec<u8>,
+	chunk_offset: usize,
+	chunk_hart_id: u32,
+	next_pc: HashMap<u32, Option<u32>>,
+}
+
+fn invalid_data(message: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+impl <R: Read> TraceReader<R> {
+	pub fn new(mut reader: R) -> io::Result<Self> {
+		let mut header = [0u8; TRACE_HEADER_SIZE];
+		reader.read_exact(&mut header)?;
+		if &header[0 .. 4] != TRACE_MAGIC {
+			return Err(invalid_data("not a trace file"));
+		}
+		let version = LittleEndian::read_u16(&header[4 .. 6]);
+		if version != TRACE_VERSION {
+			return Err(invalid_data(format!("unsupported trace version {}", version).as_str()));
+		}
+		Ok(TraceReader {
+			reader,
+			chunk: Vec::new(),
+			chunk_offset: 0,
+			chunk_hart_id: 0,
+			next_pc: HashMap::new(),
+		})
+	}
+
+	// reads the next chunk header, returning false at a clean end of file
+	fn next_chunk(&mut self) -> io::Result<bool> {
+		let mut chunk_header = [0u8; CHUNK_HEADER_SIZE];
+		let mut read = 0;
+		while read < CHUNK_HEADER_SIZE {
+			match self.reader.read(&mut chunk_header[read ..])? {
+				0 if read == 0 => return Ok(false),
+				0 => return Err(invalid_data("truncated chunk header")),
+				count => read += count,
+			}
+		}
+		self.chunk_hart_id = LittleEndian::read_u32(&chunk_header[0 .. 4]);
+		let length = LittleEndian::read_u32(&chunk_header[4 .. 8]) as usize;
+		if length > MAX_CHUNK_SIZE {
+			return Err(invalid_data("oversized chunk"));
+		}
+		self.chunk.resize(length, 0);
+		self.reader.read_exact(&mut self.chunk)?;
+		self.chunk_offset = 0;
+		Ok(true)
+	}
+
+	fn take_u8(&mut self) -> io::Result<u8> {
+		let value = *self.chunk.get(self.chunk_offset).ok_or_else(|| invalid_data("truncated record"))?;
+		self.chunk_offset += 1;
+		Ok(value)
+	}
+
+	fn take_u32(&mut self) -> io::Result<u32> {
+		if self.chunk_offset + 4 > self.chunk.len() {
+			return Err(invalid_data("truncated record"));
+		}
+		let value = LittleEndian::read_u32(&self.chunk[self.chunk_offset ..]);
+		self.chunk_offset += 4;
+		Ok(value)
+	}
+
+	fn take_u64(&mut self) -> io::Result<u64> {
+		if self.chunk_offset + 8 > self.chunk.len() {
+			return Err(invalid_data("truncated record"));
+		}
+		let value = LittleEndian::read_u64(&self.chunk[self.chunk_offset ..]);
+		self.chunk_offset += 8;
+		Ok(value)
+	}
+
+	pub fn next_event(&mut self) -> io::Result<Option<TraceEvent>> {
+		while self.chunk_offset >= self.chunk.len() {
+			if ! self.next_chunk()? {
+				return Ok(None);
+			}
+		}
+		let hart_id = self.chunk_hart_id;
+		let flags = self.take_u8()?;
+		if flags & FLAG_TRAP != 0 {
+			let cause = self.take_u32()?;
+			let epc = self.take_u32()?;
+			let tval = self.take_u32()?;
+			self.next_pc.insert(hart_id, None);
+			return Ok(Some(TraceEvent::Trap {
+				hart_id,
+				cause,
+				epc,
+				tval,
+			}));
+		}
+		let pc = if flags & FLAG_PC != 0 {
+			self.take_u32()?
+		} else {
+			match self.next_pc.get(&hart_id) {
+				Some(Some(pc)) => *pc,
+				_ => return Err(invalid_data("record without a pc")),
+			}
+		};
+		let opcode = self.take_u32()?;
+		let reg_write = if flags & (FLAG_GPR_WRITE | FLAG_FPR_WRITE) != 0 {
+			let reg = self.take_u8()? as u32;
+			Some(if flags & FLAG_GPR_WRITE != 0 {
+				TraceRegWrite::Gpr(reg, self.take_u32()?)
+			} else {
+				TraceRegWrite::Fpr(reg, self.take_u64()?)
+			})
+		} else {
+			None
+		};
+		let mem_addr = if flags & FLAG_MEM != 0 {
+			Some(self.take_u32()?)
+		} else {
+			None
+		};
+		let length = if flags & FLAG_COMPRESSED != 0 { 2 } else { 4 };
+		self.next_pc.insert(hart_id, Some(pc.wrapping_add(length)));
+		Ok(Some(TraceEvent::Retire {
+			hart_id,
+			pc,
+			opcode,
+			length,
+			reg_write,
+			mem_addr,
+		}))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn events_read_back_as_recorded() {
+		let path = std::env::temp_dir().join(format!("rv_vsys_trace_test_{}.bin", std::process::id()));
+		let path = path.to_str().unwrap();
+		let trace = TraceFile::create(path).unwrap();
+		let mut hart0 = trace.recorder(0);
+		let mut hart1 = trace.recorder(1);
+		hart0.begin_instruction(0x100, 0x0050_0513, 4);
+		hart0.record_reg_write(TraceRegWrite::Gpr(10, 5));
+		hart0.end_instruction(true);
+		// falls through, so the pc isn't stored
+		hart0.begin_instruction(0x104, 0x0005_a583, 2);
+		hart0.record_mem_access(0x8000);
+		hart0.end_instruction(true);
+		// faulted, so only the trap is recorded
+		hart0.begin_instruction(0x106, 0x0000_0073, 4);
+		hart0.end_instruction(false);
+		hart0.record_trap(11, 0x106, 0);
+		hart1.begin_instruction(0x200, 0x0000_7053, 4);
+		hart1.record_reg_write(TraceRegWrite::Fpr(0, 0xFFFF_FFFF_3F80_0000));
+		hart1.end_instruction(true);
+		hart0.begin_instruction(0x40, 0x0000_0013, 4);
+		hart0.end_instruction(true);
+		drop(hart0);
+		drop(hart1);
+		let mut reader = TraceReader::new(File::open(path).unwrap()).unwrap();
+		let mut events = Vec::new();
+		while let Some(event) = reader.next_event().unwrap() {
+			events.push(event);
+		}
+		std::fs::remove_file(path).unwrap();
+		assert_eq!(events, vec![
+			TraceEvent::Retire { hart_id: 0, pc: 0x100, opcode: 0x0050_0513, length: 4, reg_write: Some(TraceRegWrite::Gpr(10, 5)), mem_addr: None },
+			TraceEvent::Retire { hart_id: 0, pc: 0x104, opcode: 0x0005_a583, length: 2, reg_write: None, mem_addr: Some(0x8000) },
+			TraceEvent::Trap { hart_id: 0, cause: 11, epc: 0x106, tval: 0 },
+			TraceEvent::Retire { hart_id: 0, pc: 0x40, opcode: 0x0000_0013, length: 4, reg_write: None, mem_addr: None },
+			TraceEvent::Retire { hart_id: 1, pc: 0x200, opcode: 0x0000_7053, length: 4, reg_write: Some(TraceRegWrite::Fpr(0, 0xFFFF_FFFF_3F80_0000)), mem_addr: None },
+		]);
+	}
+
+	#[test]
+	fn trap_storms_stay_within_the_chunk_size() {
+		let path = std::env::temp_dir().join(format!("rv_vsys_trace_traps_{}.bin", std::process::id()));
+		let path = path.to_str().unwrap();
+		let trace = TraceFile::create(path).unwrap();
+		let mut hart = trace.recorder(0);
+		// a handler which faults on its own fetch traps over and over without retiring anything
+		let traps = 2 * CHUNK_FLUSH_SIZE / 13;
+		for _ in 0 .. traps {
+			hart.record_trap(1, 0x100, 0x100);
+		}
+		drop(hart);
+		let mut reader = TraceReader::new(File::open(path).unwrap()).unwrap();
+		let mut count = 0;
+		while let Some(event) = reader.next_event().unwrap() {
+			assert_eq!(event, TraceEvent::Trap { hart_id: 0, cause: 1, epc: 0x100, tval: 0x100 });
+			count += 1;
+		}
+		std::fs::remove_file(path).unwrap();
+		assert_eq!(count, traps);
+	}
+
+	#[test]
+	fn oversized_chunks_are_rejected() {
+		let mut file = b"RVTR\x02\x00\x00\x00\x00\x00\x00\x00".to_vec();
+		file.extend_from_slice(&(MAX_CHUNK_SIZE as u32 + 1).to_le_bytes());
+		let error = TraceReader::new(&file[..]).unwrap().next_event().unwrap_err();
+		assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+	}
+
+	#[test]
+	fn other_files_are_rejected() {
+		assert!(TraceReader::new(&b"RVCS\x02\x00\x00\x00"[..]).is_err());
+		assert!(TraceReader::new(&b"RVTR\x01\x00\x00\x00"[..]).is_err());
+	}
+}
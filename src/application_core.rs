@@ -1,60 +1,113 @@
-use std::{env::args, time::Duration};
+use std::{sync::Arc, thread, time::Duration};
 use std::fs::File;
 use std::io::Read;
 
-use crate::{cart_loader::{CartLoader, CartLoaderCpuBarrier}, cpu1_controller::Cpu1Controller, mtimer::MTimerPeripheral, gpu::GpuResetHandle};
+use crate::{cart_loader::{CartLoader, CartLoaderCpuBarrier}, fm_mio::{DEVICE_RANGE_SIZE, HART_CONTROLLER_BASE}, hart_controller::HartController, mtimer::MTimerPeripheral, gpu::GpuResetHandle, launch_options::{LaunchOptions, GdbListenAddress}};
 
-use rv_vsys::{Cpu, CpuWakeupHandle};
+use rv_vsys::{load_elf, Cpu, CpuSpeedHandle, CpuWakeupHandle, GdbServer, MachineClock, Semihosting, TraceFile};
 use crate::fm_mio::FmMemoryIO;
 use crate::fm_interrupt_bus::FmInterruptBus;
-use crate::elf_loader;
 
-pub const CPU_INSTRUCTIONS_PER_PERIOD: u32 = 50000;
 pub const CPU_PERIOD_MICROSECONDS: u64 = 2500;
 
+const SPEED_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+fn report_speed(speed_handles: Vec<CpuSpeedHandle>, clock: MachineClock) {
+	loop {
+		thread::sleep(SPEED_REPORT_INTERVAL);
+		let harts: Vec<_> = speed_handles.iter().enumerate().map(|(hart_id, speed)| format!("hart {} {:.2}", hart_id, speed.effective_mips())).collect();
+		println!("speed: {} MIPS (clock {:.2} MHz)", harts.join(", "), clock.instructions_per_second() as f64 / 1_000_000.0);
+	}
+}
+
 pub struct ApplicationCore {
-	cpu0: Cpu<MTimerPeripheral, FmMemoryIO, FmInterruptBus>,
-	cpu1: Cpu<MTimerPeripheral, FmMemoryIO, FmInterruptBus>,
+	cpus: Vec<Cpu<MTimerPeripheral, FmMemoryIO, FmInterruptBus>>,
 	cart_loader_barrier: CartLoaderCpuBarrier,
+	options: LaunchOptions,
 }
 
 impl ApplicationCore {
-	pub fn new(mio: FmMemoryIO, interrupt_bus: FmInterruptBus, cpu0_wakeup_handle: CpuWakeupHandle, cpu1_wakeup_handle: CpuWakeupHandle, gpu_reset_handle: GpuResetHandle) -> Self {
-		let cpu0 = Cpu::new(mio.clone(), interrupt_bus.clone(), cpu0_wakeup_handle, 0);
-		let cpu1 = Cpu::new(mio.clone(), interrupt_bus, cpu1_wakeup_handle, 1);
-		let cart_loader_barrier = CartLoader::start(mio, &cpu0, &cpu1, gpu_reset_handle);
+	pub fn new(mio: FmMemoryIO, interrupt_bus: FmInterruptBus, wakeup_handles: Vec<CpuWakeupHandle>, gpu_reset_handle: GpuResetHandle, clock: MachineClock, options: LaunchOptions) -> Self {
+		let mut cpus: Vec<_> = wakeup_handles.into_iter().enumerate().map(|(hart_id, wakeup_handle)| {
+			let mut cpu = Cpu::new(mio.clone(), interrupt_bus.clone(), wakeup_handle, hart_id as u32);
+			cpu.set_clock(clock.clone());
+			cpu.set_misaligned_emulation(options.emulate_misaligned);
+			if let Some(history) = options.replay_history {
+				cpu.enable_replay(history);
+			}
+			cpu
+		}).collect();
+		if let Some(gdb_address) = &options.gdb {
+			let gdb_server = GdbServer::new(cpus.len() as u32);
+			for (hart_id, cpu) in cpus.iter_mut().enumerate() {
+				cpu.set_debug_adapter(Some(Box::new(gdb_server.hart_adapter(hart_id as u32))));
+			}
+			let listen_result = match gdb_address {
+				GdbListenAddress::Tcp(address) => gdb_server.listen_tcp(address.as_str()),
+				#[cfg(unix)]
+				GdbListenAddress::Unix(path) => gdb_server.listen_unix(path.as_str()),
+				#[cfg(not(unix))]
+				GdbListenAddress::Unix(_) => unreachable!(),
+			};
+			match listen_result {
+				Ok(_) => println!("gdb server listening on {:?}", gdb_address),
+				Err(error) => panic!("failed to start gdb server: {}", error),
+			}
+		}
+		if let Some(trace_path) = &options.trace {
+			match TraceFile::create(trace_path.as_str()) {
+				Ok(trace_file) => {
+					for (hart_id, cpu) in cpus.iter_mut().enumerate() {
+						cpu.set_trace_recorder(Some(trace_file.recorder(hart_id as u32)));
+					}
+				},
+				Err(error) => panic!("failed to create trace file {}: {}", trace_path, error),
+			}
+		}
+		if let Some(root) = &options.semihosting {
+			for cpu in cpus.iter_mut() {
+				match Semihosting::new(root.as_str(), Box::new(|code| std::process::exit(code))) {
+					Ok(semihosting) => cpu.set_semihosting(Some(semihosting)),
+					Err(error) => panic!("failed to open semihosting directory {}: {}", root, error),
+				}
+			}
+		}
+		if options.report_speed {
+			let speed_handles = cpus.iter().map(|cpu| cpu.get_speed_handle()).collect();
+			let clock = clock.clone();
+			thread::spawn(move || report_speed(speed_handles, clock));
+		}
+		let cart_loader_barrier = CartLoader::start(mio, &cpus, clock, gpu_reset_handle);
 		ApplicationCore {
-			cpu0,
-			cpu1,
-			cart_loader_barrier
+			cpus,
+			cart_loader_barrier,
+			options,
 		}
 	}
 	
 	pub fn run(mut self) {
-		let args: Vec<String> = args().collect();
-		if args.len() < 1 {
-			panic!("no boot rom specified!");
-		}
 		let mut start_pc = {
-			let mut file = File::open(&args[1]).unwrap();
+			let mut file = File::open(&self.options.boot_rom).unwrap();
 			let mut data = Vec::new();
 			file.read_to_end(&mut data).unwrap();
 			let data_box = data.into_boxed_slice();
-			elf_loader::load_elf(data_box.as_ref(), &mut self.cpu0.mio, 0x0000_0000).unwrap()
+			load_elf(data_box.as_ref(), &mut self.cpus[0].mio, 0x0000_0000).unwrap()
 		};
 		
 		let ApplicationCore {
-			mut cpu0,
-			cpu1,
+			mut cpus,
 			..
 		} = self;
 		
-		let cpu1_controller = Cpu1Controller::new(cpu1);
-		cpu0.mio.set_cpu1_controller(cpu1_controller);
+		let secondary_cpus = cpus.split_off(1);
+		let mut cpu0 = cpus.pop().unwrap();
+		let hart_controller = HartController::new(secondary_cpus);
+		cpu0.mio.peripherals().map(HART_CONTROLLER_BASE, DEVICE_RANGE_SIZE, Arc::new(hart_controller)).unwrap();
 		
 		loop {
+			cpu0.mio.peripherals().reset();
 			cpu0.reset(start_pc);
-			cpu0.run_loop(CPU_INSTRUCTIONS_PER_PERIOD, Duration::from_micros(CPU_PERIOD_MICROSECONDS));
+			cpu0.run_loop(Duration::from_micros(CPU_PERIOD_MICROSECONDS));
 			start_pc = self.cart_loader_barrier.wait_barrier();
 		}
 	}
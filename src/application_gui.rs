@@ -6,10 +6,10 @@ use winit::{self, dpi::PhysicalSize, event::{Event, WindowEvent, VirtualKeyCode}
 		WindowBuilder
 	}};
 	
-use crate::{application_core::ApplicationCore, fm_interrupt_bus::FmInterruptBus, fm_mio::FmMemoryIO, gpu, input::{InputEventSink, InputPeripheral}, sound_out::SoundOutPeripheral};
-use rv_vsys::CpuWakeupHandle;
+use crate::{application_core::ApplicationCore, launch_options::LaunchOptions, fm_interrupt_bus::{FmInterruptBus, sound_interrupt_hart}, fm_mio::FmMemoryIO, gpu, input::{InputEventSink, InputPeripheral}, peripheral::PeripheralBus, sound_out::SoundOutPeripheral};
+use rv_vsys::{CpuWakeupHandle, MachineClock};
 
-use std::{sync::mpsc, sync::mpsc::{TryRecvError, Sender, Receiver}, thread};
+use std::{sync::mpsc, sync::mpsc::{TryRecvError, Sender, Receiver}, thread, time::Duration};
 
 #[allow(dead_code)]
 pub struct ApplicationGUI {
@@ -30,7 +30,7 @@ enum ApplicationGuiEventMessage {
 }
 
 impl ApplicationGUI {
-	pub fn run(screen_scale: u32) {
+	pub fn run(screen_scale: u32, options: LaunchOptions) {
 		let (_gui_outbox, logic_inbox) = mpsc::channel();
 		let (logic_outbox, gui_inbox) = mpsc::channel();
 		let event_loop = EventLoop::new();
@@ -40,14 +40,23 @@ impl ApplicationGUI {
 			.with_resizable(false)
 			.with_visible(true)
 			.build(&event_loop).unwrap();
-		let cpu0_wakeup = CpuWakeupHandle::new();
-		let cpu1_wakeup = CpuWakeupHandle::new();
-		let mut interrupt_bus = FmInterruptBus::new();
+		let wakeup_handles: Vec<_> = (0 .. options.harts).map(|_| CpuWakeupHandle::new()).collect();
+		let sound_wakeup = wakeup_handles[sound_interrupt_hart(options.harts) as usize].clone();
+		// devices beyond the built in ones can be mapped on this before the harts start
+		let peripherals = PeripheralBus::new();
+		let mut interrupt_bus = FmInterruptBus::new(options.harts, peripherals.clone(), options.standard_interrupts);
 		let logic_interrupt_bus = interrupt_bus.clone();
-		let mut mio = FmMemoryIO::new(interrupt_bus.clone());
+		let clock = MachineClock::new(options.clock, options.instructions_per_second);
+		let mut mio = FmMemoryIO::new(peripherals, interrupt_bus.clone(), clock.clone());
 		let mut input_sink = InputPeripheral::new(&mut mio);
 		let logic_mio = mio.clone();
-		let (gpu, mut gpu_event_sink, gpu_reset_handle) = futures::executor::block_on(gpu::Gpu::new(&window, &mut mio, &mut interrupt_bus, cpu0_wakeup.clone(), screen_scale));
+		let (gpu, mut gpu_event_sink, gpu_reset_handle) = futures::executor::block_on(gpu::Gpu::new(&window, &mut mio, &mut interrupt_bus, wakeup_handles[0].clone(), screen_scale));
+		if clock.is_virtual() {
+			// vsync follows virtual time at 60hz rather than the host's redraws
+			if let Some(mut vsync) = gpu_event_sink.take_vsync() {
+				clock.add_virtual_timer(Duration::from_nanos(1_000_000_000 / 60), Box::new(move || vsync.signal()));
+			}
+		}
 		let _application_gui = ApplicationGUI {
 			inbox: logic_outbox,
 			outbox: logic_inbox,
@@ -56,8 +65,8 @@ impl ApplicationGUI {
 		gpu.run();
 		let _logic_thread = thread::spawn(move || {
 			// start sound device from non-main thread to support winit/windows
-			SoundOutPeripheral::new(cpu1_wakeup.clone(), &mut interrupt_bus, &mut mio, None, None).unwrap();
-			let app_core = ApplicationCore::new(logic_mio, logic_interrupt_bus, cpu0_wakeup, cpu1_wakeup, gpu_reset_handle);
+			SoundOutPeripheral::new(sound_wakeup, &mut interrupt_bus, &mut mio, &clock, None, None).unwrap();
+			let app_core = ApplicationCore::new(logic_mio, logic_interrupt_bus, wakeup_handles, gpu_reset_handle, clock, options);
 			app_core.run();
 		});
 		event_loop.run(move |event, _, control_flow| {
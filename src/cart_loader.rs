@@ -2,10 +2,10 @@ use std::{fs::File, os::unix::prelude::FileExt, path::{PathBuf}, sync::{Arc, ato
 use image::EncodableLayout;
 use parking_lot::{Condvar, Mutex};
 use regex::Regex;
-use rv_vsys::{Cpu, CpuKillHandle, MemIO, MemReadResult, MemWriteResult};
+use rv_vsys::{load_elf, Cpu, CpuKillHandle, MachineClock, MemIO, MemReadResult, MemWriteResult};
 use std::sync::mpsc;
 
-use crate::{elf_loader, fm_interrupt_bus::FmInterruptBus, fm_mio::FmMemoryIO, gpu::GpuResetHandle, mtimer::MTimerPeripheral};
+use crate::{fm_interrupt_bus::FmInterruptBus, fm_mio::{FmMemoryIO, CART_LOADER_BASE, DEVICE_RANGE_SIZE}, gpu::GpuResetHandle, launch_options::mhz_to_instructions_per_second, mtimer::MTimerPeripheral, peripheral::Peripheral};
 
 #[derive(Debug, Clone)]
 enum CartData {
@@ -27,6 +27,8 @@ struct Cart {
 	pub developer_url: String,
 	pub source: String,
 	pub icon: Option<PathBuf>,
+	// the machine's clock speed while the cart runs, if it asks for one
+	pub instructions_per_second: Option<u64>,
 }
 
 #[derive(Clone, Debug)]
@@ -86,8 +88,9 @@ pub struct CartLoader {
 	mio: FmMemoryIO,
 	wait_lock: Arc<Mutex<CartLoaderWaitState>>,
 	wait_cond: Arc<Condvar>,
-	cpu0_kill: CpuKillHandle,
-	cpu1_kill: CpuKillHandle,
+	cpu_kill_handles: Vec<CpuKillHandle>,
+	clock: MachineClock,
+	default_instructions_per_second: u64,
 	command_channel: mpsc::Receiver<CartLoaderCmd>,
 	carts: Vec<Cart>,
 	cart_count: Arc<AtomicU32>,
@@ -121,7 +124,7 @@ fn get_json_string(value: Option<&json::JsonValue>) -> Option<String> {
 }
 
 impl CartLoader {
-	pub fn start(mut mio: FmMemoryIO, cpu0: &Cpu<MTimerPeripheral, FmMemoryIO, FmInterruptBus>, cpu1: &Cpu<MTimerPeripheral, FmMemoryIO, FmInterruptBus>, gpu_reset_handle: GpuResetHandle) -> CartLoaderCpuBarrier {
+	pub fn start(mio: FmMemoryIO, cpus: &[Cpu<MTimerPeripheral, FmMemoryIO, FmInterruptBus>], clock: MachineClock, gpu_reset_handle: GpuResetHandle) -> CartLoaderCpuBarrier {
 		let (cmd_tx, cmd_rx) = mpsc::channel();
 		let cart_count = Arc::new(AtomicU32::new(0));
 		let peripheral = CartLoaderPeripheral {
@@ -134,7 +137,7 @@ impl CartLoader {
 			param4: Arc::new(AtomicU32::new(0)),
 			param5: Arc::new(AtomicU32::new(0)),
 		};
-		mio.set_cart_loader(peripheral);
+		mio.peripherals().map(CART_LOADER_BASE, DEVICE_RANGE_SIZE, Arc::new(peripheral)).unwrap();
 		let mut cart_dir = std::env::current_dir().unwrap();
 		cart_dir.push("test");
 		cart_dir.push("cart_test");
@@ -150,8 +153,9 @@ impl CartLoader {
 				start_pc: 0
 			})),
 			wait_cond: Arc::new(Condvar::new()),
-			cpu0_kill: cpu0.get_kill_handle(),
-			cpu1_kill: cpu1.get_kill_handle(),
+			cpu_kill_handles: cpus.iter().map(|cpu| cpu.get_kill_handle()).collect(),
+			default_instructions_per_second: clock.instructions_per_second(),
+			clock,
 			command_channel: cmd_rx,
 			carts: Vec::new(),
 			cart_count,
@@ -196,11 +200,14 @@ impl CartLoader {
 			{
 				let mut wait_gaurd = self.wait_lock.lock();
 				wait_gaurd.wait = true;
-				self.cpu1_kill.kill();
-				self.cpu0_kill.kill();
+				// the secondary harts go first, hart 0 is the one waiting on the barrier
+				for kill_handle in self.cpu_kill_handles.iter_mut().rev() {
+					kill_handle.kill();
+				}
 			}
+			self.clock.set_instructions_per_second(cart.instructions_per_second.unwrap_or(self.default_instructions_per_second));
 			self.gpu_reset_handle.reset_gpu().wait();
-			let start_pc = elf_loader::load_elf(elf_bytes.as_bytes(), &mut self.mio, 0x0000_0000).unwrap();
+			let start_pc = load_elf(elf_bytes.as_bytes(), &mut self.mio, 0x0000_0000).unwrap();
 			{
 				let mut wait_gaurd = self.wait_lock.lock();
 				wait_gaurd.start_pc = start_pc;
@@ -375,6 +382,16 @@ impl CartLoader {
 								},
 								_ => CartData::None
 							};
+							let instructions_per_second = match info_fields.get("cpu_mhz") {
+								Some(cpu_mhz) => {
+									let instructions_per_second = cpu_mhz.as_f64().and_then(mhz_to_instructions_per_second);
+									if instructions_per_second.is_none() {
+										println!("CartLoader warning: Cart at {} has invalid cpu_mhz: {}", cart_path_str, cpu_mhz);
+									}
+									instructions_per_second
+								},
+								None => None
+							};
 							let binary_path_string = get_json_string(info_fields.get("binary"));
 							if let Some(binary_path) = binary_path_string {
 								let binary = PathBuf::from(binary_path);
@@ -387,7 +404,8 @@ impl CartLoader {
 									developer,
 									developer_url,
 									source,
-									icon
+									icon,
+									instructions_per_second
 								};
 								carts.push(new_cart);
 							} else {
@@ -942,3 +960,13 @@ impl CartLoaderPeripheral {
 
 unsafe impl Send for CartLoaderPeripheral {}
 unsafe impl Sync for CartLoaderPeripheral {}
+
+impl Peripheral for CartLoaderPeripheral {
+	fn read_32(&self, _mio: &FmMemoryIO, offset: u32) -> MemReadResult<u32> {
+		self.read_32(offset)
+	}
+	
+	fn write_32(&self, mio: &mut FmMemoryIO, offset: u32, value: u32) -> MemWriteResult {
+		self.write_32(mio, offset, value)
+	}
+}
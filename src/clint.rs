@@ -0,0 +1,110 @@
+use std::sync::Arc;
+use rv_vsys::{MemReadResult, MemWriteResult};
+
+use crate::{fm_interrupt_bus::FmInterruptBus, fm_mio::FmMemoryIO, mtimer::MTimerPeripheral, peripheral::Peripheral};
+
+// A core local interruptor with the SiFive register layout. msip is the same ipi flag the interrupt
+// bus has for each hart, raised as a machine software interrupt, and mtimecmp and mtime are the
+// harts' own mtimers. mtime counts milliseconds like the time csr, and writing it sets every hart's.
+
+pub const CLINT_RANGE_SIZE: u32 = 0x1_0000;
+
+// one word per hart
+const OFFSET_MSIP_BASE: u32 = 0x0000;
+// two words per hart
+const OFFSET_MTIMECMP_BASE: u32 = 0x4000;
+const OFFSET_MTIME_LOW: u32 = 0xBFF8;
+const OFFSET_MTIME_HIGH: u32 = 0xBFFC;
+
+#[derive(Clone)]
+pub struct Clint {
+	interrupt_bus: FmInterruptBus,
+	mtimers: Arc<[Arc<MTimerPeripheral>]>,
+}
+
+impl Clint {
+	pub fn new(interrupt_bus: FmInterruptBus, mtimers: Arc<[Arc<MTimerPeripheral>]>) -> Self {
+		Clint {
+			interrupt_bus,
+			mtimers,
+		}
+	}
+
+	// the hart an mtimecmp word belongs to, and whether it's the high half
+	fn mtimecmp_register(&self, offset: u32) -> Option<(&MTimerPeripheral, bool)> {
+		let relative = offset.checked_sub(OFFSET_MTIMECMP_BASE)?;
+		let mtimer = self.mtimers.get((relative / 8) as usize)?;
+		Some((mtimer, relative % 8 == 4))
+	}
+
+	fn write_mtime(&self, mtime: u64) {
+		for mtimer in self.mtimers.iter() {
+			mtimer.set_mtime(mtime);
+		}
+	}
+
+	pub fn read_32(&self, offset: u32) -> MemReadResult<u32> {
+		if offset & 3 != 0 {
+			return MemReadResult::ErrAlignment;
+		}
+		match offset {
+			OFFSET_MTIME_LOW => MemReadResult::Ok(self.mtimers[0].mtime() as u32),
+			OFFSET_MTIME_HIGH => MemReadResult::Ok((self.mtimers[0].mtime() >> 32) as u32),
+			_ if offset < OFFSET_MTIMECMP_BASE => match self.interrupt_bus.ipi_pending((offset - OFFSET_MSIP_BASE) / 4) {
+				Some(pending) => MemReadResult::Ok(if pending { 1 } else { 0 }),
+				None => MemReadResult::ErrUnmapped
+			},
+			_ => match self.mtimecmp_register(offset) {
+				Some((mtimer, false)) => MemReadResult::Ok(mtimer.compare() as u32),
+				Some((mtimer, true)) => MemReadResult::Ok((mtimer.compare() >> 32) as u32),
+				None => MemReadResult::ErrUnmapped
+			}
+		}
+	}
+
+	pub fn write_32(&self, offset: u32, value: u32) -> MemWriteResult {
+		if offset & 3 != 0 {
+			return MemWriteResult::ErrAlignment;
+		}
+		match offset {
+			OFFSET_MTIME_LOW => {
+				let mtime = self.mtimers[0].mtime();
+				self.write_mtime((mtime & 0xFFFF_FFFF_0000_0000) | value as u64);
+				MemWriteResult::Ok
+			},
+			OFFSET_MTIME_HIGH => {
+				let mtime = self.mtimers[0].mtime();
+				self.write_mtime((mtime & 0x0000_0000_FFFF_FFFF) | (value as u64) << 32);
+				MemWriteResult::Ok
+			},
+			_ if offset < OFFSET_MTIMECMP_BASE => {
+				if self.interrupt_bus.set_ipi((offset - OFFSET_MSIP_BASE) / 4, value & 1 != 0) {
+					MemWriteResult::Ok
+				} else {
+					MemWriteResult::ErrUnmapped
+				}
+			},
+			_ => match self.mtimecmp_register(offset) {
+				Some((mtimer, false)) => {
+					mtimer.set_compare((mtimer.compare() & 0xFFFF_FFFF_0000_0000) | value as u64);
+					MemWriteResult::Ok
+				},
+				Some((mtimer, true)) => {
+					mtimer.set_compare((mtimer.compare() & 0x0000_0000_FFFF_FFFF) | (value as u64) << 32);
+					MemWriteResult::Ok
+				},
+				None => MemWriteResult::ErrUnmapped
+			}
+		}
+	}
+}
+
+impl Peripheral for Clint {
+	fn read_32(&self, _mio: &FmMemoryIO, offset: u32) -> MemReadResult<u32> {
+		self.read_32(offset)
+	}
+
+	fn write_32(&self, _mio: &mut FmMemoryIO, offset: u32, value: u32) -> MemWriteResult {
+		self.write_32(offset, value)
+	}
+}
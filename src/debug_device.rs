@@ -11,6 +11,8 @@ regs:
 use rv_vsys::{MemReadResult, MemWriteResult};
 use std::sync::{Arc, Mutex};
 
+use crate::{fm_mio::FmMemoryIO, peripheral::Peripheral};
+
 #[derive(Debug)]
 struct DebugDeviceData {
 	message_addr: u32,
@@ -42,7 +44,7 @@ impl DebugDevice {
 		}
 	}
 	
-	fn print_debug_message(&mut self, ram: &[u8]) -> bool {
+	fn print_debug_message(&self, ram: &[u8]) -> bool {
 		let mut data = self.data.lock().unwrap();
 		if data.message_len > 0x1000 {
 			if cfg!(rvfm_debug_device_debug) { println!("DEBUG DEVICE ERROR: message too long: {:#010x}", data.message_len); }
@@ -70,22 +72,22 @@ impl DebugDevice {
 		}
 	}
 	
-	fn print_debug_u32(&mut self) {
+	fn print_debug_u32(&self) {
 		let data = self.data.lock().unwrap();
 		println!("DEBUG: {}", data.message_addr);
 	}
 	
-	fn print_debug_u32_hex(&mut self) {
+	fn print_debug_u32_hex(&self) {
 		let data = self.data.lock().unwrap();
 		println!("DEBUG: {:#010x}", data.message_addr);
 	}
 	
-	fn print_debug_f32(&mut self) {
+	fn print_debug_f32(&self) {
 		let data = self.data.lock().unwrap();
 		println!("DEBUG: {}", f32::from_bits(data.message_addr));
 	}
 	// feb 19th 1:00 pm
-	pub fn write_32(&mut self, offset: u32, value: u32, ram: &[u8]) -> MemWriteResult{
+	pub fn write_32(&self, offset: u32, value: u32, ram: &[u8]) -> MemWriteResult{
 		if cfg!(rvfm_debug_device_debug) { println!("Debug Device Write: offset: {:#06x}, value: {:#010x}", offset, value); }
 		if offset > 0x000C {
 			MemWriteResult::ErrUnmapped
@@ -167,4 +169,14 @@ impl DebugDevice {
 			}
 		}
 	}
+}
+
+impl Peripheral for DebugDevice {
+	fn read_32(&self, _mio: &FmMemoryIO, offset: u32) -> MemReadResult<u32> {
+		self.read_32(offset)
+	}
+	
+	fn write_32(&self, mio: &mut FmMemoryIO, offset: u32, value: u32) -> MemWriteResult {
+		self.write_32(offset, value, mio.ram())
+	}
 }
\ No newline at end of file
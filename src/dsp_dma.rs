@@ -3,7 +3,7 @@ use std::{cell::RefCell, cmp::min, usize};
 
 use parking_lot::ReentrantMutex;
 use rv_vsys::{MemIO, MemReadResult, MemWriteResult};
-use crate::{fm_mio::FmMemoryIO};
+use crate::{fm_mio::FmMemoryIO, peripheral::Peripheral};
 
 #[derive(Debug, Clone, Copy)]
 struct DspDmaMemIOParams {
@@ -1580,3 +1580,13 @@ impl DspDmaDeviceInterface {
 		result
 	}
 }
+
+impl Peripheral for DspDmaDeviceInterface {
+	fn read_32(&self, _mio: &FmMemoryIO, offset: u32) -> MemReadResult<u32> {
+		self.read_32(offset)
+	}
+	
+	fn write_32(&self, mio: &mut FmMemoryIO, offset: u32, value: u32) -> MemWriteResult {
+		self.write_32(mio, offset, value)
+	}
+}
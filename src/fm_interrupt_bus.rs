@@ -1,40 +1,95 @@
-use rv_vsys::{InterruptBus, MemReadResult, MemWriteResult};
+use rv_vsys::{InterruptBus, InterruptLevels, MemReadResult, MemWriteResult};
 use std::{sync::{Arc, atomic::{AtomicBool, Ordering, AtomicU32}}};
+use crate::fm_mio::FmMemoryIO;
 use crate::gpu::GpuInterruptOutput;
+use crate::peripheral::{Peripheral, PeripheralBus};
+use crate::plic::{Plic, SOURCE_SOUND_FIFO, SOURCE_VSYNC};
 use crate::sound_out::SoundInterruptOutput;
 use once_cell::sync::OnceCell;
 
 const OFFSET_VSYNC_INTERRUPT: u32 = 0;
 const OFFSET_SOUND_INTERRUPT: u32 = 4;
-const OFFSET_CPU0_IPI: u32 = 8;
-const OFFSET_CPU1_IPI: u32 = 12;
+// one word per hart, from hart 0 up
+const OFFSET_IPI_BASE: u32 = 8;
+const OFFSET_IMASK_BASE: u32 = 512;
 
-const OFFSET_CPU0_IMASK: u32 = 512;
-const OFFSET_CPU1_IMASK: u32 = 516;
+pub const MAX_HART_COUNT: u32 = (OFFSET_IMASK_BASE - OFFSET_IPI_BASE) / 4;
 
 const IMASK_BIT_VSYNC: u32 = 1 << 0;
 const IMASK_BIT_SOUND_FIFO: u32 = 1 << 1;
 const IMASK_BIT_IPI: u32 = 1 << 2;
 
+// the sound fifo interrupt goes to hart 1, or hart 0 if it's alone
+pub fn sound_interrupt_hart(hart_count: u32) -> u32 {
+	if hart_count > 1 { 1 } else { 0 }
+}
+
 #[derive(Clone)]
 pub struct FmInterruptBus {
 	gpu_interrupts: Arc<OnceCell<GpuInterruptOutput>>,
 	sound_interrupt: Arc<OnceCell<SoundInterruptOutput>>,
-	cpu0_ipi: Arc<AtomicBool>,
-	cpu1_ipi: Arc<AtomicBool>,
-	cpu0_imask: Arc<AtomicU32>,
-	cpu1_imask: Arc<AtomicU32>,
+	ipi: Arc<[AtomicBool]>,
+	imask: Arc<[AtomicU32]>,
+	// raises the interrupt lines of mapped devices, from bit 3 of the mask up
+	peripherals: PeripheralBus,
+	// with the standard controllers, interrupts go through the plic and ipis through the clint's
+	// msip instead of the masks
+	plic: Option<Plic>,
 }
 
 impl FmInterruptBus {
-	pub fn new() -> Self {
+	// every hart takes ipis, vsync starts out unmasked for hart 0 and the sound fifo for its own hart
+	pub fn new(hart_count: u32, peripherals: PeripheralBus, standard_interrupts: bool) -> Self {
+		let sound_hart = sound_interrupt_hart(hart_count);
+		let ipi: Vec<_> = (0 .. hart_count).map(|_| AtomicBool::new(false)).collect();
+		let imask: Vec<_> = (0 .. hart_count).map(|hart| {
+			let mut mask = IMASK_BIT_IPI;
+			if hart == 0 {
+				mask |= IMASK_BIT_VSYNC;
+			}
+			if hart == sound_hart {
+				mask |= IMASK_BIT_SOUND_FIFO;
+			}
+			AtomicU32::new(mask)
+		}).collect();
 		Self {
 			gpu_interrupts: Arc::new(OnceCell::default()),
 			sound_interrupt: Arc::new(OnceCell::default()),
-			cpu0_ipi: Arc::new(AtomicBool::new(false)),
-			cpu1_ipi: Arc::new(AtomicBool::new(false)),
-			cpu0_imask: Arc::new(AtomicU32::new(IMASK_BIT_IPI | IMASK_BIT_VSYNC)),
-			cpu1_imask: Arc::new(AtomicU32::new(IMASK_BIT_IPI | IMASK_BIT_SOUND_FIFO))
+			ipi: ipi.into(),
+			imask: imask.into(),
+			peripherals,
+			plic: if standard_interrupts { Some(Plic::new(hart_count)) } else { None },
+		}
+	}
+	
+	pub fn hart_count(&self) -> u32 {
+		self.ipi.len() as u32
+	}
+	
+	pub fn plic(&self) -> Option<Plic> {
+		self.plic.clone()
+	}
+	
+	pub fn ipi_pending(&self, hart_id: u32) -> Option<bool> {
+		self.ipi.get(hart_id as usize).map(|ipi| ipi.load(Ordering::SeqCst))
+	}
+	
+	pub fn set_ipi(&self, hart_id: u32, pending: bool) -> bool {
+		match self.ipi.get(hart_id as usize) {
+			Some(ipi) => {
+				ipi.store(pending, Ordering::SeqCst);
+				true
+			},
+			None => false
+		}
+	}
+	
+	fn hart_register(&self, offset: u32, base: u32) -> Option<usize> {
+		let index = (offset.checked_sub(base)? / 4) as usize;
+		if offset & 3 == 0 && index < self.ipi.len() {
+			Some(index)
+		} else {
+			None
 		}
 	}
 	
@@ -46,7 +101,7 @@ impl FmInterruptBus {
 		self.sound_interrupt.set(sound_int_out).unwrap();
 	}
 	
-	pub fn write_32(&mut self, offset: u32, val: u32) -> MemWriteResult {
+	pub fn write_32(&self, offset: u32, val: u32) -> MemWriteResult {
 		match offset {
 			OFFSET_VSYNC_INTERRUPT => {
 				if val == 0 {
@@ -60,24 +115,17 @@ impl FmInterruptBus {
 				}
 				MemWriteResult::Ok
 			},
-			OFFSET_CPU0_IPI => {
-				self.cpu0_ipi.store(val != 0, Ordering::SeqCst);
-				MemWriteResult::Ok
-			},
-			OFFSET_CPU1_IPI => {
-				self.cpu1_ipi.store(val != 0, Ordering::SeqCst);
-				MemWriteResult::Ok
-			},
-			
-			OFFSET_CPU0_IMASK => {
-				self.cpu0_imask.store(val, Ordering::SeqCst);
-				MemWriteResult::Ok
-			},
-			OFFSET_CPU1_IMASK => {
-				self.cpu0_imask.store(val, Ordering::SeqCst);
-				MemWriteResult::Ok
-			},
-			_ => MemWriteResult::PeripheralError
+			_ => {
+				if let Some(hart) = self.hart_register(offset, OFFSET_IMASK_BASE) {
+					self.imask[hart].store(val, Ordering::SeqCst);
+					MemWriteResult::Ok
+				} else if let Some(hart) = self.hart_register(offset, OFFSET_IPI_BASE) {
+					self.ipi[hart].store(val != 0, Ordering::SeqCst);
+					MemWriteResult::Ok
+				} else {
+					MemWriteResult::PeripheralError
+				}
+			}
 		}
 	}
 	
@@ -85,27 +133,43 @@ impl FmInterruptBus {
 		match offset {
 			OFFSET_VSYNC_INTERRUPT => MemReadResult::Ok(if self.gpu_interrupts.get().unwrap().clone().get_sync_interrupt_state() { 1 } else { 0 }),
 			OFFSET_SOUND_INTERRUPT => MemReadResult::Ok(if self.sound_interrupt.get().unwrap().clone().get_fifo_int_state() { 1 } else { 0 }),
-			OFFSET_CPU0_IPI => MemReadResult::Ok(if self.cpu0_ipi.load(Ordering::SeqCst) { 1 } else { 0 }),
-			OFFSET_CPU1_IPI => MemReadResult::Ok(if self.cpu1_ipi.load(Ordering::SeqCst) { 1 } else { 0 }),
-			_ => MemReadResult::PeripheralError
+			_ => {
+				if let Some(hart) = self.hart_register(offset, OFFSET_IMASK_BASE) {
+					MemReadResult::Ok(self.imask[hart].load(Ordering::SeqCst))
+				} else if let Some(hart) = self.hart_register(offset, OFFSET_IPI_BASE) {
+					MemReadResult::Ok(if self.ipi[hart].load(Ordering::SeqCst) { 1 } else { 0 })
+				} else {
+					MemReadResult::PeripheralError
+				}
+			}
 		}
 	}
 	
 	fn get_ibits(&self, hart_id: u32) -> u32 {
 		(if self.gpu_interrupts.get().unwrap().clone().get_sync_interrupt_state() { IMASK_BIT_VSYNC } else { 0 }) |
 		(if self.sound_interrupt.get().unwrap().clone().get_fifo_int_state() { IMASK_BIT_SOUND_FIFO } else { 0 }) |
-		match hart_id {
-			0 => if self.cpu0_ipi.load(Ordering::SeqCst) { IMASK_BIT_IPI } else { 0 },
-			1 => if self.cpu1_ipi.load(Ordering::SeqCst) { IMASK_BIT_IPI } else { 0 },
+		match self.ipi.get(hart_id as usize) {
+			Some(ipi) if ipi.load(Ordering::SeqCst) => IMASK_BIT_IPI,
 			_ => 0
+		} |
+		self.peripherals.pending_interrupts(hart_id)
+	}
+	
+	// the device lines are raised to the plic whichever hart they're raised to
+	fn get_plic_levels(&self) -> u32 {
+		let mut levels =
+			(if self.gpu_interrupts.get().unwrap().clone().get_sync_interrupt_state() { 1 << SOURCE_VSYNC } else { 0 }) |
+			(if self.sound_interrupt.get().unwrap().get_fifo_int_state() { 1 << SOURCE_SOUND_FIFO } else { 0 });
+		for hart_id in 0 .. self.hart_count() {
+			levels |= self.peripherals.pending_interrupts(hart_id);
 		}
+		levels
 	}
 	
 	fn get_imask(&self, hart_id: u32) -> u32 {
-		match hart_id {
-			0 => self.cpu0_imask.load(Ordering::SeqCst),
-			1 => self.cpu1_imask.load(Ordering::SeqCst),
-			_ => 0,
+		match self.imask.get(hart_id as usize) {
+			Some(imask) => imask.load(Ordering::SeqCst),
+			None => 0,
 		}
 	}
 }
@@ -117,4 +181,24 @@ impl InterruptBus for FmInterruptBus {
 	fn poll_interrupts(&mut self, hart_id: u32) -> bool {
 		(self.get_ibits(hart_id) & self.get_imask(hart_id)) != 0
 	}
+	
+	fn poll_interrupt_levels(&mut self, hart_id: u32) -> Option<InterruptLevels> {
+		let plic = self.plic.as_ref()?;
+		plic.update(self.get_plic_levels());
+		Some(InterruptLevels {
+			machine_software: self.ipi_pending(hart_id).unwrap_or(false),
+			machine_external: plic.context_pending(Plic::machine_context(hart_id)),
+			supervisor_external: plic.context_pending(Plic::supervisor_context(hart_id)),
+		})
+	}
+}
+
+impl Peripheral for FmInterruptBus {
+	fn read_32(&self, _mio: &FmMemoryIO, offset: u32) -> MemReadResult<u32> {
+		self.read_32(offset)
+	}
+	
+	fn write_32(&self, _mio: &mut FmMemoryIO, offset: u32, value: u32) -> MemWriteResult {
+		self.write_32(offset, value)
+	}
 }
@@ -1,16 +1,30 @@
-use std::{cell::UnsafeCell, ops::{Deref, DerefMut}, sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}}, usize};
+use std::{cell::{Cell, UnsafeCell}, ops::{Deref, DerefMut}, sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}}, usize};
 use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 use core::convert::{AsMut, AsRef};
 use atomic_counter::{AtomicCounter, ConsistentCounter};
 
-use rv_vsys::{MemIO, MemReadResult, MemWriteResult};
+use rv_vsys::{MachineClock, MemAccessEvent, MemIO, MemReadResult, MemWriteResult};
 use byteorder::{LE, ByteOrder};
-use crate::{cart_loader::CartLoaderPeripheral, cpu1_controller::Cpu1Controller, debug_device::DebugDevice, dsp_dma::{DspDmaDevice, DspDmaDeviceInterface}, fm_interrupt_bus::FmInterruptBus, gpu::GpuPeripheralInterface, input::InputPeripheral, math_accel::MathAccelerator, mtimer::{MTimerPeripheral}, sound_out::SoundOutPeripheral};
-use once_cell::sync::OnceCell;
+use crate::{clint::{Clint, CLINT_RANGE_SIZE}, debug_device::DebugDevice, dsp_dma::{DspDmaDevice, DspDmaDeviceInterface}, fm_interrupt_bus::FmInterruptBus, math_accel::MathAccelerator, mtimer::{MTimerPeripheral}, peripheral::{HartLocal, PeripheralBus}, plic::PLIC_RANGE_SIZE};
 
 const RAM_SIZE: usize = 0x1000_0000;
 const LOCK_GRANULARITY: usize = 0x1000;
-const HART_COUNT: usize = 2;
+
+// where the built in devices are mapped, see memory_map.txt
+pub const DEVICE_RANGE_SIZE: u32 = 0x1_0000;
+pub const DEBUG_DEVICE_BASE: u32 = 0xF000_0000;
+pub const GPU_BASE: u32 = 0xF001_0000;
+pub const DSP_DMA_BASE: u32 = 0xF002_0000;
+pub const INTERRUPT_BUS_BASE: u32 = 0xF003_0000;
+pub const HART_CONTROLLER_BASE: u32 = 0xF004_0000;
+pub const SOUND_OUT_BASE: u32 = 0xF005_0000;
+pub const MTIMER_BASE: u32 = 0xF006_0000;
+pub const MATH_ACCELERATOR_BASE: u32 = 0xF007_0000;
+pub const CART_LOADER_BASE: u32 = 0xF008_0000;
+pub const INPUT_BASE: u32 = 0xF009_0000;
+// only with --standard-interrupts
+pub const CLINT_BASE: u32 = 0xF200_0000;
+pub const PLIC_BASE: u32 = 0xF400_0000;
 
 struct ArcMutPtr<T: ?Sized> {
 	data_ptr: *mut T,
@@ -97,22 +111,16 @@ impl Drop for MemLockHold {
 pub struct FmMemoryIO {
 	ram: ArcMutPtr<[u8]>,
 	page_locks: ArcMutPtr<[Arc<PageGaurd>]>,
-	debug_device: ArcMutPtr<DebugDevice>,
-	gpu_interface_device: Arc<OnceCell<GpuPeripheralInterface>>,
-	dsp_dma_device: Arc<DspDmaDeviceInterface>,
-	interrupt_bus_device: FmInterruptBus,
-	cpu1_controller_device: Arc<OnceCell<Cpu1Controller>>,
-	sound_out: Arc<OnceCell<SoundOutPeripheral>>,
+	peripherals: PeripheralBus,
 	mem_lock_hold_d: UnsafeCell<MemLockHold>,
 	mem_lock_hold_i: UnsafeCell<MemLockHold>,
+	peripheral_access_count: Cell<u64>,
+	page_lock_contention_count: Cell<u64>,
 	write_cycle_counter: Arc<ConsistentCounter>,
 	id_counter: Arc<ConsistentCounter>,
 	interface_id: u32,
 	hart_id: u32,
 	mtimers: Arc<[Arc<MTimerPeripheral>]>,
-	math_accelerators: Arc<[Arc<MathAccelerator>]>,
-	cart_loader_device: Arc<OnceCell<CartLoaderPeripheral>>,
-	input: Arc<OnceCell<InputPeripheral>>
 }
 
 unsafe impl Send for FmMemoryIO {
@@ -146,59 +154,83 @@ impl Clone for FmMemoryIO {
 		FmMemoryIO {
 			ram: self.ram.clone(),
 			page_locks: self.page_locks.clone(),
-			debug_device: self.debug_device.clone(),
-			gpu_interface_device: self.gpu_interface_device.clone(),
-			dsp_dma_device: self.dsp_dma_device.clone(),
-			interrupt_bus_device: self.interrupt_bus_device.clone(),
-			cpu1_controller_device: self.cpu1_controller_device.clone(),
-			sound_out: self.sound_out.clone(),
+			peripherals: self.peripherals.clone(),
 			mem_lock_hold_d: UnsafeCell::new(MemLockHold::Clear),
 			mem_lock_hold_i: UnsafeCell::new(MemLockHold::Clear),
+			peripheral_access_count: Cell::new(0),
+			page_lock_contention_count: Cell::new(0),
 			write_cycle_counter: self.write_cycle_counter.clone(),
 			id_counter: self.id_counter.clone(),
 			interface_id: self.id_counter.inc() as u32,
 			hart_id: self.hart_id,
 			mtimers: self.mtimers.clone(),
-			math_accelerators: self.math_accelerators.clone(),
-			cart_loader_device: self.cart_loader_device.clone(),
-			input: self.input.clone()
 		}
 	}
 }
 
 impl FmMemoryIO {
-	pub fn new(interrupt_bus: FmInterruptBus) -> FmMemoryIO {
+	// maps the devices every machine has, and every hart on the interrupt bus gets its own mtimer and
+	// math accelerator, along with the clint and plic when the bus has them. the rest are mapped by
+	// whatever creates them.
+	pub fn new(peripherals: PeripheralBus, interrupt_bus: FmInterruptBus, clock: MachineClock) -> FmMemoryIO {
 		let mut lock_vec = Vec::new();
 		for _ in 0 .. (RAM_SIZE / LOCK_GRANULARITY) {
 			lock_vec.push(Arc::new(PageGaurd::new()));
 		}
 		let mut mtimers = Vec::new();
 		let mut math_accelerators = Vec::new();
-		for _ in 0 .. HART_COUNT {
-			mtimers.push(Arc::new(MTimerPeripheral::new()));
+		for _ in 0 .. interrupt_bus.hart_count() {
+			mtimers.push(Arc::new(MTimerPeripheral::new(clock.clone())));
 			math_accelerators.push(Arc::new(MathAccelerator::new()));
 		};
+		let mtimers: Arc<[Arc<MTimerPeripheral>]> = mtimers.into();
+		peripherals.map(DEBUG_DEVICE_BASE, DEVICE_RANGE_SIZE, Arc::new(DebugDevice::new())).unwrap();
+		peripherals.map(DSP_DMA_BASE, DEVICE_RANGE_SIZE, Arc::new(DspDmaDeviceInterface::new(DspDmaDevice::new()))).unwrap();
+		if let Some(plic) = interrupt_bus.plic() {
+			peripherals.map(CLINT_BASE, CLINT_RANGE_SIZE, Arc::new(Clint::new(interrupt_bus.clone(), mtimers.clone()))).unwrap();
+			peripherals.map(PLIC_BASE, PLIC_RANGE_SIZE, Arc::new(plic)).unwrap();
+		}
+		peripherals.map(INTERRUPT_BUS_BASE, DEVICE_RANGE_SIZE, Arc::new(interrupt_bus)).unwrap();
+		peripherals.map(MTIMER_BASE, DEVICE_RANGE_SIZE, Arc::new(HartLocal::new(mtimers.clone()))).unwrap();
+		peripherals.map(MATH_ACCELERATOR_BASE, DEVICE_RANGE_SIZE, Arc::new(HartLocal::new(math_accelerators.into()))).unwrap();
 		// the problem i'm having is that page_locks is initialized with clone(), meaning every page shares the same Arc'd PageGaurd
 		// to solve, fill lock_vec with individual Arc<PageGaurd>'s constructed separately
 		FmMemoryIO {
 			ram: ArcMutPtr::new(vec![0u8; RAM_SIZE].into_boxed_slice()),
 			page_locks: ArcMutPtr::new(lock_vec.into_boxed_slice()),
-			debug_device: ArcMutPtr::new(Box::new(DebugDevice::new())),
-			gpu_interface_device: Arc::new(OnceCell::default()),
-			dsp_dma_device: Arc::new(DspDmaDeviceInterface::new(DspDmaDevice::new())),
-			interrupt_bus_device: interrupt_bus,
-			cpu1_controller_device: Arc::new(OnceCell::new()),
-			sound_out: Arc::new(OnceCell::default()),
+			peripherals,
 			mem_lock_hold_d: UnsafeCell::new(MemLockHold::Clear),
 			mem_lock_hold_i: UnsafeCell::new(MemLockHold::Clear),
+			peripheral_access_count: Cell::new(0),
+			page_lock_contention_count: Cell::new(0),
 			write_cycle_counter: Arc::new(ConsistentCounter::new(1)),
 			id_counter: Arc::new(ConsistentCounter::new(1)),
 			interface_id: 0,
 			hart_id: 0xFFFF_FFFF,
-			mtimers: mtimers.into(),
-			math_accelerators: math_accelerators.into(),
-			cart_loader_device: Arc::new(OnceCell::default()),
-			input: Arc::new(OnceCell::default()),
+			mtimers,
+		}
+	}
+	
+	// page locks are tried first so that waiting on another hart can be counted
+	fn lock_page_read(&self, page_num: u32) -> RwLockReadGuard<'static, ()> {
+		let lock = &self.page_locks.deref_mut_static()[page_num as usize].lock;
+		match lock.try_read() {
+			Some(lock_gaurd) => lock_gaurd,
+			None => {
+				self.page_lock_contention_count.set(self.page_lock_contention_count.get() + 1);
+				lock.read()
+			}
+		}
+	}
+	
+	fn lock_page_write(&self, page_num: u32) -> RwLockWriteGuard<'static, ()> {
+		let lock = &self.page_locks.deref_mut_static()[page_num as usize].lock;
+		match lock.try_write() {
+			Some(lock_gaurd) => lock_gaurd,
+			None => {
+				self.page_lock_contention_count.set(self.page_lock_contention_count.get() + 1);
+				lock.write()
+			}
 		}
 	}
 	
@@ -214,14 +246,14 @@ impl FmMemoryIO {
 			match &*self.mem_lock_hold_d.get() {
 				&MemLockHold::Read(page, ..) => if page != page_num {
 					*self.mem_lock_hold_d.get() = MemLockHold::Clear;
-					*self.mem_lock_hold_d.get() = MemLockHold::Read(page_num, self.page_locks.deref_mut_static()[page_num as usize].lock.read(), self.page_locks.deref_mut_static()[page_num as usize].write_cycle.clone());
+					*self.mem_lock_hold_d.get() = MemLockHold::Read(page_num, self.lock_page_read(page_num), self.page_locks.deref_mut_static()[page_num as usize].write_cycle.clone());
 				},
 				&MemLockHold::Write(page, _, _, _) => if page != page_num {
 					*self.mem_lock_hold_d.get() = MemLockHold::Clear;
-					*self.mem_lock_hold_d.get() = MemLockHold::Read(page_num, self.page_locks.deref_mut_static()[page_num as usize].lock.read(), self.page_locks.deref_mut_static()[page_num as usize].write_cycle.clone());
+					*self.mem_lock_hold_d.get() = MemLockHold::Read(page_num, self.lock_page_read(page_num), self.page_locks.deref_mut_static()[page_num as usize].write_cycle.clone());
 				},
 				&MemLockHold::Clear => {
-					*self.mem_lock_hold_d.get() = MemLockHold::Read(page_num, self.page_locks.deref_mut_static()[page_num as usize].lock.read(), self.page_locks.deref_mut_static()[page_num as usize].write_cycle.clone());
+					*self.mem_lock_hold_d.get() = MemLockHold::Read(page_num, self.lock_page_read(page_num), self.page_locks.deref_mut_static()[page_num as usize].write_cycle.clone());
 				}
 			}
 		}
@@ -239,20 +271,20 @@ impl FmMemoryIO {
 			match &*self.mem_lock_hold_d.get() {
 				MemLockHold::Read(page, _, write_cycle) => if *page != page_num {
 					*self.mem_lock_hold_d.get() = MemLockHold::Clear;
-					*self.mem_lock_hold_d.get() = MemLockHold::Read(page_num, self.page_locks.deref_mut_static()[page_num as usize].lock.read(), self.page_locks.deref_mut_static()[page_num as usize].write_cycle.clone());
+					*self.mem_lock_hold_d.get() = MemLockHold::Read(page_num, self.lock_page_read(page_num), self.page_locks.deref_mut_static()[page_num as usize].write_cycle.clone());
 					(self.page_locks.deref_mut_static()[page_num as usize].write_cycle.load(Ordering::SeqCst), page_num)
 				} else {
 					(write_cycle.load(Ordering::SeqCst), page_num)
 				},
 				MemLockHold::Write(page, _, write_cycle, _) => if *page != page_num {
 					*self.mem_lock_hold_d.get() = MemLockHold::Clear;
-					*self.mem_lock_hold_d.get() = MemLockHold::Read(page_num, self.page_locks.deref_mut_static()[page_num as usize].lock.read(), self.page_locks.deref_mut_static()[page_num as usize].write_cycle.clone());
+					*self.mem_lock_hold_d.get() = MemLockHold::Read(page_num, self.lock_page_read(page_num), self.page_locks.deref_mut_static()[page_num as usize].write_cycle.clone());
 					(self.page_locks.deref_mut_static()[page_num as usize].write_cycle.load(Ordering::SeqCst), page_num)
 				} else {
 					(write_cycle.load(Ordering::SeqCst), page_num)
 				},
 				MemLockHold::Clear => {
-					*self.mem_lock_hold_d.get() = MemLockHold::Read(page_num, self.page_locks.deref_mut_static()[page_num as usize].lock.read(), self.page_locks.deref_mut_static()[page_num as usize].write_cycle.clone());
+					*self.mem_lock_hold_d.get() = MemLockHold::Read(page_num, self.lock_page_read(page_num), self.page_locks.deref_mut_static()[page_num as usize].write_cycle.clone());
 					(self.page_locks.deref_mut_static()[page_num as usize].write_cycle.load(Ordering::SeqCst), page_num)
 				}
 			}
@@ -274,13 +306,13 @@ impl FmMemoryIO {
 			match &*self.mem_lock_hold_i.get() {
 				&MemLockHold::Read(page, ..) => if page != page_num {
 					*self.mem_lock_hold_i.get() = MemLockHold::Clear;
-					*self.mem_lock_hold_i.get() = MemLockHold::Read(page_num, self.page_locks.deref_mut_static()[page_num as usize].lock.read(), self.page_locks.deref_mut_static()[page_num as usize].write_cycle.clone());
+					*self.mem_lock_hold_i.get() = MemLockHold::Read(page_num, self.lock_page_read(page_num), self.page_locks.deref_mut_static()[page_num as usize].write_cycle.clone());
 				},
 				&MemLockHold::Write(_, _, _, _) => {
 					panic!("instruction mem lock hold should never have Write status!");
 				},
 				&MemLockHold::Clear => {
-					*self.mem_lock_hold_i.get() = MemLockHold::Read(page_num, self.page_locks.deref_mut_static()[page_num as usize].lock.read(),  self.page_locks.deref_mut_static()[page_num as usize].write_cycle.clone());
+					*self.mem_lock_hold_i.get() = MemLockHold::Read(page_num, self.lock_page_read(page_num),  self.page_locks.deref_mut_static()[page_num as usize].write_cycle.clone());
 				}
 			}
 		}
@@ -299,16 +331,16 @@ impl FmMemoryIO {
 				&MemLockHold::Read(..) => {
 					*self.mem_lock_hold_d.get() = MemLockHold::Clear;
 					let page_gaurd = &self.page_locks.deref_mut_static()[page_num as usize];
-					*self.mem_lock_hold_d.get() = MemLockHold::Write(page_num, page_gaurd.lock.write(), page_gaurd.write_cycle.clone(), self.write_cycle_counter.clone());
+					*self.mem_lock_hold_d.get() = MemLockHold::Write(page_num, self.lock_page_write(page_num), page_gaurd.write_cycle.clone(), self.write_cycle_counter.clone());
 				},
 				&MemLockHold::Write(page, _, _, _) => if page != page_num {
 					*self.mem_lock_hold_d.get() = MemLockHold::Clear;
 					let page_gaurd = &self.page_locks.deref_mut_static()[page_num as usize];
-					*self.mem_lock_hold_d.get() = MemLockHold::Write(page_num, page_gaurd.lock.write(), page_gaurd.write_cycle.clone(), self.write_cycle_counter.clone());
+					*self.mem_lock_hold_d.get() = MemLockHold::Write(page_num, self.lock_page_write(page_num), page_gaurd.write_cycle.clone(), self.write_cycle_counter.clone());
 				},
 				&MemLockHold::Clear => {
 					let page_gaurd = &self.page_locks.deref_mut_static()[page_num as usize];
-					let lock_gaurd = page_gaurd.lock.write();
+					let lock_gaurd = self.lock_page_write(page_num);
 					*self.mem_lock_hold_d.get() = MemLockHold::Write(page_num, lock_gaurd, page_gaurd.write_cycle.clone(), self.write_cycle_counter.clone());
 				}
 			}
@@ -334,7 +366,7 @@ impl FmMemoryIO {
 					}
 					*self.mem_lock_hold_d.get() = MemLockHold::Clear;
 					let page_gaurd = &self.page_locks.deref_mut_static()[page_num as usize];
-					*self.mem_lock_hold_d.get() = MemLockHold::Write(page_num, page_gaurd.lock.write(), page_gaurd.write_cycle.clone(), self.write_cycle_counter.clone());
+					*self.mem_lock_hold_d.get() = MemLockHold::Write(page_num, self.lock_page_write(page_num), page_gaurd.write_cycle.clone(), self.write_cycle_counter.clone());
 					if page_gaurd.write_cycle.load(Ordering::SeqCst) != ll_write_cycle {
 						return false;
 					}
@@ -342,7 +374,7 @@ impl FmMemoryIO {
 				MemLockHold::Write(page, _, write_cycle, _) => if *page != page_num {
 					*self.mem_lock_hold_d.get() = MemLockHold::Clear;
 					let page_gaurd = &self.page_locks.deref_mut_static()[page_num as usize];
-					*self.mem_lock_hold_d.get() = MemLockHold::Write(page_num, page_gaurd.lock.write(), page_gaurd.write_cycle.clone(), self.write_cycle_counter.clone());
+					*self.mem_lock_hold_d.get() = MemLockHold::Write(page_num, self.lock_page_write(page_num), page_gaurd.write_cycle.clone(), self.write_cycle_counter.clone());
 					if page_gaurd.write_cycle.load(Ordering::SeqCst) != ll_write_cycle {
 						return false;
 					}
@@ -356,7 +388,7 @@ impl FmMemoryIO {
 					if page_gaurd.write_cycle.load(Ordering::SeqCst) != ll_write_cycle {
 						return false;
 					}
-					let lock_gaurd = page_gaurd.lock.write();
+					let lock_gaurd = self.lock_page_write(page_num);
 					*self.mem_lock_hold_d.get() = MemLockHold::Write(page_num, lock_gaurd, page_gaurd.write_cycle.clone(), self.write_cycle_counter.clone());
 					if page_gaurd.write_cycle.load(Ordering::SeqCst) != ll_write_cycle {
 						return false;
@@ -381,24 +413,21 @@ impl FmMemoryIO {
 		}
 	}
 	
-	pub fn set_gpu_interface(&mut self, interface: GpuPeripheralInterface) {
-		self.gpu_interface_device.set(interface).unwrap();
+	// ram as it is, without taking page locks
+	pub fn ram(&self) -> &[u8] {
+		self.ram.as_ref()
 	}
 	
-	pub fn set_cpu1_controller(&mut self, controller: Cpu1Controller) {
-		self.cpu1_controller_device.set(controller).unwrap();
+	pub fn hart_id(&self) -> u32 {
+		self.hart_id
 	}
 	
-	pub fn set_sound_out(&mut self, sound_out: SoundOutPeripheral) {
-		self.sound_out.set(sound_out).unwrap();
+	pub fn peripherals(&self) -> &PeripheralBus {
+		&self.peripherals
 	}
 	
-	pub fn set_cart_loader(&mut self, loader_peripheral: CartLoaderPeripheral) {
-		self.cart_loader_device.set(loader_peripheral).unwrap();
-	}
-	
-	pub fn set_input(&mut self, input_peripheral: InputPeripheral) {
-		self.input.set(input_peripheral).unwrap();
+	fn count_peripheral_access(&self) {
+		self.peripheral_access_count.set(self.peripheral_access_count.get() + 1);
 	}
 }
 
@@ -407,6 +436,13 @@ impl MemIO<MTimerPeripheral> for FmMemoryIO {
 		self.hart_id = id;
 	}
 	
+	fn access_event_count(&self, event: MemAccessEvent) -> u64 {
+		match event {
+			MemAccessEvent::PeripheralAccess => self.peripheral_access_count.get(),
+			MemAccessEvent::PageLockContention => self.page_lock_contention_count.get(),
+		}
+	}
+	
 	fn get_mtimer(&self, hart_id: u32) -> Option<Arc<MTimerPeripheral>> {
 		if hart_id as usize >= self.mtimers.len() {
 			return None;
@@ -446,6 +482,10 @@ impl MemIO<MTimerPeripheral> for FmMemoryIO {
 				self.ram_sync_read(addr);
 				MemReadResult::Ok(self.ram.as_ref()[addr as usize])
 			},
+			0xF => {
+				self.count_peripheral_access();
+				self.peripherals.read_8(self, addr)
+			},
 			_ => MemReadResult::ErrUnmapped
 		}
 	}
@@ -459,6 +499,10 @@ impl MemIO<MTimerPeripheral> for FmMemoryIO {
 				self.ram_sync_read(addr);
 				MemReadResult::Ok(LE::read_u16(&self.ram.as_ref()[addr as usize ..]))
 			},
+			0xF => {
+				self.count_peripheral_access();
+				self.peripherals.read_16(self, addr)
+			},
 			_ => MemReadResult::ErrUnmapped
 		}
 	}
@@ -473,52 +517,8 @@ impl MemIO<MTimerPeripheral> for FmMemoryIO {
 				MemReadResult::Ok(LE::read_u32(&self.ram.as_ref()[addr as usize ..]))
 			},
 			0xF => {
-				let peripheral_offset = addr & 0xFFFF;
-				let peripheral = (addr >> 16) & 0xFFF;
-				match peripheral {
-					0 => {
-						self.debug_device.as_ref().read_32(peripheral_offset)
-					},
-					2 => {
-						self.dsp_dma_device.clone().read_32(peripheral_offset)
-					},
-					3 => {
-						self.interrupt_bus_device.read_32(peripheral_offset)
-					},
-					4 => {
-						self.cpu1_controller_device.get().unwrap().read_32(peripheral_offset)
-					},
-					5 => {
-						self.sound_out.get().unwrap().read_32(peripheral_offset)
-					},
-					6 => {
-						if self.hart_id as usize <= HART_COUNT {
-							let device = self.mtimers[self.hart_id as usize].clone();
-							device.read_32(peripheral_offset)
-						} else {
-							MemReadResult::ErrUnmapped
-						}
-					},
-					7 => {
-						if self.hart_id as usize <= HART_COUNT {
-							let device = self.math_accelerators[self.hart_id as usize].clone();
-							device.read_32(peripheral_offset)
-						} else {
-							MemReadResult::ErrUnmapped
-						}
-					},
-					8 => {
-						let device = self.cart_loader_device.clone();
-						device.get().unwrap().read_32(peripheral_offset)
-					},
-					9 => {
-						let device = self.input.clone();
-						device.get().unwrap().read_32(peripheral_offset)
-					},
-					_ => {
-						MemReadResult::ErrUnmapped
-					}
-				}
+				self.count_peripheral_access();
+				self.peripherals.read_32(self, addr)
 			},
 			_ => MemReadResult::ErrUnmapped,
 		}
@@ -528,15 +528,51 @@ impl MemIO<MTimerPeripheral> for FmMemoryIO {
 		if addr == 0 {
 			return MemReadResult::ErrUnmapped;
 		}
-		match (addr + 3) >> 28 {
+		match addr >> 28 {
 			0 => {
-				self.ram_sync_read_ifetch(addr);
-				MemReadResult::Ok(LE::read_u32(&self.ram.as_ref()[addr as usize ..]))
+				if (addr as usize % LOCK_GRANULARITY) > LOCK_GRANULARITY - 4 {
+					// compressed instructions only guarantee 16 bit alignment, so a 32 bit fetch can straddle two pages,
+					// or run off the end of ram when the last halfword holds a compressed instruction
+					self.ram_sync_read_ifetch(addr);
+					let low = LE::read_u16(&self.ram.as_ref()[addr as usize ..]) as u32;
+					if low & 3 != 3 {
+						return MemReadResult::Ok(low);
+					}
+					if (addr + 2) >> 28 != 0 {
+						return MemReadResult::ErrUnmapped;
+					}
+					self.ram_sync_read_ifetch(addr + 2);
+					let high = LE::read_u16(&self.ram.as_ref()[addr as usize + 2 ..]) as u32;
+					MemReadResult::Ok(low | (high << 16))
+				} else {
+					self.ram_sync_read_ifetch(addr);
+					MemReadResult::Ok(LE::read_u32(&self.ram.as_ref()[addr as usize ..]))
+				}
 			},
 			_ => MemReadResult::ErrUnmapped,
 		}
 	}
 	
+	fn code_write_cycle(&self, addr: u32) -> Option<usize> {
+		if addr == 0 || addr >= RAM_SIZE as u32 {
+			return None;
+		}
+		let page_num = addr / 0x1000;
+		unsafe {
+			// our own pending writes to this page haven't bumped the write cycle yet
+			if let &MemLockHold::Write(page, ..) = &*self.mem_lock_hold_d.get() {
+				if page == page_num {
+					return None;
+				}
+			}
+		}
+		Some(self.page_locks.deref_mut_static()[page_num as usize].write_cycle.load(Ordering::SeqCst))
+	}
+	
+	fn is_plain_memory(&self, addr: u32, size: u32) -> bool {
+		addr != 0 && (addr as usize) + (size as usize) <= RAM_SIZE
+	}
+	
 	fn read_32_ll(&self, addr: u32) -> (MemReadResult<u32>, usize, u32) {
 		if addr == 0 {
 			return (MemReadResult::ErrUnmapped, 0, 0);
@@ -578,6 +614,11 @@ impl MemIO<MTimerPeripheral> for FmMemoryIO {
 				self.ram.as_mut()[addr as usize] = value;
 				MemWriteResult::Ok
 			}
+			0xF => {
+				self.count_peripheral_access();
+				let peripherals = self.peripherals.clone();
+				peripherals.write_8(self, addr, value)
+			},
 			_ => {
 				MemWriteResult::ErrUnmapped
 			}
@@ -594,6 +635,11 @@ impl MemIO<MTimerPeripheral> for FmMemoryIO {
 				LE::write_u16(&mut self.ram.as_mut()[addr as usize ..], value);
 				MemWriteResult::Ok
 			}
+			0xF => {
+				self.count_peripheral_access();
+				let peripherals = self.peripherals.clone();
+				peripherals.write_16(self, addr, value)
+			},
 			_ => MemWriteResult::ErrUnmapped
 		}
 	}
@@ -609,57 +655,9 @@ impl MemIO<MTimerPeripheral> for FmMemoryIO {
 				MemWriteResult::Ok
 			},
 			0xF => {
-				let peripheral_offset = addr & 0xFFFF;
-				let peripheral = (addr >> 16) & 0xFFF;
-				match peripheral {
-					0 => {
-						self.debug_device.deref_mut().write_32(peripheral_offset, value, &self.ram.as_ref())
-					},
-					1 => {
-						self.gpu_interface_device.get().unwrap().clone().write_u32(peripheral_offset, value)
-					},
-					2 => {
-						let device = self.dsp_dma_device.clone();
-						device.write_32(self, peripheral_offset, value)
-					},
-					3 => {
-						self.interrupt_bus_device.write_32(peripheral_offset, value)
-					},
-					4 => {
-						self.cpu1_controller_device.get().unwrap().clone().write_32(peripheral_offset, value)
-					},
-					5 => {
-						let device = self.sound_out.clone();
-						device.get().unwrap().write_32(self, peripheral_offset, value)
-					},
-					6 => {
-						if self.hart_id as usize <= HART_COUNT {
-							let device = self.mtimers[self.hart_id as usize].clone();
-							device.write_32(peripheral_offset, value)
-						} else {
-							MemWriteResult::ErrUnmapped
-						}
-					},
-					7 => {
-						if self.hart_id as usize <= HART_COUNT {
-							let device = self.math_accelerators[self.hart_id as usize].clone();
-							device.write_32(self, peripheral_offset, value)
-						} else {
-							MemWriteResult::ErrUnmapped
-						}
-					},
-					8 => {
-						let device = self.cart_loader_device.clone();
-						device.get().unwrap().write_32(self, peripheral_offset, value)
-					},
-					9 => {
-						let device = self.input.clone();
-						device.get().unwrap().write_32(peripheral_offset, value)
-					},
-					_ => {
-						MemWriteResult::ErrUnmapped
-					}
-				}
+				self.count_peripheral_access();
+				let peripherals = self.peripherals.clone();
+				peripherals.write_32(self, addr, value)
 			}
 			_ => MemWriteResult::ErrUnmapped
 		}
@@ -4,8 +4,8 @@ use parking_lot::{Condvar, Mutex};
 use wgpu::{self};
 use winit::window::Window;
 
-use crate::{fm_mio::FmMemoryIO, raw_fb_renderer::RawFBRenderer, fm_interrupt_bus::FmInterruptBus, fb_present_renderer::FramebufferPresentRenderer};
-use rv_vsys::{CpuWakeupHandle, MemIO, MemWriteResult};
+use crate::{fm_mio::{FmMemoryIO, GPU_BASE, DEVICE_RANGE_SIZE}, raw_fb_renderer::RawFBRenderer, fm_interrupt_bus::FmInterruptBus, fb_present_renderer::FramebufferPresentRenderer, peripheral::Peripheral};
+use rv_vsys::{CpuWakeupHandle, MemIO, MemReadResult, MemWriteResult};
 
 pub struct Gpu {
 	device: Arc<wgpu::Device>,
@@ -43,11 +43,24 @@ pub struct GpuWindowEventSink {
 	queue: Arc<wgpu::Queue>,
 	present_chain: GpuPresentChain,
 	present_renderer: FramebufferPresentRenderer,
+	vsync: Option<GpuVsync>
+}
+
+pub struct GpuVsync {
 	cpu_wakeup: CpuWakeupHandle,
 	sync_interrupt_enable: Arc<AtomicBool>,
 	sync_interrupt_state: Arc<AtomicBool>
 }
 
+impl GpuVsync {
+	pub fn signal(&mut self) {
+		if self.sync_interrupt_enable.load(Ordering::SeqCst) {
+			self.sync_interrupt_state.store(true, Ordering::SeqCst);
+			self.cpu_wakeup.cpu_wake();
+		}
+	}
+}
+
 const DEFAULT_CLEAR_COLOR: wgpu::Color = wgpu::Color {r: 0.0, g: 0.0, b: 0.1, a: 1.0};
 
 impl GpuWindowEventSink {
@@ -88,11 +101,15 @@ impl GpuWindowEventSink {
 				self.queue.submit(Some(command_encoder.finish()));
 			}
 		}
-		if self.sync_interrupt_enable.load(Ordering::SeqCst) {
-			self.sync_interrupt_state.store(true, Ordering::SeqCst);
-			self.cpu_wakeup.cpu_wake();
+		if let Some(vsync) = &mut self.vsync {
+			vsync.signal();
 		}
 	}
+	
+	// hands vsync over to another time source, after which presenting no longer raises it
+	pub fn take_vsync(&mut self) -> Option<GpuVsync> {
+		self.vsync.take()
+	}
 }
 
 enum GpuPresentState {
@@ -216,7 +233,7 @@ impl Gpu {
 		let swap_chain = device.create_swap_chain(&surface, &swap_desc);
 		let sync_interrupt_enable = Arc::new(AtomicBool::new(false));
 		let sync_interrupt_state: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
-		mio.set_gpu_interface(GpuPeripheralInterface::new(cmd_queue_tx, sync_interrupt_enable.clone()));
+		mio.peripherals().map(GPU_BASE, DEVICE_RANGE_SIZE, Arc::new(GpuPeripheralInterface::new(cmd_queue_tx, sync_interrupt_enable.clone()))).unwrap();
 		let interrupt_output = GpuInterruptOutput::new(
 			sync_interrupt_enable.clone(),
 			sync_interrupt_state.clone()
@@ -243,9 +260,11 @@ impl Gpu {
 			swap_chain: swap_chain,
 			present_chain: present_chain,
 			present_renderer: present_renderer,
-			cpu_wakeup: cpu_wakeup,
-			sync_interrupt_enable: sync_interrupt_enable,
-			sync_interrupt_state: sync_interrupt_state
+			vsync: Some(GpuVsync {
+				cpu_wakeup: cpu_wakeup,
+				sync_interrupt_enable: sync_interrupt_enable,
+				sync_interrupt_state: sync_interrupt_state
+			})
 		},
 		GpuResetHandle {
 			cmd_tx: reset_queue_tx,
@@ -386,6 +405,9 @@ pub struct GpuPeripheralInterface {
 	sync_interrupt_enable: Arc<AtomicBool>
 }
 
+unsafe impl Send for GpuPeripheralInterface {}
+unsafe impl Sync for GpuPeripheralInterface {}
+
 pub const GPU_REGISTER_MODE: u32 = 0;
 pub const GPU_MODE_VALUE_DISABLED: u32 = 0;
 pub const GPU_MODE_VALUE_RAW_FB: u32 = 1;
@@ -404,7 +426,7 @@ impl GpuPeripheralInterface {
 		}
 	}
 	
-	pub fn write_u32(&mut self, offset: u32, value: u32) -> MemWriteResult {
+	pub fn write_u32(&self, offset: u32, value: u32) -> MemWriteResult {
 		match offset {
 			GPU_REGISTER_MODE => {
 				match value {
@@ -440,6 +462,17 @@ impl GpuPeripheralInterface {
 	}
 }
 
+impl Peripheral for GpuPeripheralInterface {
+	// the gpu registers are write only
+	fn read_32(&self, _mio: &FmMemoryIO, _offset: u32) -> MemReadResult<u32> {
+		MemReadResult::ErrUnmapped
+	}
+	
+	fn write_32(&self, _mio: &mut FmMemoryIO, offset: u32, value: u32) -> MemWriteResult {
+		self.write_u32(offset, value)
+	}
+}
+
 #[derive(Debug, Clone)]
 pub struct GpuInterruptOutput {
 	sync_interrupt_state: Arc<AtomicBool>,
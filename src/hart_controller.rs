@@ -0,0 +1,167 @@
+use rv_vsys::{Cpu, MemWriteResult, MemReadResult, CpuKillHandle};
+use crate::{application_core, fm_interrupt_bus::FmInterruptBus, fm_mio::FmMemoryIO, mtimer::MTimerPeripheral, peripheral::Peripheral};
+use std::{fmt, thread, time::Duration};
+use std::sync::Arc;
+use parking_lot::Mutex;
+
+enum HartState {
+	Idle(Cpu<MTimerPeripheral, FmMemoryIO, FmInterruptBus>),
+	Running,
+}
+
+impl fmt::Debug for HartState {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			HartState::Running => f.write_str("Running"),
+			HartState::Idle(..) => f.write_str("Idle"),
+		}
+	}
+}
+
+#[derive(Debug)]
+pub struct HartControllerInternal {
+	cpu_kill_handle: CpuKillHandle,
+	state: HartState,
+	start_address: u32,
+}
+
+// starts the secondary harts, 1 and up. hart 0 boots on its own and always reads as running.
+#[derive(Clone, Debug)]
+pub struct HartController {
+	harts: Arc<[Arc<Mutex<HartControllerInternal>>]>,
+}
+
+unsafe impl Send for HartController {}
+unsafe impl Sync for HartController {}
+
+// the registers at the bottom are the original core 1 controller, and stay aliased to hart 1
+const OFFSET_START_ADDRESS: u32 = 0;
+const OFFSET_STARTUP_TRIGGER: u32 = 4;
+const OFFSET_IS_RUNNING: u32 = 8;
+const OFFSET_HART_COUNT: u32 = 12;
+
+// hart n's start address, startup trigger and state live at HART_BANK_BASE + n * HART_BANK_SIZE
+const HART_BANK_BASE: u32 = 0x100;
+const HART_BANK_SIZE: u32 = 0x10;
+
+impl HartController {
+	pub fn new(cpus: Vec<Cpu<MTimerPeripheral, FmMemoryIO, FmInterruptBus>>) -> Self {
+		let harts: Vec<_> = cpus.into_iter().map(|cpu| {
+			Arc::new(Mutex::new(HartControllerInternal {
+				cpu_kill_handle: cpu.get_kill_handle(),
+				state: HartState::Idle(cpu),
+				start_address: 0
+			}))
+		}).collect();
+		Self {
+			harts: harts.into(),
+		}
+	}
+	
+	pub fn hart_count(&self) -> u32 {
+		self.harts.len() as u32 + 1
+	}
+	
+	// splits an offset into a hart and the register within its bank
+	fn decode_offset(&self, offset: u32) -> Option<(u32, u32)> {
+		let (hart, register) = if offset < HART_BANK_BASE {
+			(1, offset)
+		} else {
+			((offset - HART_BANK_BASE) / HART_BANK_SIZE, (offset - HART_BANK_BASE) % HART_BANK_SIZE)
+		};
+		if hart < self.hart_count() {
+			Some((hart, register))
+		} else {
+			None
+		}
+	}
+	
+	pub fn write_32(&self, offset: u32, value: u32) -> MemWriteResult {
+		let (state_lock, register) = match self.decode_offset(offset) {
+			Some((hart, register)) if hart != 0 => (self.harts[hart as usize - 1].clone(), register),
+			_ => return MemWriteResult::PeripheralError
+		};
+		let mut gaurd = state_lock.lock();
+		match register {
+			OFFSET_START_ADDRESS => {
+				gaurd.start_address = value;
+				MemWriteResult::Ok
+			},
+			OFFSET_STARTUP_TRIGGER => {
+				let start_pc = gaurd.start_address;
+				match &mut gaurd.state {
+					HartState::Idle(cpu) => {
+						// joined here, so a virtual clock starts the hart at the same point on every run
+						cpu.join_clock_lockstep();
+						let state_lock = state_lock.clone();
+						thread::spawn(move || {
+							let cpu_opt = {
+								let mut local_gaurd = state_lock.lock();
+								let mut state_swap = HartState::Running;
+								std::mem::swap(&mut local_gaurd.state, &mut state_swap);
+								match state_swap {
+									HartState::Idle(cpu) => Some(cpu),
+									HartState::Running => None,
+								}
+							};
+							match cpu_opt {
+								Some(mut cpu) => {
+									cpu.reset(start_pc);
+									cpu.run_loop(Duration::from_micros(application_core::CPU_PERIOD_MICROSECONDS));
+									let mut local_gaurd = state_lock.lock();
+									let mut state_swap = HartState::Idle(cpu);
+									std::mem::swap(&mut local_gaurd.state, &mut state_swap);
+								},
+								None => {
+									return;
+								}
+							}
+						});
+						MemWriteResult::Ok
+					},
+					HartState::Running => MemWriteResult::PeripheralError,
+				}
+			},
+			_ => MemWriteResult::PeripheralError
+		}
+	}
+	
+	pub fn read_32(&self, offset: u32) -> MemReadResult<u32> {
+		if offset == OFFSET_HART_COUNT {
+			return MemReadResult::Ok(self.hart_count());
+		}
+		let (hart, register) = match self.decode_offset(offset) {
+			Some(decoded) => decoded,
+			None => return MemReadResult::PeripheralError
+		};
+		if hart == 0 {
+			return match register {
+				OFFSET_START_ADDRESS | OFFSET_STARTUP_TRIGGER => MemReadResult::Ok(0),
+				OFFSET_IS_RUNNING => MemReadResult::Ok(1),
+				_ => MemReadResult::PeripheralError
+			};
+		}
+		let gaurd = self.harts[hart as usize - 1].lock();
+		match register {
+			OFFSET_START_ADDRESS => MemReadResult::Ok(gaurd.start_address),
+			OFFSET_STARTUP_TRIGGER => MemReadResult::Ok(0),
+			OFFSET_IS_RUNNING => {
+				match &gaurd.state {
+					HartState::Idle(..) => MemReadResult::Ok(0),
+					HartState::Running => MemReadResult::Ok(1),
+				}
+			},
+			_ => MemReadResult::PeripheralError,
+		}
+	}
+}
+
+impl Peripheral for HartController {
+	fn read_32(&self, _mio: &FmMemoryIO, offset: u32) -> MemReadResult<u32> {
+		self.read_32(offset)
+	}
+	
+	fn write_32(&self, _mio: &mut FmMemoryIO, offset: u32, value: u32) -> MemWriteResult {
+		self.write_32(offset, value)
+	}
+}
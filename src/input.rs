@@ -1,6 +1,6 @@
 use std::sync::{Arc, atomic::{AtomicU32, Ordering}};
 
-use crate::fm_mio::FmMemoryIO;
+use crate::{fm_mio::{FmMemoryIO, INPUT_BASE, DEVICE_RANGE_SIZE}, peripheral::Peripheral};
 use rv_vsys::{MemReadResult, MemWriteResult};
 use winit::event::{MouseButton, VirtualKeyCode};
 
@@ -216,7 +216,7 @@ impl InputPeripheral {
 			mouse_x: mouse_x.clone(),
 			mouse_y: mouse_y.clone(),
 		};
-		mio.set_input(peripheral);
+		mio.peripherals().map(INPUT_BASE, DEVICE_RANGE_SIZE, Arc::new(peripheral)).unwrap();
 		InputEventSink {
 			key_change_events_0_31,
 			key_change_events_32_63,
@@ -275,3 +275,13 @@ impl InputPeripheral {
 		}
 	}
 }
+
+impl Peripheral for InputPeripheral {
+	fn read_32(&self, _mio: &FmMemoryIO, offset: u32) -> MemReadResult<u32> {
+		self.read_32(offset)
+	}
+	
+	fn write_32(&self, _mio: &mut FmMemoryIO, offset: u32, value: u32) -> MemWriteResult {
+		self.write_32(offset, value)
+	}
+}
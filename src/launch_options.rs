@@ -0,0 +1,136 @@
+use std::env::args;
+use rv_vsys::{ClockMode, DEFAULT_INSTRUCTIONS_PER_SECOND};
+use crate::fm_interrupt_bus::MAX_HART_COUNT;
+
+const DEFAULT_HART_COUNT: u32 = 2;
+
+#[derive(Clone, Debug)]
+pub enum GdbListenAddress {
+	Tcp(String),
+	Unix(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct LaunchOptions {
+	pub boot_rom: String,
+	pub gdb: Option<GdbListenAddress>,
+	pub trace: Option<String>,
+	pub clock: ClockMode,
+	pub harts: u32,
+	pub instructions_per_second: u64,
+	pub report_speed: bool,
+	pub replay_history: Option<u64>,
+	pub emulate_misaligned: bool,
+	pub standard_interrupts: bool,
+	pub semihosting: Option<String>,
+}
+
+fn parse_gdb_address(value: &str) -> Result<GdbListenAddress, String> {
+	if let Some(path) = value.strip_prefix("unix:") {
+		if cfg!(unix) {
+			Ok(GdbListenAddress::Unix(path.to_string()))
+		} else {
+			Err("unix domain sockets are not supported on this platform".to_string())
+		}
+	} else if let Ok(port) = value.parse::<u16>() {
+		Ok(GdbListenAddress::Tcp(format!("127.0.0.1:{}", port)))
+	} else if value.contains(':') {
+		Ok(GdbListenAddress::Tcp(value.to_string()))
+	} else {
+		Err(format!("invalid gdb listen address: {}", value))
+	}
+}
+
+// harts retire one instruction per cycle, so the clock speed is the instruction rate
+pub fn mhz_to_instructions_per_second(mhz: f64) -> Option<u64> {
+	if (0.001 ..= 100_000.0).contains(&mhz) {
+		Some((mhz * 1_000_000.0) as u64)
+	} else {
+		None
+	}
+}
+
+impl LaunchOptions {
+	pub fn from_args() -> Result<Self, String> {
+		let mut boot_rom = None;
+		let mut gdb = None;
+		let mut trace = None;
+		let mut clock = ClockMode::Real;
+		let mut harts = DEFAULT_HART_COUNT;
+		let mut instructions_per_second = DEFAULT_INSTRUCTIONS_PER_SECOND;
+		let mut report_speed = false;
+		let mut replay_history = None;
+		let mut emulate_misaligned = false;
+		let mut standard_interrupts = false;
+		let mut semihosting = None;
+		let mut args = args().skip(1);
+		while let Some(arg) = args.next() {
+			match arg.as_str() {
+				"--gdb" => {
+					let value = args.next().ok_or("--gdb requires an address (<port>, <host>:<port> or unix:<path>)".to_string())?;
+					gdb = Some(parse_gdb_address(value.as_str())?);
+				},
+				"--trace" => {
+					trace = Some(args.next().ok_or("--trace requires an output file".to_string())?);
+				},
+				"--clock" => {
+					clock = match args.next().as_deref() {
+						Some("real") => ClockMode::Real,
+						Some("virtual") => ClockMode::Virtual,
+						_ => return Err("--clock requires a mode (real or virtual)".to_string()),
+					};
+				},
+				"--harts" => {
+					harts = match args.next().and_then(|value| value.parse::<u32>().ok()) {
+						Some(count) if (1 ..= MAX_HART_COUNT).contains(&count) => count,
+						_ => return Err(format!("--harts requires a hart count from 1 to {}", MAX_HART_COUNT)),
+					};
+				},
+				"--cpu-mhz" => {
+					instructions_per_second = match args.next().and_then(|value| value.parse::<f64>().ok()).and_then(mhz_to_instructions_per_second) {
+						Some(instructions_per_second) => instructions_per_second,
+						None => return Err("--cpu-mhz requires a clock speed in MHz, e.g. 20 or 12.5".to_string()),
+					};
+				},
+				"--report-speed" => {
+					report_speed = true;
+				},
+				"--replay-history" => {
+					replay_history = match args.next().and_then(|value| value.parse::<u64>().ok()) {
+						Some(instructions) if instructions > 0 => Some(instructions),
+						_ => return Err("--replay-history requires a number of instructions to keep".to_string()),
+					};
+				},
+				"--emulate-misaligned" => {
+					emulate_misaligned = true;
+				},
+				"--standard-interrupts" => {
+					standard_interrupts = true;
+				},
+				"--semihosting" => {
+					semihosting = Some(args.next().ok_or("--semihosting requires a directory to root host files in".to_string())?);
+				},
+				_ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+				_ => {
+					if boot_rom.is_some() {
+						return Err(format!("unexpected argument: {}", arg));
+					}
+					boot_rom = Some(arg);
+				}
+			}
+		}
+		Ok(LaunchOptions {
+			boot_rom: boot_rom.ok_or("no boot rom specified!".to_string())?,
+			gdb,
+			trace,
+			clock,
+			harts,
+			instructions_per_second,
+			report_speed,
+			replay_history,
+			emulate_misaligned,
+			standard_interrupts,
+			semihosting,
+		})
+	}
+}
@@ -2,21 +2,33 @@ mod application_gui;
 mod application_core;
 mod fm_mio;
 mod debug_device;
-mod elf_loader;
 mod gpu;
 mod raw_fb_renderer;
 mod fm_interrupt_bus;
 mod fb_present_renderer;
 mod dsp_dma;
-mod cpu1_controller;
+mod hart_controller;
 mod mtimer;
+mod peripheral;
+mod clint;
+mod plic;
 mod math_accel;
 mod cart_loader;
 mod sound_out;
 mod input;
+mod launch_options;
 
 use application_gui::ApplicationGUI;
+use launch_options::LaunchOptions;
 
 fn main() {
-	ApplicationGUI::run(6);
+	let options = match LaunchOptions::from_args() {
+		Ok(options) => options,
+		Err(error) => {
+			eprintln!("{}", error);
+			eprintln!("usage: rvfm_main [--gdb <port | host:port | unix:path>] [--trace <file>] [--clock <real | virtual>] [--harts <count>] [--cpu-mhz <mhz>] [--report-speed] [--replay-history <instructions>] [--emulate-misaligned] [--standard-interrupts] [--semihosting <dir>] <boot rom>");
+			std::process::exit(1);
+		}
+	};
+	ApplicationGUI::run(6, options);
 }
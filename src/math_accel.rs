@@ -4,7 +4,7 @@ use std::slice;
 use num_traits::pow::Pow;
 use parking_lot::Mutex;
 use rv_vsys::{MemIO, MemReadResult, MemWriteResult};
-use crate::fm_mio::FmMemoryIO;
+use crate::{fm_mio::FmMemoryIO, peripheral::Peripheral};
 
 pub struct MathAcceleratorData {
 	regs: Box<[f32]>,
@@ -1495,4 +1495,14 @@ impl MathAccelerator {
 	pub fn write_32(&self, mio: &mut FmMemoryIO, offset: u32, data: u32) -> MemWriteResult {
 		self.data.lock().write_32(mio, offset, data)
 	}
+}
+
+impl Peripheral for MathAccelerator {
+	fn read_32(&self, _mio: &FmMemoryIO, offset: u32) -> MemReadResult<u32> {
+		self.read_32(offset)
+	}
+	
+	fn write_32(&self, mio: &mut FmMemoryIO, offset: u32, value: u32) -> MemWriteResult {
+		self.write_32(mio, offset, value)
+	}
 }
\ No newline at end of file
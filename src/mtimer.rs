@@ -1,11 +1,12 @@
-use std::time::Instant;
 use parking_lot::Mutex;
-use rv_vsys::{MemReadResult, MemWriteResult, MTimer};
+use rv_vsys::{MachineClock, MemReadResult, MemWriteResult, MTimer};
 use std::sync::Arc;
 
+use crate::{fm_mio::FmMemoryIO, peripheral::Peripheral};
+
 #[derive(Debug, Clone)]
 struct MTimerPeripheralData {
-	start_time: Instant,
+	start_time: u64,
 	mtime_at_start: u64,
 	mtime_compare: u64,
 	
@@ -15,7 +16,8 @@ struct MTimerPeripheralData {
 
 #[derive(Clone, Debug)]
 pub struct MTimerPeripheral {
-	data: Arc<Mutex<MTimerPeripheralData>>
+	data: Arc<Mutex<MTimerPeripheralData>>,
+	clock: MachineClock,
 }
 
 unsafe impl Sync for MTimerPeripheral {}
@@ -40,15 +42,16 @@ const OFFSET_DUAL_ATOMIC_WRITE_TRIGGER: u32 = 0x40;
 const OFFSET_DUAL_ATOMIC_SWAP_TRIGGER: u32 = 0x44;
 
 impl MTimerPeripheral {
-	pub fn new() -> Self {
+	pub fn new(clock: MachineClock) -> Self {
 		MTimerPeripheral {
 			data: Arc::new(Mutex::new(MTimerPeripheralData {
-				start_time: Instant::now(),
+				start_time: clock.millis(),
 				mtime_at_start: 0,
 				mtime_compare: 0xFFFF_FFFF_FFFF_FFFF,
 				mtime_atomic_buff: 0,
 				mtime_compare_atomic_buff: 0xFFFF_FFFF_FFFF_FFFF,
-			}))
+			})),
+			clock,
 		}
 	}
 	
@@ -75,15 +78,16 @@ impl MTimerPeripheral {
 	
 	pub fn write_32(&self, offset: u32, value: u32) -> MemWriteResult {
 		let mut data = self.data.lock();
+		let now = self.clock.millis();
 		match offset {
 			OFFSET_MTIME_LOW => {
 				data.mtime_at_start = (data.mtime_at_start & 0xFFFF_FFFF_0000_0000) | (value as u64);
-				data.start_time = Instant::now();
+				data.start_time = now;
 				MemWriteResult::Ok
 			},
 			OFFSET_MTIME_HIGH => {
 				data.mtime_at_start = (data.mtime_at_start & 0x0000_0000_FFFF_FFFF) | ((value as u64) << 32);
-				data.start_time = Instant::now();
+				data.start_time = now;
 				MemWriteResult::Ok
 			}
 			OFFSET_MTIME_LOW_ATOMIC_BUFFER => {
@@ -96,12 +100,12 @@ impl MTimerPeripheral {
 			},
 			OFFSET_MTIME_ATOMIC_READ_TRIGGER => {
 				data.mtime_atomic_buff = data.mtime_at_start;
-				data.mtime_atomic_buff += (Instant::now() - data.start_time).as_millis() as u64;
+				data.mtime_atomic_buff += now - data.start_time;
 				MemWriteResult::Ok
 			},
 			OFFSET_MTIME_ATOMIC_WRITE_TRIGGER => {
 				data.mtime_at_start = data.mtime_atomic_buff;
-				data.start_time = Instant::now();
+				data.start_time = now;
 				MemWriteResult::Ok
 			},
 			OFFSET_MTIME_ATOMIC_SWAP_TRIGGER => {
@@ -112,8 +116,8 @@ impl MTimerPeripheral {
 					..
 				} = &mut *data;
 				std::mem::swap(mtime_at_start, mtime_atomic_buff);
-				*mtime_atomic_buff += (Instant::now() - *start_time).as_millis() as u64;
-				data.start_time = Instant::now();
+				*mtime_atomic_buff += now - *start_time;
+				data.start_time = now;
 				MemWriteResult::Ok
 			},
 			
@@ -153,7 +157,7 @@ impl MTimerPeripheral {
 			OFFSET_DUAL_ATOMIC_WRITE_TRIGGER => {
 				data.mtime_compare = data.mtime_compare_atomic_buff;
 				data.mtime_at_start = data.mtime_atomic_buff;
-				data.start_time = Instant::now();
+				data.start_time = now;
 				MemWriteResult::Ok
 			},
 			OFFSET_DUAL_ATOMIC_SWAP_TRIGGER => {
@@ -166,26 +170,53 @@ impl MTimerPeripheral {
 				} = &mut *data;
 				std::mem::swap(mtime_compare, mtime_compare_atomic_buff);
 				std::mem::swap(mtime_at_start, mtime_atomic_buff);
-				*mtime_atomic_buff += (Instant::now() - *start_time).as_millis() as u64;
-				data.start_time = Instant::now();
+				*mtime_atomic_buff += now - *start_time;
+				data.start_time = now;
 				MemWriteResult::Ok
 			}
 			_ => MemWriteResult::ErrUnmapped,
 		}
 	}
 	
-	fn get_mtime(data: &MTimerPeripheralData) -> u64 {
-		let t = Instant::now() - data.start_time;
-		let millis = t.as_millis() as u64;
+	fn get_mtime(&self, data: &MTimerPeripheralData) -> u64 {
+		let millis = self.clock.millis() - data.start_time;
 		millis + data.mtime_at_start
 	}
+	
+	pub fn mtime(&self) -> u64 {
+		let data = self.data.lock();
+		self.get_mtime(&data)
+	}
+	
+	pub fn set_mtime(&self, mtime: u64) {
+		let mut data = self.data.lock();
+		data.mtime_at_start = mtime;
+		data.start_time = self.clock.millis();
+	}
+	
+	pub fn compare(&self) -> u64 {
+		self.data.lock().mtime_compare
+	}
+	
+	pub fn set_compare(&self, compare: u64) {
+		self.data.lock().mtime_compare = compare;
+	}
 }
 
 impl MTimer for MTimerPeripheral {
 	fn check_timer(&self) -> bool {
 		let data = self.data.lock();
-		let mtime = Self::get_mtime(&*data);
+		let mtime = self.get_mtime(&*data);
 		data.mtime_compare <= mtime
 	}
 }
 
+impl Peripheral for MTimerPeripheral {
+	fn read_32(&self, _mio: &FmMemoryIO, offset: u32) -> MemReadResult<u32> {
+		self.read_32(offset)
+	}
+	
+	fn write_32(&self, _mio: &mut FmMemoryIO, offset: u32, value: u32) -> MemWriteResult {
+		self.write_32(offset, value)
+	}
+}
@@ -0,0 +1,231 @@
+use std::sync::Arc;
+use parking_lot::RwLock;
+use rv_vsys::{MemReadResult, MemWriteResult};
+
+use crate::fm_mio::FmMemoryIO;
+
+// Memory mapped devices, and the bus which decodes the peripheral space to them. Devices are
+// mapped over an address range while the machine is being built, and see each access as an offset
+// into their range, along with the memory interface of the hart which made it. Devices which read
+// or write RAM go through that interface, and hart local devices use it to tell harts apart.
+
+pub const PERIPHERAL_SPACE_BASE: u32 = 0xF000_0000;
+
+// the interrupt bus keeps the mask bits below this for its own interrupts
+pub const FIRST_PERIPHERAL_INTERRUPT_LINE: u32 = 3;
+
+pub trait Peripheral: Send + Sync {
+	fn read_8(&self, _mio: &FmMemoryIO, _offset: u32) -> MemReadResult<u8> {
+		MemReadResult::ErrSize
+	}
+
+	fn read_16(&self, _mio: &FmMemoryIO, _offset: u32) -> MemReadResult<u16> {
+		MemReadResult::ErrSize
+	}
+
+	fn read_32(&self, mio: &FmMemoryIO, offset: u32) -> MemReadResult<u32>;
+
+	fn write_8(&self, _mio: &mut FmMemoryIO, _offset: u32, _value: u8) -> MemWriteResult {
+		MemWriteResult::ErrSize
+	}
+
+	fn write_16(&self, _mio: &mut FmMemoryIO, _offset: u32, _value: u16) -> MemWriteResult {
+		MemWriteResult::ErrSize
+	}
+
+	fn write_32(&self, mio: &mut FmMemoryIO, offset: u32, value: u32) -> MemWriteResult;
+
+	// back to the state the device powers on in, whenever the machine is reset to start a program
+	fn reset(&self) {}
+
+	// the interrupt mask bit the device raises, from FIRST_PERIPHERAL_INTERRUPT_LINE up to 31
+	fn interrupt_line(&self) -> Option<u32> {
+		None
+	}
+
+	// polled by every hart, which takes the interrupt if it has the line unmasked
+	fn interrupt_pending(&self, _hart_id: u32) -> bool {
+		false
+	}
+}
+
+// a device with an instance for each hart, where every hart sees its own through the same range
+pub struct HartLocal<P: Peripheral> {
+	harts: Arc<[Arc<P>]>,
+}
+
+impl <P: Peripheral> HartLocal<P> {
+	pub fn new(harts: Arc<[Arc<P>]>) -> Self {
+		HartLocal {
+			harts,
+		}
+	}
+
+	fn hart(&self, mio: &FmMemoryIO) -> Option<Arc<P>> {
+		self.harts.get(mio.hart_id() as usize).cloned()
+	}
+}
+
+impl <P: Peripheral> Peripheral for HartLocal<P> {
+	fn read_8(&self, mio: &FmMemoryIO, offset: u32) -> MemReadResult<u8> {
+		match self.hart(mio) {
+			Some(device) => device.read_8(mio, offset),
+			None => MemReadResult::ErrUnmapped
+		}
+	}
+
+	fn read_16(&self, mio: &FmMemoryIO, offset: u32) -> MemReadResult<u16> {
+		match self.hart(mio) {
+			Some(device) => device.read_16(mio, offset),
+			None => MemReadResult::ErrUnmapped
+		}
+	}
+
+	fn read_32(&self, mio: &FmMemoryIO, offset: u32) -> MemReadResult<u32> {
+		match self.hart(mio) {
+			Some(device) => device.read_32(mio, offset),
+			None => MemReadResult::ErrUnmapped
+		}
+	}
+
+	fn write_8(&self, mio: &mut FmMemoryIO, offset: u32, value: u8) -> MemWriteResult {
+		match self.hart(mio) {
+			Some(device) => device.write_8(mio, offset, value),
+			None => MemWriteResult::ErrUnmapped
+		}
+	}
+
+	fn write_16(&self, mio: &mut FmMemoryIO, offset: u32, value: u16) -> MemWriteResult {
+		match self.hart(mio) {
+			Some(device) => device.write_16(mio, offset, value),
+			None => MemWriteResult::ErrUnmapped
+		}
+	}
+
+	fn write_32(&self, mio: &mut FmMemoryIO, offset: u32, value: u32) -> MemWriteResult {
+		match self.hart(mio) {
+			Some(device) => device.write_32(mio, offset, value),
+			None => MemWriteResult::ErrUnmapped
+		}
+	}
+
+	fn reset(&self) {
+		for device in self.harts.iter() {
+			device.reset();
+		}
+	}
+
+	fn interrupt_line(&self) -> Option<u32> {
+		self.harts.first()?.interrupt_line()
+	}
+
+	fn interrupt_pending(&self, hart_id: u32) -> bool {
+		match self.harts.get(hart_id as usize) {
+			Some(device) => device.interrupt_pending(hart_id),
+			None => false
+		}
+	}
+}
+
+struct PeripheralMapping {
+	base: u32,
+	size: u32,
+	peripheral: Arc<dyn Peripheral>,
+}
+
+#[derive(Clone)]
+pub struct PeripheralBus {
+	// kept sorted by base address
+	mappings: Arc<RwLock<Vec<PeripheralMapping>>>,
+}
+
+impl PeripheralBus {
+	pub fn new() -> Self {
+		PeripheralBus {
+			mappings: Arc::new(RwLock::new(Vec::new())),
+		}
+	}
+
+	pub fn map(&self, base: u32, size: u32, peripheral: Arc<dyn Peripheral>) -> Result<(), String> {
+		let end = base as u64 + size as u64;
+		if size == 0 || base < PERIPHERAL_SPACE_BASE || end > 0x1_0000_0000 {
+			return Err(format!("peripheral range {:#010x} + {:#x} is outside of the peripheral space", base, size));
+		}
+		if let Some(line) = peripheral.interrupt_line() {
+			if ! (FIRST_PERIPHERAL_INTERRUPT_LINE ..= 31).contains(&line) {
+				return Err(format!("peripheral interrupt line {} is out of range", line));
+			}
+		}
+		let mut mappings = self.mappings.write();
+		if let Some(existing) = mappings.iter().find(|mapping| (base as u64) < mapping.base as u64 + mapping.size as u64 && end > mapping.base as u64) {
+			return Err(format!("peripheral range {:#010x} + {:#x} overlaps the device at {:#010x}", base, size, existing.base));
+		}
+		let index = mappings.iter().position(|mapping| mapping.base > base).unwrap_or(mappings.len());
+		mappings.insert(index, PeripheralMapping {
+			base,
+			size,
+			peripheral,
+		});
+		Ok(())
+	}
+
+	// a device may access the bus again from inside an access, so the map is only ever read recursively
+	fn with_peripheral<R>(&self, addr: u32, unmapped: R, access: impl FnOnce(&dyn Peripheral, u32) -> R) -> R {
+		let mappings = self.mappings.read_recursive();
+		let index = match mappings.binary_search_by(|mapping| mapping.base.cmp(&addr)) {
+			Ok(index) => index,
+			Err(0) => return unmapped,
+			Err(index) => index - 1,
+		};
+		let mapping = &mappings[index];
+		let offset = addr - mapping.base;
+		if offset < mapping.size {
+			access(&*mapping.peripheral, offset)
+		} else {
+			unmapped
+		}
+	}
+
+	pub fn read_8(&self, mio: &FmMemoryIO, addr: u32) -> MemReadResult<u8> {
+		self.with_peripheral(addr, MemReadResult::ErrUnmapped, |peripheral, offset| peripheral.read_8(mio, offset))
+	}
+
+	pub fn read_16(&self, mio: &FmMemoryIO, addr: u32) -> MemReadResult<u16> {
+		self.with_peripheral(addr, MemReadResult::ErrUnmapped, |peripheral, offset| peripheral.read_16(mio, offset))
+	}
+
+	pub fn read_32(&self, mio: &FmMemoryIO, addr: u32) -> MemReadResult<u32> {
+		self.with_peripheral(addr, MemReadResult::ErrUnmapped, |peripheral, offset| peripheral.read_32(mio, offset))
+	}
+
+	pub fn write_8(&self, mio: &mut FmMemoryIO, addr: u32, value: u8) -> MemWriteResult {
+		self.with_peripheral(addr, MemWriteResult::ErrUnmapped, |peripheral, offset| peripheral.write_8(mio, offset, value))
+	}
+
+	pub fn write_16(&self, mio: &mut FmMemoryIO, addr: u32, value: u16) -> MemWriteResult {
+		self.with_peripheral(addr, MemWriteResult::ErrUnmapped, |peripheral, offset| peripheral.write_16(mio, offset, value))
+	}
+
+	pub fn write_32(&self, mio: &mut FmMemoryIO, addr: u32, value: u32) -> MemWriteResult {
+		self.with_peripheral(addr, MemWriteResult::ErrUnmapped, |peripheral, offset| peripheral.write_32(mio, offset, value))
+	}
+
+	pub fn reset(&self) {
+		for mapping in self.mappings.read_recursive().iter() {
+			mapping.peripheral.reset();
+		}
+	}
+
+	// the mask bits of the lines raised to hart_id
+	pub fn pending_interrupts(&self, hart_id: u32) -> u32 {
+		let mut pending = 0;
+		for mapping in self.mappings.read_recursive().iter() {
+			if let Some(line) = mapping.peripheral.interrupt_line() {
+				if mapping.peripheral.interrupt_pending(hart_id) {
+					pending |= 1 << line;
+				}
+			}
+		}
+		pending
+	}
+}
@@ -0,0 +1,216 @@
+use std::sync::Arc;
+use parking_lot::Mutex;
+use rv_vsys::{MemReadResult, MemWriteResult};
+
+use crate::{fm_mio::FmMemoryIO, peripheral::Peripheral};
+
+// A platform level interrupt controller with the SiFive register layout. Every hart has a machine
+// context (2 * hart) and a supervisor context (2 * hart + 1). Sources are level triggered: a raised
+// source becomes pending, a claim hands the highest priority pending source to a context, and the
+// source can't become pending again until the context writes it back to complete it.
+//
+// Source 1 is vsync, 2 is the sound fifo, and 3 to 31 are the interrupt lines of the devices mapped
+// on the peripheral bus. Source 0 doesn't exist.
+
+pub const PLIC_RANGE_SIZE: u32 = 0x400_0000;
+
+const SOURCE_COUNT: usize = 32;
+const MAX_PRIORITY: u32 = 7;
+
+const OFFSET_PRIORITY_BASE: u32 = 0x00_0000;
+const OFFSET_PENDING: u32 = 0x00_1000;
+// one bank of enable bits per context
+const OFFSET_ENABLE_BASE: u32 = 0x00_2000;
+const ENABLE_STRIDE: u32 = 0x80;
+// the threshold and claim/complete registers of each context
+const OFFSET_CONTEXT_BASE: u32 = 0x20_0000;
+const CONTEXT_STRIDE: u32 = 0x1000;
+const CONTEXT_THRESHOLD: u32 = 0;
+const CONTEXT_CLAIM: u32 = 4;
+
+pub const SOURCE_VSYNC: u32 = 1;
+pub const SOURCE_SOUND_FIFO: u32 = 2;
+
+struct PlicState {
+	priority: [u32; SOURCE_COUNT],
+	pending: u32,
+	// claimed and not completed yet
+	in_flight: u32,
+	enable: Vec<u32>,
+	threshold: Vec<u32>,
+}
+
+impl PlicState {
+	fn new(context_count: u32) -> Self {
+		PlicState {
+			priority: [0; SOURCE_COUNT],
+			pending: 0,
+			in_flight: 0,
+			enable: vec![0; context_count as usize],
+			threshold: vec![0; context_count as usize],
+		}
+	}
+
+	// the pending and enabled source with the highest priority, the lowest numbered one of equals
+	fn best_source(&self, context: usize) -> Option<u32> {
+		let candidates = self.pending & self.enable[context];
+		let mut best: Option<u32> = None;
+		for source in 1 .. SOURCE_COUNT as u32 {
+			if candidates & (1 << source) == 0 || self.priority[source as usize] == 0 {
+				continue;
+			}
+			match best {
+				Some(best_source) if self.priority[best_source as usize] >= self.priority[source as usize] => {},
+				_ => best = Some(source),
+			}
+		}
+		best
+	}
+}
+
+#[derive(Clone)]
+pub struct Plic {
+	state: Arc<Mutex<PlicState>>,
+}
+
+impl Plic {
+	pub fn new(hart_count: u32) -> Self {
+		Plic {
+			state: Arc::new(Mutex::new(PlicState::new(hart_count * 2))),
+		}
+	}
+
+	pub fn machine_context(hart_id: u32) -> u32 {
+		hart_id * 2
+	}
+
+	pub fn supervisor_context(hart_id: u32) -> u32 {
+		hart_id * 2 + 1
+	}
+
+	// takes the current level of every source, as a bit for each
+	pub fn update(&self, levels: u32) {
+		let mut state = self.state.lock();
+		state.pending |= levels & !state.in_flight & !1;
+	}
+
+	// whether the context has a source above its threshold to claim
+	pub fn context_pending(&self, context: u32) -> bool {
+		let state = self.state.lock();
+		if context as usize >= state.enable.len() {
+			return false;
+		}
+		match state.best_source(context as usize) {
+			Some(source) => state.priority[source as usize] > state.threshold[context as usize],
+			None => false
+		}
+	}
+
+	fn context_register(&self, offset: u32, base: u32, stride: u32) -> Option<(usize, u32)> {
+		let relative = offset.checked_sub(base)?;
+		let context = (relative / stride) as usize;
+		if context < self.state.lock().enable.len() {
+			Some((context, relative % stride))
+		} else {
+			None
+		}
+	}
+
+	pub fn read_32(&self, offset: u32) -> MemReadResult<u32> {
+		if offset & 3 != 0 {
+			return MemReadResult::ErrAlignment;
+		}
+		if offset < OFFSET_PENDING {
+			let source = ((offset - OFFSET_PRIORITY_BASE) / 4) as usize;
+			return match self.state.lock().priority.get(source) {
+				Some(priority) => MemReadResult::Ok(*priority),
+				None => MemReadResult::Ok(0)
+			};
+		}
+		if offset < OFFSET_ENABLE_BASE {
+			return MemReadResult::Ok(if offset == OFFSET_PENDING { self.state.lock().pending } else { 0 });
+		}
+		if offset < OFFSET_CONTEXT_BASE {
+			return match self.context_register(offset, OFFSET_ENABLE_BASE, ENABLE_STRIDE) {
+				Some((context, 0)) => MemReadResult::Ok(self.state.lock().enable[context]),
+				Some(_) => MemReadResult::Ok(0),
+				None => MemReadResult::ErrUnmapped
+			};
+		}
+		match self.context_register(offset, OFFSET_CONTEXT_BASE, CONTEXT_STRIDE) {
+			Some((context, CONTEXT_THRESHOLD)) => MemReadResult::Ok(self.state.lock().threshold[context]),
+			Some((context, CONTEXT_CLAIM)) => {
+				let mut state = self.state.lock();
+				match state.best_source(context) {
+					Some(source) => {
+						state.pending &= !(1 << source);
+						state.in_flight |= 1 << source;
+						MemReadResult::Ok(source)
+					},
+					None => MemReadResult::Ok(0)
+				}
+			},
+			Some(_) => MemReadResult::Ok(0),
+			None => MemReadResult::ErrUnmapped
+		}
+	}
+
+	pub fn write_32(&self, offset: u32, value: u32) -> MemWriteResult {
+		if offset & 3 != 0 {
+			return MemWriteResult::ErrAlignment;
+		}
+		if offset < OFFSET_PENDING {
+			let source = ((offset - OFFSET_PRIORITY_BASE) / 4) as usize;
+			if source != 0 && source < SOURCE_COUNT {
+				self.state.lock().priority[source] = value.min(MAX_PRIORITY);
+			}
+			return MemWriteResult::Ok;
+		}
+		if offset < OFFSET_ENABLE_BASE {
+			// pending bits are read only
+			return MemWriteResult::Ok;
+		}
+		if offset < OFFSET_CONTEXT_BASE {
+			return match self.context_register(offset, OFFSET_ENABLE_BASE, ENABLE_STRIDE) {
+				Some((context, 0)) => {
+					self.state.lock().enable[context] = value & !1;
+					MemWriteResult::Ok
+				},
+				Some(_) => MemWriteResult::Ok,
+				None => MemWriteResult::ErrUnmapped
+			};
+		}
+		match self.context_register(offset, OFFSET_CONTEXT_BASE, CONTEXT_STRIDE) {
+			Some((context, CONTEXT_THRESHOLD)) => {
+				self.state.lock().threshold[context] = value.min(MAX_PRIORITY);
+				MemWriteResult::Ok
+			},
+			// completing a source the context doesn't have enabled is ignored
+			Some((context, CONTEXT_CLAIM)) => {
+				let mut state = self.state.lock();
+				if (value as usize) < SOURCE_COUNT && state.enable[context] & (1 << value) != 0 {
+					state.in_flight &= !(1 << value);
+				}
+				MemWriteResult::Ok
+			},
+			Some(_) => MemWriteResult::Ok,
+			None => MemWriteResult::ErrUnmapped
+		}
+	}
+}
+
+impl Peripheral for Plic {
+	fn read_32(&self, _mio: &FmMemoryIO, offset: u32) -> MemReadResult<u32> {
+		self.read_32(offset)
+	}
+
+	fn write_32(&self, _mio: &mut FmMemoryIO, offset: u32, value: u32) -> MemWriteResult {
+		self.write_32(offset, value)
+	}
+
+	fn reset(&self) {
+		let mut state = self.state.lock();
+		let context_count = state.enable.len() as u32;
+		*state = PlicState::new(context_count);
+	}
+}
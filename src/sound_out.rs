@@ -1,12 +1,12 @@
 use cpal::{self, traits::{DeviceTrait, HostTrait, StreamTrait}};
-use rv_vsys::{CpuWakeupHandle, MemIO, MemReadResult, MemWriteResult};
+use rv_vsys::{CpuWakeupHandle, MachineClock, MemIO, MemReadResult, MemWriteResult};
 use core::f32;
-use std::{sync::{Arc, Barrier, atomic::{AtomicBool, AtomicU32, Ordering}}, usize};
+use std::{sync::{Arc, Barrier, atomic::{AtomicBool, AtomicU32, Ordering}}, time::Duration, usize};
 use std::fmt::{self, Debug, Formatter};
 use parking_lot::{Mutex};
 use ringbuf;
 
-use crate::{fm_interrupt_bus::FmInterruptBus, fm_mio::FmMemoryIO};
+use crate::{fm_interrupt_bus::FmInterruptBus, fm_mio::{FmMemoryIO, SOUND_OUT_BASE, DEVICE_RANGE_SIZE}, peripheral::Peripheral};
 
 trait FromNativeSample {
 	fn from_native_sample(v: i16) -> Self;
@@ -45,12 +45,28 @@ const ELEMENTS_PER_FRAME: u32 = TARGET_FRAME_LENGTH * CHANNEL_COUNT;
 const SOUND_FIFO_LENGTH: u32 = ELEMENTS_PER_FRAME * 5;
 const FIFO_FILL_THRESHOLD: u32 = ELEMENTS_PER_FRAME * 3;
 
-struct SoundCallbackData {
-	ring_buffer: ringbuf::Consumer<i16>,
-	enabled: Arc<AtomicBool>,
+struct FifoInterrupt {
 	fifo_int_enabled: Arc<AtomicBool>,
 	fifo_int_state: Arc<AtomicBool>,
 	cpu_wakeup: CpuWakeupHandle,
+}
+
+impl FifoInterrupt {
+	fn check_fill(&mut self, fifo_length: usize) {
+		if (fifo_length as u32) < FIFO_FILL_THRESHOLD {
+			self.fifo_int_state.store(true, Ordering::SeqCst);
+			if self.fifo_int_enabled.load(Ordering::SeqCst) {
+				self.cpu_wakeup.cpu_wake();
+			}
+		}
+	}
+}
+
+struct SoundCallbackData {
+	ring_buffer: ringbuf::Consumer<i16>,
+	enabled: Arc<AtomicBool>,
+	// None when the guest fifo is drained on virtual time instead of by the output stream
+	fifo_interrupt: Option<FifoInterrupt>,
 	double_rate: bool,
 	odd_sample: bool,
 	double_rate_sample: i16,
@@ -84,11 +100,8 @@ impl SoundCallbackData {
 					buffer[i] = T::SAMPLE_ZERO;
 				}
 			}
-			if (self.ring_buffer.len() as u32) < FIFO_FILL_THRESHOLD {
-				self.fifo_int_state.store(true, Ordering::SeqCst);
-				if self.fifo_int_enabled.load(Ordering::SeqCst) {
-					self.cpu_wakeup.cpu_wake();
-				}
+			if let Some(fifo_interrupt) = &mut self.fifo_interrupt {
+				fifo_interrupt.check_fill(self.ring_buffer.len());
 			}
 		} else {
 			for i in 0 .. buffer.len() {
@@ -129,6 +142,10 @@ pub struct SoundOutPeripheral {
 	last_fill_count: Arc<AtomicU32>
 }
 
+// the stream is only held to keep it playing, and is never touched from the harts
+unsafe impl Send for SoundOutPeripheral {}
+unsafe impl Sync for SoundOutPeripheral {}
+
 impl Debug for SoundOutPeripheral {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         f.write_str("SoundOutPeripheral")
@@ -235,7 +252,7 @@ fn pick_sound_format(configs: cpal::SupportedOutputConfigs) -> Option<(cpal::Str
 }
 
 impl SoundOutPeripheral {
-	pub fn new(cpu_wakeup: CpuWakeupHandle, interrupt_bus: &mut FmInterruptBus, mio: &mut FmMemoryIO, with_host_name: Option<String>, with_device_name: Option<String>) -> Result<(), String> {
+	pub fn new(cpu_wakeup: CpuWakeupHandle, interrupt_bus: &mut FmInterruptBus, mio: &mut FmMemoryIO, clock: &MachineClock, with_host_name: Option<String>, with_device_name: Option<String>) -> Result<(), String> {
 		let ring_buffer = ringbuf::RingBuffer::new(SOUND_FIFO_LENGTH as usize);
 		let (ring_buff_in, ring_buff_out) = ring_buffer.split();
 		let enabled = Arc::new(AtomicBool::new(false));
@@ -299,14 +316,42 @@ impl SoundOutPeripheral {
 		} else {
 			return Err("No adequate sound output format found for sound device".to_string());
 		};
+		let mut fifo_interrupt = FifoInterrupt {
+			fifo_int_enabled: fifo_int_enabled.clone(),
+			fifo_int_state: fifo_int_state.clone(),
+			cpu_wakeup,
+		};
+		// with a virtual clock the guest fifo drains at the nominal sample rate in virtual time,
+		// into a second fifo which the output stream plays from at whatever pace the host runs
+		let (stream_ring_buffer, fifo_interrupt) = if clock.is_virtual() {
+			let host_ring_buffer = ringbuf::RingBuffer::new(SOUND_FIFO_LENGTH as usize);
+			let (mut host_buff_in, host_buff_out) = host_ring_buffer.split();
+			let mut guest_buff_out = ring_buff_out;
+			let drain_enabled = enabled.clone();
+			let frame_period = Duration::from_nanos(TARGET_FRAME_LENGTH as u64 * 1_000_000_000 / SAMPLE_RATE as u64);
+			clock.add_virtual_timer(frame_period, Box::new(move || {
+				if drain_enabled.load(Ordering::SeqCst) {
+					for _ in 0 .. ELEMENTS_PER_FRAME {
+						match guest_buff_out.pop() {
+							Some(sample) => {
+								let _ = host_buff_in.push(sample);
+							},
+							None => break
+						}
+					}
+					fifo_interrupt.check_fill(guest_buff_out.len());
+				}
+			}));
+			(host_buff_out, None)
+		} else {
+			(ring_buff_out, Some(fifo_interrupt))
+		};
 		let mut barrier_waited = false;
 		let callback_barrier = stream_started.clone();
 		let mut callback_data = SoundCallbackData {
-			ring_buffer: ring_buff_out,
+			ring_buffer: stream_ring_buffer,
 			enabled: enabled.clone(),
-			fifo_int_enabled: fifo_int_enabled.clone(),
-			fifo_int_state: fifo_int_state.clone(),
-			cpu_wakeup,
+			fifo_interrupt,
 			double_rate,
 			double_rate_sample: 0,
 			odd_sample: false
@@ -356,14 +401,14 @@ impl SoundOutPeripheral {
 			return Err("Failed to play sound output stream".to_string());
 		}
 		stream_started.wait();
-		mio.set_sound_out(SoundOutPeripheral {
+		mio.peripherals().map(SOUND_OUT_BASE, DEVICE_RANGE_SIZE, Arc::new(SoundOutPeripheral {
 			ring_buffer: Arc::new(Mutex::new(ring_buff_in)),
 			enabled,
 			source_ptr: Arc::new(AtomicU32::new(0)),
 			_stream: stream,
 			fifo_int_enabled,
 			last_fill_count: Arc::new(AtomicU32::new(0))
-		});
+		}))?;
 		interrupt_bus.set_sound_interrupt(SoundInterruptOutput {
 			fifo_int_state
 		});
@@ -418,3 +463,13 @@ impl SoundOutPeripheral {
 		}
 	}
 }
+
+impl Peripheral for SoundOutPeripheral {
+	fn read_32(&self, _mio: &FmMemoryIO, offset: u32) -> MemReadResult<u32> {
+		self.read_32(offset)
+	}
+	
+	fn write_32(&self, mio: &mut FmMemoryIO, offset: u32, value: u32) -> MemWriteResult {
+		self.write_32(mio, offset, value)
+	}
+}